@@ -0,0 +1,45 @@
+//! ビルドを特定するための情報を環境変数として埋め込む。`build_info` が `env!` で読む。
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let revision = git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    let revision = if dirty {
+        format!("{revision}-dirty")
+    } else {
+        revision
+    };
+    let profile = env::var("PROFILE").unwrap_or_else(|_| "unknown".into());
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=GINKO_GIT_REVISION={revision}");
+    println!("cargo:rustc-env=GINKO_BUILD_PROFILE={profile}");
+    println!("cargo:rustc-env=GINKO_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-changed=build.rs");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let git_dir = PathBuf::from(git_dir);
+        for path in ["HEAD", "index", "refs", "packed-refs"] {
+            println!("cargo:rerun-if-changed={}", git_dir.join(path).display());
+        }
+    }
+}
+
+/// `git` を実行した標準出力。gitがないか、リポジトリの外なら `None`。
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
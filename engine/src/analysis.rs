@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use crate::moves::{Move, MoveList};
+use crate::position::{Position, PositionError};
+use crate::score::RelScore;
+use crate::search::{NullInfoSink, SearchBackend, SearchLimits, Searcher, StopToken};
+
+/// 解析済みの局面1つ分の結果。
+#[derive(Clone, Debug)]
+pub struct AnalysisNode {
+    /// 局面の手番側から見た評価値。
+    pub score: RelScore,
+    pub depth: usize,
+    pub pv: MoveList,
+}
+
+/// GUIでの検討向けに、局面の移動をまたいで解析結果と置換表を使い回すセッション。
+///
+/// 解析した局面はハッシュをキーに覚えておき、同じ局面に戻ったときは探索し直さない。
+/// `play` でたどった手は局面どうしの枝として記録し、変化の一覧に使う。
+pub struct AnalysisSession<B = Searcher> {
+    backend: B,
+    root: Position,
+    limits: SearchLimits,
+    nodes: HashMap<u64, AnalysisNode>,
+    children: HashMap<u64, Vec<(Move, u64)>>,
+}
+
+impl AnalysisSession {
+    pub fn new(root: Position, limits: SearchLimits) -> Self {
+        Self::with_backend(Searcher::new(), root, limits)
+    }
+}
+
+impl<B: SearchBackend> AnalysisSession<B> {
+    pub fn with_backend(mut backend: B, root: Position, limits: SearchLimits) -> Self {
+        backend.set_info_sink(Box::new(NullInfoSink));
+        backend.set_keep_tt(true);
+        Self {
+            backend,
+            root,
+            limits,
+            nodes: HashMap::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Position {
+        &self.root
+    }
+
+    pub fn limits(&self) -> SearchLimits {
+        self.limits
+    }
+
+    /// 以降の解析の深さなどを変える。覚えている結果は、浅いものだけが解析し直される。
+    pub fn set_limits(&mut self, limits: SearchLimits) {
+        self.limits = limits;
+    }
+
+    /// 任意の局面へ移る。棋譜の別の手数をクリックしたときなどに使う。
+    pub fn set_root(&mut self, position: Position) {
+        self.root = position;
+    }
+
+    /// 現在の局面から1手進め、その枝を記録する。
+    pub fn play(&mut self, mv: &Move) -> Result<(), PositionError> {
+        if !self.root.generate_legal_moves()?.contains(mv) {
+            return Err(PositionError::message(format!(
+                "illegal move: {}",
+                mv.to_usi()
+            )));
+        }
+        let parent = self.root.zobrist_key();
+        self.root.play_move_mut(mv)?;
+        let child = self.root.zobrist_key();
+        let edges = self.children.entry(parent).or_default();
+        if !edges.iter().any(|&(edge, _)| edge == *mv) {
+            edges.push((*mv, child));
+        }
+        Ok(())
+    }
+
+    /// 現在の局面を解析する。要求深さ以上の結果を覚えていれば探索しない。
+    pub fn analyze(&mut self) -> Result<&AnalysisNode, PositionError> {
+        self.analyze_with_stop(&StopToken::new())
+    }
+
+    /// `stop` が立てられた時点で打ち切る `analyze`。
+    pub fn analyze_with_stop(&mut self, stop: &StopToken) -> Result<&AnalysisNode, PositionError> {
+        let key = self.root.zobrist_key();
+        if !self.is_analyzed(&self.root) {
+            let result = self
+                .backend
+                .search_with_stop(&self.root, self.limits, stop)?;
+            let pv = self.backend.principal_variation(&self.root, result.depth);
+            self.nodes.insert(
+                key,
+                AnalysisNode {
+                    score: result.score,
+                    depth: result.depth,
+                    pv,
+                },
+            );
+        }
+        Ok(&self.nodes[&key])
+    }
+
+    /// `position` を現在の深さ設定で解析済みか。
+    pub fn is_analyzed(&self, position: &Position) -> bool {
+        self.node(position)
+            .is_some_and(|node| node.depth >= self.limits.depth.max(1))
+    }
+
+    pub fn node(&self, position: &Position) -> Option<&AnalysisNode> {
+        self.nodes.get(&position.zobrist_key())
+    }
+
+    /// `position` から `play` でたどった手と、その先の解析結果（あれば）。
+    pub fn variations(&self, position: &Position) -> Vec<(Move, Option<&AnalysisNode>)> {
+        self.children
+            .get(&position.zobrist_key())
+            .map(|edges| {
+                edges
+                    .iter()
+                    .map(|(mv, child)| (*mv, self.nodes.get(child)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// 覚えている解析結果の数。
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// 解析結果と枝を忘れる。置換表はそのまま残る。
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.children.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn navigating_back_reuses_the_cached_analysis() {
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let start = Position::initial().unwrap();
+        let mut session = AnalysisSession::new(start.clone(), limits);
+        let first = session.analyze().unwrap().clone();
+        assert!(!first.pv.is_empty());
+
+        let mv = first.pv[0];
+        session.play(&mv).unwrap();
+        assert!(!session.is_analyzed(session.root()));
+        session.analyze().unwrap();
+        assert_eq!(session.len(), 2);
+
+        session.set_root(start.clone());
+        assert!(session.is_analyzed(&start));
+        assert_eq!(session.analyze().unwrap().score, first.score);
+        let variations = session.variations(&start);
+        assert_eq!(variations.len(), 1);
+        assert_eq!(variations[0].0, mv);
+        assert!(variations[0].1.is_some());
+
+        session.set_limits(SearchLimits { depth: 3, ..limits });
+        assert!(!session.is_analyzed(&start));
+        assert_eq!(session.analyze().unwrap().depth, 3);
+    }
+}
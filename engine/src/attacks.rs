@@ -1,6 +1,6 @@
 use crate::bitboard::Bitboard;
 use crate::board::{BOARD_FILES, BOARD_RANKS, Square};
-use crate::piece::Color;
+use crate::piece::{Color, Movement, PieceKind};
 
 const DIR_ROOK: &[(i8, i8)] = &[(0, 1), (0, -1), (-1, 0), (1, 0)];
 const DIR_BISHOP: &[(i8, i8)] = &[(1, 1), (1, -1), (-1, 1), (-1, -1)];
@@ -86,6 +86,25 @@ pub fn dragon_attacks(square: Square, occupancy: Bitboard) -> Bitboard {
     rook_attacks(square, occupancy) | king_attacks(square)
 }
 
+/// 駒種に応じた利きを返す。
+pub fn piece_attacks(
+    color: Color,
+    kind: PieceKind,
+    square: Square,
+    occupancy: Bitboard,
+) -> Bitboard {
+    match kind.movement() {
+        Movement::King => king_attacks(square),
+        Movement::Gold => gold_attacks(color, square),
+        Movement::Silver => silver_attacks(color, square),
+        Movement::Pawn => pawn_attacks(color, square),
+        Movement::Bishop => bishop_attacks(square, occupancy),
+        Movement::Rook => rook_attacks(square, occupancy),
+        Movement::Horse => horse_attacks(square, occupancy),
+        Movement::Dragon => dragon_attacks(square, occupancy),
+    }
+}
+
 pub fn pawn_attack_bitboard(color: Color, occupancy: Bitboard) -> Bitboard {
     let mut result = Bitboard::EMPTY;
     for rank in 0..BOARD_RANKS {
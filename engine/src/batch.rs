@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::position::{Position, PositionError};
+use crate::search::{InfoSink, SearchBackend, SearchInfo, SearchLimits, SearchResult};
+
+/// 解析の依頼1件。`id` で途中経過と結果を対応付ける。
+#[derive(Clone, Debug)]
+pub struct AnalysisJob {
+    pub id: u64,
+    pub position: Position,
+    pub limits: SearchLimits,
+}
+
+/// 解析キューの出力先。複数スレッドから呼ばれる場合は `Mutex` 越しに順番に呼ばれる。
+pub trait BatchSink: Send {
+    fn info(&mut self, id: u64, info: &SearchInfo);
+    fn finished(&mut self, id: u64, result: Result<SearchResult, PositionError>);
+}
+
+/// 探索器の途中経過に依頼の `id` を付けて `BatchSink` へ渡す。
+struct TaggedInfoSink<S> {
+    id: u64,
+    sink: Arc<Mutex<S>>,
+}
+
+impl<S: BatchSink> InfoSink for TaggedInfoSink<S> {
+    fn info(&mut self, info: &SearchInfo) {
+        if let Ok(mut sink) = self.sink.lock() {
+            sink.info(self.id, info);
+        }
+    }
+}
+
+/// 複数の局面をまとめて解析するキュー。
+#[derive(Debug, Default)]
+pub struct AnalysisQueue {
+    jobs: VecDeque<AnalysisJob>,
+    next_id: u64,
+}
+
+impl AnalysisQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 依頼を追加し、割り当てた `id` を返す。
+    pub fn push(&mut self, position: Position, limits: SearchLimits) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push_back(AnalysisJob {
+            id,
+            position,
+            limits,
+        });
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// 1つの探索器で先頭から順に処理する。探索器の途中経過の出力先は差し替えられたままになる。
+    pub fn run<B, S>(&mut self, backend: &mut B, sink: &Arc<Mutex<S>>)
+    where
+        B: SearchBackend,
+        S: BatchSink + 'static,
+    {
+        while let Some(job) = self.jobs.pop_front() {
+            process(backend, job, sink);
+        }
+    }
+
+    /// `threads` 本のスレッドで並列に処理する。各スレッドは `B::default()` で探索器を作る。
+    /// 結果は終わった順に `sink` へ渡される。
+    pub fn run_parallel<B, S>(&mut self, threads: usize, sink: &Arc<Mutex<S>>)
+    where
+        B: SearchBackend + Default,
+        S: BatchSink + 'static,
+    {
+        let jobs = Mutex::new(std::mem::take(&mut self.jobs));
+        thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| {
+                    let mut backend = B::default();
+                    while let Some(job) = jobs.lock().ok().and_then(|mut jobs| jobs.pop_front()) {
+                        process(&mut backend, job, sink);
+                    }
+                });
+            }
+        });
+    }
+}
+
+fn process<B, S>(backend: &mut B, job: AnalysisJob, sink: &Arc<Mutex<S>>)
+where
+    B: SearchBackend,
+    S: BatchSink + 'static,
+{
+    backend.set_info_sink(Box::new(TaggedInfoSink {
+        id: job.id,
+        sink: Arc::clone(sink),
+    }));
+    let result = backend.search(&job.position, job.limits);
+    if let Ok(mut sink) = sink.lock() {
+        sink.finished(job.id, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Searcher;
+
+    #[derive(Default)]
+    struct Collect {
+        infos: Vec<u64>,
+        results: Vec<(u64, SearchResult)>,
+    }
+
+    impl BatchSink for Collect {
+        fn info(&mut self, id: u64, _info: &SearchInfo) {
+            self.infos.push(id);
+        }
+
+        fn finished(&mut self, id: u64, result: Result<SearchResult, PositionError>) {
+            self.results.push((id, result.expect("search")));
+        }
+    }
+
+    fn queue() -> (AnalysisQueue, Vec<u64>) {
+        let mut queue = AnalysisQueue::new();
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let ids = ["4k/5/3S1/5/K4 b G 1", "rbsgk/4p/5/P4/KGSBR b - 1"]
+            .iter()
+            .map(|sfen| queue.push(Position::from_sfen(sfen).unwrap(), limits))
+            .collect();
+        (queue, ids)
+    }
+
+    #[test]
+    fn sequential_queue_tags_every_result() {
+        let (mut queue, ids) = queue();
+        let sink = Arc::new(Mutex::new(Collect::default()));
+        queue.run(&mut Searcher::new(), &sink);
+        assert!(queue.is_empty());
+        let collected = sink.lock().unwrap();
+        let finished: Vec<u64> = collected.results.iter().map(|(id, _)| *id).collect();
+        assert_eq!(finished, ids);
+        assert!(ids.iter().all(|id| collected.infos.contains(id)));
+    }
+
+    #[test]
+    fn parallel_queue_processes_each_job_once() {
+        let (mut queue, ids) = queue();
+        let sink = Arc::new(Mutex::new(Collect::default()));
+        queue.run_parallel::<Searcher, _>(2, &sink);
+        let mut finished: Vec<u64> = sink
+            .lock()
+            .unwrap()
+            .results
+            .iter()
+            .map(|(id, result)| {
+                assert!(result.best_move.is_some());
+                *id
+            })
+            .collect();
+        finished.sort_unstable();
+        assert_eq!(finished, ids);
+    }
+}
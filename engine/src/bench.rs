@@ -0,0 +1,298 @@
+//! 決まった局面の組を決まった深さで探索し、節点数と速さを測る。
+//!
+//! 節点数は探索の変更で変わるので、探索の挙動を変えないはずの変更の確認にも使える。
+//! `tracing` フィーチャーを有効にしたビルドでは、最後に枝刈りと処理ごとの集計を出す。
+//! `--scaling` ではスレッド数と置換表の大きさを変えながら同じ組を読み、決まった深さに
+//! 着くまでの時間とNPSの伸びを並べる。
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::match_runner::parse_value;
+use crate::position::{INITIAL_SFEN, Position, PositionError};
+use crate::root_split::RootSplitSearcher;
+use crate::search::{MemoryUsage, NullInfoSink, SearchBackend, SearchLimits, Searcher};
+use crate::table::{DEFAULT_HASH_MB, SharedTable};
+use crate::trace::{self, TraceReport};
+
+/// 既定の探索の深さ。
+pub const DEFAULT_BENCH_DEPTH: usize = 5;
+
+/// 測る局面。序盤・中盤・持ち駒の多い局面・終盤を混ぜる。
+pub const BENCH_SFENS: [&str; 6] = [
+    INITIAL_SFEN,
+    "rbsgk/4p/P4/5/KGSBR w - 2",
+    "rb1gk/1s2R/5/P1B2/K4 w GSP 10",
+    "k1+R2/1P3/5/2b2/4K b G 1",
+    "2k2/5/2G2/5/2K2 b RBr 1",
+    "4k/2P2/1b3/5/K3r w - 2",
+];
+
+/// `--scaling` で既定に試すスレッド数。
+pub const DEFAULT_SCALING_THREADS: [usize; 3] = [1, 2, 4];
+
+/// 1局面の結果。
+#[derive(Clone, Debug)]
+pub struct BenchEntry {
+    pub sfen: &'static str,
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+/// すべての局面の結果と、計測が有効なら集計。
+#[derive(Clone, Debug)]
+pub struct BenchReport {
+    pub depth: usize,
+    pub entries: Vec<BenchEntry>,
+    pub trace: TraceReport,
+    /// 全局面を読み終えたときの探索器のメモリ。
+    pub memory: MemoryUsage,
+}
+
+impl BenchReport {
+    pub fn nodes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.nodes).sum()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.entries.iter().map(|entry| entry.elapsed).sum()
+    }
+
+    pub fn nps(&self) -> u64 {
+        let seconds = self.elapsed().as_secs_f64();
+        if seconds == 0.0 {
+            0
+        } else {
+            (self.nodes() as f64 / seconds) as u64
+        }
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, entry) in self.entries.iter().enumerate() {
+            writeln!(
+                f,
+                "position {:>2} nodes {:>10} time {:>7}ms  {}",
+                index + 1,
+                entry.nodes,
+                entry.elapsed.as_millis(),
+                entry.sfen
+            )?;
+        }
+        writeln!(
+            f,
+            "depth {} nodes {} time {}ms nps {}",
+            self.depth,
+            self.nodes(),
+            self.elapsed().as_millis(),
+            self.nps()
+        )?;
+        writeln!(f, "memory {}", self.memory)?;
+        if trace::ENABLED {
+            write!(f, "{}", self.trace)?;
+        }
+        Ok(())
+    }
+}
+
+/// `BENCH_SFENS` をそれぞれ置換表を空にしてから深さ `depth` まで探索する。
+pub fn run_bench(depth: usize) -> Result<BenchReport, PositionError> {
+    let mut searcher = Searcher::new();
+    searcher.set_info_sink(Box::new(NullInfoSink));
+    let limits = SearchLimits {
+        depth,
+        ..SearchLimits::default()
+    };
+    trace::reset();
+    let mut entries = Vec::with_capacity(BENCH_SFENS.len());
+    for sfen in BENCH_SFENS {
+        let position = Position::from_sfen(sfen)?;
+        let result = searcher.search(&position, limits)?;
+        entries.push(BenchEntry {
+            sfen,
+            nodes: result.nodes,
+            elapsed: result.elapsed,
+        });
+    }
+    Ok(BenchReport {
+        depth,
+        entries,
+        trace: trace::snapshot(),
+        memory: searcher.memory_usage(),
+    })
+}
+
+/// スレッド数と置換表の大きさの1つの組み合わせで組を読んだ結果。
+#[derive(Clone, Copy, Debug)]
+pub struct ScalingPoint {
+    pub threads: usize,
+    /// 実際に確保できた置換表の大きさ（MiB）。
+    pub hash_megabytes: usize,
+    pub nodes: u64,
+    /// 全局面で深さに着くまでの時間の合計。
+    pub elapsed: Duration,
+}
+
+impl ScalingPoint {
+    pub fn nps(&self) -> u64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            0
+        } else {
+            (self.nodes as f64 / seconds) as u64
+        }
+    }
+}
+
+/// 組み合わせごとの結果。先頭を基準に伸びを出す。
+#[derive(Clone, Debug)]
+pub struct ScalingReport {
+    pub depth: usize,
+    pub points: Vec<ScalingPoint>,
+}
+
+impl fmt::Display for ScalingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(base) = self.points.first() else {
+            return Ok(());
+        };
+        let ratio = |value: f64, base: f64| if base == 0.0 { 0.0 } else { value / base };
+        writeln!(f, "depth {}", self.depth)?;
+        writeln!(
+            f,
+            "threads   hash      nodes     time        nps  speedup  nps-scale"
+        )?;
+        for point in &self.points {
+            writeln!(
+                f,
+                "{:>7} {:>4}MB {:>10} {:>7}ms {:>10} {:>7.2}x {:>9.2}x",
+                point.threads,
+                point.hash_megabytes,
+                point.nodes,
+                point.elapsed.as_millis(),
+                point.nps(),
+                ratio(base.elapsed.as_secs_f64(), point.elapsed.as_secs_f64()),
+                ratio(point.nps() as f64, base.nps() as f64),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `hashes` と `threads` のすべての組み合わせで、`BENCH_SFENS` をそれぞれ置換表を空にしてから
+/// 深さ `depth` まで読む。2スレッド以上はルート分割の並列探索で読む。
+pub fn run_scaling(
+    depth: usize,
+    threads: &[usize],
+    hashes: &[usize],
+) -> Result<ScalingReport, PositionError> {
+    let limits = SearchLimits {
+        depth,
+        ..SearchLimits::default()
+    };
+    let mut points = Vec::with_capacity(threads.len() * hashes.len());
+    for &megabytes in hashes {
+        for &threads in threads {
+            let table = SharedTable::with_megabytes(megabytes)
+                .map_err(|err| PositionError::message(err.to_string()))?;
+            let table = Arc::new(table);
+            let mut backend: Box<dyn SearchBackend> = if threads > 1 {
+                let mut searcher = RootSplitSearcher::new(threads);
+                searcher.set_shared_table(Arc::clone(&table));
+                Box::new(searcher)
+            } else {
+                let mut searcher = Searcher::new();
+                searcher.set_transposition_table(Box::new(Arc::clone(&table)));
+                Box::new(searcher)
+            };
+            backend.set_info_sink(Box::new(NullInfoSink));
+            let mut point = ScalingPoint {
+                threads: threads.max(1),
+                hash_megabytes: table.megabytes(),
+                nodes: 0,
+                elapsed: Duration::ZERO,
+            };
+            for sfen in BENCH_SFENS {
+                let result = backend.search(&Position::from_sfen(sfen)?, limits)?;
+                point.nodes += result.nodes;
+                point.elapsed += result.elapsed;
+            }
+            points.push(point);
+        }
+    }
+    Ok(ScalingReport { depth, points })
+}
+
+/// `1,2,4` のようなカンマ区切りの数の並び。
+fn parse_list(flag: &str, value: Option<&String>) -> Result<Vec<usize>, String> {
+    let list: String = parse_value(flag, value)?;
+    list.split(',')
+        .map(|item| item.trim().parse().ok().filter(|&n| n > 0))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| format!("{flag} requires a comma separated list of positive numbers"))
+}
+
+/// `bench` サブコマンド。
+///
+/// `bench [--depth D] [--scaling [--threads 1,2,4] [--hash 16,64]]`
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut depth = DEFAULT_BENCH_DEPTH;
+    let mut scaling = false;
+    let mut threads = DEFAULT_SCALING_THREADS.to_vec();
+    let mut hashes = vec![DEFAULT_HASH_MB];
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--depth" => depth = parse_value(flag, iter.next())?,
+            "--scaling" => scaling = true,
+            "--threads" => threads = parse_list(flag, iter.next())?,
+            "--hash" => hashes = parse_list(flag, iter.next())?,
+            other => return Err(format!("unknown bench option: {other}").into()),
+        }
+    }
+    if scaling {
+        print!("{}", run_scaling(depth.max(1), &threads, &hashes)?);
+    } else {
+        print!("{}", run_bench(depth.max(1))?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Counter;
+
+    #[test]
+    fn bench_is_deterministic_and_traces_when_enabled() {
+        let first = run_bench(2).expect("bench");
+        let second = run_bench(2).expect("bench");
+        let nodes = |report: &BenchReport| -> Vec<u64> {
+            report.entries.iter().map(|entry| entry.nodes).collect()
+        };
+        assert_eq!(nodes(&first), nodes(&second));
+        assert!(first.nodes() > 0);
+        if trace::ENABLED {
+            assert!(second.trace.count(Counter::MainNodes) > 0);
+        } else {
+            assert_eq!(second.trace, TraceReport::default());
+        }
+    }
+
+    #[test]
+    fn scaling_covers_every_combination_in_order() {
+        let report = run_scaling(2, &[1, 2], &[1, 2]).expect("scaling");
+        let combinations: Vec<(usize, usize)> = report
+            .points
+            .iter()
+            .map(|point| (point.hash_megabytes, point.threads))
+            .collect();
+        assert_eq!(combinations, [(1, 1), (1, 2), (2, 1), (2, 2)]);
+        assert!(report.points.iter().all(|point| point.nodes > 0));
+        assert_eq!(report.to_string().lines().count(), 6);
+        assert!(parse_list("--threads", Some(&"1,0".to_string())).is_err());
+    }
+}
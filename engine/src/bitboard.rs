@@ -1,7 +1,9 @@
-use crate::board::Square;
+use core::fmt;
+
+use crate::board::{BOARD_FILES, BOARD_RANKS, Square};
 
 /// 5x5将棋盤用の25ビットビットボード。
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
 pub struct Bitboard(u32);
 
 impl Bitboard {
@@ -18,6 +20,24 @@ impl Bitboard {
         self.0
     }
 
+    /// `rank` 段のマスすべて。
+    pub const fn rank_mask(rank: u8) -> Self {
+        assert!(rank < BOARD_RANKS as u8, "rank out of range");
+        Self(((1u32 << BOARD_FILES) - 1) << (rank as usize * BOARD_FILES))
+    }
+
+    /// `file` 筋のマスすべて。
+    pub const fn file_mask(file: u8) -> Self {
+        assert!(file < BOARD_FILES as u8, "file out of range");
+        let mut bits = 0;
+        let mut rank = 0;
+        while rank < BOARD_RANKS {
+            bits |= 1u32 << (rank * BOARD_FILES + file as usize);
+            rank += 1;
+        }
+        Self(bits)
+    }
+
     #[inline]
     pub const fn from_square(square: Square) -> Self {
         Self(1u32 << square.index())
@@ -43,6 +63,18 @@ impl Bitboard {
         self.0 == 0
     }
 
+    /// 立っているマスの数。
+    #[inline]
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// 2マス以上立っているか。
+    #[inline]
+    pub const fn more_than_one(self) -> bool {
+        self.0 & self.0.wrapping_sub(1) != 0
+    }
+
     #[inline]
     pub fn iter(self) -> BitboardIter {
         BitboardIter(self.0)
@@ -110,6 +142,27 @@ impl core::ops::Not for Bitboard {
     }
 }
 
+/// 盤面と同じ向き（左上が5a）に、立っているマスを `X`、それ以外を `.` で描く。
+impl fmt::Display for Bitboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rank in 0..BOARD_RANKS as u8 {
+            for file in (0..BOARD_FILES as u8).rev() {
+                let square = Square::from_file_rank(file, rank);
+                f.write_str(if self.contains(square) { "X" } else { "." })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Bitboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bitboard({:#09x})", self.0)?;
+        fmt::Display::fmt(self, f)
+    }
+}
+
 pub struct BitboardIter(u32);
 
 impl Iterator for BitboardIter {
@@ -124,3 +177,33 @@ impl Iterator for BitboardIter {
         Some(Square::from_index(lsb as u8))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn display_draws_the_board_from_5a() {
+        let mut bb = Bitboard::EMPTY;
+        bb.insert(Square::from_coord("5a").unwrap());
+        bb.insert(Square::from_coord("1e").unwrap());
+        assert_eq!(format!("{bb}"), "X....\n.....\n.....\n.....\n....X\n");
+        assert_eq!(bb.count(), 2);
+        assert!(bb.more_than_one());
+        assert!(!Bitboard::from_square(Square::from_coord("3c").unwrap()).more_than_one());
+        assert!(!Bitboard::EMPTY.more_than_one());
+    }
+
+    #[test]
+    fn rank_and_file_masks() {
+        assert_eq!(
+            format!("{}", Bitboard::rank_mask(1)),
+            ".....\nXXXXX\n.....\n.....\n.....\n"
+        );
+        assert_eq!(
+            format!("{}", Bitboard::file_mask(4)),
+            "X....\nX....\nX....\nX....\nX....\n"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::evaluation::Evaluator;
+use crate::facade::Engine;
+use crate::moves::{Move, MoveList};
+use crate::position::{Position, PositionError};
+use crate::score::RelScore;
+use crate::search::SearchLimits;
+
+/// 最善手との差（センチポーン）による分類の境界。
+const INACCURACY_THRESHOLD: i32 = 50;
+const MISTAKE_THRESHOLD: i32 = 150;
+const BLUNDER_THRESHOLD: i32 = 400;
+
+/// 指し手の質の分類。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveClassification {
+    Ok,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl MoveClassification {
+    pub fn from_loss(loss: i32) -> Self {
+        if loss >= BLUNDER_THRESHOLD {
+            Self::Blunder
+        } else if loss >= MISTAKE_THRESHOLD {
+            Self::Mistake
+        } else if loss >= INACCURACY_THRESHOLD {
+            Self::Inaccuracy
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+/// 指した手と最善手の比較結果。評価値はいずれも指す前の手番側から見た値。
+#[derive(Clone, Debug)]
+pub struct BlunderReport {
+    pub played_move: Move,
+    pub played_score: RelScore,
+    pub best_move: Move,
+    pub best_score: RelScore,
+    /// 最善手に対して失った評価値（0以上）。
+    pub loss: i32,
+    pub classification: MoveClassification,
+    /// 指した手に対する相手の最善の応手から始まる読み筋。
+    pub refutation: MoveList,
+}
+
+/// 棋譜の1手に付ける解析結果。
+#[derive(Clone, Debug)]
+pub struct MoveAnnotation {
+    pub report: BlunderReport,
+    /// 最善手から始まる読み筋。指した手が最善手なら空。
+    pub variation: MoveList,
+}
+
+impl<E: Evaluator> Engine<E> {
+    /// `played_move` をルートの全合法手と同じ深さで比較し、悪手かどうかを判定する。
+    pub fn check_blunder(
+        &mut self,
+        position: &Position,
+        played_move: Move,
+        limits: SearchLimits,
+    ) -> Result<BlunderReport, PositionError> {
+        let depth = limits.depth.max(1);
+        let scored = self.searcher.evaluate_all_moves(position, depth)?;
+        let &(best_move, best_score) = scored
+            .first()
+            .ok_or_else(|| PositionError::message("no legal moves"))?;
+        let played_score = scored
+            .iter()
+            .find(|(mv, _)| *mv == played_move)
+            .map(|&(_, score)| score)
+            .ok_or_else(|| {
+                PositionError::message(format!("illegal move: {}", played_move.to_usi()))
+            })?;
+
+        let loss = (best_score.value() - played_score.value()).max(0);
+        let refutation = if loss > 0 {
+            let after = position.play_move(&played_move)?;
+            let reply_limits = SearchLimits {
+                depth: depth.saturating_sub(1).max(1),
+                randomness: 0,
+                ..limits
+            };
+            self.searcher.search(&after, reply_limits)?;
+            self.searcher.principal_variation(&after, depth)
+        } else {
+            MoveList::new()
+        };
+
+        Ok(BlunderReport {
+            played_move,
+            played_score,
+            best_move,
+            best_score,
+            loss,
+            classification: MoveClassification::from_loss(loss),
+            refutation,
+        })
+    }
+
+    /// `start` から指された `moves` を1手ずつ `check_blunder` で調べ、
+    /// 最善手と異なる手には最善手からの読み筋を付ける。
+    pub fn annotate_game(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        limits: SearchLimits,
+    ) -> Result<Vec<MoveAnnotation>, PositionError> {
+        let depth = limits.depth.max(1);
+        let mut position = start.clone();
+        let mut annotations = Vec::with_capacity(moves.len());
+        for &mv in moves {
+            let report = self.check_blunder(&position, mv, limits)?;
+            let mut variation = MoveList::new();
+            if report.best_move != mv {
+                let after = position.play_move(&report.best_move)?;
+                let reply_limits = SearchLimits {
+                    depth: depth.saturating_sub(1).max(1),
+                    randomness: 0,
+                    ..limits
+                };
+                self.searcher.search(&after, reply_limits)?;
+                variation.push(report.best_move);
+                variation.extend(self.searcher.principal_variation(&after, depth));
+            }
+            annotations.push(MoveAnnotation { report, variation });
+            position.play_move_mut(&mv)?;
+        }
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Square;
+    use crate::piece::PieceKind;
+
+    #[test]
+    fn missing_a_mate_in_one_is_a_blunder() {
+        let position = Position::from_sfen("4k/5/3S1/5/K4 b G 1").expect("sfen");
+        let quiet = Move::drop(Square::from_coord("5a").unwrap(), PieceKind::Gold);
+        let mut engine = Engine::new();
+        let limits = SearchLimits {
+            depth: 1,
+            ..SearchLimits::default()
+        };
+        let report = engine
+            .check_blunder(&position, quiet, limits)
+            .expect("report");
+        assert_eq!(report.classification, MoveClassification::Blunder);
+        assert_ne!(report.best_move, quiet);
+    }
+}
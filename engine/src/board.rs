@@ -1,5 +1,9 @@
+use alloc::format;
+use alloc::string::String;
 use core::fmt;
 
+use crate::piece::Color;
+
 pub const BOARD_FILES: usize = 5;
 pub const BOARD_RANKS: usize = 5;
 pub const BOARD_SQUARES: usize = BOARD_FILES * BOARD_RANKS;
@@ -43,6 +47,36 @@ impl Square {
         }
     }
 
+    /// 玉の歩数で数えた距離（チェビシェフ距離）。
+    pub const fn distance(self, other: Self) -> u8 {
+        let df = self.file().abs_diff(other.file());
+        let dr = self.rank().abs_diff(other.rank());
+        if df > dr { df } else { dr }
+    }
+
+    /// `other` が同じ筋・段・斜めの線上にあれば、そちらへ1マス進む `(筋, 段)` の差分。
+    pub fn direction_to(self, other: Self) -> Option<(i8, i8)> {
+        let df = other.file() as i8 - self.file() as i8;
+        let dr = other.rank() as i8 - self.rank() as i8;
+        if self == other || (df != 0 && dr != 0 && df.abs() != dr.abs()) {
+            return None;
+        }
+        Some((df.signum(), dr.signum()))
+    }
+
+    /// `color` から見た段。自陣の一番奥を0として、敵陣に近づくほど大きい。
+    pub const fn relative_rank(self, color: Color) -> u8 {
+        match color {
+            Color::Black => BOARD_RANKS as u8 - 1 - self.rank(),
+            Color::White => self.rank(),
+        }
+    }
+
+    /// `color` にとって1マス前のマス。
+    pub fn forward(self, color: Color) -> Option<Self> {
+        self.offset(0, forward_rank_step(color))
+    }
+
     pub fn from_coord(coord: &str) -> Option<Self> {
         if coord.len() != 2 {
             return None;
@@ -75,6 +109,14 @@ impl fmt::Display for Square {
     }
 }
 
+/// `color` にとって前進する向きの段の差分。先手は段が減る向きに進む。
+pub const fn forward_rank_step(color: Color) -> i8 {
+    match color {
+        Color::Black => -1,
+        Color::White => 1,
+    }
+}
+
 /// 左上（5a）から右下（1e）までの全マスを返す。
 pub const fn all_squares() -> [Square; BOARD_SQUARES] {
     let mut squares = [Square(0); BOARD_SQUARES];
@@ -85,3 +127,31 @@ pub const fn all_squares() -> [Square; BOARD_SQUARES] {
     }
     squares
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sq(coord: &str) -> Square {
+        Square::from_coord(coord).unwrap()
+    }
+
+    #[test]
+    fn distance_and_direction_follow_lines() {
+        assert_eq!(sq("1e").distance(sq("5a")), 4);
+        assert_eq!(sq("3c").distance(sq("4e")), 2);
+        assert_eq!(sq("1e").direction_to(sq("5a")), Some((1, -1)));
+        assert_eq!(sq("3c").direction_to(sq("3a")), Some((0, -1)));
+        assert_eq!(sq("3c").direction_to(sq("4a")), None);
+        assert_eq!(sq("3c").direction_to(sq("3c")), None);
+    }
+
+    #[test]
+    fn forward_depends_on_color() {
+        assert_eq!(sq("3c").forward(Color::Black), Some(sq("3b")));
+        assert_eq!(sq("3c").forward(Color::White), Some(sq("3d")));
+        assert_eq!(sq("3a").forward(Color::Black), None);
+        assert_eq!(sq("2e").relative_rank(Color::Black), 0);
+        assert_eq!(sq("2e").relative_rank(Color::White), 4);
+    }
+}
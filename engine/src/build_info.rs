@@ -0,0 +1,89 @@
+//! このバイナリを作ったビルドの情報。
+//!
+//! 版・gitのリビジョン・ビルドプロファイル・有効なフィーチャーをビルドスクリプトが埋め込む。
+//! ログや対局結果に残して、どのビルドの結果かを後から特定できるようにする。
+
+use core::fmt;
+
+/// エンジンの名前と作者。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EngineIdentity {
+    pub name: &'static str,
+    pub author: &'static str,
+}
+
+pub const ENGINE_IDENTITY: EngineIdentity = EngineIdentity {
+    name: "Ginko5x5",
+    author: "AkaakuHub",
+};
+
+/// 指せる将棋の種類。
+pub const VARIANT: &str = "minishogi";
+
+/// ビルドを特定する情報。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EngineInfo {
+    pub identity: EngineIdentity,
+    /// `Cargo.toml` の版。
+    pub version: &'static str,
+    /// gitの短いリビジョン。未コミットの変更があれば `-dirty` が付く。分からなければ `unknown`。
+    pub git_revision: &'static str,
+    /// `debug` か `release`。
+    pub profile: &'static str,
+    /// 有効なCargoのフィーチャーをカンマで区切ったもの。
+    pub features: &'static str,
+    pub variant: &'static str,
+}
+
+impl EngineInfo {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.split(',').any(|enabled| enabled == feature)
+    }
+}
+
+impl fmt::Display for EngineInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} revision {} profile {} features {} variant {}",
+            self.identity.name,
+            self.version,
+            self.git_revision,
+            self.profile,
+            if self.features.is_empty() {
+                "none"
+            } else {
+                self.features
+            },
+            self.variant
+        )
+    }
+}
+
+/// このバイナリのビルド情報。
+pub fn engine_info() -> EngineInfo {
+    EngineInfo {
+        identity: ENGINE_IDENTITY,
+        version: env!("CARGO_PKG_VERSION"),
+        git_revision: env!("GINKO_GIT_REVISION"),
+        profile: env!("GINKO_BUILD_PROFILE"),
+        features: env!("GINKO_FEATURES"),
+        variant: VARIANT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn info_reports_this_build() {
+        let info = engine_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.has_feature("std"), cfg!(feature = "std"));
+        assert_eq!(info.has_feature("tracing"), cfg!(feature = "tracing"));
+        assert!(!info.git_revision.is_empty());
+        assert!(info.to_string().starts_with("Ginko5x5 "));
+    }
+}
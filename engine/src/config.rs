@@ -0,0 +1,186 @@
+//! 起動時に読むエンジンの設定ファイル。
+//!
+//! USIのオプション名をキーにしたTOMLの小さな部分集合を読む。キーは最上位か `[options]`
+//! 表に書き、値は文字列・整数・真偽値のいずれか。例:
+//!
+//! ```toml
+//! # ginko.toml
+//! [options]
+//! Threads = 2
+//! RootSplit = true
+//! ExperienceFile = "experience.txt"
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 実行ファイルと同じディレクトリで探す設定ファイルの名前。
+pub const CONFIG_FILE: &str = "ginko.toml";
+
+/// 設定ファイルの読み込みの失敗。
+#[derive(Debug)]
+pub struct ConfigError {
+    /// 1始まりの行番号。ファイルを読めなかったときは0。
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// 書かれた順のオプション名と値。値は `setoption` に渡す文字列にしてある。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub options: Vec<(String, String)>,
+}
+
+impl EngineConfig {
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut options = Vec::new();
+        for (index, raw) in text.lines().enumerate() {
+            let error = |message: &str| ConfigError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix('[') {
+                if table.trim_end_matches(']').trim() != "options" || !table.ends_with(']') {
+                    return Err(error("only the [options] table is supported"));
+                }
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `name = value`"))?;
+            let key = parse_key(key.trim()).ok_or_else(|| error("invalid option name"))?;
+            let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+            if options.iter().any(|(name, _)| *name == key) {
+                return Err(error("duplicate option"));
+            }
+            options.push((key, value));
+        }
+        Ok(Self { options })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|err| ConfigError {
+            line: 0,
+            message: format!("{}: {err}", path.display()),
+        })?;
+        Self::parse(&text).map_err(|err| ConfigError {
+            line: err.line,
+            message: format!("{}: {}", path.display(), err.message),
+        })
+    }
+
+    /// `name` に設定された値。書かれていなければ `None`。
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 実行ファイルの隣の `ginko.toml`。実行ファイルの場所が分からなければ `None`。
+pub fn default_path() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.parent()?.join(CONFIG_FILE))
+}
+
+/// 引用符の外にある `#` 以降を取り除く。
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_key(key: &str) -> Option<String> {
+    if let Some(quoted) = key.strip_prefix('"') {
+        return parse_string(quoted);
+    }
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-');
+    bare.then(|| key.to_string())
+}
+
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return parse_string(quoted);
+    }
+    match value {
+        "true" | "false" => Some(value.to_string()),
+        _ => value
+            .replace('_', "")
+            .parse::<i64>()
+            .ok()
+            .map(|number| number.to_string()),
+    }
+}
+
+/// 開き引用符の後ろから、閉じ引用符までの基本文字列を読む。閉じ引用符の後ろに何かあれば `None`。
+fn parse_string(rest: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = rest.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => return chars.as_str().trim().is_empty().then_some(out),
+            '\\' => out.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                other @ ('"' | '\\') => other,
+                _ => return None,
+            }),
+            _ => out.push(ch),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_supported_subset() {
+        let config = EngineConfig::parse(
+            "# server defaults\n[options]\nThreads = 2 # two cores\nRootSplit = true\n\"ExperienceFile\" = \"games #1.txt\"\nMaxPly = 1_00\n",
+        )
+        .expect("config");
+        assert_eq!(config.get("Threads"), Some("2"));
+        assert_eq!(config.get("RootSplit"), Some("true"));
+        assert_eq!(config.get("ExperienceFile"), Some("games #1.txt"));
+        assert_eq!(config.get("MaxPly"), Some("100"));
+
+        let err = EngineConfig::parse("[engine]\n").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(EngineConfig::parse("Threads = two\n").is_err());
+        assert!(EngineConfig::parse("Threads = 1\nThreads = 2\n").is_err());
+        assert!(EngineConfig::parse("Locale = \"ja\" extra\n").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! 評価関数のパラメータファイル。USIの `EvalFile` で読み、`tune-pst` が書く。
+//!
+//! 設定ファイルと同じ書式で、キーは駒の価値のオプション名（`PawnValue` など）と駒の位置の表。
+//! 表のキーは `PawnMiddlegame` や `KingEndgame` のように駒の名前と局面の段階をつなげたもので、
+//! 値は持ち主から見た升目（`pst_square`）の順に25個の整数をカンマで区切った文字列。
+//! 書かれていない値は既定値になる。
+//!
+//! ```toml
+//! RookValue = 950
+//! PawnEndgame = "0,0,0,0,0,40,40,40,40,40,20,20,20,20,20,0,0,0,0,0,0,0,0,0,0"
+//! ```
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::board::BOARD_SQUARES;
+use crate::config::{ConfigError, EngineConfig};
+use crate::evaluation::{EvalParams, PieceSquareTable};
+use crate::piece::{PIECE_KIND_COUNT, PieceKind};
+
+/// 駒の価値を変えるオプションの名前と駒種。USIしか話さない調整ツールから駒の価値を調整できる。
+pub const PIECE_VALUE_OPTIONS: [(&str, PieceKind); 9] = [
+    ("PawnValue", PieceKind::Pawn),
+    ("SilverValue", PieceKind::Silver),
+    ("GoldValue", PieceKind::Gold),
+    ("BishopValue", PieceKind::Bishop),
+    ("RookValue", PieceKind::Rook),
+    ("TokinValue", PieceKind::Tokin),
+    ("PromotedSilverValue", PieceKind::PromotedSilver),
+    ("HorseValue", PieceKind::PromotedBishop),
+    ("DragonValue", PieceKind::PromotedRook),
+];
+
+/// 駒の価値のオプションに指定できる上限。
+pub const MAX_PIECE_VALUE: i32 = 5_000;
+
+/// 駒の位置の表の値の絶対値の上限。
+pub const MAX_PST_VALUE: i32 = 1_000;
+
+/// 駒の位置の表のキーに使う駒の名前。
+const PST_PIECE_NAMES: [(&str, PieceKind); PIECE_KIND_COUNT] = [
+    ("King", PieceKind::King),
+    ("Gold", PieceKind::Gold),
+    ("Silver", PieceKind::Silver),
+    ("PromotedSilver", PieceKind::PromotedSilver),
+    ("Bishop", PieceKind::Bishop),
+    ("Horse", PieceKind::PromotedBishop),
+    ("Rook", PieceKind::Rook),
+    ("Dragon", PieceKind::PromotedRook),
+    ("Pawn", PieceKind::Pawn),
+    ("Tokin", PieceKind::Tokin),
+];
+
+/// 駒の位置の表のキーの後ろに付ける局面の段階の名前。中盤、終盤の順。
+const PST_PHASES: [&str; 2] = ["Middlegame", "Endgame"];
+
+/// パラメータファイルに書ける評価関数のパラメータ。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvalFileParams {
+    pub piece_values: [i32; PIECE_KIND_COUNT],
+    pub pst_middlegame: PieceSquareTable,
+    pub pst_endgame: PieceSquareTable,
+}
+
+impl Default for EvalFileParams {
+    fn default() -> Self {
+        Self::from_params(&EvalParams::default())
+    }
+}
+
+impl EvalFileParams {
+    /// 評価関数のパラメータのうちファイルに書ける部分。
+    pub fn from_params(params: &EvalParams) -> Self {
+        Self {
+            piece_values: params.piece_values,
+            pst_middlegame: params.pst_middlegame,
+            pst_endgame: params.pst_endgame,
+        }
+    }
+
+    /// `params` のうちファイルに書ける部分をこの値で置き換える。
+    pub fn apply(&self, params: &mut EvalParams) {
+        params.piece_values = self.piece_values;
+        params.pst_middlegame = self.pst_middlegame;
+        params.pst_endgame = self.pst_endgame;
+    }
+
+    /// 読んだ設定からパラメータを作る。知らないキーや範囲外の値は行番号なしのエラーにする。
+    pub fn from_config(config: &EngineConfig) -> Result<Self, ConfigError> {
+        let mut params = Self::default();
+        for (name, value) in &config.options {
+            let error = |message: String| ConfigError { line: 0, message };
+            if let Some(&(_, kind)) = PIECE_VALUE_OPTIONS.iter().find(|(key, _)| key == name) {
+                params.piece_values[kind.index()] = value
+                    .parse()
+                    .ok()
+                    .filter(|value| (0..=MAX_PIECE_VALUE).contains(value))
+                    .ok_or_else(|| error(format!("invalid value for {name}: {value}")))?;
+                continue;
+            }
+            let (table, kind) = params
+                .pst_key(name)
+                .ok_or_else(|| error(format!("unknown eval parameter {name}")))?;
+            table[kind.index()] = parse_table(value)
+                .ok_or_else(|| error(format!("invalid table for {name}: {value}")))?;
+        }
+        Ok(params)
+    }
+
+    /// パラメータファイルを読む。
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let config = EngineConfig::load(path)?;
+        Self::from_config(&config).map_err(|err| ConfigError {
+            line: err.line,
+            message: format!("{}: {}", path.display(), err.message),
+        })
+    }
+
+    /// `load` で読める形式。既定値と同じ値も含めてすべて書く。
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (name, kind) in PIECE_VALUE_OPTIONS {
+            let _ = writeln!(text, "{name} = {}", self.piece_values[kind.index()]);
+        }
+        for (phase, table) in PST_PHASES
+            .iter()
+            .zip([&self.pst_middlegame, &self.pst_endgame])
+        {
+            for (name, kind) in PST_PIECE_NAMES {
+                let values: Vec<String> = table[kind.index()].iter().map(i32::to_string).collect();
+                let _ = writeln!(text, "{name}{phase} = \"{}\"", values.join(","));
+            }
+        }
+        text
+    }
+
+    /// 駒の位置の表のキーが指す表と駒種。
+    fn pst_key(&mut self, name: &str) -> Option<(&mut PieceSquareTable, PieceKind)> {
+        let (piece, table) = if let Some(piece) = name.strip_suffix(PST_PHASES[0]) {
+            (piece, &mut self.pst_middlegame)
+        } else {
+            (name.strip_suffix(PST_PHASES[1])?, &mut self.pst_endgame)
+        };
+        let &(_, kind) = PST_PIECE_NAMES.iter().find(|(key, _)| *key == piece)?;
+        Some((table, kind))
+    }
+}
+
+/// カンマ区切りの25個の整数。
+fn parse_table(value: &str) -> Option<[i32; BOARD_SQUARES]> {
+    let mut table = [0; BOARD_SQUARES];
+    let mut values = value.split(',').map(|value| value.trim().parse::<i32>());
+    for entry in &mut table {
+        *entry = values
+            .next()?
+            .ok()
+            .filter(|value| value.abs() <= MAX_PST_VALUE)?;
+    }
+    values.next().is_none().then_some(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_parameters_read_back() {
+        let mut params = EvalFileParams::default();
+        params.piece_values[PieceKind::Rook.index()] = 950;
+        params.pst_middlegame[PieceKind::Pawn.index()][7] = 12;
+        params.pst_endgame[PieceKind::King.index()][24] = -30;
+        let config = EngineConfig::parse(&params.to_text()).expect("config");
+        assert_eq!(
+            EvalFileParams::from_config(&config).expect("params"),
+            params
+        );
+
+        let partial = EngineConfig::parse("TokinValue = 450\n").unwrap();
+        let read = EvalFileParams::from_config(&partial).unwrap();
+        assert_eq!(read.piece_values[PieceKind::Tokin.index()], 450);
+        assert_eq!(read.pst_endgame, EvalFileParams::default().pst_endgame);
+
+        for bad in [
+            "KnightValue = 300",
+            "PawnValue = 9000",
+            "PawnMiddlegame = \"1,2,3\"",
+            "LanceEndgame = \"0\"",
+        ] {
+            let config = EngineConfig::parse(bad).unwrap();
+            assert!(EvalFileParams::from_config(&config).is_err(), "{bad}");
+        }
+    }
+}
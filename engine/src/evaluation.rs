@@ -1,9 +1,16 @@
-use crate::board::{all_squares, Square, BOARD_FILES, BOARD_RANKS};
-use crate::hand::{Hand, HandPieceKind};
-use crate::piece::{Color, Piece, PieceKind};
-use crate::position::Position;
+use core::fmt;
 
-const PIECE_VALUES: [i32; 10] = [
+use crate::attacks;
+use crate::bitboard::Bitboard;
+use crate::board::{BOARD_FILES, BOARD_RANKS, BOARD_SQUARES, Square, all_squares};
+use crate::hand::{HAND_PIECE_KIND_COUNT, Hand, HandPieceKind};
+use crate::handicap::Handicap;
+use crate::piece::{Color, PIECE_KIND_COUNT, Piece, PieceKind};
+use crate::position::{DirtyPieces, Position};
+use crate::score::{AbsScore, RelScore};
+
+/// 駒の価値の既定値。`PieceKind` の順。
+pub const DEFAULT_PIECE_VALUES: [i32; PIECE_KIND_COUNT] = [
     15_000, // King
     700,    // Gold
     600,    // Silver
@@ -16,38 +23,359 @@ const PIECE_VALUES: [i32; 10] = [
     400,    // Tokin
 ];
 
+/// 手番側に与える既定のテンポボーナス。
+pub const DEFAULT_TEMPO_BONUS: i32 = 20;
+
+/// 双方が裸玉の局面での後手の勝ちの大きさ。王手をかけられず千日手でしか終わらないので、
+/// 後手の勝ちと決まっている。駒割りでは届かず、詰みの値よりは小さい。
+pub const BARE_KINGS_SCORE: i32 = 10_000;
+
+/// 入玉した側に与えるボーナス。入玉した玉は詰みにくいので、玉の前進を嫌う位置評価を打ち消す。
+const ENTERING_KING_BONUS: i32 = 250;
+
+/// 序盤の項を効かせる手数。これを過ぎると序盤の項は0になる。
+const OPENING_PLIES: u32 = 16;
+
+/// 中央の筋か自玉の隣にいる金銀へのボーナス。
+const DEVELOPMENT_BONUS: i32 = 15;
+
+/// 序盤に玉が自陣の一番奥から1段上がるごとのペナルティ。
+const KING_WALK_PENALTY: i32 = 30;
+
+/// 序盤に飛車を手放して銀を2枚持っている側へのペナルティ。
+const EARLY_ROOK_TRADE_PENALTY: i32 = 120;
+
+/// 裸玉を追う側で、玉が盤の中央から1マス（縦横の歩数）離れるごとのボーナス。
+const MATING_EDGE_BONUS: i32 = 40;
+
+/// 裸玉を追う側で、玉どうしの距離が1縮まるごとのボーナス。
+const MATING_KING_PROXIMITY_BONUS: i32 = 20;
+
+/// 玉の逃げ道（自駒がなく相手の利きもない隣のマス）の数ごとのペナルティ。これより多ければ0。
+const KING_ESCAPE_PENALTY: [i32; 3] = [90, 45, 15];
+
+/// 相手が飛車か金を持っているときに逃げ道のペナルティに掛ける倍率。
+/// 5五将棋の打ち込みの詰みはほとんどこの2枚で、逃げ道のない玉はすぐ詰む。
+const KING_ESCAPE_DROP_THREAT: i32 = 3;
+
+/// 双方に止まらない歩があるときの、成り合いに勝つ側へのボーナス。歩がと金になる分の駒の価値に近い。
+const PROMOTION_RACE_BONUS: i32 = 300;
+
+/// 成り合いで、相手より1手早く成るごとに足すボーナス。
+const PROMOTION_RACE_TEMPO_BONUS: i32 = 40;
+
+/// 持ち駒の価値の曲線の長さ。これより多い枚数は最後の値を使う。
+pub const HAND_CURVE_LEN: usize = 3;
+
+/// 同じ駒種の持ち駒の、1枚目・2枚目・3枚目以降の価値（駒の価値に対する百分率）。
+/// `HandPieceKind` の順。歩は2枚目から打つ場所が限られ、金銀は2枚目の働きが1枚目ほどない。
+pub const DEFAULT_HAND_CURVES: [[i32; HAND_CURVE_LEN]; HAND_PIECE_KIND_COUNT] = [
+    [100, 90, 80],   // Gold
+    [100, 90, 80],   // Silver
+    [100, 100, 100], // Bishop
+    [100, 100, 100], // Rook
+    [100, 60, 40],   // Pawn
+];
+
+/// 飛車と角を両方持っている側へのボーナス。大駒2枚の打ち込みは1枚ずつより厳しい。
+pub const DEFAULT_HAND_ROOK_BISHOP_BONUS: i32 = 80;
+
+/// 駒の位置の表。`[駒種][升目]` で、升目は駒の持ち主から見た番号（`pst_square`）。
+pub type PieceSquareTable = [[i32; BOARD_SQUARES]; PIECE_KIND_COUNT];
+
+/// 中盤の表の重みが0になる手数。それまでは中盤と終盤の表を手数で線形に補間する。
+pub const GAME_PHASE_PLIES: u32 = 60;
+
+/// 評価関数の調整可能なパラメータ。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvalParams {
+    /// 盤上と持ち駒の駒の価値。`PieceKind` の順。
+    pub piece_values: [i32; PIECE_KIND_COUNT],
+    /// 手番側に加算するボーナス。対称な局面でも評価値が0に張り付かないようにする。
+    pub tempo: i32,
+    /// 駒得の項から差し引く、先手から見た駒割り。駒落ちでは開始局面の駒の差を0とみなし、
+    /// 下手が駒を取り返すためだけの手を指さないようにする。
+    pub material_baseline: i32,
+    /// 持ち駒の枚数ごとの価値の曲線。`DEFAULT_HAND_CURVES` を参照。
+    pub hand_curves: [[i32; HAND_CURVE_LEN]; HAND_PIECE_KIND_COUNT],
+    /// 飛車と角を両方持っているときに足す値。
+    pub hand_rook_bishop_bonus: i32,
+    /// 位置評価に足す中盤と終盤の駒の位置の表。既定はどちらも0で、調整した表はファイルから読む。
+    pub pst_middlegame: PieceSquareTable,
+    pub pst_endgame: PieceSquareTable,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self {
+            piece_values: DEFAULT_PIECE_VALUES,
+            tempo: DEFAULT_TEMPO_BONUS,
+            material_baseline: 0,
+            hand_curves: DEFAULT_HAND_CURVES,
+            hand_rook_bishop_bonus: DEFAULT_HAND_ROOK_BISHOP_BONUS,
+            pst_middlegame: [[0; BOARD_SQUARES]; PIECE_KIND_COUNT],
+            pst_endgame: [[0; BOARD_SQUARES]; PIECE_KIND_COUNT],
+        }
+    }
+}
+
+/// 局面の評価方式。探索器はこのトレイトを通して評価値を得る。
+pub trait Evaluator: Send {
+    /// 先手から見た評価値。
+    fn evaluate(&self, position: &Position) -> AbsScore;
+
+    /// 手番側から見た評価値。
+    fn evaluate_relative(&self, position: &Position) -> RelScore {
+        self.evaluate(position).relative_to(position.side_to_move())
+    }
+
+    /// `evaluate` の値に、子の局面を差分で評価するための値を添えて返す。
+    fn evaluate_state(&self, position: &Position) -> EvalState {
+        EvalState {
+            score: self.evaluate(position),
+            board: BoardTerms::default(),
+        }
+    }
+
+    /// 1手前の局面の評価 `parent` と、その手で変わった駒 `dirty` から `position` を評価する。
+    /// 探索は1手前の局面を評価してあればこちらを呼ぶ。差分で更新できる評価関数はこれを
+    /// 実装する。既定では局面全体を評価し直す。
+    fn evaluate_incremental(
+        &self,
+        position: &Position,
+        _parent: &EvalState,
+        _dirty: &DirtyPieces,
+    ) -> EvalState {
+        self.evaluate_state(position)
+    }
+
+    /// 評価値の内訳。項に分けられない評価関数は `None` を返す。
+    fn breakdown(&self, _position: &Position) -> Option<EvalBreakdown> {
+        None
+    }
+
+    /// 駒落ちの手合いを設定する。`None` なら平手。駒割りを使わない評価関数は無視してよい。
+    fn set_handicap(&mut self, _handicap: Option<Handicap>) {}
+
+    /// 駒の価値を設定する。駒の価値を使わない評価関数は無視してよい。
+    fn set_piece_values(&mut self, _values: [i32; PIECE_KIND_COUNT]) {}
+
+    /// 中盤と終盤の駒の位置の表を設定する。表を使わない評価関数は無視してよい。
+    fn set_piece_square_tables(
+        &mut self,
+        _middlegame: &PieceSquareTable,
+        _endgame: &PieceSquareTable,
+    ) {
+    }
+}
+
+/// 先手から見た評価値の項ごとの内訳。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalBreakdown {
+    pub material: i32,
+    pub positional: i32,
+    pub hands: i32,
+    pub tropism: i32,
+    pub king_escapes: i32,
+    pub entering_king: i32,
+    pub race: i32,
+    pub opening: i32,
+    pub mating: i32,
+    pub tempo: i32,
+}
+
+impl EvalBreakdown {
+    pub fn total(&self) -> AbsScore {
+        AbsScore(
+            self.material
+                + self.positional
+                + self.hands
+                + self.tropism
+                + self.king_escapes
+                + self.entering_king
+                + self.race
+                + self.opening
+                + self.mating
+                + self.tempo,
+        )
+    }
+}
+
+impl fmt::Display for EvalBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "material {} positional {} hands {} tropism {} king_escapes {} entering_king {} race {} opening {} mating {} tempo {} total {}",
+            self.material,
+            self.positional,
+            self.hands,
+            self.tropism,
+            self.king_escapes,
+            self.entering_king,
+            self.race,
+            self.opening,
+            self.mating,
+            self.tempo,
+            self.total().value()
+        )
+    }
+}
+
+/// 盤上の駒ごとに決まる項の合計（先手から見た値）。駒が動けば、変わった駒の分だけ足し引きして更新できる。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BoardTerms {
+    pub material: i32,
+    /// `positional_bonus` の合計。
+    pub positional: i32,
+    /// 中盤と終盤の駒の位置の表の合計。手数に応じて混ぜる前の値。
+    pub middlegame: i32,
+    pub endgame: i32,
+}
+
+impl BoardTerms {
+    /// 盤全体を数える。
+    pub fn of(position: &Position, params: &EvalParams) -> Self {
+        let mut terms = Self::default();
+        for square in all_squares() {
+            if let Some(piece) = position.piece_at(square) {
+                terms.add(piece, square, params, 1);
+            }
+        }
+        terms
+    }
+
+    /// `square` の `piece` の分を `count` 回足す。取り除くなら `count` は -1。
+    fn add(&mut self, piece: Piece, square: Square, params: &EvalParams, count: i32) {
+        let sign = match piece.color {
+            Color::Black => count,
+            Color::White => -count,
+        };
+        let kind = piece.kind.index();
+        let pst = pst_square(piece.color, square);
+        self.material += sign * params.piece_values[kind];
+        self.positional += sign * positional_bonus(piece, square);
+        self.middlegame += sign * params.pst_middlegame[kind][pst];
+        self.endgame += sign * params.pst_endgame[kind][pst];
+    }
+
+    /// 駒の価値と、中盤と終盤の表を `position` の手数で混ぜた位置の加点。
+    fn blend(&self, position: &Position) -> (i32, i32) {
+        let phase = game_phase(position);
+        let full = GAME_PHASE_PLIES as i32;
+        let pst = (self.middlegame * phase + self.endgame * (full - phase)) / full;
+        (self.material, self.positional + pst)
+    }
+}
+
+/// 探索が局面ごとに覚えておく静的評価。子の局面を差分で評価するときに1手前の値として渡す。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalState {
+    /// 先手から見た評価値。
+    pub score: AbsScore,
+    /// `HandcraftedEval` が差分で更新する盤上の項。ほかの評価関数は使わなくてよい。
+    pub board: BoardTerms,
+}
+
+/// 駒得、持ち駒、玉への接近度とテンポからなる手作りの評価関数。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HandcraftedEval {
+    pub params: EvalParams,
+}
+
+impl Evaluator for HandcraftedEval {
+    fn evaluate(&self, position: &Position) -> AbsScore {
+        evaluate_absolute(position, &self.params)
+    }
+
+    fn evaluate_state(&self, position: &Position) -> EvalState {
+        let board = BoardTerms::of(position, &self.params);
+        EvalState {
+            score: breakdown_with_board(position, &self.params, &board).total(),
+            board,
+        }
+    }
+
+    /// 駒の価値と位置の項は動いた駒と取られた駒の分だけ更新し、ほかの項は数え直す。
+    fn evaluate_incremental(
+        &self,
+        position: &Position,
+        parent: &EvalState,
+        dirty: &DirtyPieces,
+    ) -> EvalState {
+        let mut board = parent.board;
+        for change in dirty.iter() {
+            if let Some((square, piece)) = change.removed {
+                board.add(piece, square, &self.params, -1);
+            }
+            if let Some((square, piece)) = change.added {
+                board.add(piece, square, &self.params, 1);
+            }
+        }
+        EvalState {
+            score: breakdown_with_board(position, &self.params, &board).total(),
+            board,
+        }
+    }
+
+    fn breakdown(&self, position: &Position) -> Option<EvalBreakdown> {
+        Some(evaluate_breakdown(position, &self.params))
+    }
+
+    fn set_handicap(&mut self, handicap: Option<Handicap>) {
+        self.params.material_baseline = handicap.map_or(0, Handicap::material_baseline);
+    }
+
+    fn set_piece_values(&mut self, values: [i32; PIECE_KIND_COUNT]) {
+        self.params.piece_values = values;
+    }
+
+    fn set_piece_square_tables(
+        &mut self,
+        middlegame: &PieceSquareTable,
+        endgame: &PieceSquareTable,
+    ) {
+        self.params.pst_middlegame = *middlegame;
+        self.params.pst_endgame = *endgame;
+    }
+}
+
 fn piece_value(kind: PieceKind) -> i32 {
-    PIECE_VALUES[kind as usize]
+    DEFAULT_PIECE_VALUES[kind.index()]
 }
 
+/// 既定の駒の価値。手の並べ替えや静的交換評価は、評価関数の設定によらずこれを使う。
 pub fn piece_material_value(kind: PieceKind) -> i32 {
     piece_value(kind)
 }
 
-fn hand_piece_value(kind: HandPieceKind) -> i32 {
-    match kind {
-        HandPieceKind::Gold => piece_value(PieceKind::Gold),
-        HandPieceKind::Silver => piece_value(PieceKind::Silver),
-        HandPieceKind::Bishop => piece_value(PieceKind::Bishop),
-        HandPieceKind::Rook => piece_value(PieceKind::Rook),
-        HandPieceKind::Pawn => piece_value(PieceKind::Pawn),
-    }
+fn hand_piece_value(kind: HandPieceKind, values: &[i32; PIECE_KIND_COUNT]) -> i32 {
+    let kind = match kind {
+        HandPieceKind::Gold => PieceKind::Gold,
+        HandPieceKind::Silver => PieceKind::Silver,
+        HandPieceKind::Bishop => PieceKind::Bishop,
+        HandPieceKind::Rook => PieceKind::Rook,
+        HandPieceKind::Pawn => PieceKind::Pawn,
+    };
+    values[kind.index()]
 }
 
-fn score_hand(color: Color, hand: &Hand) -> i32 {
+/// `color` の持ち駒の価値。同じ駒種の2枚目以降は `hand_curves` で割り引き、飛車角の両取りに加点する。
+fn score_hand(color: Color, hand: &Hand, params: &EvalParams) -> i32 {
     let mut score = 0;
     for kind in HandPieceKind::all() {
-        let count = hand.count(kind) as i32;
-        if count == 0 {
-            continue;
-        }
-        let value = hand_piece_value(kind) * count;
-        score += match color {
-            Color::Black => value,
-            Color::White => -value,
-        };
+        let curve = &params.hand_curves[kind.index()];
+        let percent: i32 = (0..hand.count(kind) as usize)
+            .map(|copy| curve[copy.min(HAND_CURVE_LEN - 1)])
+            .sum();
+        score += hand_piece_value(kind, &params.piece_values) * percent / 100;
+    }
+    if hand.count(HandPieceKind::Rook) > 0 && hand.count(HandPieceKind::Bishop) > 0 {
+        score += params.hand_rook_bishop_bonus;
+    }
+    match color {
+        Color::Black => score,
+        Color::White => -score,
     }
-    score
 }
 
 fn positional_bonus(piece: Piece, square: Square) -> i32 {
@@ -58,10 +386,7 @@ fn positional_bonus(piece: Piece, square: Square) -> i32 {
     let center_distance = (file - center_file).abs() + (rank - center_rank).abs();
     let center_bonus = (4 - center_distance).max(0) * 10;
 
-    let advancement = match piece.color {
-        Color::Black => BOARD_RANKS as i32 - 1 - rank,
-        Color::White => rank,
-    };
+    let advancement = square.relative_rank(piece.color) as i32;
 
     match piece.kind {
         PieceKind::Pawn => advancement * 25 + center_bonus * 2,
@@ -77,40 +402,552 @@ fn positional_bonus(piece: Piece, square: Square) -> i32 {
     }
 }
 
-fn score_board(position: &Position) -> i32 {
+/// `color` の駒から見た升目の番号。後手の駒は盤を180度回して数える。
+pub fn pst_square(color: Color, square: Square) -> usize {
+    match color {
+        Color::Black => square.index() as usize,
+        Color::White => BOARD_SQUARES - 1 - square.index() as usize,
+    }
+}
+
+/// 中盤の表の重み。`GAME_PHASE_PLIES` から始まり、手数が進むごとに1ずつ0まで減る。
+/// 終盤の表の重みは `GAME_PHASE_PLIES` からこれを引いた値。
+pub fn game_phase(position: &Position) -> i32 {
+    GAME_PHASE_PLIES.saturating_sub(position.ply()) as i32
+}
+
+/// 敵玉との距離に応じた駒ごとの重み（距離が1縮まるごとの加点）。
+fn tropism_weight(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::King => 0,
+        PieceKind::Pawn => 2,
+        PieceKind::Silver | PieceKind::Gold => 6,
+        PieceKind::Tokin | PieceKind::PromotedSilver => 7,
+        PieceKind::Bishop | PieceKind::Rook => 5,
+        PieceKind::PromotedBishop | PieceKind::PromotedRook => 9,
+    }
+}
+
+/// 玉周辺に利きを持つ駒の数に応じたボーナス。複数の攻め駒が揃うほど大きくなる。
+const KING_ATTACKER_BONUS: [i32; 6] = [0, 10, 35, 70, 110, 150];
+
+/// 5x5盤上の最大距離。
+const MAX_DISTANCE: i32 = 4;
+
+/// `color` の駒が敵玉へ向かっている度合い。距離による接近点と、
+/// 玉周辺（玉の位置を含む）に利きを持つ攻め駒の数を合わせて評価する。
+fn king_tropism(position: &Position, color: Color) -> i32 {
+    let Some(enemy_king) = position.king_square(color.opponent()) else {
+        return 0;
+    };
+    let king_zone = attacks::king_attacks(enemy_king) | Bitboard::from_square(enemy_king);
+    let occupancy = position.occupancy_all();
+
+    let mut tropism = 0;
+    let mut attackers = 0usize;
+    for kind in PieceKind::all() {
+        if kind == PieceKind::King {
+            continue;
+        }
+        for square in position.pieces(color, kind).iter() {
+            let closeness = MAX_DISTANCE - square.distance(enemy_king) as i32;
+            tropism += tropism_weight(kind) * closeness;
+            let reach = attacks::piece_attacks(color, kind, square, occupancy);
+            if !(reach & king_zone).is_empty() {
+                attackers += 1;
+            }
+        }
+    }
+    tropism + KING_ATTACKER_BONUS[attackers.min(KING_ATTACKER_BONUS.len() - 1)]
+}
+
+/// `color` の玉の逃げ道。自駒がなく、玉を取り除いた盤で相手の利きもない隣のマス。
+/// 玉がなければ空。
+pub fn king_escape_squares(position: &Position, color: Color) -> Bitboard {
+    let Some(king) = position.king_square(color) else {
+        return Bitboard::EMPTY;
+    };
+    let attacked = position.attack_map_through(
+        color.opponent(),
+        position.occupancy_all() & !Bitboard::from_square(king),
+    );
+    attacks::king_attacks(king) & !position.occupancy(color) & !attacked
+}
+
+/// `color` の玉の逃げ道が少ないことへのペナルティ（正の値）。相手が飛車か金を持っていれば重くする。
+fn king_escape_penalty(position: &Position, color: Color) -> i32 {
+    if position.king_square(color).is_none() {
+        return 0;
+    }
+    let escapes = king_escape_squares(position, color);
+    let Some(&penalty) = KING_ESCAPE_PENALTY.get(escapes.count() as usize) else {
+        return 0;
+    };
+    let hand = position.hand(color.opponent());
+    if hand.count(HandPieceKind::Rook) > 0 || hand.count(HandPieceKind::Gold) > 0 {
+        penalty * KING_ESCAPE_DROP_THREAT
+    } else {
+        penalty
+    }
+}
+
+fn entering_king_bonus(position: &Position, color: Color) -> i32 {
+    if position.is_entering_king(color) {
+        ENTERING_KING_BONUS
+    } else {
+        0
+    }
+}
+
+/// `color` の止まらない歩のうち、成るまでの手数が最も少ないもの。止まらない歩とは、
+/// 成るまでの道に駒がなく、歩とその道に相手の利きがなく、相手玉も成る前に追いつけない歩。
+/// 相手に持ち駒があれば道に打って止められるので、止まらない歩はない。
+fn unstoppable_pawn_tempo(position: &Position, color: Color) -> Option<u32> {
+    let enemy = color.opponent();
+    if !position.hand(enemy).is_empty() {
+        return None;
+    }
+    let enemy_king = position.king_square(enemy)?;
+    let attacked = position.attack_map(enemy);
+    let occupied = position.occupancy_all();
+    let last_rank = BOARD_RANKS as u8 - 1;
+    position
+        .pieces(color, PieceKind::Pawn)
+        .iter()
+        .filter_map(|pawn| {
+            if attacked.contains(pawn) {
+                return None;
+            }
+            let steps = (last_rank - pawn.relative_rank(color)) as u32;
+            let mut square = pawn;
+            for _ in 0..steps {
+                square = square.forward(color)?;
+                if occupied.contains(square) || attacked.contains(square) {
+                    return None;
+                }
+            }
+            (enemy_king.distance(square) as u32 > steps).then_some(steps)
+        })
+        .min()
+}
+
+/// 双方に止まらない歩がある成り合いを、駒の価値ではなく成るまでの手数で評価する（先手から見た値）。
+/// 手数が同じなら手番側が先に成る。片方にしか止まらない歩がなければ0。
+fn promotion_race(position: &Position) -> i32 {
+    let (Some(black), Some(white)) = (
+        unstoppable_pawn_tempo(position, Color::Black),
+        unstoppable_pawn_tempo(position, Color::White),
+    ) else {
+        return 0;
+    };
+    let mover = position.side_to_move();
+    let (own, other) = match mover {
+        Color::Black => (black, white),
+        Color::White => (white, black),
+    };
+    let (winner, lead) = if own <= other {
+        (mover, other - own)
+    } else {
+        (mover.opponent(), own - other - 1)
+    };
+    let bonus = PROMOTION_RACE_BONUS + PROMOTION_RACE_TEMPO_BONUS * lead as i32;
+    RelScore(bonus).absolute_from(winner).value()
+}
+
+/// `color` の序盤の指し方の評価。金銀を中央か玉の近くへ寄せ、玉を早く動かさず、
+/// 飛車を銀と交換しないことを促す。
+fn opening_principles(position: &Position, color: Color) -> i32 {
+    let Some(king) = position.king_square(color) else {
+        return 0;
+    };
+    let center_file = (BOARD_FILES as u8 - 1) / 2;
     let mut score = 0;
-    for square in all_squares() {
-        if let Some(piece) = position.piece_at(square) {
-            let material = piece_value(piece.kind);
-            let positional = positional_bonus(piece, square);
-            let value = material + positional;
-            score += match piece.color {
-                Color::Black => value,
-                Color::White => -value,
-            };
+    for kind in [PieceKind::Silver, PieceKind::Gold] {
+        for square in position.pieces(color, kind).iter() {
+            let near_center = square.file().abs_diff(center_file) <= 1;
+            if square.file() == center_file || (near_center && square.distance(king) <= 1) {
+                score += DEVELOPMENT_BONUS;
+            }
         }
     }
+    score -= KING_WALK_PENALTY * king.relative_rank(color) as i32;
+
+    let hand = position.hand(color);
+    let rooks = (position.pieces(color, PieceKind::Rook)
+        | position.pieces(color, PieceKind::PromotedRook))
+    .count()
+        + hand.count(HandPieceKind::Rook) as u32;
+    let silvers = (position.pieces(color, PieceKind::Silver)
+        | position.pieces(color, PieceKind::PromotedSilver))
+    .count()
+        + hand.count(HandPieceKind::Silver) as u32;
+    if rooks == 0 && silvers >= 2 {
+        score -= EARLY_ROOK_TRADE_PENALTY;
+    }
     score
 }
 
+/// 相手が玉だけで持ち駒もなく、`color` に駒が残っている勝ち筋の終盤での `color` の加点。
+/// 相手玉を盤の端へ追い、自玉を近づけるほど高い。相手玉の位置評価は中央を好むので、
+/// それを打ち消して追い詰める方向だけを評価する。
+fn mating_knowledge(position: &Position, color: Color) -> i32 {
+    let defender = color.opponent();
+    let (Some(own_king), Some(lone_king)) =
+        (position.king_square(color), position.king_square(defender))
+    else {
+        return 0;
+    };
+    let material = position.material();
+    if !material.is_bare_king(defender) || material.is_bare_king(color) {
+        return 0;
+    }
+    let center_file = (BOARD_FILES as i32 - 1) / 2;
+    let center_rank = (BOARD_RANKS as i32 - 1) / 2;
+    let from_center = (lone_king.file() as i32 - center_file).abs()
+        + (lone_king.rank() as i32 - center_rank).abs();
+    let proximity = MAX_DISTANCE - own_king.distance(lone_king) as i32;
+    from_center * MATING_EDGE_BONUS
+        + proximity * MATING_KING_PROXIMITY_BONUS
+        + positional_bonus(Piece::new(defender, PieceKind::King), lone_king)
+}
+
+/// 序盤の項を、`OPENING_PLIES` に向けて0まで線形に減らして返す。
+fn opening_score(position: &Position) -> i32 {
+    let remaining = OPENING_PLIES.saturating_sub(position.ply()) as i32;
+    if remaining == 0 {
+        return 0;
+    }
+    let raw =
+        opening_principles(position, Color::Black) - opening_principles(position, Color::White);
+    raw * remaining / OPENING_PLIES as i32
+}
+
+/// 手番側から見た評価値を既定のパラメータで返す。
 pub fn evaluate(position: &Position) -> i32 {
-    let mut score = score_board(position);
-    for color in [Color::Black, Color::White] {
-        score += score_hand(color, position.hand(color));
+    evaluate_relative(position, &EvalParams::default()).value()
+}
+
+/// 先手から見た評価値を返す。テンポボーナスは手番側に有利な方向に加算される。
+pub fn evaluate_absolute(position: &Position, params: &EvalParams) -> AbsScore {
+    evaluate_breakdown(position, params).total()
+}
+
+/// `evaluate_absolute` を項ごとに分けて返す。
+///
+/// 双方が裸玉なら後手の勝ちなので、`mating` の項に `BARE_KINGS_SCORE` だけを返す。
+pub fn evaluate_breakdown(position: &Position, params: &EvalParams) -> EvalBreakdown {
+    breakdown_with_board(position, params, &BoardTerms::of(position, params))
+}
+
+/// `evaluate_breakdown` のうち、盤上の駒の項は数えてある `board` を使う。
+fn breakdown_with_board(
+    position: &Position,
+    params: &EvalParams,
+    board: &BoardTerms,
+) -> EvalBreakdown {
+    if position.material().is_bare_kings() {
+        return EvalBreakdown {
+            mating: -BARE_KINGS_SCORE,
+            ..EvalBreakdown::default()
+        };
     }
-    match position.side_to_move() {
-        Color::Black => score,
-        Color::White => -score,
+    let (material, positional) = board.blend(position);
+    EvalBreakdown {
+        material: material - params.material_baseline,
+        positional,
+        hands: [Color::Black, Color::White]
+            .into_iter()
+            .map(|color| score_hand(color, position.hand(color), params))
+            .sum(),
+        tropism: king_tropism(position, Color::Black) - king_tropism(position, Color::White),
+        king_escapes: king_escape_penalty(position, Color::White)
+            - king_escape_penalty(position, Color::Black),
+        entering_king: entering_king_bonus(position, Color::Black)
+            - entering_king_bonus(position, Color::White),
+        race: promotion_race(position),
+        opening: opening_score(position),
+        mating: mating_knowledge(position, Color::Black) - mating_knowledge(position, Color::White),
+        tempo: RelScore(params.tempo)
+            .absolute_from(position.side_to_move())
+            .value(),
     }
 }
 
+/// 手番側から見た評価値を返す。静止探索のstand-patや探索内部はこちらを使う。
+pub fn evaluate_relative(position: &Position, params: &EvalParams) -> RelScore {
+    evaluate_absolute(position, params).relative_to(position.side_to_move())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn initial_position_is_equal() {
+    fn initial_position_scores_tempo_only() {
         let position = Position::initial().expect("initial");
-        assert_eq!(evaluate(&position), 0);
+        assert_eq!(evaluate(&position), DEFAULT_TEMPO_BONUS);
+    }
+
+    #[test]
+    fn cornered_king_is_penalized_more_with_drops_in_hand() {
+        let escapes = |sfen: &str| {
+            let position = Position::from_sfen(sfen).expect("sfen");
+            king_escape_penalty(&position, Color::White)
+        };
+        assert_eq!(escapes("2k2/5/5/5/2K2 b - 1"), 0);
+        // 1aの玉は2aを自分の銀でふさがれ、1bと2bには先手の銀が利いている。
+        assert_eq!(escapes("3sk/5/3S1/5/K4 b - 1"), KING_ESCAPE_PENALTY[0]);
+        assert_eq!(
+            escapes("3sk/5/3S1/5/K4 b G 1"),
+            KING_ESCAPE_PENALTY[0] * KING_ESCAPE_DROP_THREAT
+        );
+        assert_eq!(escapes("3sk/5/3S1/5/K4 b B 1"), KING_ESCAPE_PENALTY[0]);
+    }
+
+    #[test]
+    fn promotion_race_is_won_by_the_faster_pawn() {
+        let race = |sfen: &str| promotion_race(&Position::from_sfen(sfen).expect("sfen"));
+        // どちらの歩も2手で成る。手数が同じなら手番側が先に成る。
+        assert_eq!(race("4k/5/P3p/5/K4 b - 1"), PROMOTION_RACE_BONUS);
+        assert_eq!(race("4k/5/P3p/5/K4 w - 1"), -PROMOTION_RACE_BONUS);
+        // 先手の歩が1手早いので、後手番でも先手が勝つ。
+        assert_eq!(race("4k/P4/4p/5/K4 w - 1"), PROMOTION_RACE_BONUS);
+        assert_eq!(
+            race("4k/P4/4p/5/K4 b - 1"),
+            PROMOTION_RACE_BONUS + PROMOTION_RACE_TEMPO_BONUS
+        );
+        // 持ち駒を打てば止められる。
+        assert_eq!(race("4k/5/P3p/5/K4 b g 1"), 0);
+    }
+
+    #[test]
+    fn extra_hand_pieces_follow_the_curve() {
+        let params = EvalParams::default();
+        let hand = |sfen: &str| {
+            let position = Position::from_sfen(sfen).expect("sfen");
+            score_hand(Color::Black, position.hand(Color::Black), &params)
+        };
+        let pawn = piece_value(PieceKind::Pawn);
+        assert_eq!(hand("4k/5/5/5/4K b P 1"), pawn);
+        assert_eq!(hand("4k/5/5/5/4K b 2P 1"), pawn + pawn * 60 / 100);
+        let (rook, bishop) = (hand("4k/5/5/5/4K b R 1"), hand("4k/5/5/5/4K b B 1"));
+        assert_eq!(
+            hand("4k/5/5/5/4K b RB 1"),
+            rook + bishop + DEFAULT_HAND_ROOK_BISHOP_BONUS
+        );
+        let linear = EvalParams {
+            hand_curves: [[100; HAND_CURVE_LEN]; HAND_PIECE_KIND_COUNT],
+            ..params
+        };
+        let position = Position::from_sfen("4k/5/5/5/4K w 2G 1").expect("sfen");
+        assert_eq!(
+            score_hand(Color::White, position.hand(Color::White), &linear),
+            0
+        );
+        assert_eq!(
+            score_hand(Color::Black, position.hand(Color::Black), &linear),
+            2 * piece_value(PieceKind::Gold)
+        );
+    }
+
+    #[test]
+    fn tropism_rewards_pieces_near_enemy_king() {
+        let far = Position::from_sfen("4k/5/5/5/G3K b - 1").expect("far");
+        let near = Position::from_sfen("4k/3G1/5/5/4K b - 1").expect("near");
+        assert!(king_tropism(&near, Color::Black) > king_tropism(&far, Color::Black));
+    }
+
+    #[test]
+    fn entering_king_is_rewarded() {
+        let params = EvalParams {
+            tempo: 0,
+            ..EvalParams::default()
+        };
+        let entered = Position::from_sfen("K1G2/1S3/5/5/4k b - 1").expect("entered");
+        let breakdown = evaluate_breakdown(&entered, &params);
+        assert_eq!(breakdown.entering_king, ENTERING_KING_BONUS);
+        assert_eq!(breakdown.total(), evaluate_absolute(&entered, &params));
+    }
+
+    #[test]
+    fn opening_term_punishes_early_king_walks_and_fades() {
+        let walked = Position::from_sfen("rbsgk/4p/5/PK3/1GSBR w - 2").expect("walked");
+        assert!(opening_score(&walked) < 0);
+        let mut late = walked.clone();
+        late.set_ply(OPENING_PLIES);
+        assert_eq!(opening_score(&late), 0);
+    }
+
+    #[test]
+    fn lone_king_is_driven_to_the_edge() {
+        let centre = Position::from_sfen("5/5/2k2/5/R3K b - 1").expect("centre");
+        let edge = Position::from_sfen("k4/5/5/5/R3K b - 1").expect("edge");
+        let near = Position::from_sfen("k4/5/1K3/5/R4 b - 1").expect("near");
+        assert!(mating_knowledge(&edge, Color::Black) > mating_knowledge(&centre, Color::Black));
+        assert!(mating_knowledge(&near, Color::Black) > mating_knowledge(&edge, Color::Black));
+        assert!(evaluate(&edge) > evaluate(&centre));
+        assert_eq!(mating_knowledge(&edge, Color::White), 0);
+        let defended = Position::from_sfen("k4/p4/5/5/R3K b - 1").expect("defended");
+        assert_eq!(mating_knowledge(&defended, Color::Black), 0);
+        let bare = Position::from_sfen("k4/5/5/5/4K b - 1").expect("bare");
+        assert_eq!(evaluate(&bare), -BARE_KINGS_SCORE);
+        let bare = Position::from_sfen("k4/5/5/5/4K w - 1").expect("bare");
+        assert_eq!(evaluate(&bare), BARE_KINGS_SCORE);
+    }
+
+    /// 盤上と持ち駒の駒得だけを数え、1手ごとの更新は変わった駒の分だけで済ませる評価関数。
+    struct MaterialOnly;
+
+    impl MaterialOnly {
+        fn signed(color: Color, value: i32) -> i32 {
+            match color {
+                Color::Black => value,
+                Color::White => -value,
+            }
+        }
+    }
+
+    impl Evaluator for MaterialOnly {
+        fn evaluate(&self, position: &Position) -> AbsScore {
+            let material = BoardTerms::of(position, &EvalParams::default()).material;
+            let hands: i32 = [Color::Black, Color::White]
+                .into_iter()
+                .map(|color| {
+                    HandPieceKind::all()
+                        .into_iter()
+                        .map(|kind| {
+                            let count = position.hand(color).count(kind) as i32;
+                            Self::signed(
+                                color,
+                                count * hand_piece_value(kind, &DEFAULT_PIECE_VALUES),
+                            )
+                        })
+                        .sum::<i32>()
+                })
+                .sum();
+            AbsScore(material + hands)
+        }
+
+        fn evaluate_incremental(
+            &self,
+            _position: &Position,
+            parent: &EvalState,
+            dirty: &DirtyPieces,
+        ) -> EvalState {
+            let mut score = parent.score.value();
+            for change in dirty.iter() {
+                if let Some((_, piece)) = change.removed {
+                    score -= Self::signed(piece.color, piece_value(piece.kind));
+                }
+                if let Some((_, piece)) = change.added {
+                    score += Self::signed(piece.color, piece_value(piece.kind));
+                }
+            }
+            if let Some(hand) = dirty.hand {
+                let delta = (hand.after as i32 - hand.before as i32)
+                    * hand_piece_value(hand.kind, &DEFAULT_PIECE_VALUES);
+                score += Self::signed(hand.color, delta);
+            }
+            EvalState {
+                score: AbsScore(score),
+                board: BoardTerms::default(),
+            }
+        }
+    }
+
+    #[test]
+    fn dirty_pieces_allow_incremental_updates() {
+        let mut position = Position::from_sfen("rbsgk/4p/5/P4/KGSBR b - 1").expect("initial");
+        assert!(position.dirty_pieces().is_none());
+        let eval = MaterialOnly;
+        let mut state = eval.evaluate_state(&position);
+        // 駒を取る手、取った歩を打つ手、駒を取りながら成る手を含む。
+        let moves = [
+            "2e5b", "4a5b", "1e1b", "1a1b", "P*3c", "5b4c", "3c3b", "4c5b", "3b3a+",
+        ];
+        for token in moves {
+            let mv = position.parse_usi_move(token).expect("legal move");
+            position.play_move_mut(&mv).expect("move");
+            let dirty = *position.dirty_pieces().expect("dirty pieces");
+            state = eval.evaluate_incremental(&position, &state, &dirty);
+            assert_eq!(state.score, eval.evaluate(&position), "after {token}");
+        }
+    }
+
+    #[test]
+    fn handcrafted_incremental_matches_full_evaluation() {
+        let mut params = EvalParams::default();
+        for kind in 0..PIECE_KIND_COUNT {
+            for square in 0..BOARD_SQUARES {
+                params.pst_middlegame[kind][square] = ((kind * 7 + square * 3) % 41) as i32 - 20;
+                params.pst_endgame[kind][square] = ((kind * 5 + square * 11) % 37) as i32 - 18;
+            }
+        }
+        let eval = HandcraftedEval { params };
+        let mut position = Position::initial().expect("initial");
+        let mut state = eval.evaluate_state(&position);
+        assert_eq!(state.score, eval.evaluate(&position));
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        for _ in 0..200 {
+            let moves = position.generate_legal_moves().expect("moves");
+            if moves.is_empty() {
+                position = Position::initial().expect("initial");
+                state = eval.evaluate_state(&position);
+                continue;
+            }
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let mv = moves[(seed % moves.len() as u64) as usize];
+            position.play_move_mut(&mv).expect("move");
+            let dirty = *position.dirty_pieces().expect("dirty pieces");
+            state = eval.evaluate_incremental(&position, &state, &dirty);
+            assert_eq!(
+                state.score,
+                eval.evaluate(&position),
+                "after {}",
+                mv.to_usi()
+            );
+            assert_eq!(state.board, BoardTerms::of(&position, &eval.params));
+        }
+    }
+
+    #[test]
+    fn tempo_follows_side_to_move() {
+        let mut position = Position::initial().expect("initial");
+        position.set_side_to_move(Color::White);
+        let params = EvalParams {
+            tempo: 0,
+            ..EvalParams::default()
+        };
+        assert_eq!(evaluate_relative(&position, &params), RelScore(0));
+        assert_eq!(evaluate(&position), DEFAULT_TEMPO_BONUS);
+        assert_eq!(
+            evaluate_absolute(&position, &EvalParams::default()),
+            AbsScore(-DEFAULT_TEMPO_BONUS)
+        );
+    }
+
+    #[test]
+    fn piece_square_tables_are_tapered_by_ply() {
+        // 5dは先手から見た升目、1bは後手から見た同じ升目。
+        let square = pst_square(Color::Black, Square::from_coord("5d").unwrap());
+        assert_eq!(
+            square,
+            pst_square(Color::White, Square::from_coord("1b").unwrap())
+        );
+        let mut params = EvalParams::default();
+        params.pst_middlegame[PieceKind::Pawn.index()][square] = 60;
+        params.pst_endgame[PieceKind::Pawn.index()][square] = 30;
+        let table_bonus = |sfen: &str| {
+            let position = Position::from_sfen(sfen).unwrap();
+            evaluate_absolute(&position, &params).value()
+                - evaluate_absolute(&position, &EvalParams::default()).value()
+        };
+        // 双方の歩が同じ升目にいれば打ち消し合う。
+        assert_eq!(table_bonus("rbsgk/4p/5/P4/KGSBR b - 1"), 0);
+        assert_eq!(table_bonus("rbsgk/5/5/P4/KGSBR b P 1"), 59);
+        assert_eq!(table_bonus("rbsgk/5/5/P4/KGSBR b P 31"), 44);
+        assert_eq!(table_bonus("rbsgk/5/5/P4/KGSBR b P 61"), 30);
     }
 }
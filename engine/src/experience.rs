@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::moves::Move;
+use crate::position::Position;
+use crate::stable_hash::stable_key;
+
+/// 勝敗を覚える序盤の手数。これより後の局面は数えない。
+pub const RESULT_PLIES: u32 = 20;
+
+/// 負け越した局面へ進む手から、負け越し1局あたりに引く値と、その上限。
+pub const LOST_LINE_PENALTY: i32 = 40;
+pub const MAX_LOST_LINE_PENALTY: i32 = 200;
+
+/// この局数だけ負けるまでは、たまたまの負けとして減点しない。
+const MIN_LOSSES: u32 = 2;
+
+/// 過去の対局で探索したルート局面の結果。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExperienceEntry {
+    pub best_move: Move,
+    /// 局面の手番側から見た評価値。
+    pub score: i32,
+    pub depth: usize,
+}
+
+/// 対局の結果。`gameover` の引数から決める。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineOutcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl LineOutcome {
+    /// `gameover` の `win`・`lose`・`draw`。
+    pub fn from_usi(token: &str) -> Option<Self> {
+        match token {
+            "win" => Some(Self::Win),
+            "draw" => Some(Self::Draw),
+            "lose" => Some(Self::Loss),
+            _ => None,
+        }
+    }
+}
+
+/// 序盤の局面に自分の手で進んだ対局の成績。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineRecord {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl LineRecord {
+    /// この局面へ進む手に付ける減点。何度も負け越していれば大きくする。
+    pub fn penalty(&self) -> i32 {
+        if self.losses < MIN_LOSSES || self.losses <= self.wins {
+            return 0;
+        }
+        (LOST_LINE_PENALTY * (self.losses - self.wins) as i32).min(MAX_LOST_LINE_PENALTY)
+    }
+}
+
+/// 対局をまたいで覚えておくルート局面の探索結果と序盤の局面の成績（経験ファイル）。
+///
+/// ファイルは1行に1局面を `SFEN | 指し手 | 評価値 | 深さ` の形で書く。成績は
+/// `SFEN | results | 勝ち | 引き分け | 負け` の形で、その局面へ進んだ側から見た数を書く。
+/// 局面は `stable_key` で引くので、手数の違いは区別しない。
+#[derive(Clone, Debug, Default)]
+pub struct Experience {
+    entries: HashMap<u64, (String, ExperienceEntry)>,
+    lines: HashMap<u64, (String, LineRecord)>,
+}
+
+impl Experience {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 覚えている局面と序盤の勝敗のおおよそのバイト数。
+    pub fn bytes(&self) -> usize {
+        let entries: usize = self.entries.values().map(|(sfen, _)| sfen.capacity()).sum();
+        let lines: usize = self.lines.values().map(|(sfen, _)| sfen.capacity()).sum();
+        entries
+            + lines
+            + self.entries.capacity() * size_of::<(u64, (String, ExperienceEntry))>()
+            + self.lines.capacity() * size_of::<(u64, (String, LineRecord))>()
+    }
+
+    /// `path` から読み込む。ファイルがなければ空の経験を返す。読めない行は飛ばす。
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(Self::parse(&text))
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut experience = Self::new();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+            if fields.get(1) == Some(&"results") {
+                experience.parse_results(&fields);
+                continue;
+            }
+            let [sfen, mv, score, depth] = fields.as_slice() else {
+                continue;
+            };
+            let Ok(position) = Position::from_sfen(sfen) else {
+                continue;
+            };
+            let (Ok(mv), Ok(score), Ok(depth)) =
+                (position.parse_usi_move(mv), score.parse(), depth.parse())
+            else {
+                continue;
+            };
+            experience.record(&position, mv, score, depth);
+        }
+        experience
+    }
+
+    fn parse_results(&mut self, fields: &[&str]) {
+        let [sfen, _, wins, draws, losses] = fields else {
+            return;
+        };
+        let Ok(position) = Position::from_sfen(sfen) else {
+            return;
+        };
+        let (Ok(wins), Ok(draws), Ok(losses)) = (wins.parse(), draws.parse(), losses.parse())
+        else {
+            return;
+        };
+        self.lines.insert(
+            stable_key(&position),
+            (
+                position.to_sfen(),
+                LineRecord {
+                    wins,
+                    draws,
+                    losses,
+                },
+            ),
+        );
+    }
+
+    /// 局面の順を揃えて `path` へ書き出す。
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut lines: Vec<String> = self
+            .entries
+            .values()
+            .map(|(sfen, entry)| {
+                format!(
+                    "{sfen} | {} | {} | {}",
+                    entry.best_move.to_usi(),
+                    entry.score,
+                    entry.depth
+                )
+            })
+            .chain(self.lines.values().map(|(sfen, record)| {
+                format!(
+                    "{sfen} | results | {} | {} | {}",
+                    record.wins, record.draws, record.losses
+                )
+            }))
+            .collect();
+        lines.sort();
+        let mut text = lines.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        fs::write(path, text)
+    }
+
+    /// 探索結果を覚える。同じ局面は深さが同じか深いときだけ上書きする。
+    pub fn record(&mut self, position: &Position, best_move: Move, score: i32, depth: usize) {
+        let entry = ExperienceEntry {
+            best_move,
+            score,
+            depth,
+        };
+        let key = stable_key(position);
+        if self
+            .entries
+            .get(&key)
+            .is_some_and(|(_, existing)| existing.depth > depth)
+        {
+            return;
+        }
+        self.entries.insert(key, (position.to_sfen(), entry));
+    }
+
+    /// `position` の経験。覚えている手が今の局面で指せなければ返さない。
+    pub fn probe(&self, position: &Position) -> Option<ExperienceEntry> {
+        let (_, entry) = self.entries.get(&stable_key(position))?;
+        let legal = position
+            .generate_legal_moves()
+            .is_ok_and(|moves| moves.contains(&entry.best_move));
+        legal.then_some(*entry)
+    }
+
+    /// 自分の手で進んだ序盤の局面 `line` それぞれに、対局の結果を足す。
+    pub fn record_result(&mut self, line: &[Position], outcome: LineOutcome) {
+        for position in line {
+            let (_, record) = self
+                .lines
+                .entry(stable_key(position))
+                .or_insert_with(|| (position.to_sfen(), LineRecord::default()));
+            match outcome {
+                LineOutcome::Win => record.wins += 1,
+                LineOutcome::Draw => record.draws += 1,
+                LineOutcome::Loss => record.losses += 1,
+            }
+        }
+    }
+
+    /// `position` へ進んだ対局の成績。
+    pub fn line_record(&self, position: &Position) -> LineRecord {
+        self.lines
+            .get(&stable_key(position))
+            .map_or_else(LineRecord::default, |(_, record)| *record)
+    }
+
+    /// `position` の合法手のうち、負け越した局面へ進む手とその減点。
+    pub fn lost_line_penalties(&self, position: &Position) -> Vec<(Move, i32)> {
+        if self.lines.is_empty() {
+            return Vec::new();
+        }
+        let Ok(moves) = position.generate_legal_moves() else {
+            return Vec::new();
+        };
+        moves
+            .into_iter()
+            .filter_map(|mv| {
+                let next = position.play_move(&mv).ok()?;
+                let penalty = self.line_record(&next).penalty();
+                (penalty > 0).then_some((mv, penalty))
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.lines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_survive_a_save_and_load() {
+        let position = Position::initial().unwrap();
+        let mv = position.parse_usi_move("1e1d").unwrap();
+        let mut experience = Experience::new();
+        experience.record(&position, mv, 42, 5);
+        experience.record(&position, position.parse_usi_move("2e1d").unwrap(), 0, 3);
+
+        let path =
+            std::env::temp_dir().join(format!("ginko-experience-{}.txt", std::process::id()));
+        experience.save(&path).unwrap();
+        let loaded = Experience::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            loaded.probe(&position),
+            Some(ExperienceEntry {
+                best_move: mv,
+                score: 42,
+                depth: 5,
+            })
+        );
+
+        let missing = Experience::load(&path).unwrap();
+        assert!(missing.is_empty());
+        let skipped = Experience::parse("garbage\nrbsgk/4p/5/P4/KGSBR b - 1 | 9z9z | 0 | 1\n");
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn repeatedly_lost_lines_are_penalized_and_saved() {
+        let position = Position::initial().unwrap();
+        let mv = position.parse_usi_move("1e1d").unwrap();
+        let line = [position.play_move(&mv).unwrap()];
+        let mut experience = Experience::new();
+        experience.record_result(&line, LineOutcome::Loss);
+        assert!(experience.lost_line_penalties(&position).is_empty());
+        experience.record_result(&line, LineOutcome::Loss);
+        experience.record_result(&line, LineOutcome::Loss);
+        experience.record_result(&line, LineOutcome::Win);
+        assert_eq!(
+            experience.lost_line_penalties(&position),
+            vec![(mv, 2 * LOST_LINE_PENALTY)]
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("ginko-lost-lines-{}.txt", std::process::id()));
+        experience.save(&path).unwrap();
+        let loaded = Experience::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            loaded.line_record(&line[0]),
+            LineRecord {
+                wins: 1,
+                draws: 0,
+                losses: 3,
+            }
+        );
+    }
+}
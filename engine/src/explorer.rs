@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+use crate::match_runner::{GameRecord, parse_game_line, parse_opening_line, parse_value};
+use crate::moves::Move;
+use crate::piece::Color;
+use crate::position::{Position, PositionError};
+use crate::stable_hash::stable_key;
+
+/// 棋譜の結果。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    Win(Color),
+    Draw,
+    /// 結果が書かれていない棋譜。
+    Unknown,
+}
+
+impl GameResult {
+    /// `1-0`、`0-1`、`1/2-1/2`、`*` を読む。
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(Self::Win(Color::Black)),
+            "0-1" => Some(Self::Win(Color::White)),
+            "1/2-1/2" => Some(Self::Draw),
+            "*" => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+}
+
+/// ある局面で指された1手の集計。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MoveStats {
+    pub games: u32,
+    pub black_wins: u32,
+    pub white_wins: u32,
+    pub draws: u32,
+}
+
+impl MoveStats {
+    fn add(&mut self, result: GameResult) {
+        self.games += 1;
+        match result {
+            GameResult::Win(Color::Black) => self.black_wins += 1,
+            GameResult::Win(Color::White) => self.white_wins += 1,
+            GameResult::Draw => self.draws += 1,
+            GameResult::Unknown => {}
+        }
+    }
+}
+
+/// 多数の棋譜から、局面ごとに指された手とその結果を集めた定跡エクスプローラ。
+///
+/// 局面は `stable_key` で引くので、手順が違っても同じ局面に合流した手はまとめて数える。
+/// 1局の中で同じ局面が繰り返されても、その局の手は1回だけ数える。
+#[derive(Clone, Debug, Default)]
+pub struct Explorer {
+    positions: HashMap<u64, Vec<(Move, MoveStats)>>,
+    games: usize,
+    max_plies: Option<usize>,
+}
+
+impl Explorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 各局の最初の `plies` 手だけを集める。
+    pub fn with_max_plies(plies: usize) -> Self {
+        Self {
+            max_plies: Some(plies),
+            ..Self::default()
+        }
+    }
+
+    /// `start` から指された `moves` を1局として加える。
+    pub fn add_game(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        result: GameResult,
+    ) -> Result<(), PositionError> {
+        let plies = self.max_plies.unwrap_or(moves.len()).min(moves.len());
+        let mut position = start.clone();
+        let mut seen = Vec::with_capacity(plies);
+        for mv in &moves[..plies] {
+            let key = stable_key(&position);
+            if !seen.contains(&(key, *mv)) {
+                seen.push((key, *mv));
+                let entries = self.positions.entry(key).or_default();
+                let index = match entries.iter().position(|(played, _)| played == mv) {
+                    Some(index) => index,
+                    None => {
+                        entries.push((*mv, MoveStats::default()));
+                        entries.len() - 1
+                    }
+                };
+                entries[index].1.add(result);
+            }
+            position.play_move_mut(mv)?;
+        }
+        self.games += 1;
+        Ok(())
+    }
+
+    /// 対局の棋譜を加える。
+    pub fn add_record(&mut self, record: &GameRecord) -> Result<(), PositionError> {
+        let start = Position::from_sfen(&record.start_sfen)?;
+        let result = record.winner.map_or(GameResult::Draw, GameResult::Win);
+        self.add_game(&start, &record.moves, result)
+    }
+
+    /// `position` で指された手を、指された回数の多い順に返す。
+    pub fn query(&self, position: &Position) -> Vec<(Move, MoveStats)> {
+        let mut moves = self
+            .positions
+            .get(&stable_key(position))
+            .cloned()
+            .unwrap_or_default();
+        moves.sort_by(|(a, a_stats), (b, b_stats)| {
+            b_stats
+                .games
+                .cmp(&a_stats.games)
+                .then_with(|| a.to_usi().cmp(&b.to_usi()))
+        });
+        moves
+    }
+
+    /// 加えた棋譜の数。
+    pub fn games(&self) -> usize {
+        self.games
+    }
+
+    /// 1手以上が記録された局面の数。
+    pub fn positions(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+/// 棋譜ファイルの1行を、対局と結果に分ける。行末に ` | 1-0` のように結果を書ける。
+fn parse_result_line(line: &str) -> Result<(Position, Vec<Move>, GameResult), PositionError> {
+    let (game, result) = match line.rsplit_once('|') {
+        Some((game, result)) => (
+            game,
+            GameResult::parse(result.trim()).ok_or(PositionError::Format("invalid game result"))?,
+        ),
+        None => (line, GameResult::Unknown),
+    };
+    let (start, moves) = parse_game_line(game)?;
+    Ok((start, moves, result))
+}
+
+/// `explorer` サブコマンド。
+///
+/// `explorer <GAMES> [--plies N] [--position LINE]`
+///
+/// `GAMES` は1行に1局を `position sfen ... moves ... | 1-0` の形で書いたファイル。
+/// 結果（`1-0`、`0-1`、`1/2-1/2`、`*`）は省略できる。`LINE` は開始局面ファイルと同じ形式で、
+/// 省略すると初期局面を調べる。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut games_path = None;
+    let mut plies = None;
+    let mut query = Position::initial()?;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--plies" => plies = Some(parse_value(flag, iter.next())?),
+            "--position" => {
+                query = parse_opening_line(&parse_value::<String>(flag, iter.next())?)?;
+            }
+            other if !other.starts_with("--") && games_path.is_none() => {
+                games_path = Some(other.to_string());
+            }
+            other => return Err(format!("unknown explorer option: {other}").into()),
+        }
+    }
+    let games_path = games_path.ok_or("explorer requires a games file")?;
+
+    let mut explorer = plies.map_or_else(Explorer::new, Explorer::with_max_plies);
+    let text = fs::read_to_string(&games_path)?;
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (start, moves, result) =
+            parse_result_line(line).map_err(|err| format!("{games_path}:{}: {err}", idx + 1))?;
+        explorer.add_game(&start, &moves, result)?;
+    }
+
+    for (mv, stats) in explorer.query(&query) {
+        println!(
+            "{} games {} black {} white {} draws {}",
+            mv.to_usi(),
+            stats.games,
+            stats.black_wins,
+            stats.white_wins,
+            stats.draws
+        );
+    }
+    eprintln!(
+        "{} games, {} positions",
+        explorer.games(),
+        explorer.positions()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transpositions_and_results_are_merged() {
+        let mut explorer = Explorer::new();
+        for line in [
+            "startpos moves 1e1d 5a5b 1d2d | 1-0",
+            "startpos moves 1e1d 5a5b 1d1c | 0-1",
+            "startpos moves 2e1d | 1/2-1/2",
+            "startpos moves 1e1d 5a5b 1d1e 5b5a 1e1d",
+        ] {
+            let (start, moves, result) = parse_result_line(line).unwrap();
+            explorer.add_game(&start, &moves, result).unwrap();
+        }
+        assert_eq!(explorer.games(), 4);
+
+        let initial = Position::initial().unwrap();
+        let moves = explorer.query(&initial);
+        assert_eq!(moves[0].0.to_usi(), "1e1d");
+        assert_eq!(
+            moves[0].1,
+            MoveStats {
+                games: 3,
+                black_wins: 1,
+                white_wins: 1,
+                draws: 0,
+            }
+        );
+        assert_eq!(moves[1].1.draws, 1);
+
+        let after = parse_opening_line("startpos moves 1e1d 5a5b").unwrap();
+        let replies: Vec<String> = explorer
+            .query(&after)
+            .iter()
+            .map(|(mv, _)| mv.to_usi())
+            .collect();
+        assert_eq!(replies, ["1d1c", "1d1e", "1d2d"]);
+        assert!(parse_result_line("startpos | 2-0").is_err());
+    }
+}
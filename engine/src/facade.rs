@@ -0,0 +1,78 @@
+//! GUIやツールからライブラリとして使う窓口。
+//!
+//! USIのコマンドを介さずに探索や悪手の判定を呼べる。途中経過は出力せず、結果は戻り値で返す。
+
+use crate::evaluation::{Evaluator, HandcraftedEval};
+use crate::position::{Position, PositionError};
+use crate::query_cache::{PositionQuery, QueryCache};
+use crate::search::{NullInfoSink, SearchLimits, SearchResult, Searcher};
+
+/// ライブラリ向けのエンジン。探索器を1つ持ち、呼び出しをまたいで使い回す。
+pub struct Engine<E: Evaluator = HandcraftedEval> {
+    pub(crate) searcher: Searcher<E>,
+    /// GUIが同じ局面に繰り返す合法手や王手の問い合わせの答え。
+    queries: QueryCache,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_searcher(Searcher::new())
+    }
+}
+
+impl<E: Evaluator> Engine<E> {
+    /// `searcher` を使うエンジン。途中経過の出力先は捨てるものに差し替える。
+    pub fn with_searcher(mut searcher: Searcher<E>) -> Self {
+        searcher.set_info_sink(Box::new(NullInfoSink));
+        Self {
+            searcher,
+            queries: QueryCache::default(),
+        }
+    }
+
+    pub fn searcher(&self) -> &Searcher<E> {
+        &self.searcher
+    }
+
+    pub fn searcher_mut(&mut self) -> &mut Searcher<E> {
+        &mut self.searcher
+    }
+
+    pub fn search(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+    ) -> Result<SearchResult, PositionError> {
+        self.searcher.search(position, limits)
+    }
+
+    /// `position` の合法手・王手になる手・詰みの判定。同じ局面への問い合わせはキャッシュから返す。
+    pub fn query(&mut self, position: &Position) -> Result<&PositionQuery, PositionError> {
+        self.queries.query(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameStatus;
+
+    #[test]
+    fn queries_are_answered_without_a_search() {
+        let mut engine = Engine::new();
+        let mate = Position::from_sfen("k4/1G3/1P3/5/4K w - 1").unwrap();
+        assert!(engine.query(&mate).unwrap().legal_moves.is_empty());
+        assert_eq!(
+            engine.query(&mate).unwrap().status(),
+            GameStatus::Mate {
+                winner: crate::piece::Color::Black
+            }
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! 局面集の各局面について、評価関数の項や合法手の数、玉の安全度、最善手の駒の取り合いを
+//! 1行ずつCSVで書き出す。外部で学習するモデルの入力に使う。
+//!
+//! 1行目は列名で、どの行も同じ列を持つ。値は数値か真偽値（`true`/`false`）で、
+//! 最善手とSFENだけが文字列。手がなければ最善手とSEEの列は空にする。そのまま
+//! 列指向の形式（Parquetなど）に読み込める。
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::attacks;
+use crate::evaluation::{EvalBreakdown, Evaluator, HandcraftedEval, king_escape_squares};
+use crate::match_runner::parse_value;
+use crate::moves::Move;
+use crate::piece::Color;
+use crate::position::{Position, PositionError};
+use crate::search::{NullInfoSink, SearchLimits, Searcher};
+use crate::see::static_exchange;
+
+/// 既定の探索の深さ。
+pub const DEFAULT_FEATURES_DEPTH: usize = 3;
+
+/// 列名。`PositionFeatures` の `Display` と同じ順。評価の項は先手から見た値、
+/// `score` と `best_see` は手番側から見た値。
+pub const FEATURE_COLUMNS: [&str; 24] = [
+    "sfen",
+    "side_to_move",
+    "material",
+    "positional",
+    "hands",
+    "tropism",
+    "king_escapes",
+    "entering_king",
+    "race",
+    "opening",
+    "mating",
+    "tempo",
+    "eval_total",
+    "black_mobility",
+    "white_mobility",
+    "in_check",
+    "black_king_escapes",
+    "white_king_escapes",
+    "black_king_zone_attacks",
+    "white_king_zone_attacks",
+    "depth",
+    "score",
+    "best_move",
+    "best_see",
+];
+
+/// 1局面の特徴量。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PositionFeatures {
+    pub sfen: String,
+    pub side_to_move: Color,
+    pub eval: EvalBreakdown,
+    /// 先手と後手それぞれが指せる合法手の数。手番でない側は手番を入れ替えて数える。
+    pub mobility: [usize; 2],
+    pub in_check: bool,
+    /// 先手と後手それぞれの玉の逃げ道の数。
+    pub king_escapes: [u32; 2],
+    /// 先手と後手それぞれの玉の隣のマスのうち、相手の利きがあるマスの数。
+    pub king_zone_attacks: [u32; 2],
+    pub depth: usize,
+    pub score: i32,
+    pub best_move: Option<Move>,
+    /// 最善手の静的交換評価。
+    pub best_see: Option<i32>,
+}
+
+impl fmt::Display for PositionFeatures {
+    /// `FEATURE_COLUMNS` の順のCSVの1行。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let eval = &self.eval;
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.sfen,
+            match self.side_to_move {
+                Color::Black => "b",
+                Color::White => "w",
+            },
+            eval.material,
+            eval.positional,
+            eval.hands,
+            eval.tropism,
+            eval.king_escapes,
+            eval.entering_king,
+            eval.race,
+            eval.opening,
+            eval.mating,
+            eval.tempo,
+            eval.total().value()
+        )?;
+        write!(
+            f,
+            ",{},{},{},{},{},{},{},{},{},{},{}",
+            self.mobility[0],
+            self.mobility[1],
+            self.in_check,
+            self.king_escapes[0],
+            self.king_escapes[1],
+            self.king_zone_attacks[0],
+            self.king_zone_attacks[1],
+            self.depth,
+            self.score,
+            self.best_move.map_or(String::new(), |mv| mv.to_usi()),
+            self.best_see.map_or(String::new(), |see| see.to_string())
+        )
+    }
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::Black => 0,
+        Color::White => 1,
+    }
+}
+
+/// `position` の特徴量を、`searcher` で深さ `depth` まで読んで求める。
+pub fn extract_features(
+    searcher: &mut Searcher,
+    position: &Position,
+    depth: usize,
+) -> Result<PositionFeatures, PositionError> {
+    let mut mobility = [0; 2];
+    let mut king_escapes = [0; 2];
+    let mut king_zone_attacks = [0; 2];
+    for color in [Color::Black, Color::White] {
+        let mut turn = position.clone();
+        turn.set_side_to_move(color);
+        mobility[color_index(color)] = turn.generate_legal_moves()?.len();
+        king_escapes[color_index(color)] = king_escape_squares(position, color).count();
+        king_zone_attacks[color_index(color)] = position.king_square(color).map_or(0, |king| {
+            (attacks::king_attacks(king) & position.attack_map(color.opponent())).count()
+        });
+    }
+    let eval = HandcraftedEval {
+        params: *searcher.eval_params(),
+    }
+    .breakdown(position)
+    .expect("handcrafted eval has a breakdown");
+    let limits = SearchLimits {
+        depth: depth.max(1),
+        ..SearchLimits::default()
+    };
+    let result = searcher.search(position, limits)?;
+    Ok(PositionFeatures {
+        sfen: position.to_sfen(),
+        side_to_move: position.side_to_move(),
+        eval,
+        mobility,
+        in_check: position.in_check(),
+        king_escapes,
+        king_zone_attacks,
+        depth: result.depth,
+        score: result.score.value(),
+        best_move: result.best_move,
+        best_see: result.best_move.map(|mv| static_exchange(position, &mv)),
+    })
+}
+
+/// `text` の空行と `#` で始まる行を除いた各行のSFENの特徴量をCSVで `out` に書く。
+/// 読めない行は飛ばし、1始まりの行番号とエラーを返す。
+pub fn write_features(
+    text: &str,
+    depth: usize,
+    out: &mut impl Write,
+) -> io::Result<Vec<(usize, PositionError)>> {
+    let mut searcher = Searcher::new();
+    searcher.set_info_sink(Box::new(NullInfoSink));
+    let mut skipped = Vec::new();
+    writeln!(out, "{}", FEATURE_COLUMNS.join(","))?;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match Position::from_sfen(line)
+            .and_then(|position| extract_features(&mut searcher, &position, depth))
+        {
+            Ok(features) => writeln!(out, "{features}")?,
+            Err(err) => skipped.push((index + 1, err)),
+        }
+    }
+    Ok(skipped)
+}
+
+/// `features` サブコマンド。
+///
+/// `features <file> [--depth D] [--out PATH]`
+///
+/// `--out` がなければ標準出力に書く。読めない行は標準エラーに出す。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut depth = DEFAULT_FEATURES_DEPTH;
+    let mut out_path = None;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--depth" => depth = parse_value(flag, iter.next())?,
+            "--out" => out_path = Some(parse_value::<String>(flag, iter.next())?),
+            other if !other.starts_with("--") && path.is_none() => {
+                path = Some(other.to_string());
+            }
+            other => return Err(format!("unknown features option: {other}").into()),
+        }
+    }
+    let path = path.ok_or("features requires a file of SFENs")?;
+    let text = fs::read_to_string(&path)?;
+    let skipped = match out_path {
+        Some(out_path) => {
+            let mut out = io::BufWriter::new(fs::File::create(out_path)?);
+            let skipped = write_features(&text, depth, &mut out)?;
+            out.flush()?;
+            skipped
+        }
+        None => write_features(&text, depth, &mut io::stdout().lock())?,
+    };
+    for (line, err) in skipped {
+        eprintln!("{path}:{line}: {err}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::INITIAL_SFEN;
+
+    #[test]
+    fn every_row_has_the_header_columns() {
+        let text = format!("# corpus\n{INITIAL_SFEN}\nbad\n4k/5/3S1/5/K4 b G 1\n");
+        let mut out = Vec::new();
+        let skipped = write_features(&text, 2, &mut out).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, 3);
+
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], FEATURE_COLUMNS);
+        assert!(rows.iter().all(|row| row.len() == FEATURE_COLUMNS.len()));
+        let column = |row: &[&str], name: &str| {
+            let index = FEATURE_COLUMNS.iter().position(|column| *column == name);
+            row[index.expect("column")].to_string()
+        };
+        assert_eq!(column(&rows[1], "black_mobility"), "14");
+        assert_eq!(column(&rows[1], "white_mobility"), "14");
+        // 1aの玉は2aにだけ逃げられる。
+        assert_eq!(column(&rows[2], "white_king_escapes"), "1");
+        let mate = rows[2].clone();
+        let position = Position::from_sfen(&column(&mate, "sfen")).unwrap();
+        let best = position
+            .parse_usi_move(&column(&mate, "best_move"))
+            .unwrap();
+        assert!(!position.play_move(&best).unwrap().has_legal_move().unwrap());
+        assert_eq!(column(&mate, "best_see"), "0");
+    }
+}
@@ -0,0 +1,389 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::moves::Move;
+use crate::piece::Color;
+use crate::position::{MoveUndo, Position, PositionError};
+use crate::rules::ForcedPromotion;
+
+/// 対局の状態。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameStatus {
+    Ongoing,
+    /// 手番側に合法手がない（詰み）。
+    Mate {
+        winner: Color,
+    },
+    /// 同一局面が4回現れた千日手。5五将棋では先手の負けとなる。
+    RepetitionDraw,
+    /// 一方が王手をかけ続けた千日手。王手をかけ続けた側の負け。
+    PerpetualCheck {
+        loser: Color,
+    },
+}
+
+impl GameStatus {
+    pub fn is_over(self) -> bool {
+        self != Self::Ongoing
+    }
+
+    pub fn winner(self) -> Option<Color> {
+        match self {
+            Self::Ongoing => None,
+            Self::Mate { winner } => Some(winner),
+            Self::RepetitionDraw => Some(Color::White),
+            Self::PerpetualCheck { loser } => Some(loser.opponent()),
+        }
+    }
+}
+
+impl fmt::Display for GameStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let color_name = |color: Color| match color {
+            Color::Black => "black",
+            Color::White => "white",
+        };
+        match *self {
+            Self::Ongoing => write!(f, "ongoing"),
+            Self::Mate { winner } => write!(f, "mate, {} wins", color_name(winner)),
+            Self::RepetitionDraw => write!(f, "repetition, white wins"),
+            Self::PerpetualCheck { loser } => write!(
+                f,
+                "perpetual check by {}, {} wins",
+                color_name(loser),
+                color_name(loser.opponent())
+            ),
+        }
+    }
+}
+
+/// 対局の出来事を受け取る。GUIや記録係は局面を毎回調べる代わりにこれを登録する。
+/// 必要な通知だけを実装すればよい。
+pub trait GameObserver {
+    /// `mv` が指された。`position` は指した後の局面。
+    fn move_played(&mut self, _mv: &Move, _position: &Position) {}
+
+    /// `mv` が取り消された。`position` は取り消した後の局面。
+    fn move_undone(&mut self, _mv: &Move, _position: &Position) {}
+
+    /// `by` が王手をかけた。`move_played` の後に呼ばれる。
+    fn check_given(&mut self, _by: Color, _position: &Position) {}
+
+    /// 指した手で対局が終わった。`move_played` と `check_given` の後に呼ばれる。
+    fn game_over(&mut self, _status: GameStatus) {}
+}
+
+/// `Game::subscribe` が返す登録の識別子。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(usize);
+
+/// 開始局面からの指し手と、各手が王手だったかを記録する対局。
+///
+/// 複製した対局には登録済みの `GameObserver` を引き継がない。
+pub struct Game {
+    position: Position,
+    moves: Vec<Move>,
+    /// `undos[i]` は `i` 手目を戻すための状態。`undo` で使う。
+    undos: Vec<MoveUndo>,
+    /// `keys[i]` は `i` 手目を指した後の局面（`keys[0]` は開始局面）のハッシュ。
+    keys: Vec<u64>,
+    /// `gave_check[i]` は `i` 手目が王手だったか（`gave_check[0]` は未使用）。
+    gave_check: Vec<bool>,
+    observers: Vec<(ObserverId, Box<dyn GameObserver>)>,
+    next_observer: usize,
+}
+
+impl Clone for Game {
+    fn clone(&self) -> Self {
+        Self {
+            position: self.position.clone(),
+            moves: self.moves.clone(),
+            undos: self.undos.clone(),
+            keys: self.keys.clone(),
+            gave_check: self.gave_check.clone(),
+            observers: Vec::new(),
+            next_observer: 0,
+        }
+    }
+}
+
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Game")
+            .field("position", &self.position)
+            .field("moves", &self.moves)
+            .field("observers", &self.observers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Game {
+    pub fn new(start: Position) -> Self {
+        let key = start.zobrist_key();
+        Self {
+            position: start,
+            moves: Vec::new(),
+            undos: Vec::new(),
+            keys: vec![key],
+            gave_check: vec![false],
+            observers: Vec::new(),
+            next_observer: 0,
+        }
+    }
+
+    /// 出来事の通知先を登録する。登録した順に呼ばれる。
+    pub fn subscribe(&mut self, observer: Box<dyn GameObserver>) -> ObserverId {
+        let id = ObserverId(self.next_observer);
+        self.next_observer += 1;
+        self.observers.push((id, observer));
+        id
+    }
+
+    /// 登録を外し、その通知先を返す。
+    pub fn unsubscribe(&mut self, id: ObserverId) -> Option<Box<dyn GameObserver>> {
+        let index = self
+            .observers
+            .iter()
+            .position(|(observer_id, _)| *observer_id == id)?;
+        Some(self.observers.remove(index).1)
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// 合法手であることを確かめてから指す。
+    pub fn play(&mut self, mv: &Move) -> Result<(), PositionError> {
+        if !self.position.generate_legal_moves()?.contains(mv) {
+            return Err(PositionError::message(format!(
+                "illegal move: {}",
+                mv.to_usi()
+            )));
+        }
+        self.play_legal(mv)
+    }
+
+    /// 合法と分かっている手 `mv` を指す。
+    fn play_legal(&mut self, mv: &Move) -> Result<(), PositionError> {
+        let undo = self.position.make_move(mv)?;
+        self.undos.push(undo);
+        self.moves.push(*mv);
+        self.keys.push(self.position.zobrist_key());
+        self.gave_check.push(self.position.in_check());
+        self.notify_played(mv)
+    }
+
+    fn notify_played(&mut self, mv: &Move) -> Result<(), PositionError> {
+        if self.observers.is_empty() {
+            return Ok(());
+        }
+        let status = self.status()?;
+        let mover = self.position.side_to_move().opponent();
+        let check = self.position.in_check();
+        for (_, observer) in &mut self.observers {
+            observer.move_played(mv, &self.position);
+            if check {
+                observer.check_given(mover, &self.position);
+            }
+            if status.is_over() {
+                observer.game_over(status);
+            }
+        }
+        Ok(())
+    }
+
+    /// 最後の手を取り消し、その手を返す。開始局面なら `None`。
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv = self.moves.pop()?;
+        let undo = self.undos.pop().expect("an undo for every move");
+        self.position.unmake_move(undo);
+        self.keys.pop();
+        self.gave_check.pop();
+        for (_, observer) in &mut self.observers {
+            observer.move_undone(&mv, &self.position);
+        }
+        Some(mv)
+    }
+
+    /// USI形式の指し手を指す。
+    pub fn play_usi(&mut self, token: &str) -> Result<Move, PositionError> {
+        self.play_usi_with(token, ForcedPromotion::Reject)
+    }
+
+    /// `play_usi` に、成りが強制される手を `+` なしで書かれたときの扱いを指定する。
+    pub fn play_usi_with(
+        &mut self,
+        token: &str,
+        policy: ForcedPromotion,
+    ) -> Result<Move, PositionError> {
+        // 読めた手は合法手の一覧から選ばれているので、指すときに合法手を作り直さない。
+        let mv = self.position.parse_usi_move_with(token, policy)?;
+        self.play_legal(&mv)?;
+        Ok(mv)
+    }
+
+    pub fn status(&self) -> Result<GameStatus, PositionError> {
+        if self.position.is_repetition() {
+            return Ok(self.repetition_status());
+        }
+        if !self.position.has_legal_move()? {
+            return Ok(GameStatus::Mate {
+                winner: self.position.side_to_move().opponent(),
+            });
+        }
+        Ok(GameStatus::Ongoing)
+    }
+
+    /// 千日手が連続王手によるものかを判定する。同一局面が最初に現れてから後の
+    /// 一方の指し手がすべて王手なら、その側の負けとする。
+    fn repetition_status(&self) -> GameStatus {
+        let current = *self.keys.last().expect("keys is never empty");
+        let first = self
+            .keys
+            .iter()
+            .position(|&key| key == current)
+            .unwrap_or(0);
+        let last = self.keys.len() - 1;
+        if first == last {
+            return GameStatus::RepetitionDraw;
+        }
+        let to_move = self.position.side_to_move();
+        for checker in [to_move.opponent(), to_move] {
+            // `last` 手目を指したのは `to_move.opponent()`。そこから1手おきにたどる。
+            let offset = if checker == to_move.opponent() { 0 } else { 1 };
+            let mut plies = (first + 1..=last).rev().skip(offset).step_by(2).peekable();
+            if plies.peek().is_some() && plies.all(|ply| self.gave_check[ply]) {
+                return GameStatus::PerpetualCheck { loser: checker };
+            }
+        }
+        GameStatus::RepetitionDraw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::INITIAL_SFEN;
+
+    fn play_all(game: &mut Game, moves: &[&str]) {
+        for token in moves {
+            game.play_usi(token).expect("legal move");
+        }
+    }
+
+    use alloc::rc::Rc;
+    use alloc::string::String;
+    use core::cell::RefCell;
+
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl GameObserver for Recorder {
+        fn move_played(&mut self, mv: &Move, _position: &Position) {
+            self.0.borrow_mut().push(format!("played {}", mv.to_usi()));
+        }
+
+        fn move_undone(&mut self, mv: &Move, _position: &Position) {
+            self.0.borrow_mut().push(format!("undone {}", mv.to_usi()));
+        }
+
+        fn check_given(&mut self, by: Color, _position: &Position) {
+            self.0.borrow_mut().push(format!("check by {by:?}"));
+        }
+
+        fn game_over(&mut self, status: GameStatus) {
+            self.0.borrow_mut().push(format!("over: {status}"));
+        }
+    }
+
+    #[test]
+    fn undo_restores_the_repetition_history() {
+        let cycle = ["1e1d", "5a5b", "1d1e", "5b5a"];
+        let mut game = Game::new(Position::from_sfen(INITIAL_SFEN).unwrap());
+        play_all(&mut game, &["5d5c", "1b1c"]);
+        for _ in 0..3 {
+            play_all(&mut game, &cycle);
+        }
+        assert!(game.position().is_repetition());
+        for _ in 0..cycle.len() + 1 {
+            game.undo();
+        }
+        let mut replayed = Position::from_sfen(INITIAL_SFEN).unwrap();
+        for mv in game.moves() {
+            replayed.play_move_mut(mv).unwrap();
+        }
+        assert_eq!(game.position().zobrist_key(), replayed.zobrist_key());
+        assert_eq!(
+            game.position().current_repetition_count(),
+            replayed.current_repetition_count()
+        );
+        play_all(&mut game, &["5b5a"]);
+        assert!(!game.position().is_repetition());
+        play_all(&mut game, &cycle);
+        assert!(game.position().is_repetition());
+    }
+
+    #[test]
+    fn observers_hear_moves_undos_checks_and_the_result() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut game = Game::new(Position::from_sfen("4k/5/3S1/5/K4 b G 1").unwrap());
+        let id = game.subscribe(Box::new(Recorder(Rc::clone(&events))));
+        play_all(&mut game, &["G*3a"]);
+        assert_eq!(game.undo().map(|mv| mv.to_usi()), Some("G*3a".into()));
+        assert_eq!(game.position().to_sfen(), "4k/5/3S1/5/K4 b G 1");
+        play_all(&mut game, &["G*1b"]);
+        assert!(game.unsubscribe(id).is_some());
+        game.undo();
+        assert_eq!(
+            *events.borrow(),
+            [
+                "played G*3a",
+                "over: mate, black wins",
+                "undone G*3a",
+                "played G*1b",
+                "check by Black",
+                "over: mate, black wins",
+            ]
+        );
+    }
+
+    #[test]
+    fn quiet_shuffle_is_repetition() {
+        let mut game = Game::new(Position::from_sfen(INITIAL_SFEN).unwrap());
+        let cycle = ["1e1d", "5a5b", "1d1e", "5b5a"];
+        for _ in 0..2 {
+            play_all(&mut game, &cycle);
+            assert_eq!(game.status().unwrap(), GameStatus::Ongoing);
+        }
+        // 開始局面から数えて4回目の同一局面。
+        play_all(&mut game, &cycle[..3]);
+        assert_eq!(game.status().unwrap(), GameStatus::Ongoing);
+        play_all(&mut game, &cycle[3..]);
+        assert_eq!(game.status().unwrap(), GameStatus::RepetitionDraw);
+        assert_eq!(game.status().unwrap().winner(), Some(Color::White));
+    }
+
+    #[test]
+    fn repeated_checks_are_perpetual_check() {
+        // 先手の飛車が5筋と4筋を往復して王手をかけ続け、後手玉が逃げ続ける。
+        let start = Position::from_sfen("k4/5/2R2/5/4K b - 1").unwrap();
+        let mut game = Game::new(start);
+        play_all(&mut game, &["3c5c"]);
+        let cycle = ["5a4a", "5c4c", "4a5a", "4c5c"];
+        for _ in 0..3 {
+            play_all(&mut game, &cycle);
+        }
+        assert_eq!(
+            game.status().unwrap(),
+            GameStatus::PerpetualCheck {
+                loser: Color::Black
+            }
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use alloc::string::{String, ToString};
+
 use crate::piece::PieceKind;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
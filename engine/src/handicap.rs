@@ -0,0 +1,89 @@
+use crate::evaluation::piece_material_value;
+use crate::piece::PieceKind;
+use crate::position::Position;
+
+/// 駒落ちの手合い。上手（後手）が駒を落とし、上手から指す。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handicap {
+    Rook,
+    Bishop,
+    RookBishop,
+}
+
+impl Handicap {
+    pub const ALL: [Self; 3] = [Self::Rook, Self::Bishop, Self::RookBishop];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rook => "rook",
+            Self::Bishop => "bishop",
+            Self::RookBishop => "rook-bishop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|handicap| handicap.name() == name)
+    }
+
+    /// 開始局面のSFEN。
+    pub fn sfen(self) -> &'static str {
+        match self {
+            Self::Rook => "1bsgk/4p/5/P4/KGSBR w - 1",
+            Self::Bishop => "r1sgk/4p/5/P4/KGSBR w - 1",
+            Self::RookBishop => "2sgk/4p/5/P4/KGSBR w - 1",
+        }
+    }
+
+    /// 上手が落とした駒。
+    pub fn removed(self) -> &'static [PieceKind] {
+        match self {
+            Self::Rook => &[PieceKind::Rook],
+            Self::Bishop => &[PieceKind::Bishop],
+            Self::RookBishop => &[PieceKind::Rook, PieceKind::Bishop],
+        }
+    }
+
+    /// 落とした駒の分だけ先手に傾いた、開始局面の駒割り。評価関数はこれを0とみなす。
+    pub fn material_baseline(self) -> i32 {
+        self.removed()
+            .iter()
+            .map(|&kind| piece_material_value(kind))
+            .sum()
+    }
+
+    /// 盤と持ち駒が駒落ちの開始局面と同じなら、その手合い。手番と手数は比べない。
+    pub fn detect(position: &Position) -> Option<Self> {
+        let sfen = position.to_sfen();
+        let target = board_and_hands(&sfen);
+        Self::ALL
+            .into_iter()
+            .find(|handicap| board_and_hands(handicap.sfen()) == target)
+    }
+}
+
+/// SFENの盤と持ち駒の欄。
+fn board_and_hands(sfen: &str) -> (Option<&str>, Option<&str>) {
+    let mut fields = sfen.split(' ');
+    (fields.next(), fields.nth(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_detected_from_their_start_positions() {
+        for handicap in Handicap::ALL {
+            let position = Position::from_sfen(handicap.sfen()).unwrap();
+            assert_eq!(Handicap::detect(&position), Some(handicap));
+            assert_eq!(Handicap::from_name(handicap.name()), Some(handicap));
+        }
+        assert_eq!(Handicap::detect(&Position::initial().unwrap()), None);
+        assert_eq!(
+            Handicap::RookBishop.material_baseline(),
+            Handicap::Rook.material_baseline() + Handicap::Bishop.material_baseline()
+        );
+    }
+}
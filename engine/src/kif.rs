@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use crate::blunder::{MoveAnnotation, MoveClassification};
+use crate::board::{BOARD_FILES, Square};
+use crate::hand::HandPieceKind;
+use crate::match_runner::{GameRecord, Termination};
+use crate::moves::Move;
+use crate::piece::{Color, PieceKind};
+use crate::position::{INITIAL_SFEN, Position, PositionError};
+use crate::rules::promotion_zone_mask;
+use crate::score::RelScore;
+
+const FILE_DIGITS: [char; 5] = ['１', '２', '３', '４', '５'];
+const RANK_KANJI: [char; 5] = ['一', '二', '三', '四', '五'];
+const COUNT_KANJI: [&str; 7] = ["", "", "二", "三", "四", "五", "六"];
+
+/// KIFで使う駒の名前。
+pub fn piece_name(kind: PieceKind) -> &'static str {
+    match kind {
+        PieceKind::King => "玉",
+        PieceKind::Gold => "金",
+        PieceKind::Silver => "銀",
+        PieceKind::PromotedSilver => "成銀",
+        PieceKind::Bishop => "角",
+        PieceKind::PromotedBishop => "馬",
+        PieceKind::Rook => "飛",
+        PieceKind::PromotedRook => "龍",
+        PieceKind::Pawn => "歩",
+        PieceKind::Tokin => "と",
+    }
+}
+
+/// 盤面図で使う1文字の駒の名前。
+fn piece_char(kind: PieceKind) -> char {
+    match kind {
+        PieceKind::King => '玉',
+        PieceKind::Gold => '金',
+        PieceKind::Silver => '銀',
+        PieceKind::PromotedSilver => '全',
+        PieceKind::Bishop => '角',
+        PieceKind::PromotedBishop => '馬',
+        PieceKind::Rook => '飛',
+        PieceKind::PromotedRook => '龍',
+        PieceKind::Pawn => '歩',
+        PieceKind::Tokin => 'と',
+    }
+}
+
+/// 移動先の表記（例: ２四）。
+pub fn square_name(square: Square) -> String {
+    format!(
+        "{}{}",
+        FILE_DIGITS[square.file() as usize],
+        RANK_KANJI[square.rank() as usize]
+    )
+}
+
+/// 1手をKIFの指し手表記にする。`previous_to` が移動先と同じなら「同」を使う。
+/// `position` は指す前の局面。
+pub fn move_to_kif(position: &Position, mv: &Move, previous_to: Option<Square>) -> String {
+    let mut text = if previous_to == Some(mv.to) {
+        "同　".to_string()
+    } else {
+        square_name(mv.to)
+    };
+    text.push_str(piece_name(mv.piece));
+    match mv.from {
+        None => text.push('打'),
+        Some(from) => {
+            if mv.promote {
+                text.push('成');
+            } else if mv.piece.can_promote() && can_promote_on(position, mv, from) {
+                text.push_str("不成");
+            }
+            text.push_str(&format!("({}{})", from.file() + 1, from.rank() + 1));
+        }
+    }
+    text
+}
+
+fn can_promote_on(position: &Position, mv: &Move, from: Square) -> bool {
+    let zone = promotion_zone_mask(position.side_to_move());
+    zone.contains(from) || zone.contains(mv.to)
+}
+
+fn hand_text(position: &Position, color: Color) -> String {
+    let hand = position.hand(color);
+    let mut parts = Vec::new();
+    for kind in HandPieceKind::all() {
+        let count = hand.count(kind) as usize;
+        if count == 0 {
+            continue;
+        }
+        let name = piece_name(hand_kind_to_piece(kind));
+        parts.push(format!(
+            "{}{}",
+            name,
+            COUNT_KANJI[count.min(COUNT_KANJI.len() - 1)]
+        ));
+    }
+    if parts.is_empty() {
+        "なし".to_string()
+    } else {
+        parts.join("　")
+    }
+}
+
+fn hand_kind_to_piece(kind: HandPieceKind) -> PieceKind {
+    match kind {
+        HandPieceKind::Gold => PieceKind::Gold,
+        HandPieceKind::Silver => PieceKind::Silver,
+        HandPieceKind::Bishop => PieceKind::Bishop,
+        HandPieceKind::Rook => PieceKind::Rook,
+        HandPieceKind::Pawn => PieceKind::Pawn,
+    }
+}
+
+/// 局面をKIFの盤面図（BOD形式）で表す。
+pub fn board_diagram(position: &Position) -> String {
+    let mut lines = Vec::new();
+    lines.push(format!("後手の持駒：{}", hand_text(position, Color::White)));
+    let header: String = (0..BOARD_FILES)
+        .rev()
+        .map(|file| format!("  {}", FILE_DIGITS[file]))
+        .collect();
+    lines.push(header);
+    lines.push(format!("+{}+", "-".repeat(BOARD_FILES * 3)));
+    for (rank, rank_kanji) in RANK_KANJI.iter().enumerate() {
+        let mut row = String::from("|");
+        for file in (0..BOARD_FILES).rev() {
+            let square = Square::from_file_rank(file as u8, rank as u8);
+            match position.piece_at(square) {
+                Some(piece) => {
+                    row.push(if piece.color == Color::Black {
+                        ' '
+                    } else {
+                        'v'
+                    });
+                    row.push(piece_char(piece.kind));
+                }
+                None => row.push_str(" ・"),
+            }
+        }
+        row.push('|');
+        row.push(*rank_kanji);
+        lines.push(row);
+    }
+    lines.push(format!("+{}+", "-".repeat(BOARD_FILES * 3)));
+    lines.push(format!("先手の持駒：{}", hand_text(position, Color::Black)));
+    if position.side_to_move() == Color::White {
+        lines.push("後手番".to_string());
+    }
+    lines.join("\n")
+}
+
+/// 終了理由をKIFの終局表記にする。
+fn termination_text(termination: Termination) -> &'static str {
+    match termination {
+        Termination::NoLegalMoves => "詰み",
+        Termination::Resignation => "投了",
+        Termination::Repetition => "千日手",
+        Termination::PerpetualCheck => "連続王手の千日手",
+        Termination::MaxPlies => "中断",
+        Termination::EnteringKing | Termination::Declaration => "入玉勝ち",
+        Termination::IllegalDeclaration => "反則負け",
+    }
+}
+
+/// 対局を指し手番号付きのKIF（UTF-8）にする。
+pub fn game_to_kif(
+    record: &GameRecord,
+    black_name: &str,
+    white_name: &str,
+) -> Result<String, PositionError> {
+    annotated_game_to_kif(record, black_name, white_name, &[])
+}
+
+/// 解析結果をコメントと変化手順として書き込んだKIFにする。`annotations` は
+/// 指し手と同じ順に並べ、足りない手には何も付けない。
+pub fn annotated_game_to_kif(
+    record: &GameRecord,
+    black_name: &str,
+    white_name: &str,
+    annotations: &[MoveAnnotation],
+) -> Result<String, PositionError> {
+    let mut position = Position::from_sfen(&record.start_sfen)?;
+    let mut out = String::new();
+    out.push_str("# KIF形式棋譜ファイル\n");
+    if record.start_sfen == INITIAL_SFEN {
+        out.push_str("手合割：５五将棋\n");
+    } else {
+        out.push_str(&board_diagram(&position));
+        out.push('\n');
+    }
+    out.push_str(&format!("先手：{black_name}\n"));
+    out.push_str(&format!("後手：{white_name}\n"));
+    out.push_str("手数----指手---------消費時間--\n");
+
+    // 変化手順は分岐する手数の大きい順に書く。読み込む側は直前に読んだ手順から
+    // 分岐元を探すので、こうすると常に本譜から分岐する。
+    let mut variations = Vec::new();
+    let mut previous_to = None;
+    let mut totals = [Duration::ZERO; 2];
+    let timed = record.move_times.len() == record.moves.len();
+    for (idx, mv) in record.moves.iter().enumerate() {
+        let annotation = annotations.get(idx);
+        let branches = annotation.is_some_and(|annotation| !annotation.variation.is_empty());
+        let time = if timed {
+            let spent = record.move_times[idx];
+            let total = &mut totals[position.side_to_move().index()];
+            *total += spent;
+            time_text(spent, *total)
+        } else {
+            String::new()
+        };
+        out.push_str(&format!(
+            "{:>4} {}{time}{}\n",
+            idx + 1,
+            move_to_kif(&position, mv, previous_to),
+            if branches { "+" } else { "" }
+        ));
+        if let Some(annotation) = annotation {
+            out.push_str(&annotation_comment(&position, annotation));
+            if branches {
+                variations.push(variation_text(
+                    &position,
+                    idx + 1,
+                    &annotation.variation,
+                    previous_to,
+                )?);
+            }
+        }
+        position.play_move_mut(mv)?;
+        previous_to = Some(mv.to);
+    }
+    out.push_str(&format!(
+        "{:>4} {}\n",
+        record.moves.len() + 1,
+        termination_text(record.termination)
+    ));
+    match record.winner {
+        Some(color) => out.push_str(&format!(
+            "まで{}手で{}の勝ち\n",
+            record.moves.len(),
+            if color == Color::Black {
+                "先手"
+            } else {
+                "後手"
+            }
+        )),
+        None => out.push_str(&format!("まで{}手で中断\n", record.moves.len())),
+    }
+    for variation in variations.iter().rev() {
+        out.push('\n');
+        out.push_str(variation);
+    }
+    Ok(out)
+}
+
+/// 消費時間の欄（例: `   ( 0:03/00:01:12)`）。1手の時間と、その側の累計を秒単位で書く。
+fn time_text(spent: Duration, total: Duration) -> String {
+    let spent = spent.as_secs_f64().round() as u64;
+    let total = total.as_secs_f64().round() as u64;
+    format!(
+        "   ({:>2}:{:02}/{:02}:{:02}:{:02})",
+        spent / 60,
+        spent % 60,
+        total / 3600,
+        total / 60 % 60,
+        total % 60
+    )
+}
+
+/// 先手から見た評価値の表記（例: +120）。
+fn score_text(position: &Position, score: RelScore) -> String {
+    format!("{:+}", score.absolute_from(position.side_to_move()).value())
+}
+
+fn classification_text(classification: MoveClassification) -> Option<&'static str> {
+    match classification {
+        MoveClassification::Ok => None,
+        MoveClassification::Inaccuracy => Some("疑問手"),
+        MoveClassification::Mistake => Some("悪手"),
+        MoveClassification::Blunder => Some("大悪手"),
+    }
+}
+
+/// 指し手の直後に置くコメント行。`position` は指す前の局面。
+fn annotation_comment(position: &Position, annotation: &MoveAnnotation) -> String {
+    let report = &annotation.report;
+    let mut out = format!("*評価値 {}\n", score_text(position, report.played_score));
+    if report.best_move != report.played_move {
+        out.push_str(&format!(
+            "*最善 {} {}",
+            move_to_kif(position, &report.best_move, None),
+            score_text(position, report.best_score)
+        ));
+        if let Some(label) = classification_text(report.classification) {
+            out.push_str(&format!(" {label}"));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `number` 手目から分岐する変化手順。`position` は分岐する手を指す前の局面。
+fn variation_text(
+    position: &Position,
+    number: usize,
+    moves: &[Move],
+    mut previous_to: Option<Square>,
+) -> Result<String, PositionError> {
+    let mut position = position.clone();
+    let mut out = format!("変化：{number}手\n");
+    for (offset, mv) in moves.iter().enumerate() {
+        out.push_str(&format!(
+            "{:>4} {}\n",
+            number + offset,
+            move_to_kif(&position, mv, previous_to)
+        ));
+        position.play_move_mut(mv)?;
+        previous_to = Some(mv.to);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_moves_with_origin_and_recapture() {
+        let position = Position::initial().expect("initial");
+        let mv = Move::normal(
+            Square::from_coord("5e").unwrap(),
+            Square::from_coord("4d").unwrap(),
+            PieceKind::King,
+            false,
+        );
+        assert_eq!(move_to_kif(&position, &mv, None), "４四玉(55)");
+        assert_eq!(move_to_kif(&position, &mv, Some(mv.to)), "同　玉(55)");
+        let drop = Move::drop(Square::from_coord("3c").unwrap(), PieceKind::Pawn);
+        assert_eq!(move_to_kif(&position, &drop, None), "３三歩打");
+    }
+
+    #[test]
+    fn annotated_kif_branches_from_the_main_line() {
+        let start = Position::from_sfen("4k/5/3S1/5/K4 b G 1").expect("sfen");
+        let quiet = Move::drop(Square::from_coord("5a").unwrap(), PieceKind::Gold);
+        let record = GameRecord {
+            start_sfen: start.to_sfen(),
+            moves: vec![quiet],
+            move_times: vec![Duration::from_millis(2_600)],
+            winner: None,
+            termination: Termination::MaxPlies,
+        };
+        let mut engine = crate::facade::Engine::new();
+        let limits = crate::search::SearchLimits {
+            depth: 1,
+            ..crate::search::SearchLimits::default()
+        };
+        let annotations = engine
+            .annotate_game(&start, &record.moves, limits)
+            .expect("annotate");
+        let kif = annotated_game_to_kif(&record, "a", "b", &annotations).expect("kif");
+        assert!(kif.contains("   1 ５一金打   ( 0:03/00:00:03)+\n*評価値 "));
+        assert!(kif.contains("大悪手\n"));
+        assert!(kif.contains("\n変化：1手\n   1 "));
+    }
+}
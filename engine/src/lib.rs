@@ -1,18 +1,99 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod analysis;
 pub mod attacks;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "match-runner")]
+pub mod bench;
 pub mod bitboard;
+#[cfg(feature = "std")]
+pub mod blunder;
 pub mod board;
+pub mod build_info;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod eval_file;
 pub mod evaluation;
+#[cfg(feature = "std")]
+pub mod experience;
+#[cfg(feature = "match-runner")]
+pub mod explorer;
+#[cfg(feature = "std")]
+pub mod facade;
+#[cfg(feature = "match-runner")]
+pub mod features;
+pub mod game;
 pub mod hand;
+pub mod handicap;
+#[cfg(feature = "match-runner")]
+pub mod kif;
+pub mod locale;
+#[cfg(feature = "match-runner")]
+pub mod match_runner;
+#[cfg(feature = "match-runner")]
+pub mod match_stats;
+pub mod material;
 pub mod moves;
+#[cfg(feature = "std")]
+pub mod options;
+#[cfg(feature = "match-runner")]
+pub mod pgn;
 pub mod piece;
+#[cfg(feature = "match-runner")]
+pub mod play;
 pub mod position;
+#[cfg(feature = "match-runner")]
+pub mod puzzle;
+pub mod query_cache;
+#[cfg(feature = "std")]
+pub mod reference_movegen;
+#[cfg(all(test, feature = "std"))]
+mod regression;
+#[cfg(feature = "match-runner")]
+pub mod relay;
+#[cfg(feature = "std")]
+pub mod root_split;
+pub mod rules;
+pub mod score;
+#[cfg(feature = "std")]
 pub mod search;
+pub mod see;
+#[cfg(feature = "std")]
+pub mod selftest;
+#[cfg(feature = "match-runner")]
+pub mod sfen_stats;
+pub mod stable_hash;
+#[cfg(feature = "std")]
 pub mod table;
+#[cfg(feature = "match-runner")]
+pub mod tournament;
+pub mod trace;
+#[cfg(feature = "match-runner")]
+pub mod tune;
+#[cfg(feature = "usi")]
 pub mod usi;
+#[cfg(feature = "match-runner")]
+pub mod verify;
+#[cfg(feature = "std")]
+pub mod wire;
 pub mod zobrist;
 
 pub use board::Square;
+pub use build_info::engine_info;
+pub use evaluation::{Evaluator, HandcraftedEval};
+#[cfg(feature = "std")]
+pub use facade::Engine;
 pub use moves::{Move, MoveList};
 pub use piece::{Color, Piece, PieceKind};
 pub use position::Position;
-pub use search::{SearchLimits, SearchResult, Searcher};
+pub use score::{AbsScore, RelScore};
+#[cfg(feature = "std")]
+pub use search::{
+    InfoSink, SearchBackend, SearchInfo, SearchLimits, SearchParams, SearchResult, Searcher,
+    StopToken,
+};
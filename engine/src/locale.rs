@@ -0,0 +1,120 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use crate::game::GameStatus;
+use crate::piece::Color;
+
+/// 人が読む出力の言語。USIのコマンドや指し手の文字列そのものは変えない。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    English,
+    /// 盤面図と指し手をKIFと同じ表記で書く。
+    Japanese,
+}
+
+/// 出力に使う決まり文句。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    GameOver,
+    PositionError,
+    LegalMovesError,
+    GoError,
+    SetOptionError,
+    UnknownCommand,
+    InternalError,
+    EnginePlays,
+    EngineResigns,
+    Hint,
+}
+
+impl Locale {
+    pub const ALL: [Self; 2] = [Self::English, Self::Japanese];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::Japanese => "ja",
+        }
+    }
+
+    /// `en`、`ja` のほか `english`、`japanese` も受け付ける。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "en" | "english" => Some(Self::English),
+            "ja" | "japanese" => Some(Self::Japanese),
+            _ => None,
+        }
+    }
+
+    pub fn text(self, message: Message) -> &'static str {
+        match (self, message) {
+            (Self::English, Message::GameOver) => "game over",
+            (Self::English, Message::PositionError) => "position error",
+            (Self::English, Message::LegalMovesError) => "legalmoves error",
+            (Self::English, Message::GoError) => "go error",
+            (Self::English, Message::SetOptionError) => "setoption error",
+            (Self::English, Message::UnknownCommand) => "unknown command",
+            (Self::English, Message::InternalError) => "internal error",
+            (Self::English, Message::EnginePlays) => "engine plays",
+            (Self::English, Message::EngineResigns) => "engine resigns",
+            (Self::English, Message::Hint) => "hint",
+            (Self::Japanese, Message::GameOver) => "終局",
+            (Self::Japanese, Message::PositionError) => "局面を設定できません",
+            (Self::Japanese, Message::LegalMovesError) => "合法手を生成できません",
+            (Self::Japanese, Message::GoError) => "探索できません",
+            (Self::Japanese, Message::SetOptionError) => "オプションを設定できません",
+            (Self::Japanese, Message::UnknownCommand) => "不明なコマンド",
+            (Self::Japanese, Message::InternalError) => "内部エラー",
+            (Self::Japanese, Message::EnginePlays) => "エンジンの指し手",
+            (Self::Japanese, Message::EngineResigns) => "エンジンの投了",
+            (Self::Japanese, Message::Hint) => "ヒント",
+        }
+    }
+
+    pub fn color_name(self, color: Color) -> &'static str {
+        match (self, color) {
+            (Self::English, Color::Black) => "black",
+            (Self::English, Color::White) => "white",
+            (Self::Japanese, Color::Black) => "先手",
+            (Self::Japanese, Color::White) => "後手",
+        }
+    }
+
+    /// 対局の状態を文で表す。英語は `GameStatus` の `Display` と同じ。
+    pub fn game_status(self, status: GameStatus) -> String {
+        if self == Self::English {
+            return status.to_string();
+        }
+        match status {
+            GameStatus::Ongoing => "対局中".to_string(),
+            GameStatus::Mate { winner } => format!("詰み、{}の勝ち", self.color_name(winner)),
+            GameStatus::RepetitionDraw => "千日手、後手の勝ち".to_string(),
+            GameStatus::PerpetualCheck { loser } => format!(
+                "{}の連続王手の千日手、{}の勝ち",
+                self.color_name(loser),
+                self.color_name(loser.opponent())
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn japanese_names_and_statuses() {
+        assert_eq!(Locale::from_name("JA"), Some(Locale::Japanese));
+        assert_eq!(Locale::from_name("fr"), None);
+        let status = GameStatus::PerpetualCheck {
+            loser: Color::Black,
+        };
+        assert_eq!(
+            Locale::Japanese.game_status(status),
+            "先手の連続王手の千日手、後手の勝ち"
+        );
+        assert_eq!(Locale::English.game_status(status), status.to_string());
+        assert_eq!(Locale::Japanese.text(Message::GameOver), "終局");
+    }
+}
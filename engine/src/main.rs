@@ -1,5 +1,34 @@
 fn main() {
-    if let Err(err) = engine::usi::run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        #[cfg(feature = "std")]
+        Some("analyze") => engine::wire::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("bench") => engine::bench::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("explorer") => engine::explorer::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("features") => engine::features::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("match") => engine::match_runner::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("play") => engine::play::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("puzzles") => engine::puzzle::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("relay") => engine::relay::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("tournament") => engine::tournament::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("sfenstats") => engine::sfen_stats::run_cli(&args[1..]),
+        Some("selftest") => engine::selftest::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("tune-pst") => engine::tune::run_cli(&args[1..]),
+        #[cfg(feature = "match-runner")]
+        Some("verify") => engine::verify::run_cli(&args[1..]),
+        _ => engine::usi::run_cli(&args),
+    };
+    if let Err(err) = result {
         eprintln!("error: {err}");
     }
 }
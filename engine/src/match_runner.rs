@@ -0,0 +1,963 @@
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::build_info::engine_info;
+use crate::evaluation::EvalParams;
+use crate::game::{Game, GameStatus};
+use crate::match_stats::{MatchStats, Outcome};
+use crate::moves::Move;
+use crate::piece::Color;
+use crate::position::{INITIAL_SFEN, Position, PositionError};
+use crate::search::{NullInfoSink, SearchLimits, Searcher, SimpleRng};
+
+/// 対局に参加するエンジンの種類。
+#[derive(Clone, Debug)]
+pub enum EngineKind {
+    /// このクレートの探索器。
+    Internal(SearchLimits),
+    /// USIで通信する外部エンジン。`go` は毎手送るコマンド（例: `go byoyomi 1000`）。
+    External {
+        program: String,
+        args: Vec<String>,
+        go: String,
+    },
+}
+
+/// 対局に参加するエンジンの設定。
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    pub name: String,
+    pub kind: EngineKind,
+}
+
+impl EngineConfig {
+    pub fn internal(name: impl Into<String>, limits: SearchLimits) -> Self {
+        Self {
+            name: name.into(),
+            kind: EngineKind::Internal(limits),
+        }
+    }
+
+    /// 設定から対局者を作る。外部エンジンはここで起動してUSIの初期化まで済ませる。
+    pub fn create_player(&self) -> Result<Box<dyn Player>, Box<dyn Error>> {
+        match &self.kind {
+            EngineKind::Internal(limits) => {
+                Ok(Box::new(SearcherPlayer::new(self.name.clone(), *limits)))
+            }
+            EngineKind::External { program, args, go } => Ok(Box::new(UsiPlayer::spawn(
+                self.name.clone(),
+                program,
+                args,
+                go.clone(),
+            )?)),
+        }
+    }
+}
+
+/// 手番の色ごとに対局者の設定を上書きする。`None` の項目は対局者の設定のまま使う。
+/// 同じエンジンを先手と後手で違う強さにして、段階的な練習相手や非対称な棋譜を作る。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColorLimits {
+    pub depth: Option<usize>,
+    pub randomness: Option<i32>,
+    /// 外部エンジンに毎手送る `go` コマンド（例: `go byoyomi 500`）。
+    pub go: Option<String>,
+}
+
+impl ColorLimits {
+    /// 内部の探索器の制限に上書きを当てる。
+    pub fn apply(&self, limits: SearchLimits) -> SearchLimits {
+        SearchLimits {
+            depth: self.depth.unwrap_or(limits.depth),
+            randomness: self.randomness.unwrap_or(limits.randomness),
+            ..limits
+        }
+    }
+
+    /// USIで通信する外部エンジンに毎手送る `go` コマンド。`depth` は `go depth D` にする。
+    /// 上書きがなければ `None`。外部エンジンには乱択を指示できないのでエラーにする。
+    pub fn usi_go(&self) -> Result<Option<String>, String> {
+        if self.randomness.is_some() {
+            return Err("randomness cannot be set for a USI engine".to_string());
+        }
+        match (&self.go, self.depth) {
+            (Some(_), Some(_)) => Err("give either a depth or a go command, not both".to_string()),
+            (Some(go), None) => Ok(Some(go.clone())),
+            (None, Some(depth)) => Ok(Some(format!("go depth {depth}"))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// `--black-depth` など色ごとの上書きのフラグなら、値を読んで `limits` に入れて `true` を返す。
+/// フラグは `--black-` か `--white-` に `depth`・`random`・`go` を続けたもの。
+pub(crate) fn parse_color_limit<'a>(
+    flag: &str,
+    values: &mut impl Iterator<Item = &'a String>,
+    limits: &mut [ColorLimits; 2],
+) -> Result<bool, String> {
+    let (color, name) = if let Some(name) = flag.strip_prefix("--black-") {
+        (Color::Black, name)
+    } else if let Some(name) = flag.strip_prefix("--white-") {
+        (Color::White, name)
+    } else {
+        return Ok(false);
+    };
+    let limits = &mut limits[color.index()];
+    match name {
+        "depth" => limits.depth = Some(parse_value(flag, values.next())?),
+        "random" => limits.randomness = Some(parse_value(flag, values.next())?),
+        "go" => limits.go = Some(parse_value(flag, values.next())?),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// 対局者が手番で返す応答。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayerAction {
+    Move(Move),
+    Resign,
+    /// 入玉宣言（USIの `bestmove win`）。条件を満たしていなければ宣言した側の負け。
+    DeclareWin,
+}
+
+/// 対局で指し手を選ぶ主体。
+pub trait Player {
+    fn name(&self) -> &str;
+
+    /// 新しい対局の開始前に呼ばれる。
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// 対局が終わったときに、この対局者から見た結果とともに呼ばれる。
+    fn game_over(&mut self, _outcome: Outcome) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// 次の対局でこの対局者が持つ色の上書き。対局ごとに `new_game` の前に呼ばれる。
+    /// 当てられない上書きはエラーにする。
+    fn set_color_limits(&mut self, _limits: &ColorLimits) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// 開始局面と指し手列から決まる `position` での応答を返す。
+    fn choose_move(
+        &mut self,
+        start_sfen: &str,
+        moves: &[Move],
+        position: &Position,
+    ) -> Result<PlayerAction, Box<dyn Error>>;
+}
+
+/// このクレートの探索器で指す対局者。
+pub struct SearcherPlayer {
+    name: String,
+    searcher: Searcher,
+    limits: SearchLimits,
+    /// 今の対局の色の上書きを当てた制限。
+    active: SearchLimits,
+    /// 始めた対局の数。評価値の揺らぎのシードを対局ごとに変えるのに使う。
+    games: u64,
+}
+
+impl SearcherPlayer {
+    pub fn new(name: String, limits: SearchLimits) -> Self {
+        let mut searcher = Searcher::new();
+        searcher.set_info_sink(Box::new(NullInfoSink));
+        Self {
+            name,
+            searcher,
+            limits,
+            active: limits,
+            games: 0,
+        }
+    }
+
+    /// 探索器の評価関数のパラメータを差し替える。
+    pub fn set_eval_params(&mut self, params: EvalParams) {
+        self.searcher.set_eval_params(params);
+    }
+}
+
+impl Player for SearcherPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>> {
+        self.active.eval_noise_seed = self.limits.eval_noise_seed.wrapping_add(self.games);
+        self.games += 1;
+        Ok(())
+    }
+
+    fn set_color_limits(&mut self, limits: &ColorLimits) -> Result<(), Box<dyn Error>> {
+        if limits.go.is_some() {
+            return Err(format!("{}: a go command needs a USI engine", self.name).into());
+        }
+        self.active = limits.apply(self.limits);
+        Ok(())
+    }
+
+    fn choose_move(
+        &mut self,
+        _start_sfen: &str,
+        _moves: &[Move],
+        position: &Position,
+    ) -> Result<PlayerAction, Box<dyn Error>> {
+        let best_move = self.searcher.search(position, self.active)?.best_move;
+        Ok(best_move.map_or(PlayerAction::Resign, PlayerAction::Move))
+    }
+}
+
+/// 子プロセスとして起動したUSIエンジン。
+pub struct UsiPlayer {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    go: String,
+    /// 今の対局の色で上書きされた `go` コマンド。
+    active_go: Option<String>,
+}
+
+impl UsiPlayer {
+    pub fn spawn(
+        name: String,
+        program: &str,
+        args: &[String],
+        go: String,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or("failed to open engine stdin")?;
+        let stdout = child.stdout.take().ok_or("failed to open engine stdout")?;
+        let mut player = Self {
+            name,
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            go,
+            active_go: None,
+        };
+        player.send("usi")?;
+        player.wait_for("usiok")?;
+        Ok(player)
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        writeln!(self.stdin, "{line}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    /// `prefix` で始まる行が来るまで読み進め、その行を返す。
+    fn wait_for(&mut self, prefix: &str) -> Result<String, Box<dyn Error>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(format!("{} exited while waiting for {prefix}", self.name).into());
+            }
+            let trimmed = line.trim();
+            if trimmed.starts_with(prefix) {
+                return Ok(trimmed.to_string());
+            }
+        }
+    }
+}
+
+impl Player for UsiPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send("isready")?;
+        self.wait_for("readyok")?;
+        self.send("usinewgame")
+    }
+
+    fn game_over(&mut self, outcome: Outcome) -> Result<(), Box<dyn Error>> {
+        let result = match outcome {
+            Outcome::Win => "win",
+            Outcome::Draw => "draw",
+            Outcome::Loss => "lose",
+        };
+        self.send(&format!("gameover {result}"))
+    }
+
+    fn set_color_limits(&mut self, limits: &ColorLimits) -> Result<(), Box<dyn Error>> {
+        self.active_go = limits
+            .usi_go()
+            .map_err(|message| format!("{}: {message}", self.name))?;
+        Ok(())
+    }
+
+    fn choose_move(
+        &mut self,
+        start_sfen: &str,
+        moves: &[Move],
+        position: &Position,
+    ) -> Result<PlayerAction, Box<dyn Error>> {
+        let mut command = format!("position sfen {start_sfen}");
+        if !moves.is_empty() {
+            command.push_str(" moves");
+            for mv in moves {
+                command.push(' ');
+                command.push_str(&mv.to_usi());
+            }
+        }
+        self.send(&command)?;
+        let go = self.active_go.clone().unwrap_or_else(|| self.go.clone());
+        self.send(&go)?;
+        let line = self.wait_for("bestmove")?;
+        let token = line.split_whitespace().nth(1).unwrap_or("resign");
+        match token {
+            "resign" => return Ok(PlayerAction::Resign),
+            "win" => return Ok(PlayerAction::DeclareWin),
+            _ => {}
+        }
+        let mv = position
+            .generate_legal_moves()?
+            .into_iter()
+            .find(|mv| mv.to_usi() == token)
+            .ok_or_else(|| format!("{} played an illegal move: {token}", self.name))?;
+        Ok(PlayerAction::Move(mv))
+    }
+}
+
+impl Drop for UsiPlayer {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+/// 自己対局の設定。
+#[derive(Clone, Debug)]
+pub struct MatchConfig {
+    /// 先後を入れ替えて指す2局1組の数。
+    pub pairs: usize,
+    /// この手数に達した対局は引き分けとする。
+    pub max_plies: u32,
+    /// `openings` が空のときの開始局面。
+    pub start_sfen: String,
+    /// 開始局面の候補。組ごとに1つ選び、先後を入れ替えた2局で同じ局面を使う。
+    pub openings: Vec<Position>,
+    /// `Some` なら開始局面をこのシードで無作為に選ぶ。`None` ならファイル順に巡回する。
+    pub opening_seed: Option<u64>,
+    /// 手数制限に達したとき、一方だけが入玉していればその側の勝ちとする。
+    pub adjudicate_entering_king: bool,
+    /// 先手・後手それぞれを持った対局者への上書き（`Color::index` 順）。
+    pub color_limits: [ColorLimits; 2],
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            pairs: 10,
+            max_plies: 256,
+            start_sfen: INITIAL_SFEN.to_string(),
+            openings: Vec::new(),
+            opening_seed: None,
+            adjudicate_entering_king: false,
+            color_limits: Default::default(),
+        }
+    }
+}
+
+impl MatchConfig {
+    /// 各組の開始局面を決める。同じ設定なら常に同じ並びになる。
+    pub fn pair_openings(&self) -> Result<Vec<Position>, PositionError> {
+        if self.openings.is_empty() {
+            let start = Position::from_sfen(&self.start_sfen)?;
+            return Ok(vec![start; self.pairs]);
+        }
+        let mut rng = self.opening_seed.map(SimpleRng::new);
+        Ok((0..self.pairs)
+            .map(|pair| {
+                let idx = match rng.as_mut() {
+                    Some(rng) => rng.gen_range(0..self.openings.len()),
+                    None => pair % self.openings.len(),
+                };
+                self.openings[idx].clone()
+            })
+            .collect())
+    }
+}
+
+/// 開始局面ファイルの1行を局面にする。
+///
+/// `startpos moves ...`、`sfen <SFEN> moves ...`、SFENそのもの、
+/// 先頭に `position` を付けたUSIの形式を受け付ける。
+pub fn parse_opening_line(line: &str) -> Result<Position, PositionError> {
+    let (mut position, moves) = parse_game_line(line)?;
+    for mv in &moves {
+        position.play_move_mut(mv)?;
+    }
+    Ok(position)
+}
+
+/// `parse_opening_line` と同じ形式の行を、開始局面と指し手に分ける。
+pub fn parse_game_line(line: &str) -> Result<(Position, Vec<Move>), PositionError> {
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.first() == Some(&"position") {
+        tokens.remove(0);
+    }
+    let (start, rest) = match tokens.first() {
+        Some(&"startpos") => (Position::initial()?, &tokens[1..]),
+        Some(&"sfen") if tokens.len() >= 5 => {
+            (Position::from_sfen(&tokens[1..5].join(" "))?, &tokens[5..])
+        }
+        Some(_) if tokens.len() >= 4 => {
+            (Position::from_sfen(&tokens[..4].join(" "))?, &tokens[4..])
+        }
+        _ => return Err(PositionError::Format("invalid opening line")),
+    };
+    let mut moves = Vec::new();
+    match rest.split_first() {
+        None => {}
+        Some((&"moves", tokens)) => {
+            let mut position = start.clone();
+            for token in tokens {
+                let mv = position.parse_usi_move(token)?;
+                position.play_move_mut(&mv)?;
+                moves.push(mv);
+            }
+        }
+        Some(_) => return Err(PositionError::Format("invalid opening line")),
+    }
+    Ok((start, moves))
+}
+
+/// 開始局面ファイルを読む。空行と `#` で始まる行は無視する。
+pub fn load_openings(path: &str) -> Result<Vec<Position>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut openings = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let position =
+            parse_opening_line(line).map_err(|err| format!("{path}:{}: {err}", idx + 1))?;
+        openings.push(position);
+    }
+    if openings.is_empty() {
+        return Err(format!("{path}: no openings found").into());
+    }
+    Ok(openings)
+}
+
+/// 対局の終了理由。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// 手番側に合法手がない（詰み）。
+    NoLegalMoves,
+    /// 探索が指し手を返さなかった（投了）。
+    Resignation,
+    /// 千日手。5五将棋では先手の負け。
+    Repetition,
+    /// 連続王手の千日手。王手をかけ続けた側の負け。
+    PerpetualCheck,
+    /// 手数制限による引き分け。
+    MaxPlies,
+    /// 手数制限に達した時点で一方だけが入玉していたため、その側の勝ちと判定した。
+    EnteringKing,
+    /// 入玉宣言が認められ、宣言した側の勝ち。
+    Declaration,
+    /// 入玉宣言の条件を満たしていなかったため、宣言した側の負け。
+    IllegalDeclaration,
+}
+
+/// 1局分の棋譜と結果。
+#[derive(Clone, Debug)]
+pub struct GameRecord {
+    pub start_sfen: String,
+    pub moves: Vec<Move>,
+    /// 各手で対局者が指し手を返すまでにかかった時間。`moves` と同じ長さ。
+    pub move_times: Vec<Duration>,
+    pub winner: Option<Color>,
+    pub termination: Termination,
+}
+
+impl GameRecord {
+    /// `color` 側から見た結果。
+    pub fn outcome_for(&self, color: Color) -> Outcome {
+        match self.winner {
+            Some(winner) if winner == color => Outcome::Win,
+            Some(_) => Outcome::Loss,
+            None => Outcome::Draw,
+        }
+    }
+
+    /// `color` 側が使った時間の合計。
+    pub fn time_used(&self, color: Color) -> Duration {
+        let start = Position::from_sfen(&self.start_sfen)
+            .map_or(Color::Black, |position| position.side_to_move());
+        let first = if start == color { 0 } else { 1 };
+        self.move_times.iter().skip(first).step_by(2).sum()
+    }
+
+    /// `position sfen ... moves ...` 形式の棋譜。
+    pub fn to_usi_position(&self) -> String {
+        let mut line = format!("position sfen {}", self.start_sfen);
+        if !self.moves.is_empty() {
+            line.push_str(" moves");
+            for mv in &self.moves {
+                line.push(' ');
+                line.push_str(&mv.to_usi());
+            }
+        }
+        line
+    }
+}
+
+/// 2人の対局者で1局指す。手数制限と入玉の判定は `config` に従う。
+pub fn play_game<'a>(
+    black: &'a mut dyn Player,
+    white: &'a mut dyn Player,
+    start: &Position,
+    config: &MatchConfig,
+) -> Result<GameRecord, Box<dyn Error>> {
+    black.set_color_limits(&config.color_limits[Color::Black.index()])?;
+    white.set_color_limits(&config.color_limits[Color::White.index()])?;
+    black.new_game()?;
+    white.new_game()?;
+    let start_sfen = start.to_sfen();
+    // 連続王手の千日手を判定するため、王手の履歴を持つ `Game` で指し進める。
+    let mut game = Game::new(start.clone());
+    let mut moves = Vec::new();
+    let mut move_times = Vec::new();
+
+    let (winner, termination) = loop {
+        let position = game.position();
+        if moves.len() as u32 >= config.max_plies {
+            if config.adjudicate_entering_king {
+                let black_entered = position.is_entering_king(Color::Black);
+                let white_entered = position.is_entering_king(Color::White);
+                if black_entered != white_entered {
+                    let winner = if black_entered {
+                        Color::Black
+                    } else {
+                        Color::White
+                    };
+                    break (Some(winner), Termination::EnteringKing);
+                }
+            }
+            break (None, Termination::MaxPlies);
+        }
+        let mover = position.side_to_move();
+        if !position.has_legal_move()? {
+            break (Some(mover.opponent()), Termination::NoLegalMoves);
+        }
+        let player: &mut dyn Player = match mover {
+            Color::Black => &mut *black,
+            Color::White => &mut *white,
+        };
+        let started = Instant::now();
+        let mv = match player.choose_move(&start_sfen, &moves, position)? {
+            PlayerAction::Move(mv) => mv,
+            PlayerAction::Resign => break (Some(mover.opponent()), Termination::Resignation),
+            PlayerAction::DeclareWin if is_valid_declaration(position) => {
+                break (Some(mover), Termination::Declaration);
+            }
+            PlayerAction::DeclareWin => {
+                break (Some(mover.opponent()), Termination::IllegalDeclaration);
+            }
+        };
+        game.play(&mv)?;
+        moves.push(mv);
+        move_times.push(started.elapsed());
+        if game.position().is_repetition() {
+            match game.status()? {
+                GameStatus::PerpetualCheck { loser } => {
+                    break (Some(loser.opponent()), Termination::PerpetualCheck);
+                }
+                status => break (status.winner(), Termination::Repetition),
+            }
+        }
+    };
+
+    let record = GameRecord {
+        start_sfen,
+        moves,
+        move_times,
+        winner,
+        termination,
+    };
+    black.game_over(record.outcome_for(Color::Black))?;
+    white.game_over(record.outcome_for(Color::White))?;
+    Ok(record)
+}
+
+/// 手番側の入玉宣言が認められるか。王手がかかっておらず、`Position::is_entering_king` の
+/// 条件を満たしていること。
+pub fn is_valid_declaration(position: &Position) -> bool {
+    !position.in_check() && position.is_entering_king(position.side_to_move())
+}
+
+/// 対局者AとBで先後を入れ替えながら対局し、Aから見た成績を返す。
+/// `on_game` には対局番号、棋譜、Aが先手だったかどうかが渡される。
+pub fn run_pairing(
+    a: &mut dyn Player,
+    b: &mut dyn Player,
+    config: &MatchConfig,
+    mut on_game: impl FnMut(usize, &GameRecord, bool),
+) -> Result<MatchStats, Box<dyn Error>> {
+    let mut stats = MatchStats::new();
+
+    for (pair, start) in config.pair_openings()?.iter().enumerate() {
+        let first = play_game(a, b, start, config)?;
+        on_game(pair * 2 + 1, &first, true);
+        let second = play_game(b, a, start, config)?;
+        on_game(pair * 2 + 2, &second, false);
+        stats.record_pair(
+            first.outcome_for(Color::Black),
+            second.outcome_for(Color::White),
+        );
+    }
+
+    Ok(stats)
+}
+
+/// エンジンAとBの設定から対局者を作って対局する。
+pub fn run_match(
+    a: &EngineConfig,
+    b: &EngineConfig,
+    config: &MatchConfig,
+    on_game: impl FnMut(usize, &GameRecord, bool),
+) -> Result<MatchStats, Box<dyn Error>> {
+    let mut player_a = a.create_player()?;
+    let mut player_b = b.create_player()?;
+    run_pairing(player_a.as_mut(), player_b.as_mut(), config, on_game)
+}
+
+pub(crate) fn result_label(record: &GameRecord) -> &'static str {
+    match record.winner {
+        Some(Color::Black) => "1-0",
+        Some(Color::White) => "0-1",
+        None => "1/2-1/2",
+    }
+}
+
+/// 対局ログに添える先後の消費時間。
+pub(crate) fn time_label(record: &GameRecord) -> String {
+    format!(
+        "time {:.2}s/{:.2}s",
+        record.time_used(Color::Black).as_secs_f64(),
+        record.time_used(Color::White).as_secs_f64()
+    )
+}
+
+pub(crate) fn parse_value<T: std::str::FromStr>(
+    flag: &str,
+    value: Option<&String>,
+) -> Result<T, String> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("{flag} requires a valid value"))
+}
+
+/// `match` サブコマンド。
+///
+/// `match [--pairs N] [--depth-a D] [--depth-b D] [--random R] [--random-plies N] [--eval-noise CP]
+///        [--black-depth D] [--white-depth D] [--black-random R] [--white-random R]
+///        [--max-plies N] [--openings PATH] [--seed N] [--adjudicate-entering-king] [--json PATH]`
+///
+/// `--black-*` と `--white-*` は、どちらのエンジンが持つかによらずその色の手に使う。
+/// `--eval-noise` は評価値を対局ごとに違うシードで揺らし、乱択を使わずに序盤を散らす。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config = MatchConfig::default();
+    let mut limits_a = SearchLimits::default();
+    let mut limits_b = SearchLimits::default();
+    let mut json_path = None;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--pairs" => config.pairs = parse_value(flag, iter.next())?,
+            "--max-plies" => config.max_plies = parse_value(flag, iter.next())?,
+            "--depth-a" => limits_a.depth = parse_value(flag, iter.next())?,
+            "--depth-b" => limits_b.depth = parse_value(flag, iter.next())?,
+            "--random" => {
+                let randomness = parse_value(flag, iter.next())?;
+                limits_a.randomness = randomness;
+                limits_b.randomness = randomness;
+            }
+            "--eval-noise" => {
+                let noise = parse_value(flag, iter.next())?;
+                limits_a.eval_noise = noise;
+                limits_b.eval_noise = noise;
+            }
+            "--random-plies" => {
+                let plies = parse_value(flag, iter.next())?;
+                limits_a.random_plies = plies;
+                limits_b.random_plies = plies;
+            }
+            "--openings" => {
+                config.openings = load_openings(&parse_value::<String>(flag, iter.next())?)?
+            }
+            "--seed" => config.opening_seed = Some(parse_value(flag, iter.next())?),
+            "--adjudicate-entering-king" => config.adjudicate_entering_king = true,
+            "--json" => json_path = Some(parse_value::<String>(flag, iter.next())?),
+            other if parse_color_limit(other, &mut iter, &mut config.color_limits)? => {}
+            other => return Err(format!("unknown match option: {other}").into()),
+        }
+    }
+
+    println!("build {}", engine_info());
+    let a = EngineConfig::internal("A", limits_a);
+    let b = EngineConfig::internal("B", limits_b);
+    let stats = run_match(&a, &b, &config, |number, record, a_is_black| {
+        let (black, white) = if a_is_black {
+            (&a.name, &b.name)
+        } else {
+            (&b.name, &a.name)
+        };
+        println!(
+            "game {number}: {black} vs {white} {} ({:?}, {} plies, {})",
+            result_label(record),
+            record.termination,
+            record.moves.len(),
+            time_label(record)
+        );
+    })?;
+
+    println!("{stats}");
+    if let Some(path) = json_path {
+        fs::write(path, stats.to_json())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_match_records_every_game() {
+        let engine = EngineConfig::internal(
+            "depth1",
+            SearchLimits {
+                depth: 1,
+                randomness: 50,
+                ..SearchLimits::default()
+            },
+        );
+        let config = MatchConfig {
+            pairs: 1,
+            max_plies: 16,
+            ..MatchConfig::default()
+        };
+        let mut played = 0;
+        let stats = run_match(&engine, &engine, &config, |_, record, _| {
+            played += 1;
+            assert!(record.moves.len() <= 16);
+            assert_eq!(record.move_times.len(), record.moves.len());
+        })
+        .expect("match");
+        assert_eq!(played, 2);
+        assert_eq!(stats.games(), 2);
+        assert_eq!(stats.pairs(), 1);
+    }
+
+    #[test]
+    fn color_limits_follow_the_side_not_the_engine() {
+        /// 手を選ぶたびに使った深さを記録する対局者。
+        struct DepthProbe {
+            player: SearcherPlayer,
+            depths: Vec<(Color, usize)>,
+        }
+
+        impl Player for DepthProbe {
+            fn name(&self) -> &str {
+                self.player.name()
+            }
+
+            fn set_color_limits(&mut self, limits: &ColorLimits) -> Result<(), Box<dyn Error>> {
+                self.player.set_color_limits(limits)
+            }
+
+            fn choose_move(
+                &mut self,
+                start_sfen: &str,
+                moves: &[Move],
+                position: &Position,
+            ) -> Result<PlayerAction, Box<dyn Error>> {
+                self.depths
+                    .push((position.side_to_move(), self.player.active.depth));
+                self.player.choose_move(start_sfen, moves, position)
+            }
+        }
+
+        let probe = || DepthProbe {
+            player: SearcherPlayer::new("probe".to_string(), SearchLimits::default()),
+            depths: Vec::new(),
+        };
+        let (mut a, mut b) = (probe(), probe());
+        let mut config = MatchConfig {
+            pairs: 1,
+            max_plies: 6,
+            ..MatchConfig::default()
+        };
+        config.color_limits[Color::Black.index()].depth = Some(1);
+        config.color_limits[Color::White.index()].depth = Some(2);
+        run_pairing(&mut a, &mut b, &config, |_, _, _| {}).expect("pairing");
+        for (color, depth) in a.depths.iter().chain(&b.depths) {
+            assert_eq!(*depth, if *color == Color::Black { 1 } else { 2 });
+        }
+        assert!(a.depths.iter().any(|&(color, _)| color == Color::Black));
+        assert!(a.depths.iter().any(|&(color, _)| color == Color::White));
+    }
+
+    #[test]
+    fn declarations_are_adjudicated() {
+        /// 毎手入玉宣言する対局者。
+        struct Declarer;
+
+        impl Player for Declarer {
+            fn name(&self) -> &str {
+                "declarer"
+            }
+
+            fn choose_move(
+                &mut self,
+                _start_sfen: &str,
+                _moves: &[Move],
+                _position: &Position,
+            ) -> Result<PlayerAction, Box<dyn Error>> {
+                Ok(PlayerAction::DeclareWin)
+            }
+        }
+
+        let config = MatchConfig::default();
+        let entered = Position::from_sfen("K1G2/1S3/5/5/4k b - 1").unwrap();
+        let record = play_game(&mut Declarer, &mut Declarer, &entered, &config).unwrap();
+        assert_eq!(record.winner, Some(Color::Black));
+        assert_eq!(record.termination, Termination::Declaration);
+
+        let lone = Position::from_sfen("K4/5/5/5/4k b - 1").unwrap();
+        let record = play_game(&mut Declarer, &mut Declarer, &lone, &config).unwrap();
+        assert_eq!(record.winner, Some(Color::White));
+        assert_eq!(record.termination, Termination::IllegalDeclaration);
+    }
+
+    #[test]
+    fn perpetual_check_loses_for_the_checking_side() {
+        /// 決められた手を順に指し、尽きたら最後の2手を繰り返す対局者。
+        struct Scripted(Vec<&'static str>, usize);
+
+        impl Player for Scripted {
+            fn name(&self) -> &str {
+                "scripted"
+            }
+
+            fn choose_move(
+                &mut self,
+                _start_sfen: &str,
+                _moves: &[Move],
+                position: &Position,
+            ) -> Result<PlayerAction, Box<dyn Error>> {
+                let len = self.0.len();
+                let index = if self.1 < len {
+                    self.1
+                } else {
+                    len - 2 + (self.1 - len) % 2
+                };
+                self.1 += 1;
+                Ok(PlayerAction::Move(position.parse_usi_move(self.0[index])?))
+            }
+        }
+
+        // 先手の飛車が5筋と4筋を往復して王手をかけ続け、後手玉が逃げ続ける。
+        let start = Position::from_sfen("k4/5/2R2/5/4K b - 1").unwrap();
+        let mut checker = Scripted(vec!["3c5c", "5c4c", "4c5c"], 0);
+        let mut runner = Scripted(vec!["5a4a", "4a5a"], 0);
+        let record = play_game(&mut checker, &mut runner, &start, &MatchConfig::default()).unwrap();
+        assert_eq!(record.termination, Termination::PerpetualCheck);
+        assert_eq!(record.winner, Some(Color::White));
+    }
+
+    #[test]
+    fn color_limits_reach_usi_engines_as_go_commands() {
+        let args: Vec<String> = ["--white-depth", "3", "--black-go", "go byoyomi 500"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let mut limits: [ColorLimits; 2] = Default::default();
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            assert!(parse_color_limit(flag, &mut iter, &mut limits).unwrap());
+        }
+        assert!(!parse_color_limit("--black-skill", &mut iter, &mut limits).unwrap());
+        let [black, white] = &limits;
+        assert_eq!(black.usi_go().unwrap().as_deref(), Some("go byoyomi 500"));
+        assert_eq!(white.usi_go().unwrap().as_deref(), Some("go depth 3"));
+        assert_eq!(ColorLimits::default().usi_go().unwrap(), None);
+
+        let random = ColorLimits {
+            randomness: Some(20),
+            ..ColorLimits::default()
+        };
+        assert!(random.usi_go().is_err());
+        let both = ColorLimits {
+            depth: Some(2),
+            ..black.clone()
+        };
+        assert!(both.usi_go().is_err());
+        let mut player = SearcherPlayer::new("internal".to_string(), SearchLimits::default());
+        assert!(player.set_color_limits(black).is_err());
+        assert!(player.set_color_limits(white).is_ok());
+    }
+
+    #[test]
+    fn pairs_share_openings_in_file_order_or_by_seed() {
+        let openings = vec![
+            parse_opening_line("startpos moves 1e1d").unwrap(),
+            parse_opening_line("position startpos moves 2e1d").unwrap(),
+            parse_opening_line("rbsgk/4p/5/P4/KGSBR w - 1").unwrap(),
+        ];
+        let sequential = MatchConfig {
+            pairs: 4,
+            openings: openings.clone(),
+            ..MatchConfig::default()
+        };
+        let sfens: Vec<String> = sequential
+            .pair_openings()
+            .unwrap()
+            .iter()
+            .map(Position::to_sfen)
+            .collect();
+        assert_eq!(sfens[0], openings[0].to_sfen());
+        assert_eq!(sfens[2], openings[2].to_sfen());
+        assert_eq!(sfens[3], openings[0].to_sfen());
+
+        let seeded = MatchConfig {
+            opening_seed: Some(7),
+            ..sequential
+        };
+        let first: Vec<String> = seeded
+            .pair_openings()
+            .unwrap()
+            .iter()
+            .map(Position::to_sfen)
+            .collect();
+        let again: Vec<String> = seeded
+            .pair_openings()
+            .unwrap()
+            .iter()
+            .map(Position::to_sfen)
+            .collect();
+        assert_eq!(first, again);
+        assert!(parse_opening_line("startpos moves 9z9z").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+use crate::build_info::engine_info;
+
+/// 対局の結果を片方のエンジン（エンジンA）から見たもの。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl Outcome {
+    /// 勝ち1、引き分け0.5、負け0としたときの得点。
+    pub fn points(self) -> f64 {
+        match self {
+            Self::Win => 1.0,
+            Self::Draw => 0.5,
+            Self::Loss => 0.0,
+        }
+    }
+}
+
+/// 手番ごとの勝敗数。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ColorRecord {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl ColorRecord {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Win => self.wins += 1,
+            Outcome::Draw => self.draws += 1,
+            Outcome::Loss => self.losses += 1,
+        }
+    }
+}
+
+/// エンジンAから見た対局結果の集計。
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MatchStats {
+    /// Aが先手のときの成績。
+    pub as_black: ColorRecord,
+    /// Aが後手のときの成績。
+    pub as_white: ColorRecord,
+    /// 先後を入れ替えた2局1組の得点（0, 0.5, 1, 1.5, 2）ごとの組数。
+    pub pentanomial: [u32; 5],
+}
+
+/// 95%信頼区間に対応する正規分布の分位点。
+const Z_95: f64 = 1.959_964;
+
+impl MatchStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 1局の結果を記録する。`a_is_black` はAが先手だったかどうか。
+    pub fn record_game(&mut self, a_is_black: bool, outcome: Outcome) {
+        if a_is_black {
+            self.as_black.record(outcome);
+        } else {
+            self.as_white.record(outcome);
+        }
+    }
+
+    /// 先後を入れ替えた2局を記録する。1局目はAが先手、2局目はAが後手。
+    pub fn record_pair(&mut self, first: Outcome, second: Outcome) {
+        self.record_game(true, first);
+        self.record_game(false, second);
+        let pair_points = first.points() + second.points();
+        self.pentanomial[(pair_points * 2.0).round() as usize] += 1;
+    }
+
+    pub fn wins(&self) -> u32 {
+        self.as_black.wins + self.as_white.wins
+    }
+
+    pub fn draws(&self) -> u32 {
+        self.as_black.draws + self.as_white.draws
+    }
+
+    pub fn losses(&self) -> u32 {
+        self.as_black.losses + self.as_white.losses
+    }
+
+    pub fn games(&self) -> u32 {
+        self.as_black.games() + self.as_white.games()
+    }
+
+    pub fn pairs(&self) -> u32 {
+        self.pentanomial.iter().sum()
+    }
+
+    /// Aの1局あたりの平均得点（0.0〜1.0）。対局がなければ `None`。
+    pub fn score(&self) -> Option<f64> {
+        let games = self.games();
+        if games == 0 {
+            return None;
+        }
+        Some((self.wins() as f64 + 0.5 * self.draws() as f64) / games as f64)
+    }
+
+    /// Aから見たElo差。全勝・全敗のときは無限大になる。
+    pub fn elo(&self) -> Option<f64> {
+        self.score().map(elo_from_score)
+    }
+
+    /// Elo差の95%信頼区間。2局1組の記録があれば五項分布、なければ三項分布の分散を使う。
+    pub fn elo_confidence_interval(&self) -> Option<(f64, f64)> {
+        let score = self.score()?;
+        let stderr = if self.pairs() > 0 {
+            let pairs = self.pairs() as f64;
+            let variance = self
+                .pentanomial
+                .iter()
+                .enumerate()
+                .map(|(idx, &count)| {
+                    let pair_score = idx as f64 / 4.0;
+                    count as f64 * (pair_score - score).powi(2)
+                })
+                .sum::<f64>()
+                / pairs;
+            (variance / pairs).sqrt()
+        } else {
+            let games = self.games() as f64;
+            let variance = (self.wins() as f64 * (1.0 - score).powi(2)
+                + self.draws() as f64 * (0.5 - score).powi(2)
+                + self.losses() as f64 * score.powi(2))
+                / games;
+            (variance / games).sqrt()
+        };
+        let low = (score - Z_95 * stderr).clamp(0.0, 1.0);
+        let high = (score + Z_95 * stderr).clamp(0.0, 1.0);
+        Some((elo_from_score(low), elo_from_score(high)))
+    }
+
+    /// AがBより強い確率（likelihood of superiority）。引き分けは判定に寄与しない。
+    pub fn los(&self) -> Option<f64> {
+        let decisive = (self.wins() + self.losses()) as f64;
+        if decisive == 0.0 {
+            return None;
+        }
+        let diff = self.wins() as f64 - self.losses() as f64;
+        Some(0.5 * (1.0 + erf(diff / (2.0 * decisive).sqrt())))
+    }
+
+    /// ダッシュボード向けのJSON表現。結果を出したビルドの情報を `build` に入れる。
+    pub fn to_json(&self) -> String {
+        let record = |r: &ColorRecord| {
+            format!(
+                "{{\"wins\":{},\"draws\":{},\"losses\":{}}}",
+                r.wins, r.draws, r.losses
+            )
+        };
+        let pentanomial = self
+            .pentanomial
+            .iter()
+            .map(|count| count.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let (ci_low, ci_high) = match self.elo_confidence_interval() {
+            Some((low, high)) => (json_number(low), json_number(high)),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        format!(
+            "{{\"build\":\"{}\",\"games\":{},\"wins\":{},\"draws\":{},\"losses\":{},\"as_black\":{},\"as_white\":{},\"pentanomial\":[{}],\"score\":{},\"elo\":{},\"elo_ci95\":[{},{}],\"los\":{}}}",
+            engine_info(),
+            self.games(),
+            self.wins(),
+            self.draws(),
+            self.losses(),
+            record(&self.as_black),
+            record(&self.as_white),
+            pentanomial,
+            self.score().map_or("null".to_string(), json_number),
+            self.elo().map_or("null".to_string(), json_number),
+            ci_low,
+            ci_high,
+            self.los().map_or("null".to_string(), json_number),
+        )
+    }
+}
+
+impl fmt::Display for MatchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "games {} W/D/L {}/{}/{}",
+            self.games(),
+            self.wins(),
+            self.draws(),
+            self.losses()
+        )?;
+        writeln!(
+            f,
+            "as black {}/{}/{}  as white {}/{}/{}",
+            self.as_black.wins,
+            self.as_black.draws,
+            self.as_black.losses,
+            self.as_white.wins,
+            self.as_white.draws,
+            self.as_white.losses
+        )?;
+        writeln!(
+            f,
+            "pentanomial [{}]",
+            self.pentanomial
+                .iter()
+                .map(|count| count.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+        match (self.elo(), self.elo_confidence_interval()) {
+            (Some(elo), Some((low, high))) => {
+                writeln!(f, "elo {elo:+.1} (95% CI {low:+.1} .. {high:+.1})")?
+            }
+            _ => writeln!(f, "elo n/a")?,
+        }
+        match self.los() {
+            Some(los) => write!(f, "los {:.1}%", los * 100.0),
+            None => write!(f, "los n/a"),
+        }
+    }
+}
+
+fn elo_from_score(score: f64) -> f64 {
+    if score <= 0.0 {
+        f64::NEG_INFINITY
+    } else if score >= 1.0 {
+        f64::INFINITY
+    } else {
+        -400.0 * (1.0 / score - 1.0).log10()
+    }
+}
+
+/// JSONは無限大を表現できないため、範囲外の値は `null` にする。
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{value:.4}")
+    } else {
+        "null".to_string()
+    }
+}
+
+/// 誤差関数の近似（Abramowitz & Stegun 7.1.26、誤差1.5e-7以下）。
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn even_match_has_zero_elo_and_half_los() {
+        let mut stats = MatchStats::new();
+        stats.record_pair(Outcome::Win, Outcome::Loss);
+        stats.record_pair(Outcome::Loss, Outcome::Win);
+        stats.record_pair(Outcome::Draw, Outcome::Draw);
+        assert_eq!(stats.pentanomial, [0, 0, 3, 0, 0]);
+        assert!(stats.elo().unwrap().abs() < 1e-9);
+        assert!((stats.los().unwrap() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn winning_record_has_positive_elo() {
+        let mut stats = MatchStats::new();
+        for _ in 0..6 {
+            stats.record_pair(Outcome::Win, Outcome::Draw);
+        }
+        stats.record_pair(Outcome::Loss, Outcome::Loss);
+        let elo = stats.elo().unwrap();
+        let (low, high) = stats.elo_confidence_interval().unwrap();
+        assert!(elo > 0.0);
+        assert!(low < elo && elo < high);
+        assert!(stats.los().unwrap() > 0.9);
+        assert!(stats.to_json().contains("\"pentanomial\":[1,0,0,6,0]"));
+    }
+}
@@ -0,0 +1,167 @@
+use core::fmt;
+
+use crate::hand::{HAND_PIECE_KIND_COUNT, HandPieceKind};
+use crate::piece::{COLORS, Color, PIECE_KIND_COUNT, Piece, PieceKind};
+use crate::position::Position;
+use crate::score::AbsScore;
+
+/// 署名で数える欄の数。盤上の駒種と持ち駒の駒種を別に数える。
+const SIGNATURE_SLOTS: usize = PIECE_KIND_COUNT + HAND_PIECE_KIND_COUNT;
+
+/// `key` で1つの欄に使うビット数。5五将棋では各駒種が2枚ずつなので2ビットで足りる。
+const KEY_BITS: u32 = 2;
+const KEY_MAX: u8 = (1 << KEY_BITS) - 1;
+
+/// 先後それぞれの、盤上の駒種ごとと持ち駒の駒種ごとの枚数。
+///
+/// `Position` が駒の出入りに合わせて差分で更新するので、盤を走査せずに駒割りが分かる。
+/// 終盤向けの評価の切り替えや、駒割りごとの表を引くのに使う。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaterialSignature {
+    counts: [[u8; SIGNATURE_SLOTS]; 2],
+}
+
+impl MaterialSignature {
+    /// 局面を走査して作る。差分更新の確かめに使う。
+    pub fn from_position(position: &Position) -> Self {
+        let mut signature = Self::default();
+        for color in COLORS {
+            for kind in PieceKind::all() {
+                let count = position.pieces(color, kind).count() as u8;
+                signature.counts[color.index()][kind.index()] = count;
+            }
+            for kind in HandPieceKind::all() {
+                signature.set_hand(color, kind, position.hand(color).count(kind));
+            }
+        }
+        signature
+    }
+
+    pub fn board_count(&self, color: Color, kind: PieceKind) -> u8 {
+        self.counts[color.index()][kind.index()]
+    }
+
+    pub fn hand_count(&self, color: Color, kind: HandPieceKind) -> u8 {
+        self.counts[color.index()][PIECE_KIND_COUNT + kind.index()]
+    }
+
+    /// `color` の玉以外の駒の数。持ち駒を含む。
+    pub fn non_king_count(&self, color: Color) -> u32 {
+        let counts = &self.counts[color.index()];
+        counts.iter().map(|&count| count as u32).sum::<u32>()
+            - counts[PieceKind::King.index()] as u32
+    }
+
+    /// `color` が玉だけで持ち駒もない。
+    pub fn is_bare_king(&self, color: Color) -> bool {
+        self.non_king_count(color) == 0
+    }
+
+    /// 双方が裸玉。どちらも王手をかけられないので、千日手でしか終わらない。
+    pub fn is_bare_kings(&self) -> bool {
+        COLORS.into_iter().all(|color| self.is_bare_king(color))
+    }
+
+    /// 駒割りを64ビットに詰めた鍵。各欄は `KEY_MAX` で頭打ちにする。
+    pub fn key(&self) -> u64 {
+        let mut key = 0;
+        for counts in &self.counts {
+            for &count in counts {
+                key = (key << KEY_BITS) | count.min(KEY_MAX) as u64;
+            }
+        }
+        key
+    }
+
+    pub(crate) fn add_board(&mut self, color: Color, kind: PieceKind) {
+        let count = &mut self.counts[color.index()][kind.index()];
+        *count = count.saturating_add(1);
+    }
+
+    pub(crate) fn remove_board(&mut self, color: Color, kind: PieceKind) {
+        let count = &mut self.counts[color.index()][kind.index()];
+        *count = count.saturating_sub(1);
+    }
+
+    pub(crate) fn set_hand(&mut self, color: Color, kind: HandPieceKind, count: u8) {
+        self.counts[color.index()][PIECE_KIND_COUNT + kind.index()] = count;
+    }
+}
+
+/// 駒割りごとに結果を引く終盤の表。探索は末端の局面を評価する前に引く。
+pub trait EndgameTable: Send {
+    /// `key` は `position.material().key()`。表にない局面なら `None`。
+    fn probe(&self, key: u64, position: &Position) -> Option<AbsScore>;
+}
+
+impl fmt::Display for MaterialSignature {
+    /// `KGSBRP*P v kgsbr` のように、先手・後手の順に盤上の駒と `*` の後ろに持ち駒を並べる。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for color in COLORS {
+            if color == Color::White {
+                f.write_str(" v ")?;
+            }
+            for kind in PieceKind::all() {
+                for _ in 0..self.board_count(color, kind) {
+                    write!(f, "{}", Piece::new(color, kind))?;
+                }
+            }
+            let mut hand = HandPieceKind::all()
+                .into_iter()
+                .filter(|&kind| self.hand_count(color, kind) > 0)
+                .peekable();
+            if hand.peek().is_some() {
+                f.write_str("*")?;
+            }
+            for kind in hand {
+                for _ in 0..self.hand_count(color, kind) {
+                    let letter = kind.to_char();
+                    let letter = match color {
+                        Color::Black => letter,
+                        Color::White => letter.to_ascii_lowercase(),
+                    };
+                    write!(f, "{letter}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn signature_follows_captures_drops_and_promotions() {
+        let mut position = Position::from_sfen("k4/5/2p2/2R2/4K b G 1").expect("sfen");
+        for token in ["3d3c", "5a4a", "3c3a+", "4a3a", "G*4b"] {
+            let mv = position.parse_usi_move(token).expect(token);
+            position.play_move_mut(&mv).expect(token);
+            assert_eq!(
+                position.material(),
+                MaterialSignature::from_position(&position)
+            );
+        }
+        let material = position.material();
+        assert_eq!(material.board_count(Color::Black, PieceKind::Gold), 1);
+        assert_eq!(material.hand_count(Color::Black, HandPieceKind::Pawn), 1);
+        assert_eq!(material.hand_count(Color::White, HandPieceKind::Rook), 1);
+        assert_eq!(material.non_king_count(Color::Black), 2);
+        assert!(!material.is_bare_king(Color::White));
+
+        position.set_hand_count(Color::White, HandPieceKind::Rook, 0);
+        position.set_hand_count(Color::Black, HandPieceKind::Silver, 2);
+        assert_eq!(
+            position.material(),
+            MaterialSignature::from_position(&position)
+        );
+
+        let bare = Position::from_sfen("k4/5/5/5/4K b - 1").expect("bare");
+        assert!(bare.material().is_bare_kings());
+        assert_ne!(bare.material().key(), material.key());
+        assert_eq!(material.to_string(), "KG*P v k*r");
+        assert_eq!(position.material().to_string(), "KG*SSP v k");
+    }
+}
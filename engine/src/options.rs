@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fmt;
+
+pub use crate::build_info::{ENGINE_IDENTITY, EngineIdentity};
+
+/// オプションの型と既定値。USIの `type` に対応する。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionKind {
+    Check { default: bool },
+    Spin { default: i64, min: i64, max: i64 },
+    Combo { default: String, vars: Vec<String> },
+    String { default: String },
+    Button,
+}
+
+/// 検証済みのオプション値。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionValue {
+    Check(bool),
+    Spin(i64),
+    String(String),
+    Button,
+}
+
+/// エンジンが受け付けるオプションの説明。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionDescriptor {
+    pub name: String,
+    pub kind: OptionKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionError {
+    Unknown(String),
+    InvalidValue { name: String, value: String },
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "unknown option: {name}"),
+            Self::InvalidValue { name, value } => {
+                write!(f, "invalid value for {name}: {value}")
+            }
+        }
+    }
+}
+
+impl Error for OptionError {}
+
+impl OptionDescriptor {
+    pub fn check(name: &str, default: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: OptionKind::Check { default },
+        }
+    }
+
+    pub fn spin(name: &str, default: i64, min: i64, max: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: OptionKind::Spin { default, min, max },
+        }
+    }
+
+    pub fn combo(name: &str, default: &str, vars: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: OptionKind::Combo {
+                default: default.to_string(),
+                vars: vars.iter().map(|var| var.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn string(name: &str, default: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: OptionKind::String {
+                default: default.to_string(),
+            },
+        }
+    }
+
+    /// 文字列の値を型に合わせて検証する。
+    pub fn parse_value(&self, value: &str) -> Result<OptionValue, OptionError> {
+        let invalid = || OptionError::InvalidValue {
+            name: self.name.clone(),
+            value: value.to_string(),
+        };
+        match &self.kind {
+            OptionKind::Check { .. } => match value {
+                "true" => Ok(OptionValue::Check(true)),
+                "false" => Ok(OptionValue::Check(false)),
+                _ => Err(invalid()),
+            },
+            OptionKind::Spin { min, max, .. } => value
+                .parse::<i64>()
+                .ok()
+                .filter(|parsed| (*min..=*max).contains(parsed))
+                .map(OptionValue::Spin)
+                .ok_or_else(invalid),
+            OptionKind::Combo { vars, .. } => {
+                if vars.iter().any(|var| var == value) {
+                    Ok(OptionValue::String(value.to_string()))
+                } else {
+                    Err(invalid())
+                }
+            }
+            OptionKind::String { .. } => Ok(OptionValue::String(value.to_string())),
+            OptionKind::Button => Ok(OptionValue::Button),
+        }
+    }
+
+    /// 検証済みの値 `value` を既定値にしたもの。型が合わなければそのまま返す。
+    pub fn with_default(mut self, value: &OptionValue) -> Self {
+        match (&mut self.kind, value) {
+            (OptionKind::Check { default }, OptionValue::Check(value)) => *default = *value,
+            (OptionKind::Spin { default, .. }, OptionValue::Spin(value)) => *default = *value,
+            (
+                OptionKind::Combo { default, .. } | OptionKind::String { default },
+                OptionValue::String(value),
+            ) => default.clone_from(value),
+            _ => {}
+        }
+        self
+    }
+
+    /// `option name ... type ...` の行。
+    pub fn to_usi(&self) -> String {
+        let name = &self.name;
+        match &self.kind {
+            OptionKind::Check { default } => {
+                format!("option name {name} type check default {default}")
+            }
+            OptionKind::Spin { default, min, max } => {
+                format!("option name {name} type spin default {default} min {min} max {max}")
+            }
+            OptionKind::Combo { default, vars } => {
+                let mut line = format!("option name {name} type combo default {default}");
+                for var in vars {
+                    line.push_str(&format!(" var {var}"));
+                }
+                line
+            }
+            OptionKind::String { default } => {
+                let default = if default.is_empty() {
+                    "<empty>"
+                } else {
+                    default
+                };
+                format!("option name {name} type string default {default}")
+            }
+            OptionKind::Button => format!("option name {name} type button"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spin_values_are_range_checked() {
+        let option = OptionDescriptor::spin("Depth", 3, 1, 20);
+        assert_eq!(option.parse_value("5"), Ok(OptionValue::Spin(5)));
+        assert!(option.parse_value("21").is_err());
+        assert!(option.parse_value("x").is_err());
+        assert_eq!(
+            option.to_usi(),
+            "option name Depth type spin default 3 min 1 max 20"
+        );
+    }
+}
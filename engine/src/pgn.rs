@@ -0,0 +1,125 @@
+//! 対局をPGNの書式にする。
+//!
+//! チェスのPGNのタグと指し手番号の並びを借り、指し手はUSIの表記で書く。`Black` は先手、
+//! `White` は後手で、結果は先手から見た値（`1-0` は先手の勝ち）。開始局面は `SFEN` タグに書く。
+
+use crate::match_runner::{GameRecord, Termination, result_label};
+use crate::piece::Color;
+use crate::position::{Position, PositionError};
+
+/// 指し手の行をこの幅で折り返す。
+const LINE_WIDTH: usize = 79;
+
+/// PGNの `Termination` タグの値。
+fn termination_tag(termination: Termination) -> &'static str {
+    match termination {
+        Termination::NoLegalMoves
+        | Termination::Resignation
+        | Termination::Repetition
+        | Termination::Declaration => "normal",
+        Termination::MaxPlies | Termination::EnteringKing => "adjudication",
+        Termination::PerpetualCheck | Termination::IllegalDeclaration => "rules infraction",
+    }
+}
+
+/// 1局をPGNにする。`event` と `round` はタグにそのまま書く。
+pub fn game_to_pgn(
+    record: &GameRecord,
+    black_name: &str,
+    white_name: &str,
+    event: &str,
+    round: &str,
+) -> Result<String, PositionError> {
+    let start = Position::from_sfen(&record.start_sfen)?;
+    let result = result_label(record);
+    let mut out = String::new();
+    for (tag, value) in [
+        ("Event", event),
+        ("Round", round),
+        ("Black", black_name),
+        ("White", white_name),
+        ("Result", result),
+        ("SFEN", &record.start_sfen),
+        ("Termination", termination_tag(record.termination)),
+    ] {
+        out.push_str(&format!("[{tag} \"{}\"]\n", escape(value)));
+    }
+    out.push('\n');
+
+    let mut tokens = Vec::with_capacity(record.moves.len() * 3 / 2 + 1);
+    let mut mover = start.side_to_move();
+    for (index, mv) in record.moves.iter().enumerate() {
+        let number = (index + usize::from(start.side_to_move() == Color::White)) / 2 + 1;
+        if mover == Color::Black {
+            tokens.push(format!("{number}."));
+        } else if index == 0 {
+            tokens.push(format!("{number}..."));
+        }
+        tokens.push(mv.to_usi());
+        mover = mover.opponent();
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    out.push_str(&line);
+    out.push_str("\n\n");
+    Ok(out)
+}
+
+/// タグの値の `\` と `"` をエスケープする。
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::INITIAL_SFEN;
+    use std::time::Duration;
+
+    #[test]
+    fn moves_are_numbered_from_the_side_to_move() {
+        let start = Position::from_sfen(INITIAL_SFEN).unwrap();
+        let moves: Vec<_> = ["1e1d", "5a5b", "1d1c"]
+            .iter()
+            .scan(start, |position, token| {
+                let mv = position.parse_usi_move(token).unwrap();
+                position.play_move_mut(&mv).unwrap();
+                Some(mv)
+            })
+            .collect();
+        let record = GameRecord {
+            start_sfen: INITIAL_SFEN.to_string(),
+            move_times: vec![Duration::ZERO; moves.len()],
+            moves,
+            winner: Some(Color::Black),
+            termination: Termination::Resignation,
+        };
+        let pgn = game_to_pgn(&record, "a \"b\"", "c", "test", "1").unwrap();
+        assert!(pgn.starts_with("[Event \"test\"]\n[Round \"1\"]\n[Black \"a \\\"b\\\"\"]\n"));
+        assert!(pgn.contains("[Result \"1-0\"]\n"));
+        assert!(pgn.ends_with("\n\n1. 1e1d 5a5b 2. 1d1c 1-0\n\n"));
+
+        let from_white = GameRecord {
+            start_sfen: "rbsgk/4p/5/P4/KGSBR w - 1".to_string(),
+            moves: vec![record.moves[1]],
+            move_times: vec![Duration::ZERO],
+            winner: None,
+            termination: Termination::MaxPlies,
+        };
+        let pgn = game_to_pgn(&from_white, "a", "c", "test", "2").unwrap();
+        assert!(pgn.ends_with("\n\n1... 5a5b 1/2-1/2\n\n"));
+        assert!(pgn.contains("[Termination \"adjudication\"]\n"));
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::string::String;
 use core::fmt;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -37,6 +38,66 @@ pub enum PieceKind {
 
 pub const PIECE_KIND_COUNT: usize = 10;
 
+/// 駒の動き方。利きの計算はこれで分岐する。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Movement {
+    King,
+    /// 金と、金と同じ動きの成駒。
+    Gold,
+    Silver,
+    Pawn,
+    Bishop,
+    Rook,
+    /// 角の利きに玉の利きを足したもの。
+    Horse,
+    /// 飛車の利きに玉の利きを足したもの。
+    Dragon,
+}
+
+/// 駒種ごとの性質。駒種を足すときはこの表に1行足せばよい。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PieceInfo {
+    pub movement: Movement,
+    /// 成った後の駒種。成れない駒は `None`。
+    pub promoted: Option<PieceKind>,
+    /// 成る前の駒種。成駒でなければ自身。
+    pub base: PieceKind,
+}
+
+const fn info(movement: Movement, promoted: Option<PieceKind>, base: PieceKind) -> PieceInfo {
+    PieceInfo {
+        movement,
+        promoted,
+        base,
+    }
+}
+
+/// `PieceKind` の値の順に並べた駒の性質。
+const PIECE_INFO: [PieceInfo; PIECE_KIND_COUNT] = [
+    info(Movement::King, None, PieceKind::King),
+    info(Movement::Gold, None, PieceKind::Gold),
+    info(
+        Movement::Silver,
+        Some(PieceKind::PromotedSilver),
+        PieceKind::Silver,
+    ),
+    info(Movement::Gold, None, PieceKind::Silver),
+    info(
+        Movement::Bishop,
+        Some(PieceKind::PromotedBishop),
+        PieceKind::Bishop,
+    ),
+    info(Movement::Horse, None, PieceKind::Bishop),
+    info(
+        Movement::Rook,
+        Some(PieceKind::PromotedRook),
+        PieceKind::Rook,
+    ),
+    info(Movement::Dragon, None, PieceKind::Rook),
+    info(Movement::Pawn, Some(PieceKind::Tokin), PieceKind::Pawn),
+    info(Movement::Gold, None, PieceKind::Pawn),
+];
+
 impl PieceKind {
     pub const fn index(self) -> usize {
         self as usize
@@ -57,45 +118,37 @@ impl PieceKind {
         ]
     }
 
+    /// 駒種の性質。
+    pub const fn info(self) -> &'static PieceInfo {
+        &PIECE_INFO[self as usize]
+    }
+
+    pub const fn movement(self) -> Movement {
+        self.info().movement
+    }
+
     pub const fn is_promoted(self) -> bool {
-        matches!(
-            self,
-            Self::PromotedSilver | Self::PromotedBishop | Self::PromotedRook | Self::Tokin
-        )
+        self.info().base as usize != self as usize
     }
 
     pub const fn can_promote(self) -> bool {
-        matches!(self, Self::Silver | Self::Bishop | Self::Rook | Self::Pawn)
+        self.info().promoted.is_some()
     }
 
     pub const fn promote(self) -> Option<Self> {
-        match self {
-            Self::Silver => Some(Self::PromotedSilver),
-            Self::Bishop => Some(Self::PromotedBishop),
-            Self::Rook => Some(Self::PromotedRook),
-            Self::Pawn => Some(Self::Tokin),
-            _ => None,
-        }
+        self.info().promoted
     }
 
     pub const fn demote(self) -> Option<Self> {
-        match self {
-            Self::PromotedSilver => Some(Self::Silver),
-            Self::PromotedBishop => Some(Self::Bishop),
-            Self::PromotedRook => Some(Self::Rook),
-            Self::Tokin => Some(Self::Pawn),
-            _ => None,
+        if self.is_promoted() {
+            Some(self.info().base)
+        } else {
+            None
         }
     }
 
     pub const fn base(self) -> Self {
-        match self {
-            Self::PromotedSilver => Self::Silver,
-            Self::PromotedBishop => Self::Bishop,
-            Self::PromotedRook => Self::Rook,
-            Self::Tokin => Self::Pawn,
-            other => other,
-        }
+        self.info().base
     }
 
     pub fn sfen_letter(self) -> char {
@@ -157,6 +210,23 @@ impl Piece {
         Self { color, kind }
     }
 
+    /// 成った駒。成れない駒なら理由を返す。
+    pub fn promoted(self) -> Result<Self, PromotionError> {
+        match self.kind.promote() {
+            Some(kind) => Ok(Self::new(self.color, kind)),
+            None if self.kind.is_promoted() => Err(PromotionError::AlreadyPromoted(self)),
+            None => Err(PromotionError::Unpromotable(self)),
+        }
+    }
+
+    /// 成る前の駒。成駒でなければ理由を返す。
+    pub fn demoted(self) -> Result<Self, PromotionError> {
+        self.kind
+            .demote()
+            .map(|kind| Self::new(self.color, kind))
+            .ok_or(PromotionError::NotPromoted(self))
+    }
+
     pub fn to_sfen(self) -> String {
         let mut txt = String::new();
         if self.kind.is_promoted() {
@@ -176,3 +246,60 @@ impl fmt::Display for Piece {
         f.write_str(&self.to_sfen())
     }
 }
+
+/// 駒を成らせる・元に戻す変換の失敗。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromotionError {
+    /// 玉と金。成れない駒種。
+    Unpromotable(Piece),
+    /// すでに成っている。
+    AlreadyPromoted(Piece),
+    /// 成駒でないので元に戻せない。
+    NotPromoted(Piece),
+}
+
+impl fmt::Display for PromotionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unpromotable(piece) => write!(f, "{piece} cannot promote"),
+            Self::AlreadyPromoted(piece) => write!(f, "{piece} is already promoted"),
+            Self::NotPromoted(piece) => write!(f, "{piece} is not promoted"),
+        }
+    }
+}
+
+impl core::error::Error for PromotionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn promotion_table_is_consistent() {
+        for kind in PieceKind::all() {
+            if let Some(promoted) = kind.promote() {
+                assert!(!kind.is_promoted());
+                assert_eq!(promoted.demote(), Some(kind));
+                assert_eq!(promoted.base(), kind);
+            }
+            assert_eq!(kind.base().base(), kind.base());
+        }
+        assert_eq!(PieceKind::Tokin.movement(), Movement::Gold);
+    }
+
+    #[test]
+    fn piece_promotion_reports_why_it_fails() {
+        let silver = Piece::new(Color::White, PieceKind::Silver);
+        let promoted = silver.promoted().unwrap();
+        assert_eq!(promoted.kind, PieceKind::PromotedSilver);
+        assert_eq!(promoted.demoted(), Ok(silver));
+        assert_eq!(
+            promoted.promoted(),
+            Err(PromotionError::AlreadyPromoted(promoted))
+        );
+        let gold = Piece::new(Color::Black, PieceKind::Gold);
+        assert_eq!(gold.promoted().unwrap_err().to_string(), "G cannot promote");
+        assert_eq!(gold.demoted(), Err(PromotionError::NotPromoted(gold)));
+    }
+}
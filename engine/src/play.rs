@@ -0,0 +1,368 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use crate::game::{Game, GameStatus};
+use crate::handicap::Handicap;
+use crate::kif;
+use crate::locale::{Locale, Message};
+use crate::match_runner::{ColorLimits, parse_color_limit, parse_value};
+use crate::moves::Move;
+use crate::piece::{Color, Piece};
+use crate::position::{Position, PositionError};
+use crate::rules::{Rules, StandardRules};
+use crate::search::{NullInfoSink, SearchBackend, SearchLimits, Searcher};
+
+const HELP: &str = "\
+commands:
+  <move>     play a move in USI (1e1d, G*2b) or Western notation (R-1d, Sx2b+, G*2b)
+  hint       show the engine's suggestion
+  undo       take back your last move (and the engine's reply)
+  level <n>  set the engine's search depth
+  board      redraw the board
+  help       show this help
+  quit       leave the game";
+
+/// 人間との対局の設定。
+#[derive(Clone, Debug)]
+pub struct PlayOptions {
+    pub start: Position,
+    /// 人間が持つ側。
+    pub human: Color,
+    pub depth: usize,
+    /// 盤面図・指し手の表記・決まり文句の言語。
+    pub locale: Locale,
+    /// 手番の色ごとの上書き（`Color::index` 順）。エンジンの手とヒントに使う。
+    pub color_limits: [ColorLimits; 2],
+}
+
+impl PlayOptions {
+    pub fn new() -> Result<Self, PositionError> {
+        Ok(Self {
+            start: Position::initial()?,
+            human: Color::Black,
+            depth: SearchLimits::default().depth,
+            locale: Locale::English,
+            color_limits: Default::default(),
+        })
+    }
+}
+
+/// 盤面と持ち駒を端末向けに描く。先手の駒は大文字。
+pub fn render_board(position: &Position) -> String {
+    let hand = |color: Color| {
+        let text = position.hand(color).to_sfen(color == Color::White);
+        if text.is_empty() {
+            "-".to_string()
+        } else {
+            text
+        }
+    };
+    let mut out = format!("white hand: {}\n", hand(Color::White));
+    let board = position.render_attack_map(Color::Black);
+    for line in board.lines() {
+        out.push_str(line.replace('*', " ").trim_end());
+        out.push('\n');
+    }
+    out.push_str(&format!("black hand: {}\n", hand(Color::Black)));
+    let side = match position.side_to_move() {
+        Color::Black => "black",
+        Color::White => "white",
+    };
+    out.push_str(&format!("ply {}, {side} to move\n", position.ply()));
+    out
+}
+
+/// `locale` に合わせて盤面を描く。日本語ではKIFの盤面図に手数と手番を添える。
+pub fn render_board_in(position: &Position, locale: Locale) -> String {
+    match locale {
+        Locale::English => render_board(position),
+        Locale::Japanese => format!(
+            "{}\n{}手目　{}番\n",
+            kif::board_diagram(position),
+            position.ply(),
+            locale.color_name(position.side_to_move())
+        ),
+    }
+}
+
+/// 合法手 `mv` の西洋式表記。移動元を省いた短い形と、移動元を含む長い形を返す。
+/// 成れる手は成りなら `+`、不成なら `=` を付ける。
+fn western_notations(position: &Position, mv: &Move) -> [String; 2] {
+    let piece = Piece::new(Color::Black, mv.piece).to_sfen();
+    let Some(from) = mv.from else {
+        let drop = format!("{piece}*{}", mv.to);
+        return [drop.clone(), drop];
+    };
+    let separator = if position.piece_at(mv.to).is_some() {
+        'x'
+    } else {
+        '-'
+    };
+    let color = position.side_to_move();
+    let suffix = if mv.promote {
+        "+"
+    } else if StandardRules.can_promote(color, mv.piece, from, mv.to) {
+        "="
+    } else {
+        ""
+    };
+    [
+        format!("{piece}{separator}{}{suffix}", mv.to),
+        format!("{piece}{from}{separator}{}{suffix}", mv.to),
+    ]
+}
+
+/// 西洋式表記の短い形。
+pub fn to_western(position: &Position, mv: &Move) -> String {
+    let [short, _] = western_notations(position, mv);
+    short
+}
+
+/// USI形式か西洋式表記の指し手を現局面の合法手として解釈する。
+pub fn parse_move(position: &Position, token: &str) -> Result<Move, PositionError> {
+    if let Ok(mv) = position.parse_usi_move(token) {
+        return Ok(mv);
+    }
+    let candidates: Vec<Move> = position
+        .generate_legal_moves()?
+        .into_iter()
+        .filter(|mv| western_notations(position, mv).iter().any(|n| n == token))
+        .collect();
+    match candidates.as_slice() {
+        [mv] => Ok(*mv),
+        [] => Err(PositionError::message(format!("illegal move: {token}"))),
+        _ => Err(PositionError::message(format!(
+            "ambiguous move: {token} (add the origin square, e.g. {})",
+            western_notations(position, &candidates[0])[1]
+        ))),
+    }
+}
+
+fn replay(start: &Position, moves: &[Move]) -> Result<Game, PositionError> {
+    let mut game = Game::new(start.clone());
+    for mv in moves {
+        game.play(mv)?;
+    }
+    Ok(game)
+}
+
+fn describe(position: &Position, mv: &Move, locale: Locale) -> String {
+    let notation = match locale {
+        Locale::English => to_western(position, mv),
+        Locale::Japanese => kif::move_to_kif(position, mv, None),
+    };
+    format!("{} ({notation})", mv.to_usi())
+}
+
+/// 端末での対局を `input` と `output` で進める。
+pub fn run_session<R: BufRead, W: Write>(
+    options: PlayOptions,
+    input: R,
+    mut output: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut searcher = Searcher::new();
+    searcher.set_info_sink(Box::new(NullInfoSink));
+    searcher.set_handicap(Handicap::detect(&options.start));
+    let mut depth = options.depth.max(1);
+    let locale = options.locale;
+    let mut moves: Vec<Move> = Vec::new();
+    let mut lines = input.lines();
+    let mut redraw = true;
+
+    loop {
+        let game = replay(&options.start, &moves)?;
+        let position = game.position();
+        let status = game.status()?;
+        if redraw {
+            write!(output, "{}", render_board_in(position, locale))?;
+            if status.is_over() {
+                writeln!(
+                    output,
+                    "{}: {}",
+                    locale.text(Message::GameOver),
+                    locale.game_status(status)
+                )?;
+            }
+            redraw = false;
+        }
+
+        if status == GameStatus::Ongoing && position.side_to_move() != options.human {
+            let limits = session_limits(&options, depth, position.side_to_move());
+            let result = searcher.search(position, limits)?;
+            let Some(best) = result.best_move else {
+                writeln!(output, "{}", locale.text(Message::EngineResigns))?;
+                return Ok(());
+            };
+            writeln!(
+                output,
+                "{} {}",
+                locale.text(Message::EnginePlays),
+                describe(position, &best, locale)
+            )?;
+            moves.push(best);
+            redraw = true;
+            continue;
+        }
+
+        write!(output, "> ")?;
+        output.flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        let Some(command) = parts.next() else {
+            continue;
+        };
+        match command {
+            "quit" | "exit" => break,
+            "help" => writeln!(output, "{HELP}")?,
+            "board" => redraw = true,
+            "level" => match parts.next().and_then(|v| v.parse::<usize>().ok()) {
+                Some(level) if level > 0 => {
+                    depth = level;
+                    writeln!(output, "search depth set to {depth}")?;
+                }
+                _ => writeln!(output, "level requires a positive depth")?,
+            },
+            "hint" => {
+                if status.is_over() {
+                    writeln!(output, "game is over")?;
+                    continue;
+                }
+                let limits = session_limits(&options, depth, position.side_to_move());
+                let result = searcher.search(position, limits)?;
+                match result.best_move {
+                    Some(best) => writeln!(
+                        output,
+                        "{}: {} score {}",
+                        locale.text(Message::Hint),
+                        describe(position, &best, locale),
+                        result.score.value()
+                    )?,
+                    None => writeln!(output, "no legal moves")?,
+                }
+            }
+            "undo" => {
+                if moves.is_empty() {
+                    writeln!(output, "nothing to undo")?;
+                    continue;
+                }
+                moves.pop();
+                while !moves.is_empty()
+                    && replay(&options.start, &moves)?.position().side_to_move() != options.human
+                {
+                    moves.pop();
+                }
+                redraw = true;
+            }
+            token => {
+                if status.is_over() {
+                    writeln!(output, "game is over")?;
+                    continue;
+                }
+                match parse_move(position, token) {
+                    Ok(mv) => {
+                        moves.push(mv);
+                        redraw = true;
+                    }
+                    Err(err) => writeln!(output, "{err}")?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `side` の手を探すときの制限。`level` で変えた深さに色ごとの上書きを当てる。
+fn session_limits(options: &PlayOptions, depth: usize, side: Color) -> SearchLimits {
+    options.color_limits[side.index()].apply(SearchLimits {
+        depth,
+        ..SearchLimits::default()
+    })
+}
+
+/// `play` サブコマンド。
+///
+/// `play [--depth D] [--black-depth D] [--white-depth D] [--black-random R] [--white-random R]
+///       [--white] [--sfen SFEN] [--handicap rook|bishop|rook-bishop] [--locale en|ja]`
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut options = PlayOptions::new()?;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--depth" => options.depth = parse_value(flag, iter.next())?,
+            "--white" => options.human = Color::White,
+            "--handicap" => {
+                let name = parse_value::<String>(flag, iter.next())?;
+                let handicap =
+                    Handicap::from_name(&name).ok_or(format!("unknown handicap: {name}"))?;
+                options.start = Position::from_sfen(handicap.sfen())?;
+            }
+            "--locale" => {
+                let name = parse_value::<String>(flag, iter.next())?;
+                options.locale =
+                    Locale::from_name(&name).ok_or(format!("unknown locale: {name}"))?;
+            }
+            "--sfen" => {
+                let sfen = parse_value::<String>(flag, iter.next())?;
+                options.start = Position::from_sfen(&sfen)?;
+            }
+            other if parse_color_limit(other, &mut iter, &mut options.color_limits)? => {}
+            other => return Err(format!("unknown play option: {other}").into()),
+        }
+    }
+    if options
+        .color_limits
+        .iter()
+        .any(|limits| limits.go.is_some())
+    {
+        return Err("play has no USI engine to send a go command to".into());
+    }
+    println!("type 'help' for commands");
+    run_session(options, io::stdin().lock(), io::stdout())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn western_notation_is_accepted() {
+        let position = Position::initial().unwrap();
+        let mv = parse_move(&position, "R-1d").expect("rook move");
+        assert_eq!(mv.to_usi(), "1e1d");
+        assert_eq!(parse_move(&position, "R1e-1d").unwrap(), mv);
+        assert_eq!(to_western(&position, &mv), "R-1d");
+        assert!(parse_move(&position, "R-1a").is_err());
+    }
+
+    #[test]
+    fn session_plays_hints_and_undoes() {
+        let options = PlayOptions {
+            depth: 1,
+            ..PlayOptions::new().unwrap()
+        };
+        let input = b"hint\nR-1d\nundo\nfoo\nquit\n";
+        let mut output = Vec::new();
+        run_session(options, &input[..], &mut output).expect("session");
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("hint: "));
+        assert!(text.contains("engine plays "));
+        assert!(text.contains("illegal move: foo"));
+        // 取り消した後は初期局面に戻る。
+        assert!(text.matches("ply 1, black to move").count() >= 2);
+
+        let options = PlayOptions {
+            depth: 1,
+            locale: Locale::Japanese,
+            ..PlayOptions::new().unwrap()
+        };
+        let mut output = Vec::new();
+        run_session(options, &b"1e1d\nquit\n"[..], &mut output).expect("session");
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("先手の持駒：なし"));
+        assert!(text.contains("1手目　先手番"));
+        assert!(text.contains("エンジンの指し手 "));
+    }
+}
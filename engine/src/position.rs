@@ -1,15 +1,30 @@
-use std::fmt;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
 
 use crate::attacks;
 use crate::bitboard::Bitboard;
-use crate::board::{BOARD_FILES, BOARD_RANKS, BOARD_SQUARES, Square};
-use crate::hand::{Hand, HandPieceKind};
+use crate::board::{BOARD_FILES, BOARD_RANKS, BOARD_SQUARES, Square, all_squares};
+use crate::hand::{HAND_MAX_COUNT, Hand, HandPieceKind};
+use crate::material::MaterialSignature;
 use crate::moves::{Move, MoveList};
 use crate::piece::{COLORS, Color, PIECE_KIND_COUNT, Piece, PieceKind};
+use crate::rules::{ForcedPromotion, Rules, StandardRules, promotion_zone_mask};
+use crate::trace::{self, Section};
 use crate::zobrist;
 
 pub const INITIAL_SFEN: &str = "rbsgk/4p/5/P4/KGSBR b - 1";
 
+/// 入玉とみなすために敵陣側の半分に必要な、玉以外の味方の駒の数。
+pub const ENTERING_KING_SUPPORT: usize = 2;
+
+/// 千日手が成立する同一局面の出現回数。
+pub const REPETITION_LIMIT: usize = 4;
+
+/// 繰り返しの数え上げを省くための、ハッシュの下位ビットごとの出現数の表の大きさ。
+const REPETITION_FILTER_SIZE: usize = 256;
+
 #[derive(Debug)]
 pub enum PositionError {
     Format(&'static str),
@@ -31,18 +46,189 @@ impl fmt::Display for PositionError {
     }
 }
 
-impl std::error::Error for PositionError {}
+impl core::error::Error for PositionError {}
+
+/// 指せない手の理由。`Position::explain_illegal` が返す。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IllegalReason {
+    /// 移動元に駒がない。
+    NoPiece(Square),
+    /// 移動元の駒が手番でない側のもの。
+    WrongSide(Square),
+    /// 移動元の駒が手の駒種と違う。
+    PieceMismatch { square: Square, found: Piece },
+    /// 駒の動きでは行けないか、間に駒があって届かない。
+    Unreachable { from: Square, to: Square },
+    /// 移動先に自分の駒がある。
+    OwnPiece(Square),
+    /// 成れない駒か、敵陣に関わらない手での成り。打つ手の成りも含む。
+    CannotPromote,
+    /// 行き所のない駒になる不成。
+    MustPromote,
+    /// 玉や成駒のように、持ち駒にならない駒を打つ。
+    Undroppable(PieceKind),
+    /// 持っていない駒を打つ。
+    NotInHand(PieceKind),
+    /// 駒のあるマスへ打つ。
+    DropOnOccupied(Square),
+    /// 行き所のないマスへ打つ。
+    DeadDrop(Square),
+    /// 二歩。
+    DoublePawn(Square),
+    /// 打ち歩詰め。
+    PawnDropMate,
+    /// 自玉を取られる形になる。王手の放置、釘付けの駒の移動、利きへの玉の移動。
+    KingInCheck,
+}
+
+impl fmt::Display for IllegalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoPiece(square) => write!(f, "no piece on {square}"),
+            Self::WrongSide(square) => write!(f, "the piece on {square} belongs to the opponent"),
+            Self::PieceMismatch { square, found } => write!(f, "the piece on {square} is {found}"),
+            Self::Unreachable { from, to } => write!(f, "the piece on {from} cannot reach {to}"),
+            Self::OwnPiece(square) => write!(f, "own piece on {square}"),
+            Self::CannotPromote => write!(f, "cannot promote"),
+            Self::MustPromote => write!(f, "must promote"),
+            Self::Undroppable(kind) => {
+                write!(f, "{} cannot be dropped", Piece::new(Color::Black, *kind))
+            }
+            Self::NotInHand(kind) => write!(f, "no {} in hand", Piece::new(Color::Black, *kind)),
+            Self::DropOnOccupied(square) => write!(f, "{square} is occupied"),
+            Self::DeadDrop(square) => write!(f, "the piece could never move from {square}"),
+            Self::DoublePawn(square) => write!(f, "two pawns on file {}", square.file() + 1),
+            Self::PawnDropMate => write!(f, "pawn drop mate"),
+            Self::KingInCheck => write!(f, "leaves the king in check"),
+        }
+    }
+}
+
+/// 1マスの変化。`before` は元の局面、`after` は比較先の局面の駒。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SquareChange {
+    pub square: Square,
+    pub before: Option<Piece>,
+    pub after: Option<Piece>,
+}
+
+/// 持ち駒の増減（比較先 − 元）。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandDelta {
+    pub color: Color,
+    pub kind: HandPieceKind,
+    pub delta: i32,
+}
+
+/// 1手で盤上から消えた駒と現れた駒。移動は同じ駒が消えて現れたものとして表し、
+/// 成りなら現れる駒の種類が変わる。打つ手は `removed` が、取られた駒は `added` が `None`。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyPiece {
+    pub removed: Option<(Square, Piece)>,
+    pub added: Option<(Square, Piece)>,
+}
+
+/// 1手で変わった持ち駒。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandChange {
+    pub color: Color,
+    pub kind: HandPieceKind,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// 直前の1手で変わった駒と持ち駒。NNUEのアキュムレータなど、評価関数が
+/// 盤全体を読み直さずに変わった駒の分だけ更新するのに使う。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirtyPieces {
+    /// 指した駒と、取られた駒（あれば）。
+    pub pieces: [Option<DirtyPiece>; 2],
+    pub hand: Option<HandChange>,
+}
+
+impl DirtyPieces {
+    pub fn iter(&self) -> impl Iterator<Item = &DirtyPiece> {
+        self.pieces.iter().flatten()
+    }
+}
+
+/// 2つの局面の差分。GUIのアニメーションや、SFENの代わりに差分だけを送る用途に使う。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionDiff {
+    /// 駒が変わったマス（マス番号順）。
+    pub squares: Vec<SquareChange>,
+    pub hands: Vec<HandDelta>,
+    pub side_to_move_changed: bool,
+}
+
+impl PositionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.squares.is_empty() && self.hands.is_empty() && !self.side_to_move_changed
+    }
+}
+
+/// 王手の種類。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckKind {
+    None,
+    /// 動いた駒による王手。
+    Single,
+    /// 動いた駒の後ろから開いた利きによる王手（開き王手）。
+    Discovered,
+    /// 2枚の駒による王手。玉が動く以外に受けがない。
+    Double,
+}
+
+/// 手番側にかかっている王手の内容。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckInfo {
+    pub kind: CheckKind,
+    /// 王手をかけている駒。
+    pub checkers: Bitboard,
+}
+
+/// `Position::make_move` で指す前の状態。`Position::unmake_move` に渡して手を戻す。
+#[derive(Clone, Debug)]
+pub struct MoveUndo {
+    /// 履歴を持たない、指す前の局面。
+    saved: Position,
+    history_len: usize,
+}
+
+/// SFENの読み方。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SfenMode {
+    /// 4つの欄を1つの空白で区切った、規格どおりのSFENだけを受け付ける。
+    Strict,
+    /// GUIや手書きのファイルにある崩れを許す。欄の間の余分な空白、手数の省略（1手目とみなす）、
+    /// 持ち駒の `0` や `0P` のような0枚の表記を受け付ける。
+    #[default]
+    Lenient,
+}
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Position {
     board: [Option<Piece>; BOARD_SQUARES],
     bitboards: [[Bitboard; PIECE_KIND_COUNT]; 2],
     occupancy: [Bitboard; 2],
     hands: [Hand; 2],
+    /// 駒割り。`hash` と同じく駒と持ち駒の出入りのたびに差分で更新する。
+    material: MaterialSignature,
     side_to_move: Color,
     ply: u32,
     hash: u64,
     history: Vec<u64>,
+    /// `history` の各キーを `REPETITION_FILTER_SIZE` で割った余りごとの出現数。
+    /// 0や1なら履歴をたどらずに繰り返しの数が分かる。
+    repetition_filter: [u8; REPETITION_FILTER_SIZE],
+    /// 最後に駒を取る手・打つ手・歩を進める手を指した直後の局面の手数。
+    last_irreversible_ply: u32,
+    /// 手番側の玉に王手をかけている相手の駒。局面が変わるたびに更新する。
+    checkers: Bitboard,
+    /// 直前の指し手の移動先。開き王手の判定に使う。
+    last_move_to: Option<Square>,
+    /// 直前の指し手で変わった駒。指し手以外で局面を変えたら `None`。
+    dirty: Option<DirtyPieces>,
 }
 
 impl Position {
@@ -52,10 +238,16 @@ impl Position {
             bitboards: [[Bitboard::EMPTY; PIECE_KIND_COUNT]; 2],
             occupancy: [Bitboard::EMPTY; 2],
             hands: [Hand::default(), Hand::default()],
+            material: MaterialSignature::default(),
             side_to_move: Color::Black,
             ply: 1,
             hash: 0,
             history: Vec::new(),
+            repetition_filter: [0; REPETITION_FILTER_SIZE],
+            last_irreversible_ply: 1,
+            checkers: Bitboard::EMPTY,
+            last_move_to: None,
+            dirty: None,
         }
     }
 
@@ -71,6 +263,7 @@ impl Position {
         if color != self.side_to_move {
             self.hash ^= zobrist::side_to_move();
             self.side_to_move = color;
+            self.update_checkers();
         }
     }
 
@@ -87,6 +280,20 @@ impl Position {
     }
 
     pub fn set_piece(&mut self, square: Square, piece: Piece) -> Result<(), PositionError> {
+        self.put_piece(square, piece)?;
+        self.update_checkers();
+        self.dirty = None;
+        Ok(())
+    }
+
+    pub fn remove_piece(&mut self, square: Square) -> Option<Piece> {
+        let piece = self.take_piece(square);
+        self.update_checkers();
+        self.dirty = None;
+        piece
+    }
+
+    fn put_piece(&mut self, square: Square, piece: Piece) -> Result<(), PositionError> {
         if self.board[square.index() as usize].is_some() {
             return Err(PositionError::message(format!(
                 "square {} is already occupied",
@@ -96,16 +303,18 @@ impl Position {
         self.board[square.index() as usize] = Some(piece);
         self.bitboards[piece.color.index()][piece.kind as usize].insert(square);
         self.occupancy[piece.color.index()].insert(square);
+        self.material.add_board(piece.color, piece.kind);
         self.hash ^= zobrist::piece_square(piece.color, piece.kind, square);
         Ok(())
     }
 
-    pub fn remove_piece(&mut self, square: Square) -> Option<Piece> {
+    fn take_piece(&mut self, square: Square) -> Option<Piece> {
         if let Some(piece) = self.board[square.index() as usize] {
             self.hash ^= zobrist::piece_square(piece.color, piece.kind, square);
             self.board[square.index() as usize] = None;
             self.bitboards[piece.color.index()][piece.kind as usize].remove(square);
             self.occupancy[piece.color.index()].remove(square);
+            self.material.remove_board(piece.color, piece.kind);
             Some(piece)
         } else {
             None
@@ -133,8 +342,12 @@ impl Position {
         &self.hands[color.index()]
     }
 
-    pub fn hand_mut(&mut self, color: Color) -> &mut Hand {
-        &mut self.hands[color.index()]
+    /// `color` の持ち駒の `kind` を `count` 枚にする。ハッシュと駒割りも合わせて変える。
+    pub fn set_hand_count(&mut self, color: Color, kind: HandPieceKind, count: u8) {
+        let old = self.hands[color.index()].count(kind);
+        self.hands[color.index()].set(kind, count);
+        self.update_hand_hash(color, kind, old, count);
+        self.dirty = None;
     }
 
     pub fn clear(&mut self) {
@@ -144,11 +357,14 @@ impl Position {
         }
         self.occupancy = [Bitboard::EMPTY; 2];
         self.hands = [Hand::default(), Hand::default()];
+        self.material = MaterialSignature::default();
         self.side_to_move = Color::Black;
         self.ply = 1;
         self.hash = 0;
-        self.history.clear();
-        self.history.push(self.hash);
+        self.reset_history();
+        self.checkers = Bitboard::EMPTY;
+        self.last_move_to = None;
+        self.dirty = None;
     }
 
     fn switch_side(&mut self) {
@@ -159,6 +375,12 @@ impl Position {
     fn update_hand_hash(&mut self, color: Color, kind: HandPieceKind, old: u8, new: u8) {
         self.hash ^= zobrist::hand(color, kind, old as usize);
         self.hash ^= zobrist::hand(color, kind, new as usize);
+        self.material.set_hand(color, kind, new);
+    }
+
+    /// 盤上と持ち駒の駒割り。
+    pub fn material(&self) -> MaterialSignature {
+        self.material
     }
 
     pub fn zobrist_key(&self) -> u64 {
@@ -167,16 +389,51 @@ impl Position {
 
     pub fn current_repetition_count(&self) -> usize {
         match self.history.last() {
+            Some(&last) if self.repetition_filter[filter_slot(last)] == 1 => 1,
             Some(&last) => self.repetition_count(last),
             None => 0,
         }
     }
 
     pub fn repetition_count(&self, key: u64) -> usize {
+        if self.repetition_filter[filter_slot(key)] == 0 {
+            return 0;
+        }
         self.history.iter().filter(|&&k| k == key).count()
     }
 
+    /// 最後に駒を取る手・打つ手・歩を進める手を指した直後の局面の手数。
+    /// そのような手がなければ、履歴を始めた局面の手数。
+    pub fn last_irreversible_ply(&self) -> u32 {
+        self.last_irreversible_ply
+    }
+
+    /// 千日手が成立したか。盤面・持ち駒・手番が同じ局面が `REPETITION_LIMIT` 回現れたときに
+    /// 成立する。手数や、途中で王手が続いたかは問わない（連続王手の判定は `Game` が行う）。
+    pub fn is_repetition(&self) -> bool {
+        self.current_repetition_count() >= REPETITION_LIMIT
+    }
+
+    fn push_history(&mut self) {
+        self.history.push(self.hash);
+        let count = &mut self.repetition_filter[filter_slot(self.hash)];
+        *count = count.saturating_add(1);
+    }
+
+    fn reset_history(&mut self) {
+        self.history.clear();
+        self.repetition_filter = [0; REPETITION_FILTER_SIZE];
+        self.push_history();
+        self.last_irreversible_ply = self.ply;
+    }
+
     fn recompute_hash(&mut self) {
+        self.refresh_hash();
+        self.reset_history();
+    }
+
+    /// 盤面からハッシュと王手を計算し直す。履歴には触れない。
+    fn refresh_hash(&mut self) {
         self.hash = 0;
         for idx in 0..BOARD_SQUARES {
             if let Some(piece) = self.board[idx] {
@@ -186,71 +443,198 @@ impl Position {
         }
         for color in COLORS {
             for hand_kind in HandPieceKind::all() {
-                let count = self.hands[color.index()].count(hand_kind) as usize;
-                self.hash ^= zobrist::hand(color, hand_kind, count);
+                let count = self.hands[color.index()].count(hand_kind);
+                self.hash ^= zobrist::hand(color, hand_kind, count as usize);
+                self.material.set_hand(color, hand_kind, count);
             }
         }
         if self.side_to_move == Color::White {
             self.hash ^= zobrist::side_to_move();
         }
-        self.history.clear();
-        self.history.push(self.hash);
+        self.update_checkers();
     }
 
-    fn promotion_zone(color: Color, square: Square) -> bool {
-        match color {
-            Color::Black => square.rank() == 0,
-            Color::White => square.rank() == (BOARD_RANKS as u8 - 1),
+    fn is_square_attacked(&self, square: Square, by: Color) -> bool {
+        let occ = self.occupancy_all();
+        for kind in PieceKind::all() {
+            let mut pieces = self.pieces(by, kind);
+            while let Some(src) = pieces.pop() {
+                if attacks::piece_attacks(by, kind, src, occ).contains(square) {
+                    return true;
+                }
+            }
         }
+        false
     }
 
-    fn can_promote(color: Color, kind: PieceKind, from: Square, to: Square) -> bool {
-        if !kind.can_promote() {
-            return false;
+    /// `square` に利いている `by` の駒。
+    pub fn attackers_to(&self, square: Square, by: Color) -> Bitboard {
+        let occ = self.occupancy_all();
+        let mut attackers = Bitboard::EMPTY;
+        for kind in PieceKind::all() {
+            for src in self.pieces(by, kind).iter() {
+                if attacks::piece_attacks(by, kind, src, occ).contains(square) {
+                    attackers.insert(src);
+                }
+            }
         }
-        Self::promotion_zone(color, from) || Self::promotion_zone(color, to)
+        attackers
     }
 
-    fn must_promote(color: Color, kind: PieceKind, to: Square) -> bool {
-        matches!(kind, PieceKind::Pawn) && Self::promotion_zone(color, to)
+    fn update_checkers(&mut self) {
+        let color = self.side_to_move;
+        self.checkers = match self.king_square(color) {
+            Some(king) => self.attackers_to(king, color.opponent()),
+            None => Bitboard::EMPTY,
+        };
     }
 
-    fn piece_effect_contains(
-        square: Square,
-        color: Color,
-        kind: PieceKind,
-        target: Square,
-        occ: Bitboard,
-    ) -> bool {
-        let attacks = match kind {
-            PieceKind::King => attacks::king_attacks(square),
-            PieceKind::Gold | PieceKind::PromotedSilver | PieceKind::Tokin => {
-                attacks::gold_attacks(color, square)
-            }
-            PieceKind::Silver => attacks::silver_attacks(color, square),
-            PieceKind::Bishop => attacks::bishop_attacks(square, occ),
-            PieceKind::PromotedBishop => attacks::horse_attacks(square, occ),
-            PieceKind::Rook => attacks::rook_attacks(square, occ),
-            PieceKind::PromotedRook => attacks::dragon_attacks(square, occ),
-            PieceKind::Pawn => attacks::pawn_attacks(color, square),
+    /// 指し手 `mv` の後の王手をかけている駒を、動いた駒の利きと、その駒が空けた筋に
+    /// 通った飛び駒の利きだけから求める。指す前の局面で相手の玉に王手がかかっていない
+    /// （直前の手番側が王手を放置していない）なら、盤全体を調べたのと同じになる。
+    fn update_checkers_after(&mut self, mv: &Move) {
+        let color = self.side_to_move;
+        let mover = color.opponent();
+        let Some(king) = self.king_square(color) else {
+            self.checkers = Bitboard::EMPTY;
+            return;
         };
-        attacks.contains(target)
+        let occ = self.occupancy_all();
+        let mut checkers = Bitboard::EMPTY;
+        if let Some(piece) = self.piece_at(mv.to)
+            && attacks::piece_attacks(mover, piece.kind, mv.to, occ).contains(king)
+        {
+            checkers.insert(mv.to);
+        }
+        if mv.from.is_some() {
+            let rooks =
+                self.pieces(mover, PieceKind::Rook) | self.pieces(mover, PieceKind::PromotedRook);
+            let bishops = self.pieces(mover, PieceKind::Bishop)
+                | self.pieces(mover, PieceKind::PromotedBishop);
+            checkers |= attacks::rook_attacks(king, occ) & rooks;
+            checkers |= attacks::bishop_attacks(king, occ) & bishops;
+        }
+        self.checkers = checkers;
     }
 
-    fn is_square_attacked(&self, square: Square, by: Color) -> bool {
-        let occ = self.occupancy_all();
-        for kind in PieceKind::all() {
-            let mut pieces = self.pieces(by, kind);
-            while let Some(src) = pieces.pop() {
-                if Self::piece_effect_contains(src, by, kind, square, occ) {
-                    return true;
+    /// 手番側の玉に王手をかけている駒。
+    pub fn checkers(&self) -> Bitboard {
+        self.checkers
+    }
+
+    /// 手番側が王手されているか。キャッシュを読むだけなので探索中に何度呼んでもよい。
+    pub fn in_check(&self) -> bool {
+        !self.checkers.is_empty()
+    }
+
+    /// 王手を単純・開き・両王手に分類する。直前の手が分からない局面
+    /// （SFENから作った直後など）では、1枚の王手はすべて `Single` とする。
+    pub fn check_info(&self) -> CheckInfo {
+        let checkers = self.checkers;
+        let kind = if checkers.is_empty() {
+            CheckKind::None
+        } else if checkers.more_than_one() {
+            CheckKind::Double
+        } else if self.last_move_to.is_some_and(|to| !checkers.contains(to)) {
+            CheckKind::Discovered
+        } else {
+            CheckKind::Single
+        };
+        CheckInfo { kind, checkers }
+    }
+
+    /// `mv` が王手になるかを、局面を進めずに調べる。打つ手は間にある駒で利きが
+    /// 遮られるかも考え、盤上の手は開き王手も含める。`mv` は擬似合法手であること。
+    pub fn gives_check(&self, mv: &Move) -> bool {
+        let color = self.side_to_move;
+        let Some(king) = self.king_square(color.opponent()) else {
+            return false;
+        };
+        let mut occ = self.occupancy_all();
+        occ.insert(mv.to);
+        let kind = match mv.from {
+            Some(from) => {
+                occ.remove(from);
+                if mv.promote {
+                    mv.piece.promote().unwrap_or(mv.piece)
+                } else {
+                    mv.piece
                 }
             }
+            None => mv.piece,
+        };
+        if attacks::piece_attacks(color, kind, mv.to, occ).contains(king) {
+            return true;
         }
-        false
+        let Some(from) = mv.from else {
+            return false;
+        };
+        PieceKind::all().into_iter().any(|kind| {
+            self.pieces(color, kind)
+                .iter()
+                .filter(|&src| src != from)
+                .any(|src| attacks::piece_attacks(color, kind, src, occ).contains(king))
+        })
+    }
+
+    /// `color` の玉が敵陣（成れる段）に入り、盤の敵陣側の半分に玉以外の味方の駒が
+    /// `ENTERING_KING_SUPPORT` 枚以上あるか。こうなると玉を詰ますのは難しい。
+    pub fn is_entering_king(&self, color: Color) -> bool {
+        let Some(king) = self.king_square(color) else {
+            return false;
+        };
+        if !promotion_zone_mask(color).contains(king) {
+            return false;
+        }
+        let enemy_half = (BOARD_RANKS - BOARD_RANKS / 2) as u8;
+        let supporters = (self.occupancy(color) & !Bitboard::from_square(king))
+            .iter()
+            .filter(|square| square.relative_rank(color) >= enemy_half)
+            .count();
+        supporters >= ENTERING_KING_SUPPORT
+    }
+
+    /// `color` の駒が利いているマスすべて。
+    pub fn attack_map(&self, color: Color) -> Bitboard {
+        self.attack_map_through(color, self.occupancy_all())
+    }
+
+    /// 盤上の駒を `occ` とみなしたときの、`color` の駒が利いているマス。
+    /// 玉を除いた `occ` を渡せば、玉が逃げた先でも利きが残るマスが分かる。
+    pub fn attack_map_through(&self, color: Color, occ: Bitboard) -> Bitboard {
+        let mut map = Bitboard::EMPTY;
+        for kind in PieceKind::all() {
+            for square in self.pieces(color, kind).iter() {
+                map |= attacks::piece_attacks(color, kind, square, occ);
+            }
+        }
+        map
+    }
+
+    /// 盤面を文字で描き、`color` の利きがあるマスに `*` を付ける。
+    pub fn render_attack_map(&self, color: Color) -> String {
+        let map = self.attack_map(color);
+        let mut out = String::from(" 5  4  3  2  1\n");
+        for rank in 0..BOARD_RANKS as u8 {
+            for file in (0..BOARD_FILES as u8).rev() {
+                let square = Square::from_file_rank(file, rank);
+                let piece = self
+                    .piece_at(square)
+                    .map_or(".".to_string(), Piece::to_sfen);
+                let mark = if map.contains(square) { '*' } else { ' ' };
+                out.push_str(&format!("{piece:>2}{mark}"));
+            }
+            out.push(' ');
+            out.push((b'a' + rank) as char);
+            out.push('\n');
+        }
+        out
     }
 
     pub fn is_in_check(&self, color: Color) -> bool {
+        if color == self.side_to_move {
+            return self.in_check();
+        }
         if let Some(king_sq) = self.king_square(color) {
             self.is_square_attacked(king_sq, color.opponent())
         } else {
@@ -260,6 +644,8 @@ impl Position {
 
     fn apply_move_internal(&mut self, mv: &Move) -> Result<(), PositionError> {
         let color = self.side_to_move;
+        let mut dirty = DirtyPieces::default();
+        let mut irreversible = mv.is_drop() || mv.piece == PieceKind::Pawn;
 
         if mv.is_drop() {
             let hand_kind = HandPieceKind::from_piece_kind(mv.piece)
@@ -276,7 +662,17 @@ impl Position {
                 hand.remove(hand_kind, 1)
             };
             self.update_hand_hash(color, hand_kind, old, new);
-            self.set_piece(mv.to, Piece::new(color, mv.piece))?;
+            self.put_piece(mv.to, Piece::new(color, mv.piece))?;
+            dirty.pieces[0] = Some(DirtyPiece {
+                removed: None,
+                added: Some((mv.to, Piece::new(color, mv.piece))),
+            });
+            dirty.hand = Some(HandChange {
+                color,
+                kind: hand_kind,
+                before: old,
+                after: new,
+            });
         } else {
             let from = mv
                 .from
@@ -288,18 +684,36 @@ impl Position {
                 return Err(PositionError::message("moving opponent piece"));
             }
 
-            let mut resulting_kind = moving_piece.kind;
-            if mv.promote {
-                resulting_kind = resulting_kind
-                    .promote()
-                    .ok_or_else(|| PositionError::message("piece cannot promote"))?;
-            }
+            let resulting_kind = if mv.promote {
+                moving_piece
+                    .promoted()
+                    .map_err(|err| {
+                        PositionError::message(format!(
+                            "illegal promotion {}: {err} on {from}",
+                            mv.to_usi()
+                        ))
+                    })?
+                    .kind
+            } else if StandardRules.must_promote(color, moving_piece.kind, mv.to) {
+                return Err(PositionError::message(format!(
+                    "illegal move {}: {moving_piece} on {from} must promote on {}",
+                    mv.to_usi(),
+                    mv.to
+                )));
+            } else {
+                moving_piece.kind
+            };
 
             if let Some(target_piece) = self.piece_at(mv.to) {
                 if target_piece.color == color {
                     return Err(PositionError::message("cannot capture own piece"));
                 }
-                self.remove_piece(mv.to);
+                self.take_piece(mv.to);
+                irreversible = true;
+                dirty.pieces[1] = Some(DirtyPiece {
+                    removed: Some((mv.to, target_piece)),
+                    added: None,
+                });
                 if let Some(hand_kind) = HandPieceKind::from_piece_kind(target_piece.kind.base()) {
                     let old = self.hands[color.index()].count(hand_kind);
                     let new = {
@@ -307,20 +721,57 @@ impl Position {
                         hand.add(hand_kind, 1)
                     };
                     self.update_hand_hash(color, hand_kind, old, new);
+                    dirty.hand = Some(HandChange {
+                        color,
+                        kind: hand_kind,
+                        before: old,
+                        after: new,
+                    });
                 }
             }
 
-            self.remove_piece(from)
+            self.take_piece(from)
                 .ok_or_else(|| PositionError::message("piece missing"))?;
-            self.set_piece(mv.to, Piece::new(color, resulting_kind))?;
+            self.put_piece(mv.to, Piece::new(color, resulting_kind))?;
+            dirty.pieces[0] = Some(DirtyPiece {
+                removed: Some((from, moving_piece)),
+                added: Some((mv.to, Piece::new(color, resulting_kind))),
+            });
         }
 
         self.switch_side();
         self.ply += 1;
-        self.history.push(self.hash);
+        self.push_history();
+        if irreversible {
+            self.last_irreversible_ply = self.ply;
+        }
+        self.last_move_to = Some(mv.to);
+        self.dirty = Some(dirty);
+        self.update_checkers_after(mv);
         Ok(())
     }
 
+    /// 繰り返し判定用の履歴を持たない複製。合法性の確認など、指した先で繰り返しを
+    /// 調べない場面で履歴の複製を省くのに使う。
+    pub fn clone_without_history(&self) -> Self {
+        Self {
+            board: self.board,
+            bitboards: self.bitboards,
+            occupancy: self.occupancy,
+            hands: self.hands,
+            material: self.material,
+            side_to_move: self.side_to_move,
+            ply: self.ply,
+            hash: self.hash,
+            history: Vec::new(),
+            repetition_filter: [0; REPETITION_FILTER_SIZE],
+            last_irreversible_ply: self.last_irreversible_ply,
+            checkers: self.checkers,
+            last_move_to: self.last_move_to,
+            dirty: self.dirty,
+        }
+    }
+
     pub fn play_move(&self, mv: &Move) -> Result<Self, PositionError> {
         let mut next = self.clone();
         next.apply_move_internal(mv)?;
@@ -331,146 +782,577 @@ impl Position {
         self.apply_move_internal(mv)
     }
 
-    fn is_move_legal_internal(
+    /// `mv` を指し、`unmake_move` で戻すための状態を返す。履歴は複製しないので、探索の
+    /// ように同じ局面から何度も手を試す場面で使う。エラーなら局面は変わらない。
+    pub fn make_move(&mut self, mv: &Move) -> Result<MoveUndo, PositionError> {
+        let mut saved = self.clone_without_history();
+        saved.repetition_filter = self.repetition_filter;
+        let history_len = self.history.len();
+        if let Err(err) = self.apply_move_internal(mv) {
+            self.restore(saved, history_len);
+            return Err(err);
+        }
+        Ok(MoveUndo { saved, history_len })
+    }
+
+    /// `make_move` で指した手を戻す。
+    pub fn unmake_move(&mut self, undo: MoveUndo) {
+        self.restore(undo.saved, undo.history_len);
+    }
+
+    /// 履歴を `history_len` まで戻し、ほかの状態を `saved` にする。
+    fn restore(&mut self, saved: Position, history_len: usize) {
+        let mut history = core::mem::take(&mut self.history);
+        history.truncate(history_len);
+        *self = saved;
+        self.history = history;
+    }
+
+    /// 直前の指し手で変わった駒と持ち駒。指し手以外で作った・変えた局面では `None`。
+    pub fn dirty_pieces(&self) -> Option<&DirtyPieces> {
+        self.dirty.as_ref()
+    }
+
+    /// `self` から `other` への差分。手数と履歴は比較しない。
+    pub fn diff(&self, other: &Position) -> PositionDiff {
+        let squares = all_squares()
+            .into_iter()
+            .filter_map(|square| {
+                let before = self.piece_at(square);
+                let after = other.piece_at(square);
+                (before != after).then_some(SquareChange {
+                    square,
+                    before,
+                    after,
+                })
+            })
+            .collect();
+        let mut hands = Vec::new();
+        for color in COLORS {
+            for kind in HandPieceKind::all() {
+                let delta =
+                    other.hand(color).count(kind) as i32 - self.hand(color).count(kind) as i32;
+                if delta != 0 {
+                    hands.push(HandDelta { color, kind, delta });
+                }
+            }
+        }
+        PositionDiff {
+            squares,
+            hands,
+            side_to_move_changed: self.side_to_move != other.side_to_move,
+        }
+    }
+
+    /// `diff` で得た差分を適用する。元の駒や持ち駒が一致しないか、同じマスや持ち駒を
+    /// 2度変える差分なら何も変えずにエラーを返す。
+    /// 手番が変わる差分は1手として手数と履歴を進める。
+    pub fn apply_diff(&mut self, diff: &PositionDiff) -> Result<(), PositionError> {
+        let mut changed = Bitboard::EMPTY;
+        for change in &diff.squares {
+            if changed.contains(change.square) {
+                return Err(PositionError::message(format!(
+                    "diff changes square {} twice",
+                    change.square
+                )));
+            }
+            changed.insert(change.square);
+            if self.piece_at(change.square) != change.before {
+                return Err(PositionError::message(format!(
+                    "diff does not match square {}",
+                    change.square
+                )));
+            }
+        }
+        let mut new_counts: Vec<(Color, HandPieceKind, u8)> = Vec::with_capacity(diff.hands.len());
+        for delta in &diff.hands {
+            if new_counts
+                .iter()
+                .any(|&(color, kind, _)| color == delta.color && kind == delta.kind)
+            {
+                return Err(PositionError::Format("diff changes a hand count twice"));
+            }
+            let count = self.hand(delta.color).count(delta.kind) as i32 + delta.delta;
+            let count = u8::try_from(count)
+                .ok()
+                .filter(|&count| usize::from(count) < HAND_MAX_COUNT)
+                .ok_or(PositionError::Format("diff makes a hand count invalid"))?;
+            new_counts.push((delta.color, delta.kind, count));
+        }
+
+        // 歩のあるマスや持ち駒が変わったら、指し手の取る・打つ・歩を進めるに当たる。
+        let is_pawn =
+            |piece: Option<Piece>| piece.is_some_and(|piece| piece.kind == PieceKind::Pawn);
+        let pawn_changed = diff
+            .squares
+            .iter()
+            .any(|change| is_pawn(change.before) || is_pawn(change.after));
+        let irreversible = pawn_changed || !diff.hands.is_empty();
+
+        // ここから先は失敗しない。マスを空けてから置くので `put_piece` は成功する。
+        for change in &diff.squares {
+            self.take_piece(change.square);
+            if let Some(piece) = change.after {
+                let _ = self.put_piece(change.square, piece);
+            }
+        }
+        for (color, kind, count) in new_counts {
+            let old = self.hands[color.index()].count(kind);
+            self.hands[color.index()].set(kind, count);
+            self.update_hand_hash(color, kind, old, count);
+        }
+        if diff.side_to_move_changed {
+            self.switch_side();
+            self.ply += 1;
+            self.push_history();
+        }
+        if irreversible {
+            self.last_irreversible_ply = self.ply;
+        }
+        self.last_move_to = None;
+        self.dirty = None;
+        self.update_checkers();
+        Ok(())
+    }
+
+    fn is_move_legal_internal<R: Rules + ?Sized>(
         &self,
         mv: &Move,
-        enforce_drop_rule: bool,
+        rules: &R,
     ) -> Result<bool, PositionError> {
+        Ok(self.king_safety_violation(mv, rules)?.is_none())
+    }
+
+    /// 擬似合法手 `mv` を指した後に、自玉が取られる形か打ち歩詰めになるか。
+    fn king_safety_violation<R: Rules + ?Sized>(
+        &self,
+        mv: &Move,
+        rules: &R,
+    ) -> Result<Option<IllegalReason>, PositionError> {
         let mover = self.side_to_move;
-        let next = self.play_move(mv)?;
+        let mut next = self.clone_without_history();
+        next.apply_move_internal(mv)?;
         if next.is_in_check(mover) {
-            return Ok(false);
+            return Ok(Some(IllegalReason::KingInCheck));
         }
 
-        if enforce_drop_rule
+        if rules.forbids_pawn_drop_mate()
             && mv.is_drop()
             && mv.piece == PieceKind::Pawn
-            && next.is_in_check(mover.opponent())
+            && next.in_check()
+            && !next.has_any_legal_move_internal(rules)?
         {
-            if !next.has_any_legal_move_internal(true)? {
-                return Ok(false);
+            return Ok(Some(IllegalReason::PawnDropMate));
+        }
+
+        Ok(None)
+    }
+
+    /// `mv` を指せない理由。指せる手なら `None`。
+    pub fn explain_illegal(&self, mv: &Move) -> Option<IllegalReason> {
+        self.explain_illegal_with(mv, &StandardRules)
+    }
+
+    /// 指定したルールでの `explain_illegal`。
+    pub fn explain_illegal_with<R: Rules + ?Sized>(
+        &self,
+        mv: &Move,
+        rules: &R,
+    ) -> Option<IllegalReason> {
+        let reason = match mv.from {
+            Some(from) => self.explain_illegal_board_move(mv, from, rules),
+            None => self.explain_illegal_drop(mv, rules),
+        };
+        match reason {
+            Some(reason) => Some(reason),
+            None => self.king_safety_violation(mv, rules).ok().flatten(),
+        }
+    }
+
+    fn explain_illegal_board_move<R: Rules + ?Sized>(
+        &self,
+        mv: &Move,
+        from: Square,
+        rules: &R,
+    ) -> Option<IllegalReason> {
+        let color = self.side_to_move;
+        let Some(piece) = self.piece_at(from) else {
+            return Some(IllegalReason::NoPiece(from));
+        };
+        if piece.color != color {
+            return Some(IllegalReason::WrongSide(from));
+        }
+        if piece.kind != mv.piece {
+            return Some(IllegalReason::PieceMismatch {
+                square: from,
+                found: piece,
+            });
+        }
+        if self.occupancy(color).contains(mv.to) {
+            return Some(IllegalReason::OwnPiece(mv.to));
+        }
+        if !attacks::piece_attacks(color, piece.kind, from, self.occupancy_all()).contains(mv.to) {
+            return Some(IllegalReason::Unreachable { from, to: mv.to });
+        }
+        if mv.promote && !rules.can_promote(color, piece.kind, from, mv.to) {
+            return Some(IllegalReason::CannotPromote);
+        }
+        if !mv.promote && rules.must_promote(color, piece.kind, mv.to) {
+            return Some(IllegalReason::MustPromote);
+        }
+        None
+    }
+
+    fn explain_illegal_drop<R: Rules + ?Sized>(
+        &self,
+        mv: &Move,
+        rules: &R,
+    ) -> Option<IllegalReason> {
+        let color = self.side_to_move;
+        let Some(hand_kind) = HandPieceKind::from_piece_kind(mv.piece) else {
+            return Some(IllegalReason::Undroppable(mv.piece));
+        };
+        if mv.promote {
+            return Some(IllegalReason::CannotPromote);
+        }
+        if self.hand(color).count(hand_kind) == 0 {
+            return Some(IllegalReason::NotInHand(mv.piece));
+        }
+        if self.piece_at(mv.to).is_some() {
+            return Some(IllegalReason::DropOnOccupied(mv.to));
+        }
+        if rules.forced_promotion_zone(color, mv.piece).contains(mv.to) {
+            return Some(IllegalReason::DeadDrop(mv.to));
+        }
+        if !rules.can_drop(self, color, mv.piece, mv.to) {
+            return Some(IllegalReason::DoublePawn(mv.to));
+        }
+        None
+    }
+
+    /// USI形式の指し手を、合法かどうかによらず盤の駒から `Move` にする。書式が違えば `None`。
+    fn usi_token_move(&self, token: &str) -> Option<Move> {
+        if let Some((piece, to)) = token.split_once('*') {
+            let mut chars = piece.chars();
+            let kind = PieceKind::from_drop_char(chars.next()?)?;
+            if chars.next().is_some() {
+                return None;
             }
+            return Some(Move::drop(Square::from_coord(to)?, kind));
         }
+        let (body, promote) = match token.strip_suffix('+') {
+            Some(body) => (body, true),
+            None => (token, false),
+        };
+        if body.len() != 4 || !body.is_ascii() {
+            return None;
+        }
+        let from = Square::from_coord(&body[..2])?;
+        let to = Square::from_coord(&body[2..])?;
+        let kind = self
+            .piece_at(from)
+            .map_or(PieceKind::King, |piece| piece.kind);
+        Some(Move::normal(from, to, kind, promote))
+    }
+
+    fn has_any_legal_move_internal<R: Rules + ?Sized>(
+        &self,
+        rules: &R,
+    ) -> Result<bool, PositionError> {
+        let mut found = Ok(false);
+        self.visit_legal_candidates(rules, &mut |mv| {
+            match self.is_move_legal_internal(&mv, rules) {
+                Ok(false) => return true,
+                result => found = result,
+            }
+            false
+        });
+        found
+    }
 
-        Ok(true)
+    /// 合法手が1つでもあるか。最初の合法手が見つかった時点で生成をやめる。
+    pub fn has_legal_move(&self) -> Result<bool, PositionError> {
+        let _span = trace::span(Section::Movegen);
+        self.has_any_legal_move_internal(&StandardRules)
     }
 
-    fn has_any_legal_move_internal(&self, enforce_drop_rule: bool) -> Result<bool, PositionError> {
-        for mv in self.generate_pseudo_legal_moves() {
-            if self.is_move_legal_internal(&mv, enforce_drop_rule)? {
-                return Ok(true);
+    /// 合法手の数。指し手のリストは作らない。
+    pub fn count_legal_moves(&self) -> Result<usize, PositionError> {
+        let mut count = 0;
+        let mut error = None;
+        self.visit_legal_candidates(&StandardRules, &mut |mv| {
+            match self.is_move_legal_internal(&mv, &StandardRules) {
+                Ok(legal) => count += legal as usize,
+                Err(err) => error = Some(err),
             }
+            error.is_none()
+        });
+        match error {
+            Some(err) => Err(err),
+            None => Ok(count),
         }
-        Ok(false)
     }
 
     pub fn generate_legal_moves(&self) -> Result<MoveList, PositionError> {
+        self.generate_legal_moves_with(&StandardRules)
+    }
+
+    /// 指定したルールでの合法手を生成する。
+    pub fn generate_legal_moves_with<R: Rules + ?Sized>(
+        &self,
+        rules: &R,
+    ) -> Result<MoveList, PositionError> {
+        let _span = trace::span(Section::Movegen);
         let mut result = MoveList::new();
-        for mv in self.generate_pseudo_legal_moves() {
-            if self.is_move_legal_internal(&mv, true)? {
-                result.push(mv);
+        let mut error = None;
+        self.visit_legal_candidates(rules, &mut |mv| {
+            match self.is_move_legal_internal(&mv, rules) {
+                Ok(true) => result.push(mv),
+                Ok(false) => {}
+                Err(err) => error = Some(err),
             }
+            error.is_none()
+        });
+        match error {
+            Some(err) => Err(err),
+            None => Ok(result),
         }
-        Ok(result)
     }
 
-    fn generate_piece_moves(
+    fn generate_piece_moves<R: Rules + ?Sized>(
         &self,
+        rules: &R,
         color: Color,
         kind: PieceKind,
         mut pieces: Bitboard,
-        moves: &mut MoveList,
-    ) {
+        target_mask: Bitboard,
+        visit: &mut impl FnMut(Move) -> bool,
+    ) -> bool {
         if pieces.is_empty() {
-            return;
+            return true;
         }
         let our_occ = self.occupancy(color);
         let all_occ = self.occupancy_all();
 
         while let Some(from) = pieces.pop() {
-            let attacks = match kind {
-                PieceKind::King => attacks::king_attacks(from),
-                PieceKind::Gold | PieceKind::PromotedSilver | PieceKind::Tokin => {
-                    attacks::gold_attacks(color, from)
-                }
-                PieceKind::Silver => attacks::silver_attacks(color, from),
-                PieceKind::Bishop => attacks::bishop_attacks(from, all_occ),
-                PieceKind::PromotedBishop => attacks::horse_attacks(from, all_occ),
-                PieceKind::Rook => attacks::rook_attacks(from, all_occ),
-                PieceKind::PromotedRook => attacks::dragon_attacks(from, all_occ),
-                PieceKind::Pawn => attacks::pawn_attacks(color, from),
-            };
+            let attacks = attacks::piece_attacks(color, kind, from, all_occ);
 
-            let mut targets = attacks & !our_occ;
+            let mut targets = attacks & !our_occ & target_mask;
             while let Some(to) = targets.pop() {
-                let promote_forced = Self::must_promote(color, kind, to);
-                let can_promote = Self::can_promote(color, kind, from, to);
+                let promote_forced = rules.must_promote(color, kind, to);
+                let can_promote = rules.can_promote(color, kind, from, to);
                 if promote_forced {
-                    moves.push(Move::normal(from, to, kind, true));
+                    if !visit(Move::normal(from, to, kind, true)) {
+                        return false;
+                    }
                 } else {
-                    moves.push(Move::normal(from, to, kind, false));
-                    if can_promote {
-                        moves.push(Move::normal(from, to, kind, true));
+                    if !visit(Move::normal(from, to, kind, false)) {
+                        return false;
+                    }
+                    if can_promote && !visit(Move::normal(from, to, kind, true)) {
+                        return false;
                     }
                 }
             }
         }
+        true
     }
 
-    fn has_pawn_on_file(&self, color: Color, file: u8) -> bool {
-        let mut pawns = self.pieces(color, PieceKind::Pawn);
-        while let Some(square) = pawns.pop() {
-            if square.file() == file {
-                return true;
+    fn generate_drop_moves<R: Rules + ?Sized>(
+        &self,
+        rules: &R,
+        color: Color,
+        visit: &mut impl FnMut(Move) -> bool,
+    ) -> bool {
+        // 打てるマスを駒種ごとにまとめて求めてから、マス順に打つ手を並べる。
+        let targets = HandPieceKind::all().map(|hand_kind| {
+            let piece_kind = match hand_kind {
+                HandPieceKind::Gold => PieceKind::Gold,
+                HandPieceKind::Silver => PieceKind::Silver,
+                HandPieceKind::Bishop => PieceKind::Bishop,
+                HandPieceKind::Rook => PieceKind::Rook,
+                HandPieceKind::Pawn => PieceKind::Pawn,
+            };
+            if self.hand(color).count(hand_kind) == 0 {
+                (piece_kind, Bitboard::EMPTY)
+            } else {
+                (piece_kind, rules.drop_targets(self, color, piece_kind))
             }
-        }
-        false
-    }
-
-    fn generate_drop_moves(&self, color: Color, moves: &mut MoveList) {
+        });
         let mut empty = !self.occupancy_all();
         while let Some(to) = empty.pop() {
-            for hand_kind in HandPieceKind::all() {
-                let count = self.hand(color).count(hand_kind);
-                if count == 0 {
-                    continue;
+            for &(piece_kind, mask) in &targets {
+                if mask.contains(to) && !visit(Move::drop(to, piece_kind)) {
+                    return false;
                 }
-                let piece_kind = match hand_kind {
-                    HandPieceKind::Gold => PieceKind::Gold,
-                    HandPieceKind::Silver => PieceKind::Silver,
-                    HandPieceKind::Bishop => PieceKind::Bishop,
-                    HandPieceKind::Rook => PieceKind::Rook,
-                    HandPieceKind::Pawn => PieceKind::Pawn,
-                };
+            }
+        }
+        true
+    }
 
-                if piece_kind == PieceKind::Pawn {
-                    if Self::promotion_zone(color, to) {
-                        continue;
-                    }
-                    if self.has_pawn_on_file(color, to.file()) {
-                        continue;
-                    }
+    /// 駒を取る手と、打つ手以外の成る手だけを合法手として生成する。静止探索で使う。
+    /// 利きを相手の駒と成れるマスに絞ってから生成するので、静かな手や打つ手の合法性は確かめない。
+    /// 手の順序は `generate_legal_moves` から同じ手を抜き出した場合と同じ。
+    pub fn generate_tactical_moves(&self) -> Result<MoveList, PositionError> {
+        let _span = trace::span(Section::Movegen);
+        let rules = &StandardRules;
+        let color = self.side_to_move;
+        let enemy = self.occupancy(color.opponent());
+        let zone = rules.promotion_zone(color);
+        let double_check = self.check_info().kind == CheckKind::Double;
+        let mut result = MoveList::new();
+        let mut error = None;
+        let mut visit = |mv: Move| {
+            if !enemy.contains(mv.to) && !mv.promote {
+                return true;
+            }
+            match self.is_move_legal_internal(&mv, rules) {
+                Ok(true) => result.push(mv),
+                Ok(false) => {}
+                Err(err) => error = Some(err),
+            }
+            error.is_none()
+        };
+        for kind in PieceKind::all() {
+            if double_check && kind != PieceKind::King {
+                continue;
+            }
+            for from in self.pieces(color, kind).iter() {
+                // 敵陣から出る手も成れるので、敵陣にいる駒はすべての行き先を調べる。
+                let mask = match (kind.can_promote(), zone.contains(from)) {
+                    (false, _) => enemy,
+                    (true, false) => enemy | zone,
+                    (true, true) => Bitboard::FULL,
+                };
+                let piece = Bitboard::from_square(from);
+                if !self.generate_piece_moves(rules, color, kind, piece, mask, &mut visit) {
+                    return Err(error.expect("visit stops only on an error"));
                 }
-
-                moves.push(Move::drop(to, piece_kind));
             }
         }
+        match error {
+            Some(err) => Err(err),
+            None => Ok(result),
+        }
     }
 
     pub fn generate_pseudo_legal_moves(&self) -> MoveList {
+        self.generate_pseudo_legal_moves_with(&StandardRules)
+    }
+
+    /// 指定したルールでの擬似合法手（自玉の安全を確認しない手）を生成する。
+    pub fn generate_pseudo_legal_moves_with<R: Rules + ?Sized>(&self, rules: &R) -> MoveList {
         let mut moves = MoveList::new();
+        self.visit_pseudo_legal_moves(rules, &mut |mv| {
+            moves.push(mv);
+            true
+        });
+        moves
+    }
+
+    /// USI形式の指し手を現局面の合法手として解釈する。
+    pub fn parse_usi_move(&self, token: &str) -> Result<Move, PositionError> {
+        self.parse_usi_move_with(token, ForcedPromotion::Reject)
+    }
+
+    /// `parse_usi_move` に、成りが強制される手を `+` なしで書かれたときの扱いを指定する。
+    pub fn parse_usi_move_with(
+        &self,
+        token: &str,
+        policy: ForcedPromotion,
+    ) -> Result<Move, PositionError> {
+        let moves = self.generate_legal_moves()?;
+        if let Some(mv) = moves.iter().find(|mv| mv.to_usi() == token) {
+            return Ok(*mv);
+        }
+        let promoted = format!("{token}+");
+        let forced = moves.into_iter().find(|mv| {
+            mv.to_usi() == promoted
+                && StandardRules.must_promote(self.side_to_move, mv.piece, mv.to)
+        });
+        match (forced, policy) {
+            (Some(mv), ForcedPromotion::AutoCorrect) => Ok(mv),
+            (Some(mv), ForcedPromotion::Reject) => Err(PositionError::message(format!(
+                "illegal move: {token} ({} must promote on {})",
+                Piece::new(self.side_to_move, mv.piece),
+                mv.to
+            ))),
+            (None, _) => match self
+                .usi_token_move(token)
+                .and_then(|mv| self.explain_illegal(&mv))
+            {
+                Some(reason) => Err(PositionError::message(format!(
+                    "illegal move: {token} ({reason})"
+                ))),
+                None => Err(PositionError::message(format!("illegal move: {token}"))),
+            },
+        }
+    }
+
+    /// `mv` が現局面の擬似合法手かどうかを、指し手を生成せずに確かめる。
+    /// 置換表やキラー手のように、別の局面から持ち込んだ手の検証に使う。
+    pub fn is_pseudo_legal(&self, mv: &Move) -> bool {
+        self.is_pseudo_legal_with(mv, &StandardRules)
+    }
+
+    /// 指定したルールでの `is_pseudo_legal`。
+    pub fn is_pseudo_legal_with<R: Rules + ?Sized>(&self, mv: &Move, rules: &R) -> bool {
         let color = self.side_to_move;
+        let Some(from) = mv.from else {
+            return !mv.promote
+                && self.piece_at(mv.to).is_none()
+                && HandPieceKind::from_piece_kind(mv.piece)
+                    .is_some_and(|kind| self.hand(color).count(kind) > 0)
+                && rules.can_drop(self, color, mv.piece, mv.to);
+        };
+        if self.piece_at(from) != Some(Piece::new(color, mv.piece))
+            || self.occupancy(color).contains(mv.to)
+            || !attacks::piece_attacks(color, mv.piece, from, self.occupancy_all()).contains(mv.to)
+        {
+            return false;
+        }
+        if rules.must_promote(color, mv.piece, mv.to) {
+            mv.promote
+        } else {
+            !mv.promote || rules.can_promote(color, mv.piece, from, mv.to)
+        }
+    }
 
+    /// 擬似合法手を順に `visit` へ渡す。`visit` が `false` を返したら打ち切り、`false` を返す。
+    fn visit_pseudo_legal_moves<R: Rules + ?Sized>(
+        &self,
+        rules: &R,
+        visit: &mut impl FnMut(Move) -> bool,
+    ) -> bool {
+        let color = self.side_to_move;
         for kind in PieceKind::all() {
             let pieces = self.pieces(color, kind);
-            if pieces.is_empty() {
-                continue;
+            if !self.generate_piece_moves(rules, color, kind, pieces, Bitboard::FULL, visit) {
+                return false;
             }
-            self.generate_piece_moves(color, kind, pieces, &mut moves);
         }
+        self.generate_drop_moves(rules, color, visit)
+    }
 
-        self.generate_drop_moves(color, &mut moves);
-        moves
+    /// 合法手の候補を `visit` へ渡す。両王手なら玉の移動しか受けがないので、それ以外は生成しない。
+    fn visit_legal_candidates<R: Rules + ?Sized>(
+        &self,
+        rules: &R,
+        visit: &mut impl FnMut(Move) -> bool,
+    ) -> bool {
+        if self.check_info().kind == CheckKind::Double {
+            let color = self.side_to_move;
+            let kings = self.pieces(color, PieceKind::King);
+            return self.generate_piece_moves(
+                rules,
+                color,
+                PieceKind::King,
+                kings,
+                Bitboard::FULL,
+                visit,
+            );
+        }
+        self.visit_pseudo_legal_moves(rules, visit)
     }
 
     pub fn to_sfen(&self) -> String {
@@ -518,12 +1400,35 @@ impl Position {
         )
     }
 
+    /// SFENを読む。`from_sfen_with(s, SfenMode::Lenient)` と同じ。
     pub fn from_sfen(s: &str) -> Result<Self, PositionError> {
-        let mut parts = s.split_whitespace();
+        Self::from_sfen_with(s, SfenMode::Lenient)
+    }
+
+    /// 読み方を指定してSFENを読む。
+    pub fn from_sfen_with(s: &str, mode: SfenMode) -> Result<Self, PositionError> {
+        let fields: Vec<&str> = match mode {
+            SfenMode::Strict => {
+                let fields: Vec<&str> = s.split(' ').collect();
+                if fields
+                    .iter()
+                    .any(|field| field.is_empty() || field.contains(char::is_whitespace))
+                {
+                    return Err(PositionError::Format("unexpected whitespace in SFEN"));
+                }
+                fields
+            }
+            SfenMode::Lenient => s.split_whitespace().collect(),
+        };
+        let mut parts = fields.into_iter();
         let board_part = parts.next().ok_or(PositionError::Format("missing board"))?;
         let turn_part = parts.next().ok_or(PositionError::Format("missing turn"))?;
         let hand_part = parts.next().ok_or(PositionError::Format("missing hands"))?;
-        let ply_part = parts.next().ok_or(PositionError::Format("missing ply"))?;
+        let ply_part = match (parts.next(), mode) {
+            (Some(ply), _) => ply,
+            (None, SfenMode::Lenient) => "1",
+            (None, SfenMode::Strict) => return Err(PositionError::Format("missing ply")),
+        };
 
         if parts.next().is_some() {
             return Err(PositionError::Format("Too many fields in SFEN"));
@@ -590,7 +1495,7 @@ impl Position {
             _ => return Err(PositionError::message("turn must be b or w")),
         };
 
-        position.parse_hands(hand_part)?;
+        position.parse_hands(hand_part, mode)?;
 
         position.ply = ply_part
             .parse()
@@ -604,6 +1509,46 @@ impl Position {
         Ok(position)
     }
 
+    /// `to_sfen` の出力や学習データのように崩れていないと分かっているSFENを、検査を省いて読む。
+    /// 欄やマスの数を確かめず、読めない文字は読み飛ばす。繰り返し判定用の履歴も積まない。
+    /// 崩れたSFENを渡してもパニックはしないが、できる局面は保証しない。
+    pub fn from_sfen_unchecked(s: &str) -> Self {
+        let mut position = Self::empty();
+        let mut fields = s.split_whitespace();
+        let mut rank = 0;
+        let mut file = BOARD_FILES as i32 - 1;
+        let mut promoted = false;
+        for ch in fields.next().unwrap_or_default().chars() {
+            match ch {
+                '/' => {
+                    rank += 1;
+                    file = BOARD_FILES as i32 - 1;
+                }
+                '+' => promoted = true,
+                '1'..='9' => file -= ch as i32 - '0' as i32,
+                _ => {
+                    if (0..BOARD_FILES as i32).contains(&file) && rank < BOARD_RANKS {
+                        let _ = Self::place_board_piece(&mut position, ch, promoted, rank, file);
+                    }
+                    promoted = false;
+                    file -= 1;
+                }
+            }
+        }
+        if fields.next() == Some("w") {
+            position.side_to_move = Color::White;
+        }
+        let _ = position.parse_hands(fields.next().unwrap_or("-"), SfenMode::Lenient);
+        position.ply = fields
+            .next()
+            .and_then(|ply| ply.parse().ok())
+            .unwrap_or(1)
+            .max(1);
+        position.last_irreversible_ply = position.ply;
+        position.refresh_hash();
+        position
+    }
+
     fn place_board_piece(
         position: &mut Position,
         ch: char,
@@ -623,11 +1568,11 @@ impl Position {
         let kind = PieceKind::from_sfen_letter(letter, promoted)
             .ok_or_else(|| PositionError::message(format!("invalid piece letter '{}'", ch)))?;
         let square = Square::from_file_rank(file as u8, rank_idx as u8);
-        position.set_piece(square, Piece::new(color, kind))
+        position.put_piece(square, Piece::new(color, kind))
     }
 
-    fn parse_hands(&mut self, hand_part: &str) -> Result<(), PositionError> {
-        if hand_part == "-" {
+    fn parse_hands(&mut self, hand_part: &str, mode: SfenMode) -> Result<(), PositionError> {
+        if hand_part == "-" || (mode == SfenMode::Lenient && hand_part == "0") {
             return Ok(());
         }
 
@@ -646,6 +1591,9 @@ impl Position {
                     .map_err(|_| PositionError::message("invalid hand count"))?
             };
             count_buf.clear();
+            if count == 0 && mode == SfenMode::Strict {
+                return Err(PositionError::message("hand count must not be zero"));
+            }
 
             let (color, uppercase) = if ch.is_ascii_uppercase() {
                 (Color::Black, ch)
@@ -673,6 +1621,10 @@ impl Position {
     }
 }
 
+fn filter_slot(key: u64) -> usize {
+    key as usize % REPETITION_FILTER_SIZE
+}
+
 impl Default for Position {
     fn default() -> Self {
         Self::empty()
@@ -683,6 +1635,7 @@ impl Default for Position {
 mod tests {
     use super::*;
     use crate::hand::HandPieceKind;
+    use alloc::vec;
 
     #[test]
     fn initial_sfen_roundtrip() {
@@ -701,10 +1654,429 @@ mod tests {
         assert_eq!(position.to_sfen(), sfen);
     }
 
+    #[test]
+    fn unchecked_sfen_and_history_free_clones_match_the_checked_ones() {
+        for sfen in [
+            INITIAL_SFEN,
+            "5/5/5/5/5 w Pp 42",
+            "k4/1+R3/2p2/5/4K b 2GP 7",
+        ] {
+            let checked = Position::from_sfen(sfen).unwrap();
+            let unchecked = Position::from_sfen_unchecked(sfen);
+            assert_eq!(unchecked.to_sfen(), sfen);
+            assert_eq!(unchecked.zobrist_key(), checked.zobrist_key());
+            assert_eq!(unchecked.current_repetition_count(), 0);
+        }
+        let mut position = Position::initial().unwrap();
+        for token in ["1e1d", "5a5b", "1d1e", "5b5a"] {
+            let mv = position.parse_usi_move(token).unwrap();
+            position.play_move_mut(&mv).unwrap();
+        }
+        assert_eq!(position.current_repetition_count(), 2);
+        let clone = position.clone_without_history();
+        assert_eq!(clone.current_repetition_count(), 0);
+        assert_eq!(clone.zobrist_key(), position.zobrist_key());
+        assert_eq!(
+            clone.generate_legal_moves().unwrap(),
+            position.generate_legal_moves().unwrap()
+        );
+        // 崩れた入力でもパニックしない。
+        let _ = Position::from_sfen_unchecked("9z/+/x 1 b");
+    }
+
+    #[test]
+    fn repetition_counts_survive_the_filter() {
+        let mut position = Position::initial().unwrap();
+        let cycle = ["1e1d", "5a5b", "1d1e", "5b5a"];
+        for round in 1..REPETITION_LIMIT {
+            assert!(!position.is_repetition());
+            for token in cycle {
+                let mv = position.parse_usi_move(token).unwrap();
+                position.play_move_mut(&mv).unwrap();
+            }
+            assert_eq!(position.current_repetition_count(), round + 1);
+        }
+        assert!(position.is_repetition());
+        let naive = |key| position.history.iter().filter(|&&k| k == key).count();
+        for &key in &position.history {
+            assert_eq!(position.repetition_count(key), naive(key));
+        }
+        assert_eq!(position.repetition_count(0x1234), 0);
+    }
+
+    #[test]
+    fn repetitions_are_counted_across_irreversible_moves() {
+        fn play(position: &mut Position, tokens: &[&str]) {
+            for token in tokens {
+                let mv = position.parse_usi_move(token).unwrap();
+                position.play_move_mut(&mv).unwrap();
+            }
+        }
+        let mut position = Position::from_sfen("k4/5/5/p4/4K b P 5").unwrap();
+        assert_eq!(position.last_irreversible_ply(), 5);
+        play(&mut position, &["1e1d", "5a4a", "1d1e", "4a5a", "P*3c"]);
+        play(&mut position, &["5a4a", "1e1d", "4a5a", "1d1e"]);
+        assert_eq!(position.last_irreversible_ply(), 10);
+        assert_eq!(position.current_repetition_count(), 2);
+        play(&mut position, &["5d5e+"]);
+        assert_eq!(position.last_irreversible_ply(), 15);
+        assert_eq!(position.current_repetition_count(), 1);
+
+        // 駒を取り合って打ち直すと、取る手の前の局面がまた現れる。
+        let mut position = Position::from_sfen("1k3/5/2G2/5/K4 w g 1").unwrap();
+        let cycle = ["G*3b", "3c3b", "4a3b", "G*2c", "3b4a", "2c3c"];
+        play(&mut position, &cycle);
+        play(&mut position, &cycle);
+        assert_eq!(position.last_irreversible_ply(), 11);
+        assert_eq!(position.current_repetition_count(), 3);
+
+        // 進めた歩が取られても、取り返して持ち駒から同じマスに打ち直せば、歩を進める前の
+        // 局面に戻る。
+        let start = Position::from_sfen("4k/2g2/5/2P2/K1R2 b - 1").unwrap();
+        let line = [
+            "3d3c", "3b3c", "3e3c", "P*3d", "3c3d", "1a2a", "G*2b", "2a2b", "3d3e", "2b1a", "P*3d",
+            "G*3b",
+        ];
+        let mut position = start.clone();
+        play(&mut position, &line);
+        assert_eq!(position.zobrist_key(), start.zobrist_key());
+        assert_eq!(position.current_repetition_count(), 2);
+        // 探索と同じく `make_move` で進めても数える。
+        let mut searched = start.clone();
+        let mut undos = Vec::new();
+        for token in line {
+            let mv = searched.parse_usi_move(token).unwrap();
+            undos.push(searched.make_move(&mv).unwrap());
+        }
+        assert_eq!(searched.current_repetition_count(), 2);
+    }
+
+    #[test]
+    fn tactical_moves_match_filtered_legal_moves() {
+        for sfen in [
+            INITIAL_SFEN,
+            "k4/5/2p2/2R2/4K b GP 1",
+            "k1+R2/1P3/5/2b2/4K b G 1",
+            "4k/2P2/1b3/5/K3r w - 2",
+            "k4/5/1r3/5/K1S1b b - 1",
+        ] {
+            let position = Position::from_sfen(sfen).unwrap();
+            let expected: MoveList = position
+                .generate_legal_moves()
+                .unwrap()
+                .into_iter()
+                .filter(|mv| !mv.is_drop() && (position.piece_at(mv.to).is_some() || mv.promote))
+                .collect();
+            assert_eq!(
+                position.generate_tactical_moves().unwrap(),
+                expected,
+                "{sfen}"
+            );
+        }
+    }
+
     #[test]
     fn initial_position_has_moves() {
         let position = Position::initial().expect("initial");
         let moves = position.generate_legal_moves().expect("legal moves");
         assert_eq!(moves.len(), 14);
     }
+
+    #[test]
+    fn pseudo_legal_check_matches_generation() {
+        let position = Position::from_sfen("k4/5/2p2/2R2/4K b GP 1").unwrap();
+        let generated = position.generate_pseudo_legal_moves();
+        for mv in &generated {
+            assert!(position.is_pseudo_legal(mv), "{}", mv.to_usi());
+        }
+        let from_other_side = Position::from_sfen("k4/5/2p2/2R2/4K w GP 1").unwrap();
+        for mv in from_other_side.generate_pseudo_legal_moves() {
+            assert_eq!(position.is_pseudo_legal(&mv), generated.contains(&mv));
+        }
+        let blocked = Move::normal(
+            Square::from_coord("3d").unwrap(),
+            Square::from_coord("3b").unwrap(),
+            PieceKind::Rook,
+            false,
+        );
+        assert!(!position.is_pseudo_legal(&blocked));
+    }
+
+    #[test]
+    fn entering_king_needs_support_in_the_enemy_half() {
+        let lone = Position::from_sfen("K4/5/5/5/4k b - 1").unwrap();
+        assert!(!lone.is_entering_king(Color::Black));
+        let supported = Position::from_sfen("K1G2/1S3/5/5/4k b - 1").unwrap();
+        assert!(supported.is_entering_king(Color::Black));
+        assert!(!supported.is_entering_king(Color::White));
+    }
+
+    #[test]
+    fn attack_map_covers_every_piece() {
+        let position = Position::from_sfen("k4/5/2R2/5/4K b - 1").unwrap();
+        let map = position.attack_map(Color::Black);
+        for coord in [
+            "3a", "3b", "3d", "3e", "5c", "4c", "2c", "1c", "2e", "2d", "1d",
+        ] {
+            assert!(map.contains(Square::from_coord(coord).unwrap()), "{coord}");
+        }
+        assert!(!map.contains(Square::from_coord("3c").unwrap()));
+        assert!(!map.contains(Square::from_coord("5a").unwrap()));
+        let rendered = position.render_attack_map(Color::Black);
+        assert_eq!(rendered.lines().nth(3), Some(" .* .* R  .* .* c"));
+    }
+
+    #[test]
+    fn legal_move_count_matches_generated_list() {
+        for sfen in [
+            INITIAL_SFEN,
+            "4k/5/3S1/5/K4 b G 1",
+            "k4/5/2p2/2R2/4K b - 1",
+            "4k/4G/3S1/5/K4 w - 2",
+        ] {
+            let position = Position::from_sfen(sfen).expect("sfen");
+            let moves = position.generate_legal_moves().expect("moves");
+            assert_eq!(position.count_legal_moves().unwrap(), moves.len(), "{sfen}");
+            assert_eq!(
+                position.has_legal_move().unwrap(),
+                !moves.is_empty(),
+                "{sfen}"
+            );
+        }
+    }
+
+    #[test]
+    fn diff_reports_capture_and_applies_back() {
+        let before = Position::from_sfen("k4/5/2p2/2R2/4K b - 1").expect("sfen");
+        let capture = Move::normal(
+            Square::from_coord("3d").unwrap(),
+            Square::from_coord("3c").unwrap(),
+            PieceKind::Rook,
+            false,
+        );
+        let after = before.play_move(&capture).expect("capture");
+        let diff = before.diff(&after);
+        assert_eq!(diff.squares.len(), 2);
+        assert_eq!(
+            diff.hands,
+            vec![HandDelta {
+                color: Color::Black,
+                kind: HandPieceKind::Pawn,
+                delta: 1,
+            }]
+        );
+        assert!(diff.side_to_move_changed);
+
+        let mut replayed = before.clone();
+        replayed.apply_diff(&diff).expect("apply");
+        assert_eq!(replayed.to_sfen(), after.to_sfen());
+        assert_eq!(replayed.zobrist_key(), after.zobrist_key());
+        assert!(after.diff(&replayed).is_empty());
+        assert_eq!(
+            replayed.last_irreversible_ply(),
+            after.last_irreversible_ply()
+        );
+
+        let mut mismatched = after.clone();
+        assert!(mismatched.apply_diff(&diff).is_err());
+        assert_eq!(mismatched.to_sfen(), after.to_sfen());
+
+        let mut repeated = diff.clone();
+        repeated.squares.push(diff.squares[0]);
+        let mut untouched = before.clone();
+        assert!(untouched.apply_diff(&repeated).is_err());
+        assert_eq!(untouched.to_sfen(), before.to_sfen());
+        assert_eq!(untouched.zobrist_key(), before.zobrist_key());
+    }
+
+    #[test]
+    fn unmake_move_restores_the_position() {
+        let mut position = Position::initial().unwrap();
+        for token in ["1e1d", "5a5b", "1d1e", "5b5a"] {
+            let mv = position.parse_usi_move(token).unwrap();
+            position.play_move_mut(&mv).unwrap();
+        }
+        let before = position.clone();
+        for mv in before.generate_legal_moves().unwrap() {
+            let played = before.play_move(&mv).unwrap();
+            let undo = position.make_move(&mv).unwrap();
+            assert_eq!(position.to_sfen(), played.to_sfen());
+            assert_eq!(
+                position.current_repetition_count(),
+                played.current_repetition_count()
+            );
+            position.unmake_move(undo);
+            assert_eq!(position.to_sfen(), before.to_sfen());
+            assert_eq!(position.zobrist_key(), before.zobrist_key());
+            assert_eq!(position.checkers(), before.checkers());
+            assert_eq!(
+                position.current_repetition_count(),
+                before.current_repetition_count()
+            );
+        }
+        let illegal = Move::drop(Square::from_coord("3c").unwrap(), PieceKind::Gold);
+        assert!(position.make_move(&illegal).is_err());
+        assert_eq!(position.to_sfen(), before.to_sfen());
+    }
+
+    #[test]
+    fn checkers_follow_moves() {
+        let position = Position::from_sfen("k4/5/2R2/5/4K b - 1").unwrap();
+        assert!(!position.in_check());
+        let check = position.parse_usi_move("3c5c").unwrap();
+        let after = position.play_move(&check).unwrap();
+        assert!(after.in_check());
+        assert_eq!(
+            after.checkers(),
+            Bitboard::from_square(Square::from_coord("5c").unwrap())
+        );
+        let escape = after.parse_usi_move("5a4a").unwrap();
+        assert!(!after.play_move(&escape).unwrap().in_check());
+
+        // 差分で求めた王手の駒が、盤全体を調べたものと一致する。
+        let mut frontier = vec![Position::initial().unwrap()];
+        for _ in 0..3 {
+            let mut next = Vec::new();
+            for position in &frontier {
+                for mv in position.generate_legal_moves().unwrap() {
+                    let child = position.play_move(&mv).unwrap();
+                    let king = child.king_square(child.side_to_move()).unwrap();
+                    let scanned = child.attackers_to(king, child.side_to_move().opponent());
+                    assert_eq!(child.checkers(), scanned, "{}", child.to_sfen());
+                    next.push(child);
+                }
+            }
+            frontier = next;
+        }
+    }
+
+    #[test]
+    fn check_info_classifies_discovered_and_double_checks() {
+        // 3cの銀が動くと5cの飛車の利きが後手玉に通る。
+        let position = Position::from_sfen("5/5/R1S1k/5/4K b - 1").unwrap();
+        let discovered = position.parse_usi_move("3c3b").unwrap();
+        assert!(position.gives_check(&discovered));
+        let after = position.play_move(&discovered).unwrap();
+        assert_eq!(after.check_info().kind, CheckKind::Discovered);
+
+        let double = position.parse_usi_move("3c2b").unwrap();
+        assert!(position.gives_check(&double));
+        let after = position.play_move(&double).unwrap();
+        let info = after.check_info();
+        assert_eq!(info.kind, CheckKind::Double);
+        assert_eq!(info.checkers.count(), 2);
+        let evasions = after.generate_legal_moves().unwrap();
+        assert!(!evasions.is_empty());
+        assert!(evasions.iter().all(|mv| mv.piece == PieceKind::King));
+
+        // 飛車を打っても間に味方の銀があれば王手にならない。
+        let blocked = Position::from_sfen("5/5/2S1k/5/4K b R 1").unwrap();
+        let drop = Move::drop(Square::from_coord("5c").unwrap(), PieceKind::Rook);
+        assert!(!blocked.gives_check(&drop));
+    }
+
+    #[test]
+    fn lenient_sfen_accepts_common_deviations() {
+        for sfen in [
+            "  4k/5/5/5/K4   b  G ",
+            "4k/5/5/5/K4 b 0G 1",
+            "4k/5/5/5/K4\tb G 1",
+        ] {
+            assert!(
+                Position::from_sfen_with(sfen, SfenMode::Strict).is_err(),
+                "{sfen}"
+            );
+            let position = Position::from_sfen(sfen).expect(sfen);
+            assert_eq!(position.ply(), 1);
+        }
+        let empty = Position::from_sfen("4k/5/5/5/K4 b 0").unwrap();
+        assert_eq!(empty.to_sfen(), "4k/5/5/5/K4 b - 1");
+        assert!(Position::from_sfen("4k/5/5/5/K4 x -").is_err());
+    }
+
+    #[test]
+    fn illegal_promotion_names_the_piece_and_square() {
+        let position = Position::initial().unwrap();
+        let from = Square::from_coord("4e").unwrap();
+        let to = Square::from_coord("4d").unwrap();
+        let err = position
+            .play_move(&Move::normal(from, to, PieceKind::Gold, true))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "illegal promotion 4e4d+: G cannot promote on 4e"
+        );
+    }
+
+    #[test]
+    fn explain_illegal_names_the_broken_rule() {
+        let sq = |coord: &str| Square::from_coord(coord).unwrap();
+        let explain = |sfen: &str, token: &str| {
+            let position = Position::from_sfen(sfen).unwrap();
+            let mv = position.usi_token_move(token).expect(token);
+            position.explain_illegal(&mv)
+        };
+        let mate = "4k/2G2/3K1/5/5 b P 1";
+        assert_eq!(explain(mate, "P*1b"), Some(IllegalReason::PawnDropMate));
+        assert_eq!(
+            explain(mate, "P*2a"),
+            Some(IllegalReason::DeadDrop(sq("2a")))
+        );
+        assert_eq!(
+            explain(mate, "P*1a"),
+            Some(IllegalReason::DropOnOccupied(sq("1a")))
+        );
+        assert_eq!(
+            explain(mate, "S*1c"),
+            Some(IllegalReason::NotInHand(PieceKind::Silver))
+        );
+        assert_eq!(explain(mate, "P*1c"), None);
+        let nifu = "4k/5/2P2/5/K4 b P 1";
+        assert_eq!(
+            explain(nifu, "P*3d"),
+            Some(IllegalReason::DoublePawn(sq("3d")))
+        );
+        let pinned = "4k/5/5/r4/4K b G 1";
+        assert_eq!(explain(pinned, "1e1d"), Some(IllegalReason::KingInCheck));
+        assert_eq!(
+            explain(pinned, "1e1c"),
+            Some(IllegalReason::Unreachable {
+                from: sq("1e"),
+                to: sq("1c")
+            })
+        );
+        assert_eq!(
+            explain(pinned, "3c3b"),
+            Some(IllegalReason::NoPiece(sq("3c")))
+        );
+        assert_eq!(
+            explain(pinned, "1a1b"),
+            Some(IllegalReason::WrongSide(sq("1a")))
+        );
+
+        let err = Position::from_sfen(mate)
+            .unwrap()
+            .parse_usi_move("P*1b")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "illegal move: P*1b (pawn drop mate)");
+    }
+
+    #[test]
+    fn unpromoted_move_into_the_forced_zone_is_rejected() {
+        let position = Position::from_sfen("k4/4P/5/5/4K b - 1").unwrap();
+        let from = Square::from_coord("1b").unwrap();
+        let to = Square::from_coord("1a").unwrap();
+        let err = position
+            .play_move(&Move::normal(from, to, PieceKind::Pawn, false))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "illegal move 1b1a: P on 1b must promote on 1a"
+        );
+        let corrected = position
+            .parse_usi_move_with("1b1a", ForcedPromotion::AutoCorrect)
+            .unwrap();
+        assert!(corrected.promote);
+        assert!(position.parse_usi_move("1b1a").is_err());
+    }
 }
@@ -0,0 +1,223 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use crate::evaluation::Evaluator;
+use crate::match_runner::{GameRecord, parse_game_line, parse_value};
+use crate::moves::{Move, MoveList};
+use crate::position::{Position, PositionError};
+use crate::search::{NullInfoSink, SearchLimits, Searcher, mate_distance};
+
+/// 詰将棋・手筋問題を選ぶ条件。
+#[derive(Clone, Copy, Debug)]
+pub struct PuzzleConfig {
+    /// 候補手を比べる探索の深さ。
+    pub depth: usize,
+    /// この手数（両者の手を数える）以内の詰みを問題にする。
+    pub max_mate_plies: i32,
+    /// 静的評価からこれだけ評価値が上がる手を、決定的な駒得とみなす。
+    pub decisive_gain: i32,
+    /// 2番目の手が最善手よりこれだけ悪くなければ、正解が1つに決まらないとみなす。
+    pub margin: i32,
+}
+
+impl Default for PuzzleConfig {
+    fn default() -> Self {
+        Self {
+            depth: 4,
+            max_mate_plies: 5,
+            decisive_gain: 500,
+            margin: 300,
+        }
+    }
+}
+
+/// 問題の種類。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PuzzleKind {
+    /// `plies` 手で詰む。
+    Mate { plies: i32 },
+    /// 静的評価より `gain` だけ得をする。
+    Material { gain: i32 },
+}
+
+/// 局面と正解手順。
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub sfen: String,
+    pub solution: MoveList,
+    pub kind: PuzzleKind,
+}
+
+impl fmt::Display for Puzzle {
+    /// `SFEN | 正解手順 | mate N` または `SFEN | 正解手順 | material N` の1行。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let solution: Vec<String> = self.solution.iter().map(Move::to_usi).collect();
+        write!(f, "{} | {} | ", self.sfen, solution.join(" "))?;
+        match self.kind {
+            PuzzleKind::Mate { plies } => write!(f, "mate {plies}"),
+            PuzzleKind::Material { gain } => write!(f, "material {gain}"),
+        }
+    }
+}
+
+impl<E: Evaluator> Searcher<E> {
+    /// `position` が問題になるか調べる。最善手だけが詰みか決定的な駒得につながり、
+    /// ほかのすべての手がそれに届かない局面を問題とする。
+    pub fn find_puzzle(
+        &mut self,
+        position: &Position,
+        config: &PuzzleConfig,
+    ) -> Result<Option<Puzzle>, PositionError> {
+        let scored = self.evaluate_all_moves(position, config.depth)?;
+        let [(best_move, best), (_, second), ..] = scored.as_slice() else {
+            return Ok(None);
+        };
+        let static_eval = self.evaluator().evaluate_relative(position).value();
+        let kind = match mate_distance(*best) {
+            Some(moves) if moves > 0 && 2 * moves - 1 <= config.max_mate_plies => {
+                if mate_distance(*second).is_some_and(|moves| moves > 0) {
+                    return Ok(None);
+                }
+                PuzzleKind::Mate {
+                    plies: 2 * moves - 1,
+                }
+            }
+            Some(_) => return Ok(None),
+            None => {
+                let gain = best.value() - static_eval;
+                if gain < config.decisive_gain
+                    || second.value() - static_eval >= config.decisive_gain
+                {
+                    return Ok(None);
+                }
+                PuzzleKind::Material { gain }
+            }
+        };
+        if second.value() > best.value() - config.margin {
+            return Ok(None);
+        }
+
+        let mut solution = MoveList::new();
+        solution.push(*best_move);
+        let after = position.play_move(best_move)?;
+        if after.has_legal_move()? {
+            let limits = SearchLimits {
+                depth: config.depth.saturating_sub(1).max(1),
+                ..SearchLimits::default()
+            };
+            self.search(&after, limits)?;
+            solution.extend(self.principal_variation(&after, config.depth));
+        }
+        Ok(Some(Puzzle {
+            sfen: position.to_sfen(),
+            solution,
+            kind,
+        }))
+    }
+
+    /// `start` から指された `moves` の各局面から問題を集める。
+    pub fn extract_puzzles(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        config: &PuzzleConfig,
+    ) -> Result<Vec<Puzzle>, PositionError> {
+        let mut position = start.clone();
+        let mut puzzles = Vec::new();
+        for mv in moves {
+            if let Some(puzzle) = self.find_puzzle(&position, config)? {
+                puzzles.push(puzzle);
+            }
+            position.play_move_mut(mv)?;
+        }
+        Ok(puzzles)
+    }
+
+    /// 対局の棋譜から問題を集める。
+    pub fn extract_puzzles_from_record(
+        &mut self,
+        record: &GameRecord,
+        config: &PuzzleConfig,
+    ) -> Result<Vec<Puzzle>, PositionError> {
+        let start = Position::from_sfen(&record.start_sfen)?;
+        self.extract_puzzles(&start, &record.moves, config)
+    }
+}
+
+/// `puzzles` サブコマンド。
+///
+/// `puzzles <GAMES> [--depth D] [--mate-plies N] [--gain CP] [--margin CP] [--out PATH]`
+///
+/// `GAMES` は1行に1局を `position sfen ... moves ...` の形で書いたファイル。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config = PuzzleConfig::default();
+    let mut games_path = None;
+    let mut out_path = None;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--depth" => config.depth = parse_value(flag, iter.next())?,
+            "--mate-plies" => config.max_mate_plies = parse_value(flag, iter.next())?,
+            "--gain" => config.decisive_gain = parse_value(flag, iter.next())?,
+            "--margin" => config.margin = parse_value(flag, iter.next())?,
+            "--out" => out_path = Some(parse_value::<String>(flag, iter.next())?),
+            other if !other.starts_with("--") && games_path.is_none() => {
+                games_path = Some(other.to_string());
+            }
+            other => return Err(format!("unknown puzzles option: {other}").into()),
+        }
+    }
+    let games_path = games_path.ok_or("puzzles requires a games file")?;
+
+    let mut searcher = Searcher::new();
+    searcher.set_info_sink(Box::new(NullInfoSink));
+    let mut lines = Vec::new();
+    let text = fs::read_to_string(&games_path)?;
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (start, moves) =
+            parse_game_line(line).map_err(|err| format!("{games_path}:{}: {err}", idx + 1))?;
+        for puzzle in searcher.extract_puzzles(&start, &moves, &config)? {
+            if !lines.contains(&puzzle.to_string()) {
+                lines.push(puzzle.to_string());
+            }
+        }
+    }
+
+    match out_path {
+        Some(path) => fs::write(path, lines.join("\n") + "\n")?,
+        None => lines.iter().for_each(|line| println!("{line}")),
+    }
+    eprintln!("{} puzzles", lines.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_in_one_is_a_puzzle_and_quiet_positions_are_not() {
+        let config = PuzzleConfig {
+            depth: 2,
+            ..PuzzleConfig::default()
+        };
+        let mut searcher = Searcher::new();
+        // 1bへの金打ちだけが詰み。
+        let mate = Position::from_sfen("4k/3p1/3G1/5/K4 b G 1").unwrap();
+        let puzzle = searcher
+            .find_puzzle(&mate, &config)
+            .unwrap()
+            .expect("puzzle");
+        assert_eq!(puzzle.kind, PuzzleKind::Mate { plies: 1 });
+        assert!(puzzle.to_string().starts_with("4k/3p1/3G1/5/K4 b G 1 | "));
+
+        let initial = Position::initial().unwrap();
+        assert!(searcher.find_puzzle(&initial, &config).unwrap().is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::vec::Vec;
+
+use crate::board::Square;
+use crate::game::GameStatus;
+use crate::moves::{Move, MoveList};
+use crate::piece::{Color, PieceKind};
+use crate::position::{Position, PositionError};
+
+/// GUIの問い合わせ結果を覚えておく局面の数。
+pub const QUERY_CACHE_CAPACITY: usize = 64;
+
+/// 1つの局面について、指し手生成で分かる問い合わせの答え。履歴によらないものだけを持つ。
+#[derive(Clone, Debug)]
+pub struct PositionQuery {
+    pub side_to_move: Color,
+    pub legal_moves: MoveList,
+    /// `legal_moves` と同じ順の、その手が王手になるか。
+    pub gives_check: Vec<bool>,
+    /// `legal_moves` と同じ順の、その手が駒を取るか。
+    pub captures: Vec<bool>,
+    pub in_check: bool,
+}
+
+/// 合法手を絞り込む条件。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveFilter {
+    /// このマスから動く手。
+    From(Square),
+    /// 打つ手。`Some` ならその駒を打つ手だけ。
+    Drops(Option<PieceKind>),
+    /// 駒を取る手。
+    Captures,
+}
+
+impl PositionQuery {
+    pub fn compute(position: &Position) -> Result<Self, PositionError> {
+        let legal_moves = position.generate_legal_moves()?;
+        let gives_check = legal_moves
+            .iter()
+            .map(|mv| position.gives_check(mv))
+            .collect();
+        let captures = legal_moves
+            .iter()
+            .map(|mv| !mv.is_drop() && position.piece_at(mv.to).is_some())
+            .collect();
+        Ok(Self {
+            side_to_move: position.side_to_move(),
+            legal_moves,
+            gives_check,
+            captures,
+            in_check: position.in_check(),
+        })
+    }
+
+    /// `mv` が王手になるか。合法手でなければ `None`。
+    pub fn gives_check(&self, mv: &Move) -> Option<bool> {
+        let index = self.legal_moves.iter().position(|legal| legal == mv)?;
+        Some(self.gives_check[index])
+    }
+
+    /// `filters` のすべてに当てはまる合法手。生成した順に返す。
+    pub fn matching(&self, filters: &[MoveFilter]) -> Vec<Move> {
+        self.legal_moves
+            .iter()
+            .zip(&self.captures)
+            .filter(|&(mv, &captures)| {
+                filters.iter().all(|filter| match *filter {
+                    MoveFilter::From(square) => mv.from == Some(square),
+                    MoveFilter::Drops(kind) => {
+                        mv.is_drop() && kind.is_none_or(|kind| kind == mv.piece)
+                    }
+                    MoveFilter::Captures => captures,
+                })
+            })
+            .map(|(&mv, _)| mv)
+            .collect()
+    }
+
+    /// 千日手を除いた対局の状態。千日手は履歴で決まるので `Game::status` で調べる。
+    pub fn status(&self) -> GameStatus {
+        if self.legal_moves.is_empty() {
+            GameStatus::Mate {
+                winner: self.side_to_move.opponent(),
+            }
+        } else {
+            GameStatus::Ongoing
+        }
+    }
+}
+
+/// ハッシュ値をキーにした、最近使ったものから残る小さなキャッシュ。
+///
+/// 要素数が少ない前提で、線形に探す。末尾ほど最近使ったもの。
+#[derive(Clone, Debug)]
+pub struct LruCache<V> {
+    capacity: usize,
+    entries: Vec<(u64, V)>,
+}
+
+impl<V> LruCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// `key` の値を最近使ったものにして返す。
+    pub fn get(&mut self, key: u64) -> Option<&V> {
+        let index = self.entries.iter().position(|(entry, _)| *entry == key)?;
+        let entry = self.entries.remove(index);
+        self.entries.push(entry);
+        self.entries.last().map(|(_, value)| value)
+    }
+
+    /// `key` の値を入れる。いっぱいなら最も長く使われていないものを捨てる。
+    pub fn insert(&mut self, key: u64, value: V) -> &V {
+        if let Some(index) = self.entries.iter().position(|(entry, _)| *entry == key) {
+            self.entries.remove(index);
+        } else if self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+        self.entries.push((key, value));
+        &self.entries.last().expect("just pushed").1
+    }
+
+    /// `key` の値を返す。なければ `compute` で作って入れる。
+    pub fn get_or_try_insert<E>(
+        &mut self,
+        key: u64,
+        compute: impl FnOnce() -> Result<V, E>,
+    ) -> Result<&V, E> {
+        if self.get(key).is_some() {
+            return Ok(&self.entries.last().expect("just found").1);
+        }
+        Ok(self.insert(key, compute()?))
+    }
+}
+
+/// 局面のZobristハッシュをキーにした `PositionQuery` のキャッシュ。
+#[derive(Clone, Debug)]
+pub struct QueryCache {
+    cache: LruCache<PositionQuery>,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self::new(QUERY_CACHE_CAPACITY)
+    }
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: LruCache::new(capacity),
+        }
+    }
+
+    pub fn query(&mut self, position: &Position) -> Result<&PositionQuery, PositionError> {
+        self.cache
+            .get_or_try_insert(position.zobrist_key(), || PositionQuery::compute(position))
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(1), Some(&"a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.len(), 2);
+
+        let position = Position::from_sfen("k4/5/2p2/2R2/4K b G 1").unwrap();
+        let mut queries = QueryCache::default();
+        let query = queries.query(&position).unwrap().clone();
+        assert_eq!(query.legal_moves, position.generate_legal_moves().unwrap());
+        let check = position.parse_usi_move("G*4a").unwrap();
+        assert_eq!(query.gives_check(&check), Some(true));
+        assert_eq!(query.status(), GameStatus::Ongoing);
+
+        let usi = |filters: &[MoveFilter]| -> Vec<String> {
+            query.matching(filters).iter().map(Move::to_usi).collect()
+        };
+        let rook = Square::from_coord("3d").unwrap();
+        assert_eq!(
+            usi(&[MoveFilter::From(rook), MoveFilter::Captures]),
+            ["3d3c"]
+        );
+        assert_eq!(
+            usi(&[MoveFilter::Drops(Some(PieceKind::Pawn))]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            query.matching(&[MoveFilter::Drops(None)]).len(),
+            query.legal_moves.iter().filter(|mv| mv.is_drop()).count()
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! 指し手生成の確かめに使う、遅いが素直な参照実装。
+//!
+//! ビットボードも利きの表も使わず、マスの配列の上で駒の動きを1歩ずつたどって合法手を作る。
+//! 速さは求めないので、規則をそのまま書き下すことだけを優先する。`run_differential` は
+//! ランダムな対局の各局面で、これと `Position::generate_legal_moves` の結果を比べ、
+//! 最初に食い違った局面をSFENで返す。perftの局面数だけでは打ち消し合って見えない誤りも見つかる。
+
+use std::fmt;
+
+use crate::board::{BOARD_FILES, BOARD_RANKS, BOARD_SQUARES, Square};
+use crate::hand::{HAND_PIECE_KIND_COUNT, HandPieceKind};
+use crate::moves::Move;
+use crate::piece::{COLORS, Color, Piece, PieceKind};
+use crate::position::Position;
+use crate::search::SimpleRng;
+
+/// 先手から見た向き `(筋, 段)` の並び。
+type Directions = &'static [(i8, i8)];
+
+/// 先手から見た1歩の向き `(筋, 段)`。段が減る向きが前。
+const KING_STEPS: [(i8, i8); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+const GOLD_STEPS: [(i8, i8); 6] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (0, 1)];
+const SILVER_STEPS: [(i8, i8); 5] = [(-1, -1), (0, -1), (1, -1), (-1, 1), (1, 1)];
+const PAWN_STEPS: [(i8, i8); 1] = [(0, -1)];
+const DIAGONALS: [(i8, i8); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+const ORTHOGONALS: [(i8, i8); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+/// マスの配列で表した局面。
+#[derive(Clone)]
+struct Board {
+    squares: [Option<Piece>; BOARD_SQUARES],
+    hands: [[u8; HAND_PIECE_KIND_COUNT]; 2],
+    side: Color,
+}
+
+impl Board {
+    fn from_position(position: &Position) -> Self {
+        let mut squares = [None; BOARD_SQUARES];
+        for (index, square) in squares.iter_mut().enumerate() {
+            *square = position.piece_at(Square::from_index(index as u8));
+        }
+        let mut hands = [[0; HAND_PIECE_KIND_COUNT]; 2];
+        for color in COLORS {
+            for kind in HandPieceKind::all() {
+                hands[color.index()][kind.index()] = position.hand(color).count(kind);
+            }
+        }
+        Self {
+            squares,
+            hands,
+            side: position.side_to_move(),
+        }
+    }
+
+    fn at(&self, square: Square) -> Option<Piece> {
+        self.squares[square.index() as usize]
+    }
+
+    fn set(&mut self, square: Square, piece: Option<Piece>) {
+        self.squares[square.index() as usize] = piece;
+    }
+
+    /// `from` の駒 `piece` が動けるマス。味方の駒があるマスも含める。
+    fn targets(&self, from: Square, piece: Piece) -> Vec<Square> {
+        let (steps, slides): (Directions, Directions) = match piece.kind {
+            PieceKind::King => (&KING_STEPS, &[]),
+            PieceKind::Gold | PieceKind::PromotedSilver | PieceKind::Tokin => (&GOLD_STEPS, &[]),
+            PieceKind::Silver => (&SILVER_STEPS, &[]),
+            PieceKind::Pawn => (&PAWN_STEPS, &[]),
+            PieceKind::Bishop => (&[], &DIAGONALS),
+            PieceKind::Rook => (&[], &ORTHOGONALS),
+            PieceKind::PromotedBishop => (&ORTHOGONALS, &DIAGONALS),
+            PieceKind::PromotedRook => (&DIAGONALS, &ORTHOGONALS),
+        };
+        let mut targets = Vec::new();
+        for &(df, dr) in steps {
+            if let Some(to) = step(from, piece.color, df, dr) {
+                targets.push(to);
+            }
+        }
+        for &(df, dr) in slides {
+            let mut current = from;
+            while let Some(to) = step(current, piece.color, df, dr) {
+                targets.push(to);
+                if self.at(to).is_some() {
+                    break;
+                }
+                current = to;
+            }
+        }
+        targets
+    }
+
+    fn king(&self, color: Color) -> Option<Square> {
+        (0..BOARD_SQUARES)
+            .map(|index| Square::from_index(index as u8))
+            .find(|&square| self.at(square) == Some(Piece::new(color, PieceKind::King)))
+    }
+
+    fn attacked(&self, square: Square, by: Color) -> bool {
+        (0..BOARD_SQUARES).any(|index| {
+            let from = Square::from_index(index as u8);
+            self.at(from).is_some_and(|piece| {
+                piece.color == by && self.targets(from, piece).contains(&square)
+            })
+        })
+    }
+
+    fn in_check(&self, color: Color) -> bool {
+        self.king(color)
+            .is_some_and(|king| self.attacked(king, color.opponent()))
+    }
+
+    fn play(&self, mv: &Move) -> Self {
+        let mut next = self.clone();
+        let color = self.side;
+        match mv.from {
+            Some(from) => {
+                if let Some(captured) = self.at(mv.to) {
+                    let kind = HandPieceKind::from_piece_kind(captured.kind.base())
+                        .expect("kings are never captured");
+                    next.hands[color.index()][kind.index()] += 1;
+                }
+                let kind = if mv.promote {
+                    mv.piece.promote().expect("only promotable pieces promote")
+                } else {
+                    mv.piece
+                };
+                next.set(from, None);
+                next.set(mv.to, Some(Piece::new(color, kind)));
+            }
+            None => {
+                let kind = HandPieceKind::from_piece_kind(mv.piece).expect("droppable piece");
+                next.hands[color.index()][kind.index()] -= 1;
+                next.set(mv.to, Some(Piece::new(color, mv.piece)));
+            }
+        }
+        next.side = color.opponent();
+        next
+    }
+
+    /// 自玉に王手がかかったままにならない手。打ち歩詰めはここで除く。
+    fn legal_moves(&self) -> Vec<Move> {
+        let color = self.side;
+        let mut legal = Vec::new();
+        for mv in self.candidate_moves() {
+            let next = self.play(&mv);
+            if next.in_check(color) {
+                continue;
+            }
+            let pawn_drop_mate = mv.is_drop()
+                && mv.piece == PieceKind::Pawn
+                && next.in_check(color.opponent())
+                && next.legal_moves().is_empty();
+            if !pawn_drop_mate {
+                legal.push(mv);
+            }
+        }
+        legal
+    }
+
+    /// 玉の安全を考えない手。行き所のない駒と二歩はここで除く。
+    fn candidate_moves(&self) -> Vec<Move> {
+        let color = self.side;
+        let mut moves = Vec::new();
+        for index in 0..BOARD_SQUARES {
+            let from = Square::from_index(index as u8);
+            let Some(piece) = self.at(from).filter(|piece| piece.color == color) else {
+                continue;
+            };
+            for to in self.targets(from, piece) {
+                if self.at(to).is_some_and(|target| target.color == color) {
+                    continue;
+                }
+                let may_promote =
+                    piece.kind.can_promote() && (in_zone(color, from) || in_zone(color, to));
+                if may_promote {
+                    moves.push(Move::normal(from, to, piece.kind, true));
+                }
+                let dead_end = piece.kind == PieceKind::Pawn && in_zone(color, to);
+                if !dead_end {
+                    moves.push(Move::normal(from, to, piece.kind, false));
+                }
+            }
+        }
+        for kind in HandPieceKind::all() {
+            if self.hands[color.index()][kind.index()] == 0 {
+                continue;
+            }
+            let piece_kind = hand_to_piece(kind);
+            for index in 0..BOARD_SQUARES {
+                let to = Square::from_index(index as u8);
+                if self.at(to).is_some() {
+                    continue;
+                }
+                if piece_kind == PieceKind::Pawn
+                    && (in_zone(color, to) || self.has_pawn_on_file(color, to.file()))
+                {
+                    continue;
+                }
+                moves.push(Move::drop(to, piece_kind));
+            }
+        }
+        moves
+    }
+
+    fn has_pawn_on_file(&self, color: Color, file: u8) -> bool {
+        (0..BOARD_RANKS as u8).any(|rank| {
+            self.at(Square::from_file_rank(file, rank)) == Some(Piece::new(color, PieceKind::Pawn))
+        })
+    }
+}
+
+/// `color` から見て `(df, dr)` だけ進んだマス。盤の外なら `None`。
+fn step(from: Square, color: Color, df: i8, dr: i8) -> Option<Square> {
+    let (df, dr) = match color {
+        Color::Black => (df, dr),
+        Color::White => (-df, -dr),
+    };
+    let file = from.file() as i8 + df;
+    let rank = from.rank() as i8 + dr;
+    let inside = (0..BOARD_FILES as i8).contains(&file) && (0..BOARD_RANKS as i8).contains(&rank);
+    inside.then(|| Square::from_file_rank(file as u8, rank as u8))
+}
+
+/// 敵陣（最奥の1段）。
+fn in_zone(color: Color, square: Square) -> bool {
+    match color {
+        Color::Black => square.rank() == 0,
+        Color::White => square.rank() as usize == BOARD_RANKS - 1,
+    }
+}
+
+fn hand_to_piece(kind: HandPieceKind) -> PieceKind {
+    match kind {
+        HandPieceKind::Gold => PieceKind::Gold,
+        HandPieceKind::Silver => PieceKind::Silver,
+        HandPieceKind::Bishop => PieceKind::Bishop,
+        HandPieceKind::Rook => PieceKind::Rook,
+        HandPieceKind::Pawn => PieceKind::Pawn,
+    }
+}
+
+/// 参照実装で作った `position` の合法手。
+pub fn reference_legal_moves(position: &Position) -> Vec<Move> {
+    Board::from_position(position).legal_moves()
+}
+
+/// 参照実装と食い違った局面。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub sfen: String,
+    /// 参照実装にあって、指し手生成になかった手。
+    pub missing: Vec<String>,
+    /// 指し手生成にあって、参照実装になかった手。
+    pub extra: Vec<String>,
+    /// 指し手生成がエラーを返したときの内容。
+    pub error: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.sfen)?;
+        if let Some(error) = &self.error {
+            return write!(f, ": generator error: {error}");
+        }
+        if !self.missing.is_empty() {
+            write!(f, " missing {}", self.missing.join(" "))?;
+        }
+        if !self.extra.is_empty() {
+            write!(f, " extra {}", self.extra.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// `position` で指し手生成と参照実装が同じ手を返すか。
+pub fn compare(position: &Position) -> Result<(), Divergence> {
+    let divergence = |missing, extra, error| Divergence {
+        sfen: position.to_sfen(),
+        missing,
+        extra,
+        error,
+    };
+    let generated = position
+        .generate_legal_moves()
+        .map_err(|err| divergence(Vec::new(), Vec::new(), Some(err.to_string())))?;
+    let mut generated: Vec<String> = generated.iter().map(Move::to_usi).collect();
+    let mut reference: Vec<String> = reference_legal_moves(position)
+        .iter()
+        .map(Move::to_usi)
+        .collect();
+    generated.sort();
+    reference.sort();
+    let missing: Vec<String> = reference
+        .iter()
+        .filter(|mv| generated.binary_search(mv).is_err())
+        .cloned()
+        .collect();
+    let extra: Vec<String> = generated
+        .iter()
+        .filter(|mv| reference.binary_search(mv).is_err())
+        .cloned()
+        .collect();
+    if missing.is_empty() && extra.is_empty() {
+        Ok(())
+    } else {
+        Err(divergence(missing, extra, None))
+    }
+}
+
+/// 初期局面から `games` 局ランダムに指し、各局面で `compare` する。調べた局面の数を返す。
+pub fn run_differential(games: usize, plies: usize, seed: u64) -> Result<usize, Divergence> {
+    let mut rng = SimpleRng::new(seed);
+    let mut checked = 0;
+    for _ in 0..games {
+        let mut position = Position::initial().expect("initial position");
+        for _ in 0..=plies {
+            compare(&position)?;
+            checked += 1;
+            let moves = position.generate_legal_moves().expect("compared above");
+            if moves.is_empty() || position.is_repetition() {
+                break;
+            }
+            let mv = moves[rng.gen_range(0..moves.len())];
+            position.play_move_mut(&mv).expect("generated move");
+        }
+    }
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_agrees_on_drops_promotions_and_pawn_drop_mate() {
+        for sfen in [
+            // 1bへの歩打ちは打ち歩詰め。ほかの筋には二歩で打てない筋がある。
+            "4k/2G2/3K1/5/5 b P 1",
+            "4k/5/2P2/5/K4 b P 1",
+            // 成りを選べる銀と、成るしかない歩。
+            "k4/2S1P/5/5/4K b - 1",
+            "rb1gk/1s2R/5/P1B2/K4 w GSP 10",
+        ] {
+            let position = Position::from_sfen(sfen).expect(sfen);
+            assert_eq!(compare(&position), Ok(()));
+        }
+        assert!(run_differential(5, 40, 7).expect("no divergence") > 5);
+    }
+}
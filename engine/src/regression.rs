@@ -0,0 +1,177 @@
+//! ラベル付き局面による評価関数と探索の回帰テスト。
+//!
+//! 1行に1局面を `SFEN | 期待値 | 期待値 ...` の形で書く。`#` 以降はコメント。
+//! 期待値は次のいずれか。評価値は手番側から見た値。
+//!
+//! - `eval > N` / `eval < N`: 静的評価値の符号や下限・上限
+//! - `eval N..M`: 静的評価値が `N` 以上 `M` 以下
+//! - `bm D MOVE...`: 深さ `D` で探索した最善手が列挙した手のいずれか
+
+use std::fmt;
+
+use crate::evaluation::Evaluator;
+use crate::position::Position;
+use crate::search::{SearchLimits, Searcher};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expectation {
+    EvalAbove(i32),
+    EvalBelow(i32),
+    EvalRange(i32, i32),
+    BestMove { depth: usize, moves: Vec<String> },
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EvalAbove(bound) => write!(f, "eval > {bound}"),
+            Self::EvalBelow(bound) => write!(f, "eval < {bound}"),
+            Self::EvalRange(min, max) => write!(f, "eval {min}..{max}"),
+            Self::BestMove { depth, moves } => write!(f, "bm {depth} {}", moves.join(" ")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct RegressionCase {
+    line: usize,
+    sfen: String,
+    expectations: Vec<Expectation>,
+}
+
+fn parse_number(token: &str, line: usize) -> Result<i32, String> {
+    token
+        .parse()
+        .map_err(|_| format!("line {line}: invalid number '{token}'"))
+}
+
+fn parse_expectation(text: &str, line: usize) -> Result<Expectation, String> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    match tokens.as_slice() {
+        ["eval", ">", bound] => Ok(Expectation::EvalAbove(parse_number(bound, line)?)),
+        ["eval", "<", bound] => Ok(Expectation::EvalBelow(parse_number(bound, line)?)),
+        ["eval", range] => {
+            let (min, max) = range
+                .split_once("..")
+                .ok_or_else(|| format!("line {line}: invalid eval range '{range}'"))?;
+            Ok(Expectation::EvalRange(
+                parse_number(min, line)?,
+                parse_number(max, line)?,
+            ))
+        }
+        ["bm", depth, moves @ ..] if !moves.is_empty() => {
+            let depth = depth
+                .parse()
+                .map_err(|_| format!("line {line}: invalid depth '{depth}'"))?;
+            Ok(Expectation::BestMove {
+                depth,
+                moves: moves.iter().map(|mv| mv.to_string()).collect(),
+            })
+        }
+        _ => Err(format!("line {line}: unknown expectation '{text}'")),
+    }
+}
+
+fn parse_cases(text: &str) -> Result<Vec<RegressionCase>, String> {
+    let mut cases = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let content = raw.split('#').next().unwrap_or("").trim();
+        if content.is_empty() {
+            continue;
+        }
+        let mut fields = content.split('|').map(str::trim);
+        let sfen = fields.next().unwrap_or("").to_string();
+        let expectations = fields
+            .map(|field| parse_expectation(field, line))
+            .collect::<Result<Vec<_>, _>>()?;
+        if expectations.is_empty() {
+            return Err(format!("line {line}: no expectations"));
+        }
+        cases.push(RegressionCase {
+            line,
+            sfen,
+            expectations,
+        });
+    }
+    Ok(cases)
+}
+
+impl RegressionCase {
+    /// 満たされなかった期待値を説明する文字列を返す。
+    fn failures<E: Evaluator>(&self, searcher: &mut Searcher<E>) -> Vec<String> {
+        let position = match Position::from_sfen(&self.sfen) {
+            Ok(position) => position,
+            Err(err) => return vec![format!("line {}: {err}", self.line)],
+        };
+        let eval = searcher.evaluator().evaluate_relative(&position).value();
+        let mut failures = Vec::new();
+        for expectation in &self.expectations {
+            let actual = match expectation {
+                Expectation::EvalAbove(bound) if eval <= *bound => eval.to_string(),
+                Expectation::EvalBelow(bound) if eval >= *bound => eval.to_string(),
+                Expectation::EvalRange(min, max) if !(*min..=*max).contains(&eval) => {
+                    eval.to_string()
+                }
+                Expectation::BestMove { depth, moves } => {
+                    let limits = SearchLimits {
+                        depth: *depth,
+                        ..SearchLimits::default()
+                    };
+                    let best = match searcher.search(&position, limits) {
+                        Ok(result) => result.best_move.map(|mv| mv.to_usi()),
+                        Err(err) => Some(err.to_string()),
+                    };
+                    let best = best.unwrap_or_else(|| "none".to_string());
+                    if moves.contains(&best) {
+                        continue;
+                    }
+                    best
+                }
+                _ => continue,
+            };
+            failures.push(format!(
+                "line {}: expected {expectation}, got {actual} ({})",
+                self.line, self.sfen
+            ));
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASES: &str = include_str!("../testdata/regression.txt");
+
+    #[test]
+    fn parses_expectations_and_reports_bad_lines() {
+        let cases = parse_cases("4k/5/5/5/K4 b G 1 | eval > 0 | bm 2 G*2b 1a1b # x\n\n").unwrap();
+        assert_eq!(cases.len(), 1);
+        assert_eq!(
+            cases[0].expectations,
+            vec![
+                Expectation::EvalAbove(0),
+                Expectation::BestMove {
+                    depth: 2,
+                    moves: vec!["G*2b".to_string(), "1a1b".to_string()],
+                },
+            ]
+        );
+        assert!(parse_cases("4k/5/5/5/K4 b - 1").is_err());
+        assert!(parse_cases("4k/5/5/5/K4 b - 1 | eval 3..x").is_err());
+    }
+
+    #[test]
+    fn labeled_positions_hold() {
+        let cases = parse_cases(CASES).expect("regression file");
+        assert!(!cases.is_empty());
+        let mut searcher = Searcher::new();
+        let failures: Vec<String> = cases
+            .iter()
+            .flat_map(|case| case.failures(&mut searcher))
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
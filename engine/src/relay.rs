@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::match_runner::parse_value;
+
+/// 中継するエンジンと、通信に割り込ませるコマンドの設定。
+#[derive(Clone, Debug, Default)]
+pub struct RelayConfig {
+    pub program: String,
+    pub args: Vec<String>,
+    /// GUIからのコマンドがキーで始まるとき、それを転送する直前に値の行をエンジンへ送る。
+    pub inject_before: Vec<(String, String)>,
+}
+
+type SharedLog = Arc<Mutex<Box<dyn Write + Send>>>;
+
+fn log_line(log: &SharedLog, direction: &str, line: &str) {
+    if let Ok(mut log) = log.lock() {
+        let _ = writeln!(log, "{direction} {line}");
+        let _ = log.flush();
+    }
+}
+
+/// GUIとエンジンの間に入って双方向の通信を中継し、すべての行を `log` に記録する。
+///
+/// `gui_in` が閉じるか `quit` を転送した時点でエンジンの終了を待って戻る。
+pub fn relay<R, W>(
+    config: &RelayConfig,
+    gui_in: R,
+    gui_out: W,
+    log: Box<dyn Write + Send>,
+) -> Result<(), Box<dyn Error>>
+where
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    let mut child = Command::new(&config.program)
+        .args(&config.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to start {}: {err}", config.program))?;
+    let mut engine_in = child.stdin.take().ok_or("engine stdin unavailable")?;
+    let engine_out = child.stdout.take().ok_or("engine stdout unavailable")?;
+    let log: SharedLog = Arc::new(Mutex::new(log));
+
+    let reader_log = Arc::clone(&log);
+    let reader = thread::spawn(move || -> io::Result<()> {
+        let mut gui_out = gui_out;
+        for line in BufReader::new(engine_out).lines() {
+            let line = line?;
+            log_line(&reader_log, "<", &line);
+            writeln!(gui_out, "{line}")?;
+            gui_out.flush()?;
+        }
+        Ok(())
+    });
+
+    for line in gui_in.lines() {
+        let line = line?;
+        let trimmed = line.trim();
+        for (trigger, injected) in &config.inject_before {
+            if trimmed.starts_with(trigger.as_str()) {
+                log_line(&log, "*", injected);
+                writeln!(engine_in, "{injected}")?;
+            }
+        }
+        log_line(&log, ">", trimmed);
+        writeln!(engine_in, "{trimmed}")?;
+        engine_in.flush()?;
+        if trimmed == "quit" {
+            break;
+        }
+    }
+    drop(engine_in);
+
+    child.wait()?;
+    reader
+        .join()
+        .map_err(|_| "relay output thread panicked")??;
+    Ok(())
+}
+
+/// `relay` サブコマンド。
+///
+/// `relay [--log PATH] [--inject TRIGGER=LINE]... -- PROGRAM [ARGS...]`
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config = RelayConfig::default();
+    let mut log_path = None;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--log" => log_path = Some(parse_value::<String>(flag, iter.next())?),
+            "--inject" => {
+                let spec: String = parse_value(flag, iter.next())?;
+                let (trigger, line) = spec
+                    .split_once('=')
+                    .ok_or_else(|| format!("--inject expects TRIGGER=LINE: {spec}"))?;
+                config
+                    .inject_before
+                    .push((trigger.to_string(), line.to_string()));
+            }
+            "--" => {
+                config.program = iter.next().ok_or("relay requires a program")?.clone();
+                config.args = iter.by_ref().cloned().collect();
+            }
+            other => return Err(format!("unknown relay option: {other}").into()),
+        }
+    }
+    if config.program.is_empty() {
+        return Err("relay requires a program after --".into());
+    }
+
+    let log: Box<dyn Write + Send> = match log_path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stderr()),
+    };
+    relay(&config, io::stdin().lock(), io::stdout(), log)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn relays_both_directions_and_injects_commands() {
+        // `cat` は受け取った行をそのまま返すので、エンジンの代わりに使える。
+        let config = RelayConfig {
+            program: "cat".to_string(),
+            args: Vec::new(),
+            inject_before: vec![("isready".to_string(), "setoption name X".to_string())],
+        };
+        let gui_out = SharedBuffer::default();
+        let log = SharedBuffer::default();
+        relay(
+            &config,
+            &b"usi\nisready\nquit\n"[..],
+            gui_out.clone(),
+            Box::new(log.clone()),
+        )
+        .expect("relay");
+        assert_eq!(gui_out.text(), "usi\nsetoption name X\nisready\nquit\n");
+        let log = log.text();
+        assert!(log.contains("> usi\n"));
+        assert!(log.contains("* setoption name X\n"));
+        assert!(log.contains("< isready\n"));
+    }
+}
@@ -0,0 +1,356 @@
+//! ルートの指し手をスレッドに分けて読む並列探索。
+//!
+//! 各深さでは、前の深さの最善手を1本のスレッドで全幅に読んでアルファを決め、
+//! 残りの手をスレッド間で取り合う。アルファは `AtomicI32` で共有し、良い手が見つかる
+//! たびに引き上げる。置換表は `SharedTable` を全スレッドで共有する。
+//! 浅く広い探索では、同じ木を各スレッドが読む方式より無駄が少ないことがある。
+
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::evaluation::{EvalBreakdown, Evaluator, HandcraftedEval, PieceSquareTable};
+use crate::handicap::Handicap;
+use crate::moves::{Move, MoveList};
+use crate::piece::PIECE_KIND_COUNT;
+use crate::position::{Position, PositionError};
+use crate::score::RelScore;
+use crate::search::{
+    InfoSink, MATE_VALUE, MemoryUsage, NullInfoSink, SearchBackend, SearchInfo, SearchLimits,
+    SearchResult, Searcher, StdoutInfoSink, StopToken, table_is_stale,
+};
+use crate::table::{self, Bound, SharedTable, TableEntry, TranspositionTable};
+
+/// `Default` で作るときのスレッド数。
+pub const DEFAULT_ROOT_SPLIT_THREADS: usize = 2;
+
+/// ルートの1手を読んだ結果。
+#[derive(Clone, Copy)]
+struct RootScore {
+    mv: Move,
+    score: i32,
+    /// 共有のアルファを上回り、値が確定したか。下回った手の値は上界にすぎない。
+    exact: bool,
+}
+
+/// ルート分割の並列探索。ルートでの乱択は行わない。
+pub struct RootSplitSearcher<E: Evaluator + Clone = HandcraftedEval> {
+    workers: Vec<Searcher<E>>,
+    table: Arc<SharedTable>,
+    info_sink: Box<dyn InfoSink>,
+    keep_tt: bool,
+    table_epoch: Option<u32>,
+}
+
+impl<E: Evaluator + Clone + Default> Default for RootSplitSearcher<E> {
+    fn default() -> Self {
+        Self::with_evaluator(E::default(), DEFAULT_ROOT_SPLIT_THREADS)
+    }
+}
+
+impl RootSplitSearcher {
+    pub fn new(threads: usize) -> Self {
+        Self::with_evaluator(HandcraftedEval::default(), threads)
+    }
+}
+
+impl<E: Evaluator + Clone> RootSplitSearcher<E> {
+    /// `threads` は1以上に切り上げる。
+    pub fn with_evaluator(evaluator: E, threads: usize) -> Self {
+        let mut searcher = Self {
+            workers: Vec::new(),
+            table: Arc::new(SharedTable::new()),
+            info_sink: Box::new(StdoutInfoSink),
+            keep_tt: false,
+            table_epoch: None,
+        };
+        searcher.spawn_workers(evaluator, threads);
+        searcher
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// スレッド数を変える。各スレッドの評価関数は今の1本目を複製する。
+    pub fn set_threads(&mut self, threads: usize) {
+        let evaluator = self.workers[0].evaluator().clone();
+        self.spawn_workers(evaluator, threads);
+    }
+
+    /// 全スレッドで共有する置換表を差し替える。
+    pub fn set_shared_table(&mut self, table: Arc<SharedTable>) {
+        self.table = table;
+        for worker in &mut self.workers {
+            worker.set_transposition_table(Box::new(Arc::clone(&self.table)));
+        }
+    }
+
+    fn spawn_workers(&mut self, evaluator: E, threads: usize) {
+        self.workers = (0..threads.max(1))
+            .map(|_| {
+                let mut worker = Searcher::with_evaluator(evaluator.clone());
+                worker.set_info_sink(Box::new(NullInfoSink));
+                worker.set_transposition_table(Box::new(Arc::clone(&self.table)));
+                worker.set_keep_tt(true);
+                worker
+            })
+            .collect();
+    }
+
+    pub fn search_with_stop(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+    ) -> Result<SearchResult, PositionError> {
+        let started = Instant::now();
+        if table_is_stale(self.keep_tt, &mut self.table_epoch, position) {
+            self.table.clear();
+        }
+        let mut order: Vec<Move> = position.generate_legal_moves()?.into_iter().collect();
+        if order.is_empty() {
+            return Ok(SearchResult {
+                best_move: None,
+                score: RelScore(-MATE_VALUE),
+                depth: 0,
+                nodes: 0,
+                elapsed: started.elapsed(),
+            });
+        }
+        if let Some(hint) = limits.root_hint
+            && let Some(index) = order.iter().position(|&mv| mv == hint)
+        {
+            order[..=index].rotate_right(1);
+        }
+        for worker in &mut self.workers {
+            worker.begin_root_split(position, limits, stop);
+        }
+
+        let mut result = SearchResult::default();
+        for depth in 1..=limits.depth.max(1) {
+            let Some(mut scores) = self.split_iteration(position, &order, depth)? else {
+                break;
+            };
+            // 同点なら確定値を先に並べる。上界の値は実際にはもっと低いかもしれない。
+            scores.sort_by_key(|entry| (Reverse(entry.score), !entry.exact));
+            order = scores.iter().map(|entry| entry.mv).collect();
+            let best = scores[0];
+            let hash = table::compute_hash(position);
+            self.table.store(
+                hash,
+                TableEntry {
+                    depth,
+                    score: best.score,
+                    bound: Bound::Exact,
+                    best_move: Some(best.mv),
+                    check: table::verification_bits(hash),
+                },
+            );
+            result = SearchResult {
+                best_move: Some(best.mv),
+                score: RelScore(best.score),
+                depth,
+                nodes: self.nodes(),
+                elapsed: Duration::ZERO,
+            };
+            self.info_sink.info(&SearchInfo {
+                depth,
+                score: result.score,
+                nodes: result.nodes,
+                best_move: result.best_move,
+                pv: self.workers[0].reported_pv(position, result.best_move, depth),
+                elapsed: started.elapsed(),
+                complete: true,
+            });
+        }
+        self.info_sink.flush();
+        result.nodes = self.nodes();
+        result.elapsed = started.elapsed();
+        Ok(result)
+    }
+
+    /// 深さ `depth` で `order` の手をすべて読む。中断されたら `None`。
+    fn split_iteration(
+        &mut self,
+        position: &Position,
+        order: &[Move],
+        depth: usize,
+    ) -> Result<Option<Vec<RootScore>>, PositionError> {
+        let Some(first) =
+            self.workers[0].search_root_move(position, order[0], depth, -MATE_VALUE, MATE_VALUE)?
+        else {
+            return Ok(None);
+        };
+        let alpha = AtomicI32::new(first);
+        let next = AtomicUsize::new(1);
+        let scores = Mutex::new(vec![RootScore {
+            mv: order[0],
+            score: first,
+            exact: true,
+        }]);
+        let (alpha, next, scores_ref) = (&alpha, &next, &scores);
+
+        let finished = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .workers
+                .iter_mut()
+                .map(|worker| {
+                    scope.spawn(move || -> Result<bool, PositionError> {
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(&mv) = order.get(index) else {
+                                return Ok(true);
+                            };
+                            let bound = alpha.load(Ordering::Relaxed);
+                            let Some(score) =
+                                worker.search_root_move(position, mv, depth, bound, MATE_VALUE)?
+                            else {
+                                return Ok(false);
+                            };
+                            alpha.fetch_max(score, Ordering::Relaxed);
+                            scores_ref
+                                .lock()
+                                .expect("root scores lock")
+                                .push(RootScore {
+                                    mv,
+                                    score,
+                                    exact: score > bound,
+                                });
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("root split worker panicked"))
+                .collect::<Result<Vec<bool>, PositionError>>()
+        })?;
+        if !finished.into_iter().all(|done| done) {
+            return Ok(None);
+        }
+        Ok(Some(scores.into_inner().expect("root scores lock")))
+    }
+
+    fn nodes(&self) -> u64 {
+        self.workers.iter().map(Searcher::nodes).sum()
+    }
+}
+
+impl<E: Evaluator + Clone> SearchBackend for RootSplitSearcher<E> {
+    fn search_with_stop(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+    ) -> Result<SearchResult, PositionError> {
+        RootSplitSearcher::search_with_stop(self, position, limits, stop)
+    }
+
+    fn principal_variation(&self, position: &Position, max_len: usize) -> MoveList {
+        self.workers[0].principal_variation(position, max_len)
+    }
+
+    fn set_info_sink(&mut self, sink: Box<dyn InfoSink>) {
+        self.info_sink = sink;
+    }
+
+    fn set_keep_tt(&mut self, keep: bool) {
+        self.keep_tt = keep;
+    }
+
+    fn probe_tt(&self, position: &Position) -> Option<TableEntry> {
+        SearchBackend::probe_tt(&self.workers[0], position)
+    }
+
+    fn eval_breakdown(&self, position: &Position) -> Option<EvalBreakdown> {
+        self.workers[0].evaluator().breakdown(position)
+    }
+
+    fn set_handicap(&mut self, handicap: Option<Handicap>) {
+        for worker in &mut self.workers {
+            worker.set_handicap(handicap);
+        }
+    }
+
+    /// 置換表は全スレッドで共有しているので1つ分だけ数える。
+    fn memory_usage(&self) -> MemoryUsage {
+        self.workers.iter().fold(
+            MemoryUsage {
+                tt: self.table.bytes(),
+                ..MemoryUsage::default()
+            },
+            |total, worker| {
+                total
+                    + MemoryUsage {
+                        tt: 0,
+                        ..worker.memory_usage()
+                    }
+            },
+        )
+    }
+
+    fn set_piece_values(&mut self, values: [i32; PIECE_KIND_COUNT]) {
+        for worker in &mut self.workers {
+            worker.set_piece_values(values);
+        }
+    }
+
+    fn set_piece_square_tables(
+        &mut self,
+        middlegame: &PieceSquareTable,
+        endgame: &PieceSquareTable,
+    ) {
+        for worker in &mut self.workers {
+            worker.set_piece_square_tables(middlegame, endgame);
+        }
+    }
+
+    fn set_root_penalties(&mut self, position: &Position, penalties: Vec<(Move, i32)>) {
+        for worker in &mut self.workers {
+            worker.set_root_penalties(position, penalties.clone());
+        }
+    }
+
+    fn set_expected_line(&mut self, position: &Position, line: &[Move]) {
+        for worker in &mut self.workers {
+            worker.set_expected_line(position, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::INITIAL_SFEN;
+
+    fn limits(depth: usize) -> SearchLimits {
+        SearchLimits {
+            depth,
+            randomness: 0,
+            ..SearchLimits::default()
+        }
+    }
+
+    #[test]
+    fn root_split_agrees_with_the_single_thread_search() {
+        let position = Position::from_sfen(INITIAL_SFEN).unwrap();
+        let mut single = Searcher::new();
+        single.set_info_sink(Box::new(NullInfoSink));
+        let expected = single.search(&position, limits(3)).unwrap();
+
+        let mut split = RootSplitSearcher::new(3);
+        split.set_info_sink(Box::new(NullInfoSink));
+        let result = split.search(&position, limits(3)).unwrap();
+        assert_eq!(result.depth, 3);
+        assert_eq!(result.score, expected.score);
+        assert!(result.nodes > 0);
+
+        let mate = Position::from_sfen("4k/5/3S1/5/K4 b G 1").unwrap();
+        let result = split.search(&mate, limits(2)).unwrap();
+        assert_eq!(result.score, RelScore(MATE_VALUE - 1));
+        assert_eq!(split.principal_variation(&mate, 1).len(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::bitboard::Bitboard;
+use crate::board::{BOARD_RANKS, Square};
+use crate::piece::{Color, PieceKind};
+use crate::position::Position;
+
+/// 先手・後手それぞれの敵陣（5五将棋では最奥の1段）。
+pub const PROMOTION_ZONES: [Bitboard; 2] = [
+    Bitboard::rank_mask(0),
+    Bitboard::rank_mask(BOARD_RANKS as u8 - 1),
+];
+
+/// 通常ルールでの `color` の敵陣。
+pub const fn promotion_zone_mask(color: Color) -> Bitboard {
+    PROMOTION_ZONES[color.index()]
+}
+
+/// 成り・駒打ちに関する規則。変則ルールや検討用の緩和ルールはこれを差し替えて表現する。
+pub trait Rules {
+    /// `color` にとっての敵陣（成れる段）。
+    fn promotion_zone(&self, color: Color) -> Bitboard {
+        promotion_zone_mask(color)
+    }
+
+    /// `square` が `color` にとっての敵陣（成れる段）かどうか。
+    fn in_promotion_zone(&self, color: Color, square: Square) -> bool {
+        self.promotion_zone(color).contains(square)
+    }
+
+    /// `from` から `to` への移動で成りを選べるかどうか。
+    fn can_promote(&self, color: Color, kind: PieceKind, from: Square, to: Square) -> bool {
+        kind.can_promote()
+            && (self.in_promotion_zone(color, from) || self.in_promotion_zone(color, to))
+    }
+
+    /// `kind` が行き所のない駒になるため、移動すれば成りが強制されるマス。
+    fn forced_promotion_zone(&self, color: Color, kind: PieceKind) -> Bitboard {
+        if kind == PieceKind::Pawn {
+            self.promotion_zone(color)
+        } else {
+            Bitboard::EMPTY
+        }
+    }
+
+    /// 行き所のない駒になるため成りが強制されるかどうか。
+    fn must_promote(&self, color: Color, kind: PieceKind, to: Square) -> bool {
+        self.forced_promotion_zone(color, kind).contains(to)
+    }
+
+    /// 持ち駒 `kind` を打てるマスすべて（空きマスから行き所のない駒と二歩を除く）。
+    fn drop_targets(&self, position: &Position, color: Color, kind: PieceKind) -> Bitboard {
+        let mut targets = !position.occupancy_all() & !self.forced_promotion_zone(color, kind);
+        if kind == PieceKind::Pawn {
+            for pawn in position.pieces(color, PieceKind::Pawn).iter() {
+                targets &= !Bitboard::file_mask(pawn.file());
+            }
+        }
+        targets
+    }
+
+    /// 持ち駒 `kind` を空きマス `to` に打てるかどうか（行き所のない歩・二歩）。
+    fn can_drop(&self, position: &Position, color: Color, kind: PieceKind, to: Square) -> bool {
+        self.drop_targets(position, color, kind).contains(to)
+    }
+
+    /// 打ち歩詰めを禁じるかどうか。
+    fn forbids_pawn_drop_mate(&self) -> bool {
+        true
+    }
+}
+
+/// 外から受け取った、成りが強制されるのに成らない手の扱い（例: `1b1a` と書かれた歩の移動）。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForcedPromotion {
+    /// 行き所のない駒になる手として退ける。
+    #[default]
+    Reject,
+    /// 成る手として読み替える。
+    AutoCorrect,
+}
+
+impl ForcedPromotion {
+    pub const ALL: [Self; 2] = [Self::Reject, Self::AutoCorrect];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::AutoCorrect => "correct",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.name() == name)
+    }
+}
+
+/// 通常の5五将棋のルール。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StandardRules;
+
+impl Rules for StandardRules {}
+
+/// 打ち歩詰めを許す検討用のルール。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoPawnDropMateRules;
+
+impl Rules for NoPawnDropMateRules {
+    fn forbids_pawn_drop_mate(&self) -> bool {
+        false
+    }
+}
+
+/// `color` の歩が `file` の筋にあるかどうか。
+pub fn has_pawn_on_file(position: &Position, color: Color, file: u8) -> bool {
+    position
+        .pieces(color, PieceKind::Pawn)
+        .iter()
+        .any(|square| square.file() == file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pawn_drop_mate_is_only_allowed_by_relaxed_rules() {
+        // 1b への歩打ちは金と玉に支えられた打ち歩詰め。
+        let position = Position::from_sfen("4k/2G2/3K1/5/5 b P 1").expect("sfen");
+        let drop = crate::moves::Move::drop(Square::from_coord("1b").unwrap(), PieceKind::Pawn);
+        let standard = position.generate_legal_moves().expect("moves");
+        let relaxed = position
+            .generate_legal_moves_with(&NoPawnDropMateRules)
+            .expect("moves");
+        assert!(!standard.contains(&drop));
+        assert!(relaxed.contains(&drop));
+    }
+
+    #[test]
+    fn pawn_drop_targets_skip_last_rank_and_pawn_files() {
+        let position = Position::from_sfen("4k/5/5/P4/4K b P 1").expect("sfen");
+        let targets = StandardRules.drop_targets(&position, Color::Black, PieceKind::Pawn);
+        assert!((targets & promotion_zone_mask(Color::Black)).is_empty());
+        assert!((targets & Bitboard::file_mask(4)).is_empty());
+        assert!(targets.contains(Square::from_coord("3c").unwrap()));
+        // 2〜5段の1〜4筋から、玉のいる1五を除いた15マス。
+        assert_eq!(targets.count(), 15);
+    }
+}
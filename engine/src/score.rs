@@ -0,0 +1,73 @@
+use core::fmt;
+use core::ops::Neg;
+
+use crate::piece::Color;
+
+/// 先手（Black）から見た絶対評価値。正なら先手有利。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AbsScore(pub i32);
+
+/// 特定の手番（通常は手番側）から見た相対評価値。正ならその手番側が有利。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RelScore(pub i32);
+
+impl AbsScore {
+    pub const fn value(self) -> i32 {
+        self.0
+    }
+
+    /// `color` から見た相対評価値に変換する。
+    pub const fn relative_to(self, color: Color) -> RelScore {
+        match color {
+            Color::Black => RelScore(self.0),
+            Color::White => RelScore(-self.0),
+        }
+    }
+}
+
+impl RelScore {
+    pub const fn value(self) -> i32 {
+        self.0
+    }
+
+    /// `color` から見た相対評価値を先手から見た絶対評価値に戻す。
+    pub const fn absolute_from(self, color: Color) -> AbsScore {
+        match color {
+            Color::Black => AbsScore(self.0),
+            Color::White => AbsScore(-self.0),
+        }
+    }
+}
+
+impl Neg for RelScore {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
+    }
+}
+
+impl fmt::Display for AbsScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for RelScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_roundtrip() {
+        let abs = AbsScore(120);
+        assert_eq!(abs.relative_to(Color::Black), RelScore(120));
+        assert_eq!(abs.relative_to(Color::White), RelScore(-120));
+        assert_eq!(abs.relative_to(Color::White).absolute_from(Color::White), abs);
+    }
+}
@@ -1,22 +1,58 @@
-use std::ops::Range;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use crate::evaluation;
+use std::cmp::Reverse;
+use std::fmt;
+use std::ops::{Add, Range};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::evaluation::{
+    self, EvalBreakdown, EvalParams, EvalState, Evaluator, HandcraftedEval, PieceSquareTable,
+};
+use crate::handicap::Handicap;
+use crate::material::EndgameTable;
 use crate::moves::{Move, MoveList};
 use crate::piece::{Color, PIECE_KIND_COUNT};
-use crate::position::{Position, PositionError};
-use crate::table::{self, Bound, TableEntry, TranspositionTable};
+use crate::position::{Position, PositionError, REPETITION_LIMIT};
+use crate::score::{AbsScore, RelScore};
+use crate::table::{self, Bound, LocalTable, TableEntry, TranspositionTable};
+use crate::trace::{self, Counter, Section};
+use crate::zobrist;
 
 use crate::board::BOARD_SQUARES;
 
-const MATE_VALUE: i32 = 30_000;
-const MAX_PLY: usize = 64;
+pub(crate) const MATE_VALUE: i32 = 30_000;
+
+/// 3回目の繰り返しを先手に不利に寄せる量。2回目には加えない。
+const REPETITION_RULE_LEAN: i32 = 200;
+
+/// 成立前の繰り返しの、先手から見た評価値の上限。5五将棋の千日手は先手の負けなので、
+/// 補正を加えても先手から見て0点以上にはしない。
+const PENDING_REPETITION_MAX: i32 = -1;
+
+/// 既定の、ルートから読む最大の手数。
+pub const DEFAULT_MAX_PLY: usize = 64;
+
+/// `SearchLimits::max_ply` に設定できる上限。詰みの評価値はこの手数ぶんの幅を持つ。
+pub const MAX_SEARCH_PLY: usize = 1_000;
+
+/// 相手の応手のうち、最善の応手からこれだけ悪い手までを形勢を保つ手とみなす。
+const TRAP_HOLD_MARGIN: i32 = 50;
+
+/// 形勢を保つ応手1つの重み。打つ手は人には見つけにくいので軽く数える。
+const TRAP_MOVE_WEIGHT: usize = 2;
+const TRAP_DROP_WEIGHT: usize = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WindowFail {
+    Low,
+    High,
+}
 
 #[derive(Clone)]
-struct SimpleRng(u64);
+pub(crate) struct SimpleRng(u64);
 
 impl SimpleRng {
-    fn new(seed: u64) -> Self {
+    pub(crate) fn new(seed: u64) -> Self {
         let mut s = seed;
         if s == 0 {
             s = 0x9E37_79B9_7F4A_7C15;
@@ -33,7 +69,7 @@ impl SimpleRng {
         x.wrapping_mul(0x2545F4914F6CDD1D)
     }
 
-    fn gen_range(&mut self, range: Range<usize>) -> usize {
+    pub(crate) fn gen_range(&mut self, range: Range<usize>) -> usize {
         let span = range.end - range.start;
         if span == 0 {
             return range.start;
@@ -45,15 +81,98 @@ impl SimpleRng {
 #[derive(Debug, Default, Clone)]
 pub struct SearchResult {
     pub best_move: Option<Move>,
-    pub score: i32,
+    /// ルート局面の手番側から見た評価値。
+    pub score: RelScore,
     pub depth: usize,
     pub nodes: u64,
+    /// 探索の開始から指し手が決まるまでにかかった時間。
+    pub elapsed: Duration,
+}
+
+/// 探索中に数えた置換表の異常。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// 検証ビットが一致せず、別の局面のものとして捨てたエントリの数。
+    pub tt_collisions: u64,
+    /// 局面で指せない最善手を持っていたため、手を使わなかったエントリの数。
+    pub invalid_tt_moves: u64,
+}
+
+/// 探索器が使っているおおよそのメモリ（バイト）。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// 置換表。
+    pub tt: usize,
+    /// 履歴表とキラー手。
+    pub history: usize,
+    /// ルート局面の手の一覧と、ルートに与えた減点や予想手順。
+    pub root: usize,
+    /// 経験ファイルのような、探索の外で覚えている局面。
+    pub book: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.tt + self.history + self.root + self.book
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            tt: self.tt + other.tt,
+            history: self.history + other.history,
+            root: self.root + other.root,
+            book: self.book + other.book,
+        }
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    /// KiB単位（切り上げ）で `tt N history N root N book N total N` と書く。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kib = |bytes: usize| bytes.div_ceil(1024);
+        write!(
+            f,
+            "tt {} history {} root {} book {} total {} KiB",
+            kib(self.tt),
+            kib(self.history),
+            kib(self.root),
+            kib(self.book),
+            kib(self.total())
+        )
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct SearchLimits {
     pub depth: usize,
     pub randomness: i32,
+    /// `randomness` を使う手数。局面の手数がこれを超えたら最善手だけを選ぶ。
+    /// 0なら手数によらず乱択する。
+    pub random_plies: u32,
+    /// 検討モード。千日手を単純な引き分け（0点）として扱い、
+    /// 成立前の繰り返しに対する段階的なペナルティを無効にする。
+    pub analysis: bool,
+    /// ルート局面に要求深さ以上の確定値が置換表に残っていれば、探索せずにその手を返す。
+    pub tt_instant_move: bool,
+    /// ルート局面で最初に読む手。置換表に手がないときの並べ替えに使う。
+    pub root_hint: Option<Move>,
+    /// ルートから読む最大の手数。王手の延長で深くなってもここで読みを止め、評価値を返す。
+    /// `MAX_SEARCH_PLY` で頭打ちにする。
+    pub max_ply: usize,
+    /// 深さごとの読みが確定するたびに読み筋を指し直し、指せない手や評価値の食い違いを
+    /// `SearchDetail::PvFault` で報告する。置換表の境界の扱いの誤りを見つける開発用。
+    pub verify_pv: bool,
+    /// 評価値に足す揺らぎの最大幅（センチポーン）。0なら揺らさない。
+    /// 局面のハッシュと `eval_noise_seed` だけで決まるので、同じシードなら同じ手を選ぶ。
+    pub eval_noise: i32,
+    pub eval_noise_seed: u64,
+    /// 最善手からこれだけ悪い手までのうち、相手が形勢を保つ応手の少ない手を選ぶ。
+    /// 人との対局で、正しく応じにくい手を指させる。0なら最善手を選ぶ。`randomness` より優先する。
+    pub trap_margin: i32,
 }
 
 impl Default for SearchLimits {
@@ -61,8 +180,359 @@ impl Default for SearchLimits {
         Self {
             depth: 3,
             randomness: 0,
+            random_plies: 0,
+            analysis: false,
+            tt_instant_move: false,
+            root_hint: None,
+            max_ply: DEFAULT_MAX_PLY,
+            verify_pv: false,
+            eval_noise: 0,
+            eval_noise_seed: 0,
+            trap_margin: 0,
+        }
+    }
+}
+
+/// 探索の調整可能なパラメータ。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchParams {
+    /// 深さ2以降で前回の評価値の周りに張る窓の半幅。0なら窓を使わない。
+    pub aspiration_window: i32,
+    /// 窓を外れるたびに、外れた側の半幅に掛ける倍率。
+    pub aspiration_growth: i32,
+    /// この回数だけ窓を外れたら、外れた側を全幅に開く。
+    pub aspiration_max_retries: usize,
+    /// 窓を外れたとき、返ってきた評価値を基準に次の窓を張る。
+    /// `false` なら元の窓の端を基準にする。
+    pub aspiration_fail_soft: bool,
+    /// 成立前の繰り返しに付ける補正の上限。ルート局面で優勢な側には繰り返しを悪く、
+    /// 劣勢な側には良く見せる。ただし先手から見て0点以上にはしない。0なら補正しない。
+    pub max_contempt: i32,
+    /// この深さ以下の節点で、駒を取らず成らず王手でもない手を間引く。0なら間引かない。
+    pub lmp_max_depth: usize,
+    /// 深さ1あたり、間引かずに読む手の数。深さ `d` では `lmp_move_count * d` 手目から間引く。
+    pub lmp_move_count: usize,
+    /// ヒストリーの値がこれ以下の手だけを間引く。
+    pub lmp_history_limit: i32,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            aspiration_window: 50,
+            aspiration_growth: 4,
+            aspiration_max_retries: 3,
+            aspiration_fail_soft: true,
+            max_contempt: 300,
+            lmp_max_depth: 2,
+            lmp_move_count: 8,
+            lmp_history_limit: 0,
+        }
+    }
+}
+
+/// ハッシュ `key` の局面の評価値に足す、`-amplitude..=amplitude` の揺らぎ。
+pub fn eval_noise(key: u64, seed: u64, amplitude: i32) -> i32 {
+    if amplitude <= 0 {
+        return 0;
+    }
+    let span = amplitude as u64 * 2 + 1;
+    (zobrist::splitmix64(key ^ seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)) % span) as i32 - amplitude
+}
+
+/// 評価値が詰みを表すなら、詰むまでの手数（自分の手の数）を返す。詰まされる側なら負の値。
+pub fn mate_distance(score: RelScore) -> Option<i32> {
+    let score = score.value();
+    if score.abs() < MATE_VALUE - MAX_SEARCH_PLY as i32 {
+        None
+    } else if score > 0 {
+        Some((MATE_VALUE - score + 1) / 2)
+    } else {
+        Some(-((MATE_VALUE + score + 1) / 2))
+    }
+}
+
+/// 反復深化の各深さが終わるたびに報告される途中経過。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchInfo {
+    pub depth: usize,
+    /// ルート局面の手番側から見た評価値。
+    pub score: RelScore,
+    pub nodes: u64,
+    pub best_move: Option<Move>,
+    /// `best_move` から始まる読み筋。置換表から辿れない場合は `best_move` だけ。
+    pub pv: MoveList,
+    /// 探索開始からの経過時間。
+    pub elapsed: Duration,
+    /// 探索窓の内側に収まり、その深さの評価値が確定した報告か。
+    /// 窓を外れて探索し直す前の報告では `false`。
+    pub complete: bool,
+}
+
+impl SearchInfo {
+    /// 1秒あたりの探索ノード数。経過時間が1ミリ秒未満なら0。
+    pub fn nps(&self) -> u64 {
+        let millis = self.elapsed.as_millis() as u64;
+        self.nodes
+            .saturating_mul(1000)
+            .checked_div(millis)
+            .unwrap_or(0)
+    }
+
+    /// USIの `info` 行として整形する。
+    pub fn to_usi(&self) -> String {
+        let score = self.score.value();
+        let (score_tag, score_value) = match mate_distance(self.score) {
+            Some(mate) => ("mate", mate.to_string()),
+            None => ("cp", score.to_string()),
+        };
+        let mut line = format!(
+            "info depth {} score {} {} nodes {} time {} nps {}",
+            self.depth,
+            score_tag,
+            score_value,
+            self.nodes,
+            self.elapsed.as_millis(),
+            self.nps()
+        );
+        if !self.pv.is_empty() {
+            line.push_str(" pv");
+            for mv in &self.pv {
+                line.push(' ');
+                line.push_str(&mv.to_usi());
+            }
+        }
+        line
+    }
+}
+
+/// 深さごとの報告より細かい探索の出来事。`Verbosity::Verbose` のときだけ報告される。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchDetail {
+    /// ルート局面で `number` 番目（1から数える）の手 `mv` を読み始めた。
+    CurrentMove {
+        depth: usize,
+        mv: Move,
+        number: usize,
+    },
+    /// 探索窓 `alpha..beta` を外れたので探索し直す。
+    AspirationFail {
+        depth: usize,
+        fail_high: bool,
+        alpha: i32,
+        beta: i32,
+    },
+    /// 探索の終わりに報告する統計。
+    Stats { nodes: u64, stats: SearchStats },
+    /// `SearchLimits::verify_pv` で見つけた読み筋の異常。報告量によらず報告する。
+    PvFault { depth: usize, fault: PvFault },
+}
+
+/// 確定した深さの読み筋を指し直して見つかった異常。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PvFault {
+    /// 読み筋の `ply` 手目（0から数える）が、その局面で指せない手だった。
+    IllegalMove { ply: usize, mv: Move },
+    /// 読み筋の末端を静止探索した値（ルートの手番側から見たもの）が、報告した評価値から
+    /// `PV_SCORE_TOLERANCE` を超えてずれた。
+    ScoreMismatch {
+        reported: i32,
+        leaf: i32,
+        length: usize,
+    },
+}
+
+/// 読み筋の末端の値と報告した評価値のずれとして許す幅。
+pub const PV_SCORE_TOLERANCE: i32 = 1;
+
+impl SearchDetail {
+    /// USIの `info` 行として整形する。
+    pub fn to_usi(&self) -> String {
+        match *self {
+            Self::CurrentMove { depth, mv, number } => format!(
+                "info depth {depth} currmove {} currmovenumber {number}",
+                mv.to_usi()
+            ),
+            Self::AspirationFail {
+                depth,
+                fail_high,
+                alpha,
+                beta,
+            } => format!(
+                "info string depth {depth} fail {} window {alpha} {beta}",
+                if fail_high { "high" } else { "low" }
+            ),
+            Self::Stats { nodes, stats } => format!(
+                "info string stats nodes {nodes} tt_collisions {} invalid_tt_moves {}",
+                stats.tt_collisions, stats.invalid_tt_moves
+            ),
+            Self::PvFault {
+                depth,
+                fault: PvFault::IllegalMove { ply, mv },
+            } => format!(
+                "info string depth {depth} pv fault illegal move {} at ply {ply}",
+                mv.to_usi()
+            ),
+            Self::PvFault {
+                depth,
+                fault:
+                    PvFault::ScoreMismatch {
+                        reported,
+                        leaf,
+                        length,
+                    },
+            } => format!(
+                "info string depth {depth} pv fault score {reported} but leaf {leaf} after {length} moves"
+            ),
+        }
+    }
+}
+
+/// 探索器が報告する量。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// 何も報告しない。ライブラリ利用やベンチマーク向け。
+    Silent,
+    /// 深さごとの報告だけ。
+    #[default]
+    Normal,
+    /// 読んでいる手、探索窓の外れ、統計も報告する。
+    Verbose,
+}
+
+impl Verbosity {
+    pub const ALL: [Self; 3] = [Self::Silent, Self::Normal, Self::Verbose];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Silent => "silent",
+            Self::Normal => "normal",
+            Self::Verbose => "verbose",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|verbosity| verbosity.name() == name)
+    }
+}
+
+/// 探索の途中経過の出力先。
+pub trait InfoSink: Send {
+    fn info(&mut self, info: &SearchInfo);
+
+    /// 細かい出来事を受け取る。`SearchDetail::PvFault` を除き、`wants_detail` が `true` のときだけ呼ばれる。
+    fn detail(&mut self, _detail: &SearchDetail) {}
+
+    /// `detail` を受け取るか。探索の開始時に1度だけ問い合わせる。
+    fn wants_detail(&self) -> bool {
+        false
+    }
+
+    /// 溜めている途中経過があれば書き出す。探索の終わりに呼ばれる。
+    fn flush(&mut self) {}
+}
+
+impl<S: InfoSink + ?Sized> InfoSink for Box<S> {
+    fn info(&mut self, info: &SearchInfo) {
+        (**self).info(info)
+    }
+
+    fn detail(&mut self, detail: &SearchDetail) {
+        (**self).detail(detail)
+    }
+
+    fn wants_detail(&self) -> bool {
+        (**self).wants_detail()
+    }
+
+    fn flush(&mut self) {
+        (**self).flush()
+    }
+}
+
+/// USIの `info` 行として標準出力に書き出す。細かい出来事は `LeveledInfoSink` で
+/// 求めたときだけ書く。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdoutInfoSink;
+
+impl InfoSink for StdoutInfoSink {
+    fn info(&mut self, info: &SearchInfo) {
+        println!("{}", info.to_usi());
+    }
+
+    fn detail(&mut self, detail: &SearchDetail) {
+        println!("{}", detail.to_usi());
+    }
+}
+
+/// `verbosity` に応じて報告を `inner` へ通すか捨てる。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LeveledInfoSink<S> {
+    pub inner: S,
+    pub verbosity: Verbosity,
+}
+
+impl<S: InfoSink> LeveledInfoSink<S> {
+    pub fn new(inner: S, verbosity: Verbosity) -> Self {
+        Self { inner, verbosity }
+    }
+}
+
+impl<S: InfoSink> InfoSink for LeveledInfoSink<S> {
+    fn info(&mut self, info: &SearchInfo) {
+        if self.verbosity != Verbosity::Silent {
+            self.inner.info(info);
+        }
+    }
+
+    fn detail(&mut self, detail: &SearchDetail) {
+        let fault = matches!(detail, SearchDetail::PvFault { .. });
+        if self.verbosity == Verbosity::Verbose || (fault && self.verbosity != Verbosity::Silent) {
+            self.inner.detail(detail);
         }
     }
+
+    fn wants_detail(&self) -> bool {
+        self.verbosity == Verbosity::Verbose
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+    }
+}
+
+/// 途中経過を捨てる。ライブラリ利用や自己対局向け。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullInfoSink;
+
+impl InfoSink for NullInfoSink {
+    fn info(&mut self, _info: &SearchInfo) {}
+}
+
+/// 探索を別スレッドから止めるためのハンドル。クローンは同じフラグを共有する。
+#[derive(Clone, Debug, Default)]
+pub struct StopToken(Arc<AtomicBool>);
+
+impl StopToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 探索の停止を要求する。
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// 停止要求を取り消し、次の探索に再利用できるようにする。
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -71,37 +541,209 @@ struct RootEntry {
     score: i32,
 }
 
-pub struct Searcher {
-    tt: TranspositionTable,
+/// USI層などから使う探索の共通インターフェース。
+pub trait SearchBackend: Send {
+    /// `stop` が立てられた時点で探索を打ち切り、それまでの最善の結果を返す。
+    fn search_with_stop(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+    ) -> Result<SearchResult, PositionError>;
+
+    fn search(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+    ) -> Result<SearchResult, PositionError> {
+        self.search_with_stop(position, limits, &StopToken::new())
+    }
+
+    /// 直前の探索から得られる `position` 以降の読み筋。
+    fn principal_variation(&self, position: &Position, max_len: usize) -> MoveList;
+
+    fn set_info_sink(&mut self, sink: Box<dyn InfoSink>);
+
+    /// 置換表を持たない実装は無視してよい。
+    fn set_transposition_table(&mut self, _table: Box<dyn TranspositionTable>) {}
+
+    fn set_keep_tt(&mut self, _keep: bool) {}
+
+    /// 置換表に残っている `position` のエントリ。
+    fn probe_tt(&self, _position: &Position) -> Option<TableEntry> {
+        None
+    }
+
+    /// 評価関数が返す `position` の評価値の内訳。
+    fn eval_breakdown(&self, _position: &Position) -> Option<EvalBreakdown> {
+        None
+    }
+
+    /// 評価関数に駒落ちの手合いを伝える。
+    fn set_handicap(&mut self, _handicap: Option<Handicap>) {}
+
+    /// 評価関数に駒の価値を伝える。
+    fn set_piece_values(&mut self, _values: [i32; PIECE_KIND_COUNT]) {}
+
+    /// 評価関数に中盤と終盤の駒の位置の表を伝える。
+    fn set_piece_square_tables(
+        &mut self,
+        _middlegame: &PieceSquareTable,
+        _endgame: &PieceSquareTable,
+    ) {
+    }
+
+    /// ルート局面 `position` の手ごとに評価値から引く値。過去に負け越した序盤を避けるのに使う。
+    /// 別の局面を探索するときは使わない。
+    fn set_root_penalties(&mut self, _position: &Position, _penalties: Vec<(Move, i32)>) {}
+
+    /// 前の探索の読み筋のうち、ルート局面 `position` から先に予想される手順。
+    /// 置換表に手のない局面で、その手順の手を最初に読む。
+    fn set_expected_line(&mut self, _position: &Position, _line: &[Move]) {}
+
+    /// 直前の探索で数えた置換表の異常。
+    fn stats(&self) -> SearchStats {
+        SearchStats::default()
+    }
+
+    /// 探索器が使っているおおよそのメモリ。
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+}
+
+/// 反復深化つきのアルファベータ探索。評価関数は `E` で差し替えられる。
+pub struct Searcher<E: Evaluator = HandcraftedEval> {
+    tt: Box<dyn TranspositionTable>,
     nodes: u64,
-    killers: [[Option<Move>; 2]; MAX_PLY],
+    stats: SearchStats,
+    /// 手数ごとのキラー手。探索のたびに `max_ply` の長さで確保し直す。
+    killers: Vec<[Option<Move>; 2]>,
+    /// 手数ごとの、読んでいる局面の先手から見た静的評価値。まだ評価していなければ `None`。
+    /// 子の局面を `Evaluator::evaluate_incremental` で評価するのに使う。
+    static_evals: Vec<Option<EvalState>>,
     history: [[[i32; BOARD_SQUARES]; PIECE_KIND_COUNT]; 2],
     rng: SimpleRng,
     limits: SearchLimits,
+    params: SearchParams,
     root_entries: Vec<RootEntry>,
+    evaluator: E,
+    /// `set_endgame_table` で与えた終盤の表。引けた局面では評価関数より優先する。
+    endgame_table: Option<Box<dyn EndgameTable>>,
+    stop: StopToken,
+    interruptible: bool,
+    aborted: bool,
+    info_sink: Box<dyn InfoSink>,
+    /// 今の探索で `SearchDetail` を報告するか。
+    report_detail: bool,
+    keep_tt: bool,
+    /// 最善手の代わりに選んだルートの手と、その局面のキー。`principal_variation` はこの手から始める。
+    picked_root: Option<(u64, Move)>,
+    /// 前回の探索のルート局面の `last_irreversible_ply`。
+    table_epoch: Option<u32>,
+    /// ルート局面の手番と、その側から見た直近の評価値。繰り返しの補正に使う。
+    root_color: Color,
+    root_score: i32,
+    /// `set_root_penalties` で与えたルート局面のハッシュと、手ごとの減点。
+    root_penalties: Option<(u64, Vec<(Move, i32)>)>,
+    /// `set_expected_line` で与えた手順の、各局面のハッシュとそこで予想される手。
+    expected_line: Vec<(u64, Move)>,
 }
 
-impl Default for Searcher {
+impl<E: Evaluator + Default> Default for Searcher<E> {
     fn default() -> Self {
+        Self::with_evaluator(E::default())
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eval_params(&self) -> &EvalParams {
+        &self.evaluator.params
+    }
+
+    pub fn set_eval_params(&mut self, params: EvalParams) {
+        self.evaluator.params = params;
+    }
+}
+
+impl<E: Evaluator> Searcher<E> {
+    pub fn with_evaluator(evaluator: E) -> Self {
         let seed = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .map(|d| d.as_nanos() as u64)
             .unwrap_or(0x9E37_79B9_7F4A_7C15);
         Self {
-            tt: TranspositionTable::new(),
+            tt: Box::new(LocalTable::new()),
             nodes: 0,
-            killers: [[None; 2]; MAX_PLY],
+            stats: SearchStats::default(),
+            killers: vec![[None; 2]; DEFAULT_MAX_PLY],
+            static_evals: vec![None; DEFAULT_MAX_PLY + 1],
             history: [[[0; BOARD_SQUARES]; PIECE_KIND_COUNT]; 2],
             rng: SimpleRng::new(seed),
             limits: SearchLimits::default(),
+            params: SearchParams::default(),
             root_entries: Vec::new(),
+            evaluator,
+            endgame_table: None,
+            stop: StopToken::new(),
+            keep_tt: false,
+            picked_root: None,
+            table_epoch: None,
+            interruptible: false,
+            aborted: false,
+            info_sink: Box::new(StdoutInfoSink),
+            report_detail: false,
+            root_color: Color::Black,
+            root_score: 0,
+            root_penalties: None,
+            expected_line: Vec::new(),
         }
     }
-}
 
-impl Searcher {
-    pub fn new() -> Self {
-        Self::default()
+    /// 末端の局面を評価する前に引く終盤の表を与える。
+    pub fn set_endgame_table(&mut self, table: Box<dyn EndgameTable>) {
+        self.endgame_table = Some(table);
+    }
+
+    pub fn evaluator(&self) -> &E {
+        &self.evaluator
+    }
+
+    pub fn evaluator_mut(&mut self) -> &mut E {
+        &mut self.evaluator
+    }
+
+    pub fn search_params(&self) -> &SearchParams {
+        &self.params
+    }
+
+    pub fn set_search_params(&mut self, params: SearchParams) {
+        self.params = params;
+    }
+
+    /// ルートの乱択に使う乱数の種を決める。既定では作った時刻から決まる。
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = SimpleRng::new(seed);
+    }
+
+    /// 途中経過の出力先を差し替える。既定では標準出力にUSIの `info` 行を書く。
+    pub fn set_info_sink(&mut self, sink: Box<dyn InfoSink>) {
+        self.info_sink = sink;
+    }
+
+    /// 置換表を差し替える。`Arc<SharedTable>` を渡せば他の探索器と共有できる。
+    pub fn set_transposition_table(&mut self, table: Box<dyn TranspositionTable>) {
+        self.tt = table;
+    }
+
+    /// `true` にすると探索の開始時に置換表を消さない。先読みで埋めた表を次の探索に使うときに指定する。
+    /// ルート局面で駒を取る手・打つ手・歩を進める手が指されていたら、それでも消す。
+    pub fn set_keep_tt(&mut self, keep: bool) {
+        self.keep_tt = keep;
     }
 
     pub fn search(
@@ -109,105 +751,429 @@ impl Searcher {
         position: &Position,
         limits: SearchLimits,
     ) -> Result<SearchResult, PositionError> {
+        self.search_with_stop(position, limits, &StopToken::new())
+    }
+
+    /// `stop` が立てられた時点で探索を打ち切り、最後に完了した深さの結果を返す。
+    /// 深さ1の探索は指し手を必ず返すため中断しない。
+    pub fn search_with_stop(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+    ) -> Result<SearchResult, PositionError> {
+        let started = Instant::now();
+        let _span = trace::span(Section::Search);
+        self.report_detail = self.info_sink.wants_detail();
+        let result = self.iterative_deepening(position, limits, stop, started);
+        if self.report_detail {
+            self.info_sink.detail(&SearchDetail::Stats {
+                nodes: self.nodes,
+                stats: self.stats,
+            });
+        }
+        self.info_sink.flush();
+        let mut result = result?;
+        result.elapsed = started.elapsed();
+        Ok(result)
+    }
+
+    fn iterative_deepening(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+        started: Instant,
+    ) -> Result<SearchResult, PositionError> {
+        self.stop = stop.clone();
+        self.interruptible = false;
+        self.aborted = false;
+        self.picked_root = None;
         self.limits = limits;
         let max_depth = limits.depth.max(1);
         self.nodes = 0;
-        self.tt.clear();
+        self.stats = SearchStats::default();
+        if limits.tt_instant_move
+            && let Some(result) = self.instant_tt_move(position, max_depth)?
+        {
+            return Ok(result);
+        }
+        if table_is_stale(self.keep_tt, &mut self.table_epoch, position) {
+            self.tt.clear();
+        }
         self.clear_heuristics();
         self.root_entries.clear();
+        self.set_root_standing(position);
 
-        if position.generate_legal_moves()?.is_empty() {
+        if !position.has_legal_move()? {
             let score = terminal_score(position, 0)?;
             return Ok(SearchResult {
                 best_move: None,
-                score,
+                score: RelScore(score),
                 depth: 0,
                 nodes: self.nodes,
+                elapsed: Duration::ZERO,
             });
         }
 
         let mut result = SearchResult::default();
-        let mut last_score = 0;
-
-        for depth in 1..=max_depth {
+        let mut last_score: i32 = 0;
+        // 直前の深さで窓を外れた方向。次の深さではその側を広げておく。
+        let mut last_fail = None;
+        let params = self.params;
+        let growth = params.aspiration_growth.max(1);
+
+        'deepening: for depth in 1..=max_depth {
+            self.interruptible = depth > 1;
             let mut alpha = -MATE_VALUE;
             let mut beta = MATE_VALUE;
-
-            if depth > 1 {
-                let window = 50;
-                alpha = (last_score - window).max(-MATE_VALUE);
-                beta = (last_score + window).min(MATE_VALUE);
+            let mut lower_delta = params.aspiration_window;
+            let mut upper_delta = params.aspiration_window;
+
+            if depth > 1 && params.aspiration_window > 0 {
+                match last_fail {
+                    Some(WindowFail::Low) => lower_delta = lower_delta.saturating_mul(growth),
+                    Some(WindowFail::High) => upper_delta = upper_delta.saturating_mul(growth),
+                    None => {}
+                }
+                alpha = last_score.saturating_sub(lower_delta).max(-MATE_VALUE);
+                beta = last_score.saturating_add(upper_delta).min(MATE_VALUE);
             }
+            last_fail = None;
+            let mut retries = 0;
 
             loop {
                 let iteration = self.root_iteration(position, depth, alpha, beta)?;
+                if self.aborted {
+                    break 'deepening;
+                }
                 if iteration.best_move.is_none() {
                     break;
                 }
 
-                let score = iteration.score;
+                let score = iteration.score.value();
                 last_score = score;
+                self.root_score = score;
                 result.best_move = iteration.best_move;
-                result.score = score;
+                result.score = iteration.score;
                 result.depth = depth;
                 result.nodes = self.nodes;
-                self.print_info(depth, score, iteration.best_move, self.nodes);
+                let fail_low = score <= alpha && alpha > -MATE_VALUE;
+                let fail_high = score >= beta && beta < MATE_VALUE;
+                let pv = self.reported_pv(position, iteration.best_move, depth);
+                self.info_sink.info(&SearchInfo {
+                    depth,
+                    score: iteration.score,
+                    nodes: self.nodes,
+                    best_move: iteration.best_move,
+                    pv,
+                    elapsed: started.elapsed(),
+                    complete: !fail_low && !fail_high,
+                });
+                if self.report_detail && (fail_low || fail_high) {
+                    self.info_sink.detail(&SearchDetail::AspirationFail {
+                        depth,
+                        fail_high,
+                        alpha,
+                        beta,
+                    });
+                }
 
-                if score <= alpha {
-                    alpha = -MATE_VALUE;
-                    beta = score + 1;
+                if fail_low {
+                    last_fail = Some(WindowFail::Low);
+                    retries += 1;
+                    lower_delta = lower_delta.saturating_mul(growth);
+                    let base = if params.aspiration_fail_soft {
+                        score
+                    } else {
+                        alpha
+                    };
+                    alpha = if retries >= params.aspiration_max_retries {
+                        -MATE_VALUE
+                    } else {
+                        base.saturating_sub(lower_delta).max(-MATE_VALUE)
+                    };
                     continue;
                 }
-                if score >= beta {
-                    beta = MATE_VALUE;
-                    alpha = score - 1;
+                if fail_high {
+                    last_fail = Some(WindowFail::High);
+                    retries += 1;
+                    upper_delta = upper_delta.saturating_mul(growth);
+                    let base = if params.aspiration_fail_soft {
+                        score
+                    } else {
+                        beta
+                    };
+                    beta = if retries >= params.aspiration_max_retries {
+                        MATE_VALUE
+                    } else {
+                        base.saturating_add(upper_delta).min(MATE_VALUE)
+                    };
                     continue;
                 }
+                if limits.verify_pv
+                    && let Some(fault) = self.verify_pv(position, depth, score)?
+                {
+                    self.info_sink
+                        .detail(&SearchDetail::PvFault { depth, fault });
+                }
                 break;
             }
         }
 
-        result.best_move = self.pick_root_move();
+        result.best_move = match self.pick_trap_move(position, result.depth)? {
+            Some((mv, score)) => {
+                // 評価値と読み筋は選んだ手のものにする。この値は局面の値の下限でしかないので
+                // 置換表には書かず、表には最善手の確定値を残す。
+                self.picked_root = Some((position.zobrist_key(), mv));
+                result.score = RelScore(score);
+                Some(mv)
+            }
+            None => self.pick_root_move(position),
+        };
+        result.nodes = self.nodes;
         Ok(result)
     }
 
-    fn root_iteration(
+    /// `trap_margin` の範囲の手を読み直し、相手が形勢を保つ応手の重みの合計が最も小さい手と
+    /// 読み直した評価値を返す。同じなら評価値の高い手。範囲が0か、読み直しの途中で止められたら `None`。
+    fn pick_trap_move(
         &mut self,
         position: &Position,
         depth: usize,
-        mut alpha: i32,
-        beta: i32,
-    ) -> Result<SearchResult, PositionError> {
-        self.nodes += 1;
-        let hash = table::compute_hash(position);
-        let tt_move = self.tt.probe(hash).and_then(|entry| entry.best_move);
-
-        let mut moves = position.generate_legal_moves()?;
-        if moves.is_empty() {
-            let score = terminal_score(position, 0)?;
-            return Ok(SearchResult {
-                best_move: None,
-                score,
-                depth: 0,
-                nodes: self.nodes,
-            });
+    ) -> Result<Option<(Move, i32)>, PositionError> {
+        let margin = self.limits.trap_margin;
+        let Some(best) = self.root_entries.first().map(|entry| entry.score) else {
+            return Ok(None);
+        };
+        if margin <= 0 || depth == 0 || self.aborted || mate_distance(RelScore(best)).is_some() {
+            return Ok(None);
         }
-
-        self.order_moves(position, &mut moves, tt_move, 0);
-
-        let mut best_move = None;
-        let mut best_score = -MATE_VALUE;
-        let mut local_entries: Vec<RootEntry> = Vec::with_capacity(moves.len());
-
-        for mv in moves {
-            let mover = position.side_to_move();
+        let threshold = best - margin;
+        let candidates: Vec<Move> = self
+            .root_entries
+            .iter()
+            .take_while(|entry| entry.score >= threshold)
+            .map(|entry| entry.mv)
+            .collect();
+        let mut chosen: Option<(usize, Reverse<i32>, Move)> = None;
+        for mv in candidates {
+            // ルートの最善手以外の値は窓の外の上限なので、全幅で読み直す。
+            let score = self.root_move_score(position, mv, depth, -MATE_VALUE, MATE_VALUE)?;
+            if score < threshold {
+                continue;
+            }
             let next = position.play_move(&mv)?;
-
-            if let Some(score) =
-                repetition_terminal_value(mover, next.current_repetition_count(), 1)
-            {
-                local_entries.push(RootEntry { mv, score });
-                if score > best_score {
+            let mut replies = Vec::new();
+            for reply in next.generate_legal_moves()? {
+                let reply_score = self.root_move_score(
+                    &next,
+                    reply,
+                    depth.saturating_sub(1).max(1),
+                    -MATE_VALUE,
+                    MATE_VALUE,
+                )?;
+                replies.push((reply, reply_score));
+            }
+            if self.aborted {
+                return Ok(None);
+            }
+            let best_reply = replies.iter().map(|&(_, score)| score).max();
+            let holding: usize = replies
+                .iter()
+                .filter(|&&(_, score)| {
+                    best_reply.is_some_and(|best| score >= best - TRAP_HOLD_MARGIN)
+                })
+                .map(|(reply, _)| {
+                    if reply.is_drop() {
+                        TRAP_DROP_WEIGHT
+                    } else {
+                        TRAP_MOVE_WEIGHT
+                    }
+                })
+                .sum();
+            let key = (holding, Reverse(score), mv);
+            if chosen.is_none_or(|current| (key.0, key.1) < (current.0, current.1)) {
+                chosen = Some(key);
+            }
+        }
+        Ok(chosen.map(|(_, Reverse(score), mv)| (mv, score)))
+    }
+
+    /// ルート局面のすべての合法手を深さ `depth` で全幅探索し、手番側から見た評価値の
+    /// 高い順に返す。ルートでの乱択は行わない。
+    pub fn evaluate_all_moves(
+        &mut self,
+        position: &Position,
+        depth: usize,
+    ) -> Result<Vec<(Move, RelScore)>, PositionError> {
+        self.limits = SearchLimits {
+            depth: depth.max(1),
+            randomness: 0,
+            ..self.limits
+        };
+        self.nodes = 0;
+        self.stats = SearchStats::default();
+        self.interruptible = false;
+        self.aborted = false;
+        self.tt.clear();
+        self.clear_heuristics();
+        self.set_root_standing(position);
+
+        let mut scored = Vec::new();
+        for mv in position.generate_legal_moves()? {
+            let score = self.root_move_score(position, mv, depth, -MATE_VALUE, MATE_VALUE)?;
+            scored.push((mv, RelScore(score)));
+        }
+        scored.sort_by_key(|&(_, score)| Reverse(score));
+        Ok(scored)
+    }
+
+    /// ルートの手 `mv` を深さ `depth` で読み、ルートの手番側から見た評価値を返す。
+    fn root_move_score(
+        &mut self,
+        position: &Position,
+        mv: Move,
+        depth: usize,
+        alpha: i32,
+        beta: i32,
+    ) -> Result<i32, PositionError> {
+        let mover = position.side_to_move();
+        let mut next = position.play_move(&mv)?;
+        if let Some(score) = self
+            .repetition_value(next.current_repetition_count(), 1)
+            .map(|value| value.relative_to(mover).value())
+        {
+            return Ok(score);
+        }
+        let penalty = self.root_penalty(position, mv);
+        let mut child_depth = depth.max(1) - 1;
+        if next.in_check() {
+            child_depth += 1;
+            trace::count(Counter::CheckExtensions);
+        }
+        let (alpha, beta) = (alpha.saturating_add(penalty), beta.saturating_add(penalty));
+        Ok(-self.alpha_beta(&mut next, child_depth, -beta, -alpha, 1)? - penalty)
+    }
+
+    /// ルート分割の並列探索で、各スレッドが手を読み始める前に呼ぶ。
+    pub(crate) fn begin_root_split(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+    ) {
+        self.stop = stop.clone();
+        self.aborted = false;
+        self.limits = limits;
+        self.nodes = 0;
+        self.stats = SearchStats::default();
+        self.clear_heuristics();
+        self.set_root_standing(position);
+    }
+
+    /// ルート分割の並列探索で割り当てられた1手を読む。深さ2以降で `stop` が
+    /// 立てられたら `None` を返す。
+    pub(crate) fn search_root_move(
+        &mut self,
+        position: &Position,
+        mv: Move,
+        depth: usize,
+        alpha: i32,
+        beta: i32,
+    ) -> Result<Option<i32>, PositionError> {
+        self.interruptible = depth > 1;
+        let score = self.root_move_score(position, mv, depth, alpha, beta)?;
+        Ok((!self.aborted).then_some(score))
+    }
+
+    /// 直前の探索で調べた局面数。
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// 前回の探索で残った置換表にルート局面の確定値が `depth` 以上の深さで
+    /// 記録されていれば、その手を探索結果として返す。
+    fn instant_tt_move(
+        &mut self,
+        position: &Position,
+        depth: usize,
+    ) -> Result<Option<SearchResult>, PositionError> {
+        let Some(entry) = self.probe_entry(table::compute_hash(position)) else {
+            return Ok(None);
+        };
+        if entry.bound != Bound::Exact || entry.depth < depth {
+            return Ok(None);
+        }
+        let Some(mv) = self.entry_move(position, Some(entry)) else {
+            return Ok(None);
+        };
+        if !position.generate_legal_moves()?.contains(&mv) {
+            return Ok(None);
+        }
+        Ok(Some(SearchResult {
+            best_move: Some(mv),
+            score: RelScore(entry.score),
+            depth: entry.depth,
+            nodes: 0,
+            elapsed: Duration::ZERO,
+        }))
+    }
+
+    fn root_iteration(
+        &mut self,
+        position: &Position,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> Result<SearchResult, PositionError> {
+        self.nodes += 1;
+        let hash = table::compute_hash(position);
+        let entry = self.probe_entry(hash);
+        let tt_move = self
+            .entry_move(position, entry)
+            .or(self.limits.root_hint)
+            .or_else(|| self.expected_move(position));
+
+        let mut moves = position.generate_legal_moves()?;
+        if moves.is_empty() {
+            let score = terminal_score(position, 0)?;
+            return Ok(SearchResult {
+                best_move: None,
+                score: RelScore(score),
+                depth: 0,
+                nodes: self.nodes,
+                elapsed: Duration::ZERO,
+            });
+        }
+
+        self.order_moves(position, &mut moves, tt_move, 0);
+        let original_alpha = alpha;
+
+        let mut best_move = None;
+        let mut best_score = -MATE_VALUE;
+        let mut local_entries: Vec<RootEntry> = Vec::with_capacity(moves.len());
+        // 子の局面はこの1つの複製で手を指しては戻して作る。
+        let mut next = position.clone();
+
+        for (index, mv) in moves.into_iter().enumerate() {
+            if self.report_detail {
+                self.info_sink.detail(&SearchDetail::CurrentMove {
+                    depth,
+                    mv,
+                    number: index + 1,
+                });
+            }
+            let mover = position.side_to_move();
+            let undo = next.make_move(&mv)?;
+
+            if let Some(score) = self
+                .repetition_value(next.current_repetition_count(), 1)
+                .map(|value| value.relative_to(mover).value())
+            {
+                next.unmake_move(undo);
+                local_entries.push(RootEntry { mv, score });
+                if score > best_score {
                     best_score = score;
                     best_move = Some(mv);
                 }
@@ -217,11 +1183,22 @@ impl Searcher {
                 continue;
             }
 
+            let penalty = self.root_penalty(position, mv);
             let mut child_depth = depth - 1;
-            if next.is_in_check(next.side_to_move()) {
+            if next.in_check() {
                 child_depth += 1;
+                trace::count(Counter::CheckExtensions);
             }
-            let score = -self.alpha_beta(&next, child_depth, -beta, -alpha, 1)?;
+            // 減点の分だけ窓をずらして読み、減点後の値が元の窓で正しく比べられるようにする。
+            let child = self.alpha_beta(
+                &mut next,
+                child_depth,
+                -beta.saturating_add(penalty),
+                -alpha.saturating_add(penalty),
+                1,
+            );
+            next.unmake_move(undo);
+            let score = -child? - penalty;
             local_entries.push(RootEntry { mv, score });
 
             if score > best_score {
@@ -233,62 +1210,89 @@ impl Searcher {
             }
         }
 
-        local_entries.sort_by(|a, b| b.score.cmp(&a.score));
+        if self.aborted {
+            return Ok(SearchResult::default());
+        }
+
+        local_entries.sort_by_key(|entry| Reverse(entry.score));
         self.root_entries = local_entries;
 
         if let Some(best) = best_move {
+            // 窓を外れたときの値は確定値ではないので、置換表には境界として残す。
+            let bound = if best_score <= original_alpha {
+                Bound::Upper
+            } else if best_score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
             self.tt.store(
                 hash,
                 TableEntry {
                     depth,
                     score: best_score,
-                    bound: Bound::Exact,
+                    bound,
                     best_move: Some(best),
+                    check: table::verification_bits(hash),
                 },
             );
         }
 
         Ok(SearchResult {
             best_move,
-            score: best_score,
+            score: RelScore(best_score),
             depth,
             nodes: self.nodes,
+            elapsed: Duration::ZERO,
         })
     }
 
     fn alpha_beta(
         &mut self,
-        position: &Position,
+        position: &mut Position,
         depth: usize,
         mut alpha: i32,
         mut beta: i32,
         ply: usize,
     ) -> Result<i32, PositionError> {
+        if self.should_stop() {
+            return Ok(0);
+        }
         self.nodes += 1;
-
-        if let Some(score) = repetition_terminal_value(
-            position.side_to_move(),
-            position.current_repetition_count(),
-            ply,
-        ) {
+        trace::count(Counter::MainNodes);
+        self.forget_static_eval(ply);
+
+        if let Some(score) = self
+            .repetition_value(position.current_repetition_count(), ply)
+            .map(|value| value.relative_to(position.side_to_move()).value())
+        {
+            trace::count(Counter::RepetitionHits);
             return Ok(score);
         }
 
+        if ply >= self.max_ply() {
+            return self.horizon_value(position, ply);
+        }
         if depth == 0 {
             return self.quiescence(position, alpha, beta, ply);
         }
 
         let hash = table::compute_hash(position);
-        if let Some(entry) = self.tt.probe(hash) {
-            if entry.depth >= depth {
-                match entry.bound {
-                    Bound::Exact => return Ok(entry.score),
-                    Bound::Lower => alpha = alpha.max(entry.score),
-                    Bound::Upper => beta = beta.min(entry.score),
-                }
-                if alpha >= beta {
+        let entry = self.probe_entry(hash);
+        if let Some(entry) = entry
+            && entry.depth >= depth
+        {
+            match entry.bound {
+                Bound::Exact => {
+                    trace::count(Counter::TtCutoffs);
                     return Ok(entry.score);
                 }
+                Bound::Lower => alpha = alpha.max(entry.score),
+                Bound::Upper => beta = beta.min(entry.score),
+            }
+            if alpha >= beta {
+                trace::count(Counter::TtCutoffs);
+                return Ok(entry.score);
             }
         }
 
@@ -296,19 +1300,43 @@ impl Searcher {
         if moves.is_empty() {
             return terminal_score(position, ply);
         }
+        // 指し手のループで `alpha` は引き上がるので、境界の判定には探索前の窓を使う。
+        let original_alpha = alpha;
 
-        let tt_move = self.tt.probe(hash).and_then(|entry| entry.best_move);
+        let tt_move = self
+            .entry_move(position, entry)
+            .or_else(|| self.expected_move(position));
         self.order_moves(position, &mut moves, tt_move, ply);
 
         let mut best_value = -MATE_VALUE;
         let mut best_move = None;
         let mut searched_any = false;
-
-        for mv in moves {
+        let prune_late_moves = depth <= self.params.lmp_max_depth && !position.in_check();
+        let killers = self.killers.get(ply).copied().unwrap_or([None; 2]);
+
+        for (index, mv) in moves.into_iter().enumerate() {
+            let quiet = !mv.promote
+                && position.piece_at(mv.to).is_none()
+                && Some(mv) != tt_move
+                && !killers.contains(&Some(mv));
+            if prune_late_moves
+                && quiet
+                && index >= self.params.lmp_move_count * depth
+                && best_value > -MATE_VALUE + MAX_SEARCH_PLY as i32
+                && self.history_score(position, mv) <= self.params.lmp_history_limit
+                && !position.gives_check(&mv)
+            {
+                trace::count(Counter::LateMovePrunes);
+                continue;
+            }
             let mover = position.side_to_move();
-            let next = position.play_move(&mv)?;
+            let undo = position.make_move(&mv)?;
 
-            if let Some(score) = repetition_terminal_value(mover, next.current_repetition_count(), ply + 1) {
+            if let Some(score) = self
+                .repetition_value(position.current_repetition_count(), ply + 1)
+                .map(|value| value.relative_to(mover).value())
+            {
+                position.unmake_move(undo);
                 if score > best_value {
                     best_value = score;
                     best_move = Some(mv);
@@ -317,6 +1345,7 @@ impl Searcher {
                     alpha = score;
                 }
                 if alpha >= beta {
+                    trace::count(Counter::BetaCutoffs);
                     self.register_cutoff(position, mv, ply);
                     break;
                 }
@@ -324,11 +1353,14 @@ impl Searcher {
             }
 
             let mut child_depth = depth - 1;
-            if next.is_in_check(next.side_to_move()) {
+            if position.in_check() {
                 child_depth += 1;
+                trace::count(Counter::CheckExtensions);
             }
 
-            let score = -self.alpha_beta(&next, child_depth, -beta, -alpha, ply + 1)?;
+            let child = self.alpha_beta(position, child_depth, -beta, -alpha, ply + 1);
+            position.unmake_move(undo);
+            let score = -child?;
             searched_any = true;
 
             if score > best_value {
@@ -339,12 +1371,23 @@ impl Searcher {
                 alpha = score;
             }
             if alpha >= beta {
+                trace::count(Counter::BetaCutoffs);
                 self.register_cutoff(position, mv, ply);
                 break;
             }
         }
 
-        let bound = if best_value <= alpha { Bound::Upper } else if best_value >= beta { Bound::Lower } else { Bound::Exact };
+        if self.aborted {
+            return Ok(0);
+        }
+
+        let bound = if best_value <= original_alpha {
+            Bound::Upper
+        } else if best_value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
 
         if searched_any {
             self.tt.store(
@@ -354,6 +1397,7 @@ impl Searcher {
                     score: best_value,
                     bound,
                     best_move,
+                    check: table::verification_bits(hash),
                 },
             );
         }
@@ -363,47 +1407,69 @@ impl Searcher {
 
     fn quiescence(
         &mut self,
-        position: &Position,
+        position: &mut Position,
         mut alpha: i32,
         beta: i32,
         ply: usize,
     ) -> Result<i32, PositionError> {
+        if self.should_stop() {
+            return Ok(0);
+        }
         self.nodes += 1;
-
-        if let Some(score) = repetition_terminal_value(
-            position.side_to_move(),
-            position.current_repetition_count(),
-            ply,
-        ) {
+        trace::count(Counter::QuiescenceNodes);
+        self.forget_static_eval(ply);
+
+        if let Some(score) = self
+            .repetition_value(position.current_repetition_count(), ply)
+            .map(|value| value.relative_to(position.side_to_move()).value())
+        {
+            trace::count(Counter::RepetitionHits);
             return Ok(score);
         }
 
-        let stand_pat = evaluation::evaluate(position);
+        if ply >= self.max_ply() {
+            return self.horizon_value(position, ply);
+        }
+        // 王手されている局面ではstand-patが成立しないため、すべての応手を読む。
+        if position.in_check() {
+            return self.quiescence_evasions(position, alpha, beta, ply);
+        }
+
+        let stand_pat = self.evaluate(position, ply);
         if stand_pat >= beta {
-            return Ok(beta);
+            trace::count(Counter::StandPatCutoffs);
+            return Ok(stand_pat);
         }
         let mut value = stand_pat;
         if value > alpha {
             alpha = value;
         }
 
-        let mut moves = self.generate_tactical_moves(position)?;
+        let mut moves = position.generate_tactical_moves()?;
         if moves.is_empty() {
+            // 駒を取る手も成る手もなければ、ほかに指せる手があるかだけを確かめる。
+            if !position.has_legal_move()? {
+                return terminal_score(position, ply);
+            }
             return Ok(value);
         }
 
-        moves.sort_by(|a, b| self.capture_order_score(position, b).cmp(&self.capture_order_score(position, a)));
+        moves.sort_by_cached_key(|mv| Reverse(self.capture_order_score(position, mv)));
 
         for mv in moves {
             let mover = position.side_to_move();
-            let next = position.play_move(&mv)?;
+            let undo = position.make_move(&mv)?;
 
-            if let Some(score) = repetition_terminal_value(mover, next.current_repetition_count(), ply + 1) {
+            if let Some(score) = self
+                .repetition_value(position.current_repetition_count(), ply + 1)
+                .map(|value| value.relative_to(mover).value())
+            {
+                position.unmake_move(undo);
                 if score > value {
                     value = score;
                 }
                 if value >= beta {
-                    return Ok(beta);
+                    return Ok(value);
                 }
                 if value > alpha {
                     alpha = value;
@@ -411,9 +1477,11 @@ impl Searcher {
                 continue;
             }
 
-            let score = -self.quiescence(&next, -beta, -alpha, ply + 1)?;
+            let child = self.quiescence(position, -beta, -alpha, ply + 1);
+            position.unmake_move(undo);
+            let score = -child?;
             if score >= beta {
-                return Ok(beta);
+                return Ok(score);
             }
             if score > value {
                 value = score;
@@ -426,17 +1494,89 @@ impl Searcher {
         Ok(value)
     }
 
-    fn generate_tactical_moves(&self, position: &Position) -> Result<MoveList, PositionError> {
-        let mut result = MoveList::new();
-        for mv in position.generate_legal_moves()? {
-            if mv.is_drop() {
-                continue;
+    fn quiescence_evasions(
+        &mut self,
+        position: &mut Position,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+    ) -> Result<i32, PositionError> {
+        let mut moves = position.generate_legal_moves()?;
+        if moves.is_empty() {
+            return terminal_score(position, ply);
+        }
+
+        self.order_moves(position, &mut moves, None, ply);
+
+        let mut value = -MATE_VALUE + ply as i32;
+        for mv in moves {
+            let mover = position.side_to_move();
+            let undo = position.make_move(&mv)?;
+
+            let score = match self
+                .repetition_value(position.current_repetition_count(), ply + 1)
+                .map(|value| value.relative_to(mover).value())
+            {
+                Some(score) => Ok(score),
+                None => self
+                    .quiescence(position, -beta, -alpha, ply + 1)
+                    .map(|score| -score),
+            };
+            position.unmake_move(undo);
+            let score = score?;
+            if score >= beta {
+                return Ok(score);
+            }
+            if score > value {
+                value = score;
             }
-            if position.piece_at(mv.to).is_some() || mv.promote {
-                result.push(mv);
+            if score > alpha {
+                alpha = score;
             }
         }
-        Ok(result)
+
+        Ok(value)
+    }
+
+    /// 置換表を引き、検証ビットが一致しないエントリは衝突として数えて捨てる。
+    fn probe_entry(&mut self, hash: u64) -> Option<TableEntry> {
+        let entry = self.tt.probe(hash)?;
+        if !entry.matches(hash) {
+            self.stats.tt_collisions += 1;
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// エントリの最善手を `position` で指せる場合だけ返す。壊れた手をそのまま
+    /// `play_move` に渡さないよう、指せない手は数えて捨てる。
+    fn entry_move(&mut self, position: &Position, entry: Option<TableEntry>) -> Option<Move> {
+        let mv = entry?.best_move?;
+        if !position.is_pseudo_legal(&mv) {
+            self.stats.invalid_tt_moves += 1;
+            return None;
+        }
+        Some(mv)
+    }
+
+    /// 直前の探索で数えた置換表の異常。
+    pub fn stats(&self) -> SearchStats {
+        self.stats
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let penalties = self.root_penalties.as_ref().map_or(0, |(_, penalties)| {
+            penalties.capacity() * size_of::<(Move, i32)>()
+        });
+        MemoryUsage {
+            tt: self.tt.bytes(),
+            history: size_of_val(&self.history)
+                + self.killers.capacity() * size_of::<[Option<Move>; 2]>(),
+            root: self.root_entries.capacity() * size_of::<RootEntry>()
+                + self.expected_line.capacity() * size_of::<(u64, Move)>()
+                + penalties,
+            book: 0,
+        }
     }
 
     fn order_moves(
@@ -446,11 +1586,12 @@ impl Searcher {
         tt_move: Option<Move>,
         ply: usize,
     ) {
-        moves.sort_by(|a, b| {
-            let sb = self.move_score(position, *b, tt_move, ply);
-            let sa = self.move_score(position, *a, tt_move, ply);
-            sb.cmp(&sa)
+        // キラー手は別の局面で記録された手なので、この局面で指せるものだけを優先する。
+        let killers = self.killers.get(ply).map_or([None; 2], |killers| {
+            killers.map(|killer| killer.filter(|mv| position.is_pseudo_legal(mv)))
         });
+        // 点数は1手につき1回だけ計算する。同点の手は生成順のまま残る。
+        moves.sort_by_cached_key(|&mv| Reverse(self.move_score(position, mv, tt_move, killers)));
     }
 
     fn move_score(
@@ -458,19 +1599,17 @@ impl Searcher {
         position: &Position,
         mv: Move,
         tt_move: Option<Move>,
-        ply: usize,
+        killers: [Option<Move>; 2],
     ) -> i32 {
         if Some(mv) == tt_move {
             return 1_000_000;
         }
 
         let mut score = 0;
-        if let Some(killers) = self.killers.get(ply) {
-            if killers[0] == Some(mv) {
-                score += 900_000;
-            } else if killers[1] == Some(mv) {
-                score += 800_000;
-            }
+        if killers[0] == Some(mv) {
+            score += 900_000;
+        } else if killers[1] == Some(mv) {
+            score += 800_000;
         }
 
         if let Some(captured) = position.piece_at(mv.to) {
@@ -481,11 +1620,92 @@ impl Searcher {
             score += 400_000;
         }
 
+        score + self.history_score(position, mv)
+    }
+
+    fn history_score(&self, position: &Position, mv: Move) -> i32 {
         let color_idx = position.side_to_move().index();
-        let to_idx = mv.to.index() as usize;
-        score += self.history[color_idx][mv.piece.index()][to_idx];
+        self.history[color_idx][mv.piece.index()][mv.to.index() as usize]
+    }
 
-        score
+    fn max_ply(&self) -> usize {
+        self.limits.max_ply.clamp(1, MAX_SEARCH_PLY)
+    }
+
+    /// 最大手数に達した局面の値。指せる手がなければ終局として扱い、あれば評価値を返す。
+    fn horizon_value(&mut self, position: &Position, ply: usize) -> Result<i32, PositionError> {
+        trace::count(Counter::HorizonStops);
+        if position.has_legal_move()? {
+            Ok(self.evaluate(position, ply))
+        } else {
+            terminal_score(position, ply)
+        }
+    }
+
+    /// 手番側から見た評価値。計測が有効なら評価の時間を数える。
+    /// 1手前の局面を評価してあれば、その値と動いた駒から差分で求める。
+    fn evaluate(&mut self, position: &Position, ply: usize) -> i32 {
+        let _span = trace::span(Section::Eval);
+        let known = self
+            .endgame_table
+            .as_ref()
+            .and_then(|table| table.probe(position.material().key(), position));
+        if let Some(score) = known {
+            // 表の値は評価関数の値と比べられないので、子の差分評価の元にしない。
+            self.forget_static_eval(ply);
+            return score.relative_to(position.side_to_move()).value();
+        }
+        let parent = ply
+            .checked_sub(1)
+            .and_then(|parent| self.static_evals.get(parent).copied().flatten());
+        let state = match (parent, position.dirty_pieces()) {
+            (Some(parent), Some(dirty)) => self
+                .evaluator
+                .evaluate_incremental(position, &parent, dirty),
+            _ => self.evaluator.evaluate_state(position),
+        };
+        if let Some(slot) = self.static_evals.get_mut(ply) {
+            *slot = Some(state);
+        }
+        let score = state.score;
+        let noise = eval_noise(
+            position.zobrist_key(),
+            self.limits.eval_noise_seed,
+            self.limits.eval_noise,
+        );
+        score.relative_to(position.side_to_move()).value() + noise
+    }
+
+    /// `ply` の局面の静的評価値を忘れる。局面に入るたびに呼び、兄弟の局面の値を
+    /// 親の値と取り違えないようにする。
+    fn forget_static_eval(&mut self, ply: usize) {
+        if let Some(slot) = self.static_evals.get_mut(ply) {
+            *slot = None;
+        }
+    }
+
+    /// `set_expected_line` の手順で `position` に予想される手。
+    fn expected_move(&self, position: &Position) -> Option<Move> {
+        if self.expected_line.is_empty() {
+            return None;
+        }
+        let key = position.zobrist_key();
+        self.expected_line
+            .iter()
+            .find(|(expected, _)| *expected == key)
+            .map(|&(_, mv)| mv)
+            .filter(|mv| position.is_pseudo_legal(mv))
+    }
+
+    /// ルート局面 `position` の手 `mv` に付ける減点。
+    fn root_penalty(&self, position: &Position, mv: Move) -> i32 {
+        match &self.root_penalties {
+            Some((key, penalties)) if *key == position.zobrist_key() => penalties
+                .iter()
+                .find(|(penalized, _)| *penalized == mv)
+                .map_or(0, |&(_, penalty)| penalty),
+            _ => 0,
+        }
     }
 
     fn capture_order_score(&self, position: &Position, mv: &Move) -> i32 {
@@ -497,13 +1717,13 @@ impl Searcher {
     }
 
     fn register_cutoff(&mut self, position: &Position, mv: Move, ply: usize) {
-        let idx = ply.min(MAX_PLY - 1);
-        if !mv.is_drop() && position.piece_at(mv.to).is_none() {
-            let killers = &mut self.killers[idx];
-            if killers[0] != Some(mv) {
-                killers[1] = killers[0];
-                killers[0] = Some(mv);
-            }
+        if !mv.is_drop()
+            && position.piece_at(mv.to).is_none()
+            && let Some(killers) = self.killers.get_mut(ply)
+            && killers[0] != Some(mv)
+        {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
         }
 
         let color_idx = position.side_to_move().index();
@@ -515,13 +1735,130 @@ impl Searcher {
         }
     }
 
-    fn pick_root_move(&mut self) -> Option<Move> {
+    /// 直前の探索で置換表に残った最善手をたどり、`position` からの読み筋を返す。
+    /// 合法でない手や同一局面への戻りに当たった時点で打ち切る。
+    /// 途中経過に載せる読み筋。置換表の読み筋が `best_move` から始まらなければ
+    /// `best_move` だけを返す。
+    pub(crate) fn reported_pv(
+        &self,
+        position: &Position,
+        best_move: Option<Move>,
+        max_len: usize,
+    ) -> MoveList {
+        let Some(best) = best_move else {
+            return MoveList::new();
+        };
+        let pv = self.principal_variation(position, max_len);
+        if pv.first() == Some(&best) {
+            pv
+        } else {
+            vec![best]
+        }
+    }
+
+    pub fn principal_variation(&self, position: &Position, max_len: usize) -> MoveList {
+        let mut pv = MoveList::new();
+        let mut current = position.clone_without_history();
+        let mut seen = vec![current.zobrist_key()];
+        let mut picked = self
+            .picked_root
+            .filter(|&(key, _)| key == current.zobrist_key())
+            .map(|(_, mv)| mv);
+        while pv.len() < max_len {
+            let hash = table::compute_hash(&current);
+            let Some(mv) = picked.take().or_else(|| {
+                self.tt
+                    .probe(hash)
+                    .filter(|entry| entry.matches(hash))
+                    .and_then(|entry| entry.best_move)
+            }) else {
+                break;
+            };
+            let legal = current
+                .generate_legal_moves()
+                .map(|moves| moves.contains(&mv))
+                .unwrap_or(false);
+            if !legal {
+                break;
+            }
+            let Ok(next) = current.play_move(&mv) else {
+                break;
+            };
+            pv.push(mv);
+            if seen.contains(&next.zobrist_key()) {
+                break;
+            }
+            seen.push(next.zobrist_key());
+            current = next;
+        }
+        pv
+    }
+
+    /// 深さ `depth` で確定した評価値 `score` の読み筋を置換表からたどって指し直す。
+    ///
+    /// 各局面の残り深さを探索と同じく数え（王手は延長、深い確定値があればその深さ）、
+    /// 0になった局面を静止探索して `score` と比べる。詰みの値や、途中で繰り返しや
+    /// 表の途切れに当たった読み筋は値を比べない。
+    fn verify_pv(
+        &mut self,
+        position: &Position,
+        depth: usize,
+        score: i32,
+    ) -> Result<Option<PvFault>, PositionError> {
+        let mut current = position.clone();
+        let mut remaining = depth;
+        let mut pv = MoveList::new();
+        while remaining > 0 && pv.len() < self.max_ply() {
+            let hash = table::compute_hash(&current);
+            let Some(entry) = self.tt.probe(hash).filter(|entry| entry.matches(hash)) else {
+                return Ok(None);
+            };
+            let Some(mv) = entry.best_move else {
+                return Ok(None);
+            };
+            if !current.generate_legal_moves()?.contains(&mv) {
+                return Ok(Some(PvFault::IllegalMove { ply: pv.len(), mv }));
+            }
+            remaining = remaining.max(entry.depth) - 1;
+            current.play_move_mut(&mv)?;
+            pv.push(mv);
+            if current.current_repetition_count() > 1 {
+                return Ok(None);
+            }
+            if current.in_check() {
+                remaining += 1;
+            }
+        }
+        if mate_distance(RelScore(score)).is_some() {
+            return Ok(None);
+        }
+        let nodes = self.nodes;
+        let leaf = self.quiescence(&mut current, -MATE_VALUE, MATE_VALUE, pv.len())?;
+        self.nodes = nodes;
+        if self.aborted {
+            return Ok(None);
+        }
+        let sign = if pv.len().is_multiple_of(2) { 1 } else { -1 };
+        let leaf = sign * leaf - pv.first().map_or(0, |&mv| self.root_penalty(position, mv));
+        if (leaf - score).abs() > PV_SCORE_TOLERANCE {
+            return Ok(Some(PvFault::ScoreMismatch {
+                reported: score,
+                leaf,
+                length: pv.len(),
+            }));
+        }
+        Ok(None)
+    }
+
+    fn pick_root_move(&mut self, position: &Position) -> Option<Move> {
         if self.root_entries.is_empty() {
             return None;
         }
 
         let best_score = self.root_entries[0].score;
-        if self.limits.randomness <= 0 {
+        let past_opening =
+            self.limits.random_plies > 0 && position.ply() > self.limits.random_plies;
+        if self.limits.randomness <= 0 || past_opening {
             return Some(self.root_entries[0].mv);
         }
 
@@ -541,83 +1878,718 @@ impl Searcher {
         Some(candidates[idx])
     }
 
+    fn should_stop(&mut self) -> bool {
+        if !self.aborted && self.interruptible && self.stop.is_stopped() {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    fn set_root_standing(&mut self, position: &Position) {
+        self.root_color = position.side_to_move();
+        self.root_score = self.evaluator.evaluate_relative(position).value();
+    }
+
+    /// ルート局面の手番側が繰り返しを嫌う度合い。優勢なら正、劣勢なら負。
+    fn contempt(&self) -> i32 {
+        let max = self.params.max_contempt.max(0);
+        (self.root_score / 2).clamp(-max, max)
+    }
+
+    /// 千日手の評価値を先手から見た絶対値で返す。成立（4回目）は5五将棋の規則どおり
+    /// 先手の負け。成立前の繰り返しは、ルート局面で優勢な側が避けるように評価するが、
+    /// 先手から見て `PENDING_REPETITION_MAX` を超えないようにする。
+    fn repetition_value(&self, repeat_count: usize, ply_from_root: usize) -> Option<AbsScore> {
+        if self.limits.analysis {
+            return analysis_repetition_value(repeat_count);
+        }
+        match repeat_count {
+            0 | 1 => None,
+            2 | 3 => {
+                let contempt = RelScore(-self.contempt()).absolute_from(self.root_color);
+                // 3回目は次の繰り返しで先手の負けが決まるので、その分だけ先手に不利に寄せる。
+                let lean = if repeat_count == 3 {
+                    REPETITION_RULE_LEAN
+                } else {
+                    0
+                };
+                Some(AbsScore(
+                    contempt.value().min(PENDING_REPETITION_MAX) - lean,
+                ))
+            }
+            _ => Some(AbsScore(-(MATE_VALUE - ply_from_root as i32).max(1))),
+        }
+    }
+
     fn clear_heuristics(&mut self) {
-        self.killers = [[None; 2]; MAX_PLY];
+        self.killers.clear();
+        self.killers.resize(self.max_ply(), [None; 2]);
+        self.static_evals.clear();
+        self.static_evals.resize(self.max_ply() + 1, None);
         for color in &mut self.history {
             for piece in color.iter_mut() {
                 piece.fill(0);
             }
         }
     }
+}
 
-    fn print_info(&self, depth: usize, score: i32, best: Option<Move>, nodes: u64) {
-        let (score_tag, score_value) = if score.abs() >= MATE_VALUE - 100 {
-            let mate = if score > 0 {
-                (MATE_VALUE - score + 1) / 2
-            } else {
-                -((MATE_VALUE + score + 1) / 2)
-            };
-            ("mate", mate.to_string())
-        } else {
-            ("cp", score.to_string())
+impl<E: Evaluator> SearchBackend for Searcher<E> {
+    fn search_with_stop(
+        &mut self,
+        position: &Position,
+        limits: SearchLimits,
+        stop: &StopToken,
+    ) -> Result<SearchResult, PositionError> {
+        Searcher::search_with_stop(self, position, limits, stop)
+    }
+
+    fn principal_variation(&self, position: &Position, max_len: usize) -> MoveList {
+        Searcher::principal_variation(self, position, max_len)
+    }
+
+    fn set_info_sink(&mut self, sink: Box<dyn InfoSink>) {
+        Searcher::set_info_sink(self, sink)
+    }
+
+    fn set_transposition_table(&mut self, table: Box<dyn TranspositionTable>) {
+        Searcher::set_transposition_table(self, table)
+    }
+
+    fn set_keep_tt(&mut self, keep: bool) {
+        Searcher::set_keep_tt(self, keep)
+    }
+
+    fn probe_tt(&self, position: &Position) -> Option<TableEntry> {
+        let hash = table::compute_hash(position);
+        self.tt.probe(hash).filter(|entry| entry.matches(hash))
+    }
+
+    fn stats(&self) -> SearchStats {
+        Searcher::stats(self)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        Searcher::memory_usage(self)
+    }
+
+    fn eval_breakdown(&self, position: &Position) -> Option<EvalBreakdown> {
+        self.evaluator.breakdown(position)
+    }
+
+    fn set_root_penalties(&mut self, position: &Position, penalties: Vec<(Move, i32)>) {
+        self.root_penalties = (!penalties.is_empty()).then(|| (position.zobrist_key(), penalties));
+    }
+
+    fn set_expected_line(&mut self, position: &Position, line: &[Move]) {
+        self.expected_line.clear();
+        let mut current = position.clone_without_history();
+        for &mv in line {
+            let key = current.zobrist_key();
+            if current.play_move_mut(&mv).is_err() {
+                break;
+            }
+            self.expected_line.push((key, mv));
+        }
+    }
+
+    fn set_handicap(&mut self, handicap: Option<Handicap>) {
+        self.evaluator.set_handicap(handicap);
+    }
+
+    fn set_piece_values(&mut self, values: [i32; PIECE_KIND_COUNT]) {
+        self.evaluator.set_piece_values(values);
+    }
+
+    fn set_piece_square_tables(
+        &mut self,
+        middlegame: &PieceSquareTable,
+        endgame: &PieceSquareTable,
+    ) {
+        self.evaluator.set_piece_square_tables(middlegame, endgame);
+    }
+}
+
+/// 指し手がない局面の評価値。将棋では詰みもステイルメイトも手番側の負け。
+fn terminal_score(_position: &Position, ply: usize) -> Result<i32, PositionError> {
+    Ok(-MATE_VALUE + ply as i32)
+}
+
+/// 探索の開始時に置換表を消すか。`keep_tt` でも、前回のルート局面から駒を取る手・打つ手・
+/// 歩を進める手が指されていれば消す。表は世代を持たないので、消すことで世代を進める代わりにする。
+pub(crate) fn table_is_stale(keep_tt: bool, epoch: &mut Option<u32>, position: &Position) -> bool {
+    let ply = position.last_irreversible_ply();
+    let moved_on = epoch.replace(ply).is_some_and(|previous| previous != ply);
+    !keep_tt || moved_on
+}
+
+/// 検討モードでの千日手の評価値。成立した千日手だけを引き分けとして扱う。
+fn analysis_repetition_value(repeat_count: usize) -> Option<AbsScore> {
+    (repeat_count >= REPETITION_LIMIT).then_some(AbsScore(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Square;
+    use crate::piece::PieceKind;
+    use crate::position::INITIAL_SFEN;
+
+    #[test]
+    fn kept_tables_are_cleared_after_irreversible_moves() {
+        let mut position = Position::from_sfen(INITIAL_SFEN).unwrap();
+        let mut epoch = None;
+        assert!(!table_is_stale(true, &mut epoch, &position));
+        assert!(table_is_stale(false, &mut epoch, &position));
+        for token in ["5e4d", "1a2b"] {
+            let mv = position.parse_usi_move(token).unwrap();
+            position.play_move_mut(&mv).unwrap();
+        }
+        assert!(!table_is_stale(true, &mut epoch, &position));
+        let pawn = position.parse_usi_move("5d5c").unwrap();
+        position.play_move_mut(&pawn).unwrap();
+        assert!(table_is_stale(true, &mut epoch, &position));
+        assert!(!table_is_stale(true, &mut epoch, &position));
+    }
+
+    #[test]
+    fn repetition_is_avoided_by_the_side_ahead() {
+        let mut searcher = Searcher::new();
+        searcher.root_score = 800;
+        let ahead = searcher.repetition_value(2, 3).unwrap();
+        searcher.root_score = -800;
+        let behind = searcher.repetition_value(2, 3).unwrap();
+        assert!(ahead.value() < behind.value());
+        // 成立前の繰り返しは、どちらがどれだけ優勢でも先手から見て0点未満のまま。
+        for (color, score) in [
+            (Color::Black, -800),
+            (Color::White, 800),
+            (Color::White, -800),
+        ] {
+            searcher.root_color = color;
+            searcher.root_score = score;
+            for count in [2, 3] {
+                assert!(searcher.repetition_value(count, 3).unwrap().value() < 0);
+            }
+        }
+        searcher.root_color = Color::White;
+        searcher.root_score = 800;
+        assert!(
+            searcher.repetition_value(3, 3).unwrap().value()
+                < searcher.repetition_value(2, 3).unwrap().value()
+        );
+        assert_eq!(
+            searcher.repetition_value(4, 3),
+            Some(AbsScore(-(MATE_VALUE - 3)))
+        );
+    }
+
+    #[test]
+    fn child_nodes_are_evaluated_incrementally() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// 差分で評価した回数を数える評価関数。値は局面全体を評価し直したものと同じ。
+        struct Counting(HandcraftedEval, AtomicUsize);
+
+        impl Evaluator for Counting {
+            fn evaluate(&self, position: &Position) -> AbsScore {
+                self.0.evaluate(position)
+            }
+
+            fn evaluate_incremental(
+                &self,
+                position: &Position,
+                _parent: &EvalState,
+                _dirty: &crate::position::DirtyPieces,
+            ) -> EvalState {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                self.0.evaluate_state(position)
+            }
+        }
+
+        let position = Position::from_sfen("rbsgk/4p/2p2/P1R2/KGSB1 b - 1").expect("sfen");
+        let limits = SearchLimits {
+            depth: 3,
+            ..SearchLimits::default()
         };
+        let mut counting =
+            Searcher::with_evaluator(Counting(HandcraftedEval::default(), AtomicUsize::new(0)));
+        counting.set_info_sink(Box::new(NullInfoSink));
+        let incremental = counting.search(&position, limits).expect("search");
+        assert!(counting.evaluator().1.load(Ordering::Relaxed) > 0);
+
+        let mut plain = Searcher::new();
+        plain.set_info_sink(Box::new(NullInfoSink));
+        let full = plain.search(&position, limits).expect("search");
+        assert_eq!(incremental.score, full.score);
+    }
 
-        if let Some(mv) = best {
-            println!(
-                "info depth {} score {} {} nodes {} pv {}",
-                depth,
-                score_tag,
-                score_value,
-                nodes,
-                mv.to_usi()
-            );
-        } else {
-            println!(
-                "info depth {} score {} {} nodes {}",
-                depth, score_tag, score_value, nodes
+    #[test]
+    fn endgame_table_overrides_the_evaluator() {
+        /// 駒割りの鍵が1つの値の局面だけを引ける表。
+        struct OneEndgame(u64);
+
+        impl EndgameTable for OneEndgame {
+            fn probe(&self, key: u64, position: &Position) -> Option<AbsScore> {
+                assert_eq!(key, position.material().key());
+                (key == self.0).then_some(AbsScore(1_234))
+            }
+        }
+
+        let position = Position::from_sfen("k4/5/2g2/5/4K b - 1").expect("sfen");
+        let limits = SearchLimits {
+            depth: 1,
+            ..SearchLimits::default()
+        };
+        let mut searcher = Searcher::new();
+        searcher.set_info_sink(Box::new(NullInfoSink));
+        assert!(searcher.search(&position, limits).unwrap().score.value() < 0);
+        searcher.set_endgame_table(Box::new(OneEndgame(position.material().key())));
+        assert_eq!(
+            searcher.search(&position, limits).unwrap().score.value(),
+            1_234
+        );
+    }
+
+    #[test]
+    fn quiescence_fails_soft_above_beta() {
+        let mut position = Position::from_sfen("4k/5/5/5/R3K b - 1").expect("sfen");
+        let mut searcher = Searcher::new();
+        let stand_pat = searcher.evaluator.evaluate_relative(&position).value();
+        assert!(stand_pat > 100);
+        let value = searcher
+            .quiescence(&mut position, -20, -10, 0)
+            .expect("qsearch");
+        assert_eq!(value, stand_pat);
+    }
+
+    #[test]
+    fn quiescence_scores_checkmate_as_terminal() {
+        let mut mated = Position::from_sfen("4k/4G/3S1/5/K4 w - 2").expect("sfen");
+        let mut searcher = Searcher::new();
+        let score = searcher
+            .quiescence(&mut mated, -MATE_VALUE, MATE_VALUE, 1)
+            .expect("quiescence");
+        assert_eq!(score, -MATE_VALUE + 1);
+    }
+
+    #[test]
+    fn tt_instant_move_replays_previous_root_result() {
+        let position = Position::initial().expect("initial");
+        let mut searcher = Searcher::new();
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let first = searcher.search(&position, limits).expect("search");
+        let instant = searcher
+            .search(
+                &position,
+                SearchLimits {
+                    tt_instant_move: true,
+                    ..limits
+                },
+            )
+            .expect("search");
+        assert_eq!(instant.nodes, 0);
+        assert_eq!(instant.depth, first.depth);
+    }
+
+    /// どのハッシュにも、空きマスから動く壊れた最善手を返す置換表。
+    struct CorruptTable {
+        check_mask: u16,
+    }
+
+    impl TranspositionTable for CorruptTable {
+        fn probe(&self, hash: u64) -> Option<TableEntry> {
+            Some(TableEntry {
+                depth: 0,
+                score: 0,
+                bound: Bound::Upper,
+                best_move: Some(Move::normal(
+                    Square::from_coord("3c").unwrap(),
+                    Square::from_coord("3b").unwrap(),
+                    PieceKind::Rook,
+                    false,
+                )),
+                check: table::verification_bits(hash) ^ self.check_mask,
+            })
+        }
+
+        fn store(&self, _hash: u64, _entry: TableEntry) {}
+
+        fn clear(&self) {}
+    }
+
+    #[test]
+    fn ordering_puts_tt_move_then_killers_then_captures() {
+        let mut searcher = Searcher::new();
+        let position = Position::from_sfen("k4/5/2p2/2R2/4K b GP 1").unwrap();
+        let tt_move = position.parse_usi_move("G*2b").unwrap();
+        let killer = position.parse_usi_move("1e2e").unwrap();
+        searcher.killers[3][0] = Some(killer);
+        let mut moves = position.generate_legal_moves().unwrap();
+        searcher.order_moves(&position, &mut moves, Some(tt_move), 3);
+        let order: Vec<String> = moves.iter().take(3).map(Move::to_usi).collect();
+        assert_eq!(order, ["G*2b", "1e2e", "3d3c"]);
+        let killers = [Some(killer), None];
+        let scores: Vec<i32> = moves
+            .iter()
+            .map(|&mv| searcher.move_score(&position, mv, Some(tt_move), killers))
+            .collect();
+        assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn corrupted_tt_entries_are_counted_and_ignored() {
+        let position = Position::initial().expect("initial");
+        let limits = SearchLimits {
+            depth: 3,
+            tt_instant_move: true,
+            ..SearchLimits::default()
+        };
+        let mut searcher = Searcher::new();
+        searcher.set_transposition_table(Box::new(CorruptTable { check_mask: 0 }));
+        let result = searcher.search(&position, limits).expect("search");
+        assert!(result.best_move.is_some());
+        assert!(searcher.stats().invalid_tt_moves > 0);
+        assert_eq!(searcher.stats().tt_collisions, 0);
+
+        searcher.set_transposition_table(Box::new(CorruptTable { check_mask: 1 }));
+        searcher.search(&position, limits).expect("search");
+        assert!(searcher.stats().tt_collisions > 0);
+        assert_eq!(searcher.stats().invalid_tt_moves, 0);
+        assert!(searcher.principal_variation(&position, 4).is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct DetailRecorder(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl InfoSink for DetailRecorder {
+        fn info(&mut self, info: &SearchInfo) {
+            self.0.lock().unwrap().push(info.to_usi());
+        }
+
+        fn detail(&mut self, detail: &SearchDetail) {
+            self.0.lock().unwrap().push(detail.to_usi());
+        }
+    }
+
+    #[test]
+    fn verbosity_controls_what_is_reported() {
+        let position = Position::initial().expect("initial");
+        let limits = SearchLimits {
+            depth: 1,
+            ..SearchLimits::default()
+        };
+        let legal = position.generate_legal_moves().unwrap().len();
+        let mut counts = Vec::new();
+        for verbosity in Verbosity::ALL {
+            let recorder = DetailRecorder::default();
+            let mut searcher = Searcher::new();
+            searcher.set_info_sink(Box::new(LeveledInfoSink::new(recorder.clone(), verbosity)));
+            searcher.search(&position, limits).expect("search");
+            let lines = recorder.0.lock().unwrap().clone();
+            let currmoves = lines
+                .iter()
+                .filter(|line| line.contains(" currmove "))
+                .count();
+            let stats = lines
+                .iter()
+                .any(|line| line.starts_with("info string stats "));
+            counts.push((lines.len(), currmoves, stats));
+        }
+        assert_eq!(counts[0], (0, 0, false));
+        assert_eq!(counts[1], (1, 0, false));
+        assert_eq!(counts[2], (legal + 2, legal, true));
+    }
+
+    #[test]
+    fn pv_verification_flags_only_corrupted_lines() {
+        let limits = SearchLimits {
+            depth: 4,
+            verify_pv: true,
+            ..SearchLimits::default()
+        };
+        for sfen in [
+            INITIAL_SFEN,
+            "rb1gk/1s2R/5/P1B2/K4 w GSP 10",
+            "4k/2P2/1b3/5/K3r w - 2",
+        ] {
+            let recorder = DetailRecorder::default();
+            let mut searcher = Searcher::new();
+            searcher.set_info_sink(Box::new(recorder.clone()));
+            let position = Position::from_sfen(sfen).expect("sfen");
+            searcher.search(&position, limits).expect("search");
+            let lines = recorder.0.lock().unwrap().clone();
+            assert!(
+                !lines.iter().any(|line| line.contains("pv fault")),
+                "{sfen}: {lines:?}"
             );
         }
+
+        let recorder = DetailRecorder::default();
+        let mut searcher = Searcher::new();
+        searcher.set_info_sink(Box::new(recorder.clone()));
+        searcher.set_transposition_table(Box::new(CorruptTable { check_mask: 0 }));
+        let position = Position::from_sfen("k4/5/2p2/2R2/4K b GP 1").unwrap();
+        searcher
+            .search(&position, SearchLimits { depth: 2, ..limits })
+            .expect("search");
+        let lines = recorder.0.lock().unwrap().clone();
+        assert!(
+            lines.contains(&"info string depth 1 pv fault illegal move 3c3b at ply 0".to_string())
+        );
     }
-}
 
-fn terminal_score(position: &Position, ply: usize) -> Result<i32, PositionError> {
-    let mate_score = -MATE_VALUE + ply as i32;
-    if position.is_in_check(position.side_to_move()) {
-        Ok(mate_score)
-    } else {
-        Ok(mate_score)
+    #[test]
+    fn eval_noise_is_small_and_fixed_by_the_seed() {
+        let position = Position::initial().expect("initial");
+        let key = position.zobrist_key();
+        assert_eq!(eval_noise(key, 1, 0), 0);
+        let noises: Vec<i32> = (0..64).map(|seed| eval_noise(key, seed, 5)).collect();
+        assert!(noises.iter().all(|noise| noise.abs() <= 5));
+        assert!(noises.iter().any(|&noise| noise != noises[0]));
+
+        let limits = SearchLimits {
+            depth: 2,
+            eval_noise: 5,
+            eval_noise_seed: 3,
+            ..SearchLimits::default()
+        };
+        let mut searcher = Searcher::new();
+        let first = searcher.search(&position, limits).unwrap();
+        let again = searcher.search(&position, limits).unwrap();
+        assert_eq!(
+            (first.best_move, first.score),
+            (again.best_move, again.score)
+        );
     }
-}
 
-fn repetition_terminal_value(
-    mover: Color,
-    repeat_count: usize,
-    ply_from_root: usize,
-) -> Option<i32> {
-    if repeat_count >= 4 {
-        let mate_score = (MATE_VALUE - ply_from_root as i32).max(1);
-        let value = match mover {
-            Color::Black => -mate_score,
-            Color::White => mate_score,
+    #[test]
+    fn trap_margin_picks_a_close_move_but_never_skips_a_mate() {
+        let mut searcher = Searcher::new();
+        let trappy = SearchLimits {
+            depth: 3,
+            trap_margin: 300,
+            ..SearchLimits::default()
         };
-        return Some(value);
+        let mate = Position::from_sfen("4k/5/3S1/5/K4 b G 1").unwrap();
+        let result = searcher.search(&mate, trappy).unwrap();
+        assert_eq!(mate_distance(result.score), Some(1));
+        let next = mate.play_move(&result.best_move.unwrap()).unwrap();
+        assert!(!next.has_legal_move().unwrap());
+
+        let position = Position::initial().expect("initial");
+        let picked = searcher
+            .search(&position, trappy)
+            .unwrap()
+            .best_move
+            .unwrap();
+        let best = searcher.root_entries[0].score;
+        let entry = searcher
+            .root_entries
+            .iter()
+            .find(|entry| entry.mv == picked);
+        assert!(entry.is_some_and(|entry| entry.score >= best - trappy.trap_margin));
     }
 
-    if repeat_count == 3 {
-        let penalty = (MATE_VALUE / 4).max(1);
-        return Some(match mover {
-            Color::Black => -penalty,
-            Color::White => penalty,
-        });
+    #[test]
+    fn trap_move_reports_its_own_score_and_line() {
+        let position = Position::from_sfen("rbsgk/4p/5/PK3/1GSBR w - 2").unwrap();
+        let mut searcher = Searcher::new();
+        let limits = SearchLimits {
+            depth: 3,
+            trap_margin: 150,
+            ..SearchLimits::default()
+        };
+        let result = searcher.search(&position, limits).unwrap();
+        let objective = searcher.root_entries[0];
+        let picked = result.best_move.unwrap();
+        // 相手の応手を絞る手のほうが、評価値の高い手より優先される。
+        assert_ne!(picked, objective.mv);
+        assert!(result.score.value() < objective.score);
+        assert!(result.score.value() >= objective.score - limits.trap_margin);
+        assert_eq!(
+            searcher.principal_variation(&position, 3).first(),
+            Some(&picked)
+        );
+
+        // 選んだ手の値は置換表に残らず、次の探索は最善手をそのまま返す。
+        searcher.set_keep_tt(true);
+        let plain = SearchLimits {
+            depth: 3,
+            tt_instant_move: true,
+            ..SearchLimits::default()
+        };
+        let again = searcher.search(&position, plain).unwrap();
+        assert_eq!(again.best_move, Some(objective.mv));
+        assert_eq!(again.score.value(), objective.score);
+        assert_eq!(again.nodes, 0);
+        assert_eq!(
+            searcher.principal_variation(&position, 3).first(),
+            Some(&objective.mv)
+        );
     }
 
-    if repeat_count == 2 {
-        const SOFT_PENALTY: i32 = 500;
-        return Some(match mover {
-            Color::Black => -SOFT_PENALTY,
-            Color::White => SOFT_PENALTY,
-        });
+    #[test]
+    fn randomness_stops_after_the_opening_plies() {
+        let mut position = Position::initial().expect("initial");
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let mut searcher = Searcher::new();
+        let best = searcher.search(&position, limits).unwrap().best_move;
+        position.set_ply(9);
+        let random = SearchLimits {
+            randomness: 100_000,
+            random_plies: 8,
+            ..limits
+        };
+        for _ in 0..8 {
+            assert_eq!(searcher.search(&position, random).unwrap().best_move, best);
+        }
+    }
+
+    #[test]
+    fn stopped_search_still_returns_a_move() {
+        let position = Position::initial().expect("initial");
+        let stop = StopToken::new();
+        stop.stop();
+        let mut searcher = Searcher::new();
+        let limits = SearchLimits {
+            depth: 6,
+            ..SearchLimits::default()
+        };
+        let result = searcher
+            .search_with_stop(&position, limits, &stop)
+            .expect("search");
+        assert_eq!(result.depth, 1);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn evaluate_all_moves_scores_every_legal_move() {
+        let position = Position::from_sfen("4k/5/3S1/5/K4 b G 1").expect("sfen");
+        let mut searcher = Searcher::new();
+        let scored = searcher.evaluate_all_moves(&position, 1).expect("scores");
+        let legal = position.generate_legal_moves().expect("moves");
+        assert_eq!(scored.len(), legal.len());
+        assert!(scored.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(scored[0].1.value() >= MATE_VALUE - 100);
+    }
+
+    #[test]
+    fn finds_drop_mate() {
+        let position = Position::from_sfen("4k/5/3S1/5/K4 b G 1").expect("sfen");
+        let mut searcher = Searcher::new();
+        let limits = SearchLimits {
+            depth: 1,
+            ..SearchLimits::default()
+        };
+        let result = searcher.search(&position, limits).expect("search");
+        assert!(result.best_move.is_some_and(|mv| mv.is_drop()));
+        assert!(result.score.value() >= MATE_VALUE - 100);
+    }
+
+    #[test]
+    fn max_ply_cuts_lines_but_still_sees_mate() {
+        let mut searcher = Searcher::new();
+        searcher.set_info_sink(Box::new(NullInfoSink));
+        let mate = Position::from_sfen("4k/5/3S1/5/K4 b G 1").expect("sfen");
+        let limits = SearchLimits {
+            depth: 3,
+            max_ply: 1,
+            ..SearchLimits::default()
+        };
+        let result = searcher.search(&mate, limits).expect("search");
+        assert_eq!(result.score.value(), MATE_VALUE - 1);
+
+        let initial = Position::initial().expect("initial");
+        let nodes = |searcher: &mut Searcher, max_ply| {
+            let limits = SearchLimits {
+                depth: 4,
+                max_ply,
+                ..SearchLimits::default()
+            };
+            searcher.search(&initial, limits).expect("search").nodes
+        };
+        assert!(nodes(&mut searcher, 2) < nodes(&mut searcher, DEFAULT_MAX_PLY));
+        assert!(nodes(&mut searcher, MAX_SEARCH_PLY) > 0);
+    }
+
+    #[test]
+    fn searcher_uses_plugged_in_evaluator() {
+        struct FlatEval;
+
+        impl Evaluator for FlatEval {
+            fn evaluate(&self, _position: &Position) -> AbsScore {
+                AbsScore(0)
+            }
+        }
+
+        let position = Position::initial().expect("initial");
+        let mut searcher = Searcher::with_evaluator(FlatEval);
+        searcher.set_info_sink(Box::new(NullInfoSink));
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let result = SearchBackend::search(&mut searcher, &position, limits).expect("search");
+        assert!(result.best_move.is_some());
+        assert_eq!(result.score, RelScore(0));
+    }
+
+    #[test]
+    fn late_move_pruning_saves_nodes_without_missing_mate() {
+        let search = |sfen: &str, lmp_max_depth: usize| {
+            let mut searcher = Searcher::new();
+            searcher.set_info_sink(Box::new(NullInfoSink));
+            searcher.set_search_params(SearchParams {
+                lmp_max_depth,
+                ..SearchParams::default()
+            });
+            let limits = SearchLimits {
+                depth: 4,
+                ..SearchLimits::default()
+            };
+            let position = Position::from_sfen(sfen).expect("sfen");
+            searcher.search(&position, limits).expect("search")
+        };
+        let full = search("rb1gk/1s2R/5/P1B2/K4 w GSP 10", 0);
+        let pruned = search("rb1gk/1s2R/5/P1B2/K4 w GSP 10", 2);
+        assert!(
+            pruned.nodes < full.nodes,
+            "{} >= {}",
+            pruned.nodes,
+            full.nodes
+        );
+
+        let mate = search("4k/5/3S1/5/K4 b G 1", 2);
+        assert!(mate.score.value() >= MATE_VALUE - 100);
     }
 
-    None
+    #[test]
+    fn narrow_aspiration_window_still_finds_mate() {
+        let position = Position::from_sfen("4k/5/3S1/5/K4 b G 1").expect("sfen");
+        let limits = SearchLimits {
+            depth: 3,
+            ..SearchLimits::default()
+        };
+        for fail_soft in [true, false] {
+            let mut searcher = Searcher::new();
+            searcher.set_info_sink(Box::new(NullInfoSink));
+            searcher.set_search_params(SearchParams {
+                aspiration_window: 1,
+                aspiration_growth: 2,
+                aspiration_max_retries: 8,
+                aspiration_fail_soft: fail_soft,
+                ..SearchParams::default()
+            });
+            let result = searcher.search(&position, limits).expect("search");
+            assert!(result.best_move.is_some_and(|mv| mv.is_drop()));
+            assert!(result.score.value() >= MATE_VALUE - 100);
+        }
+    }
 }
@@ -0,0 +1,106 @@
+use alloc::vec;
+
+use crate::attacks;
+use crate::bitboard::Bitboard;
+use crate::board::Square;
+use crate::evaluation::piece_material_value;
+use crate::moves::Move;
+use crate::piece::{Color, PieceKind};
+use crate::position::Position;
+
+/// 取り返しに使う駒の順。安い駒から先に使う。
+const ATTACKER_ORDER: [PieceKind; 10] = [
+    PieceKind::Pawn,
+    PieceKind::Tokin,
+    PieceKind::Silver,
+    PieceKind::PromotedSilver,
+    PieceKind::Gold,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+    PieceKind::PromotedBishop,
+    PieceKind::PromotedRook,
+    PieceKind::King,
+];
+
+/// `mv` から始まる `mv.to` での駒の取り合いを、双方が最も安い駒で取り返し、
+/// 損になる時点で取り合いをやめると仮定したときの手番側の駒得。
+/// 成りによる駒の価値の変化は考えない。
+pub fn static_exchange(position: &Position, mv: &Move) -> i32 {
+    let target = mv.to;
+    let mut occupancy = position.occupancy_all();
+    if let Some(from) = mv.from {
+        occupancy.remove(from);
+    }
+    occupancy.insert(target);
+
+    let mut gains = vec![
+        position
+            .piece_at(target)
+            .map_or(0, |piece| piece_material_value(piece.kind)),
+    ];
+    let mut on_target = mv.piece;
+    let mut side = position.side_to_move().opponent();
+    while let Some((from, kind)) = least_valuable_attacker(position, side, target, occupancy) {
+        occupancy.remove(from);
+        // 玉は取り返されるマスへは動けない。
+        if kind == PieceKind::King
+            && least_valuable_attacker(position, side.opponent(), target, occupancy).is_some()
+        {
+            break;
+        }
+        let previous = *gains.last().expect("gains is never empty");
+        gains.push(piece_material_value(on_target) - previous);
+        on_target = kind;
+        side = side.opponent();
+    }
+
+    while gains.len() > 1 {
+        let last = gains.pop().expect("checked length");
+        let previous = gains.last_mut().expect("checked length");
+        *previous = -(-*previous).max(last);
+    }
+    gains[0]
+}
+
+fn least_valuable_attacker(
+    position: &Position,
+    color: Color,
+    target: Square,
+    occupancy: Bitboard,
+) -> Option<(Square, PieceKind)> {
+    ATTACKER_ORDER.into_iter().find_map(|kind| {
+        (position.pieces(color, kind) & occupancy)
+            .iter()
+            .find(|&square| {
+                square != target
+                    && attacks::piece_attacks(color, kind, square, occupancy).contains(target)
+            })
+            .map(|square| (square, kind))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(sfen: &str, token: &str) -> i32 {
+        let position = Position::from_sfen(sfen).expect("sfen");
+        let mv = position.parse_usi_move(token).expect("move");
+        static_exchange(&position, &mv)
+    }
+
+    #[test]
+    fn defended_pawn_costs_the_rook() {
+        assert_eq!(capture("k4/5/2p2/5/2R1K b - 1", "3e3c"), 100);
+        assert_eq!(capture("k4/2g2/2p2/5/2R1K b - 1", "3e3c"), 100 - 1_000);
+    }
+
+    #[test]
+    fn x_ray_attackers_join_the_exchange() {
+        // 3c の歩を金で取り、後手の金に取り返されても 3e の飛車で取り返せる。
+        assert_eq!(
+            capture("k4/2g2/2p2/2G2/2R1K b - 1", "3d3c"),
+            100 - 700 + 700
+        );
+    }
+}
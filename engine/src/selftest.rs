@@ -0,0 +1,206 @@
+//! ビルドが正しく動くかを確かめる組み込みの検査。
+//!
+//! 別のアーキテクチャ向けにビルドしたバイナリを対局に使う前に、指し手生成・SFEN・
+//! ハッシュ・評価関数が既知の結果と一致するかを確かめる。
+
+use std::fmt;
+
+use crate::board::{BOARD_SQUARES, Square};
+use crate::evaluation::{Evaluator, HandcraftedEval};
+use crate::hand::HandPieceKind;
+use crate::piece::{COLORS, Piece};
+use crate::position::{INITIAL_SFEN, Position, PositionError};
+use crate::reference_movegen;
+use crate::search::SimpleRng;
+
+/// 初期局面の深さ1から4までの局面数。
+const INITIAL_PERFT: [u64; 4] = [14, 181, 2512, 35401];
+
+/// 検査に使う局面。持ち駒・成駒・王手のある局面を含める。
+const TEST_SFENS: [&str; 5] = [
+    INITIAL_SFEN,
+    "k4/5/2p2/2R2/4K b GP 1",
+    "k1+R2/1P3/5/2b2/4K b G 1",
+    "4k/2P2/1b3/5/K3r w - 2",
+    "rb1gk/1s2R/5/P1B2/K4 w GSP 10",
+];
+
+/// ランダムな対局の数と、1局の最大手数。
+const RANDOM_GAMES: usize = 20;
+const RANDOM_GAME_PLIES: usize = 60;
+
+/// 検査の本体。失敗したら理由を返す。
+type Check = fn() -> Result<(), String>;
+
+/// 1つの検査の結果。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    /// 失敗した理由。成功なら `None`。
+    pub failure: Option<String>,
+}
+
+impl CheckResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failure {
+            None => write!(f, "{} ok", self.name),
+            Some(reason) => write!(f, "{} failed: {reason}", self.name),
+        }
+    }
+}
+
+/// `position` から深さ `depth` までの合法手の列の数。
+pub fn perft(position: &Position, depth: usize) -> Result<u64, PositionError> {
+    let moves = position.generate_legal_moves()?;
+    if depth <= 1 {
+        return Ok(if depth == 0 { 1 } else { moves.len() as u64 });
+    }
+    let mut nodes = 0;
+    for mv in &moves {
+        nodes += perft(&position.play_move(mv)?, depth - 1)?;
+    }
+    Ok(nodes)
+}
+
+/// すべての検査を順に行う。
+pub fn run_selftest() -> Vec<CheckResult> {
+    let checks: [(&'static str, Check); 5] = [
+        ("perft", check_perft),
+        ("movegen-reference", check_reference_movegen),
+        ("sfen-roundtrip", check_sfen_roundtrip),
+        ("hash-consistency", check_hash_consistency),
+        ("eval-symmetry", check_eval_symmetry),
+    ];
+    checks
+        .into_iter()
+        .map(|(name, check)| CheckResult {
+            name,
+            failure: check().err(),
+        })
+        .collect()
+}
+
+fn check_perft() -> Result<(), String> {
+    let initial = Position::initial().map_err(|err| err.to_string())?;
+    for (index, &expected) in INITIAL_PERFT.iter().enumerate() {
+        let depth = index + 1;
+        let nodes = perft(&initial, depth).map_err(|err| err.to_string())?;
+        if nodes != expected {
+            return Err(format!("depth {depth}: {nodes} nodes, expected {expected}"));
+        }
+    }
+    Ok(())
+}
+
+/// ランダムな対局の各局面で、指し手生成を参照実装と比べる。
+fn check_reference_movegen() -> Result<(), String> {
+    reference_movegen::run_differential(RANDOM_GAMES, RANDOM_GAME_PLIES, 0x7ef_e7e2)
+        .map(|_| ())
+        .map_err(|divergence| divergence.to_string())
+}
+
+fn check_sfen_roundtrip() -> Result<(), String> {
+    for sfen in TEST_SFENS {
+        let position = Position::from_sfen(sfen).map_err(|err| format!("{sfen}: {err}"))?;
+        if position.to_sfen() != sfen {
+            return Err(format!("{sfen} came back as {}", position.to_sfen()));
+        }
+    }
+    Ok(())
+}
+
+/// ランダムな対局の各局面で、差分更新したハッシュと作り直したハッシュを比べる。
+fn check_hash_consistency() -> Result<(), String> {
+    let mut rng = SimpleRng::new(0x5e1f_7e57);
+    for _ in 0..RANDOM_GAMES {
+        let mut position = Position::initial().map_err(|err| err.to_string())?;
+        for _ in 0..RANDOM_GAME_PLIES {
+            let moves = position
+                .generate_legal_moves()
+                .map_err(|err| err.to_string())?;
+            if moves.is_empty() {
+                break;
+            }
+            let mv = moves[rng.gen_range(0..moves.len())];
+            position.play_move_mut(&mv).map_err(|err| err.to_string())?;
+            let sfen = position.to_sfen();
+            let rebuilt = Position::from_sfen(&sfen).map_err(|err| format!("{sfen}: {err}"))?;
+            if rebuilt.zobrist_key() != position.zobrist_key() {
+                return Err(format!("{sfen}: hash differs after {}", mv.to_usi()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 盤を180度回して先後を入れ替えた局面が、手番側から見て同じ評価値になるか。
+fn check_eval_symmetry() -> Result<(), String> {
+    let evaluator = HandcraftedEval::default();
+    for sfen in TEST_SFENS {
+        let position = Position::from_sfen(sfen).map_err(|err| format!("{sfen}: {err}"))?;
+        let flipped = flip(&position).map_err(|err| format!("{sfen}: {err}"))?;
+        let (score, flipped_score) = (
+            evaluator.evaluate_relative(&position),
+            evaluator.evaluate_relative(&flipped),
+        );
+        if score != flipped_score {
+            return Err(format!(
+                "{sfen}: {} but {} when flipped",
+                score.value(),
+                flipped_score.value()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn flip(position: &Position) -> Result<Position, PositionError> {
+    let mut flipped = Position::empty();
+    for index in 0..BOARD_SQUARES {
+        if let Some(piece) = position.piece_at(Square::from_index(index as u8)) {
+            let square = Square::from_index((BOARD_SQUARES - 1 - index) as u8);
+            flipped.set_piece(square, Piece::new(piece.color.opponent(), piece.kind))?;
+        }
+    }
+    for color in COLORS {
+        for kind in HandPieceKind::all() {
+            let count = position.hand(color).count(kind);
+            flipped.set_hand_count(color.opponent(), kind, count);
+        }
+    }
+    flipped.set_side_to_move(position.side_to_move().opponent());
+    flipped.set_ply(position.ply());
+    Position::from_sfen(&flipped.to_sfen())
+}
+
+/// `selftest` サブコマンド。1つでも失敗したらエラーを返す。
+pub fn run_cli(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let results = run_selftest();
+    for result in &results {
+        println!("{result}");
+    }
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    if failed > 0 {
+        return Err(format!("{failed} of {} checks failed", results.len()).into());
+    }
+    println!("all {} checks passed", results.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_check_passes_on_this_build() {
+        for result in run_selftest() {
+            assert!(result.passed(), "{result}");
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! SFENを1行に1つ書いたファイルを読み、局面ごとの合法手の数・王手・対局の状態・駒割りを出す。
+//!
+//! 学習前のデータの掃除や、指し手生成のベンチマーク用の局面集を作るのに使う。
+//! 読めても実戦で現れない局面（玉の数が違う、手番でない側が王手されている、など）は
+//! 問題として挙げ、合法手は数えない。
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::board::Square;
+use crate::game::GameStatus;
+use crate::hand::HandPieceKind;
+use crate::match_runner::parse_value;
+use crate::material::MaterialSignature;
+use crate::piece::{COLORS, Color, PieceKind};
+use crate::position::{Position, PositionError};
+use crate::query_cache::PositionQuery;
+use crate::rules::{Rules, StandardRules};
+
+/// 5五将棋の駒種ごとの枚数。
+const PIECES_PER_KIND: u32 = 2;
+
+/// 実戦で現れない局面の理由。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SfenFault {
+    /// 玉が1枚でない。
+    KingCount(Color),
+    /// 手番でない側の玉が取られる。
+    OpponentInCheck,
+    /// 行き所のない駒。
+    DeadPiece(Square),
+    /// 同じ筋に歩が2枚ある。
+    DoublePawn(Color),
+    /// 成り駒と持ち駒を含めて、その駒種が多すぎる。
+    TooManyPieces(HandPieceKind),
+}
+
+impl fmt::Display for SfenFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let color_name = |color: Color| match color {
+            Color::Black => "black",
+            Color::White => "white",
+        };
+        match *self {
+            Self::KingCount(color) => write!(f, "{} does not have one king", color_name(color)),
+            Self::OpponentInCheck => write!(f, "side not to move is in check"),
+            Self::DeadPiece(square) => write!(f, "piece on {square} can never move"),
+            Self::DoublePawn(color) => write!(f, "{} has two pawns on a file", color_name(color)),
+            Self::TooManyPieces(kind) => write!(f, "too many {} pieces", kind.to_char()),
+        }
+    }
+}
+
+/// 1行の結果。
+#[derive(Clone, Debug)]
+pub struct SfenStats {
+    pub sfen: String,
+    pub legal_moves: usize,
+    pub in_check: bool,
+    pub status: GameStatus,
+    pub material: MaterialSignature,
+}
+
+impl fmt::Display for SfenStats {
+    /// `SFEN \t moves N \t check B \t status S \t material M` の1行。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\tmoves {}\tcheck {}\tstatus {}\tmaterial {}",
+            self.sfen, self.legal_moves, self.in_check, self.status, self.material
+        )
+    }
+}
+
+/// 読めなかった、または実戦で現れない局面。
+#[derive(Debug)]
+pub enum SfenRejection {
+    Parse(PositionError),
+    Fault(SfenFault),
+}
+
+impl fmt::Display for SfenRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::Fault(fault) => write!(f, "{fault}"),
+        }
+    }
+}
+
+/// ファイル中の1行とその結果。
+#[derive(Debug)]
+pub struct SfenLine {
+    /// 1始まりの行番号。
+    pub line: usize,
+    pub result: Result<SfenStats, SfenRejection>,
+}
+
+/// 読み取った局面が実戦で現れうるか調べ、最初に見つかった問題を返す。
+pub fn find_fault(position: &Position) -> Option<SfenFault> {
+    let material = position.material();
+    for color in COLORS {
+        if material.board_count(color, PieceKind::King) != 1 {
+            return Some(SfenFault::KingCount(color));
+        }
+    }
+    if position.is_in_check(position.side_to_move().opponent()) {
+        return Some(SfenFault::OpponentInCheck);
+    }
+    for color in COLORS {
+        for kind in PieceKind::all() {
+            let pieces = position.pieces(color, kind);
+            if let Some(square) = pieces
+                .iter()
+                .find(|&square| StandardRules.must_promote(color, kind, square))
+            {
+                return Some(SfenFault::DeadPiece(square));
+            }
+        }
+        let pawns = position.pieces(color, PieceKind::Pawn);
+        if pawns.iter().any(|pawn| {
+            pawns
+                .iter()
+                .any(|other| other != pawn && other.file() == pawn.file())
+        }) {
+            return Some(SfenFault::DoublePawn(color));
+        }
+    }
+    for kind in HandPieceKind::all() {
+        let total: u32 = COLORS
+            .into_iter()
+            .map(|color| {
+                let board: u32 = PieceKind::all()
+                    .into_iter()
+                    .filter(|other| HandPieceKind::from_piece_kind(other.base()) == Some(kind))
+                    .map(|other| material.board_count(color, other) as u32)
+                    .sum();
+                board + material.hand_count(color, kind) as u32
+            })
+            .sum();
+        if total > PIECES_PER_KIND {
+            return Some(SfenFault::TooManyPieces(kind));
+        }
+    }
+    None
+}
+
+/// 1つのSFENを読んで調べる。
+pub fn analyze_sfen(sfen: &str) -> Result<SfenStats, SfenRejection> {
+    let position = Position::from_sfen(sfen).map_err(SfenRejection::Parse)?;
+    if let Some(fault) = find_fault(&position) {
+        return Err(SfenRejection::Fault(fault));
+    }
+    let query = PositionQuery::compute(&position).map_err(SfenRejection::Parse)?;
+    Ok(SfenStats {
+        sfen: sfen.to_string(),
+        legal_moves: query.legal_moves.len(),
+        in_check: query.in_check,
+        status: query.status(),
+        material: position.material(),
+    })
+}
+
+/// `text` の空行と `#` で始まる行を除いた各行を `threads` 本のスレッドで調べ、行の順に返す。
+pub fn analyze_text(text: &str, threads: usize) -> Vec<SfenLine> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<SfenLine> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    while let Some(&(line, sfen)) = lines.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        done.push(SfenLine {
+                            line,
+                            result: analyze_sfen(sfen),
+                        });
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("sfen stats worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|result| result.line);
+    results
+}
+
+/// 全体の集計。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SfenSummary {
+    pub positions: usize,
+    pub rejected: usize,
+    pub in_check: usize,
+    pub mates: usize,
+    pub legal_moves: usize,
+}
+
+impl SfenSummary {
+    pub fn from_lines(lines: &[SfenLine]) -> Self {
+        let mut summary = Self {
+            positions: lines.len(),
+            ..Self::default()
+        };
+        for line in lines {
+            match &line.result {
+                Ok(stats) => {
+                    summary.in_check += stats.in_check as usize;
+                    summary.mates += matches!(stats.status, GameStatus::Mate { .. }) as usize;
+                    summary.legal_moves += stats.legal_moves;
+                }
+                Err(_) => summary.rejected += 1,
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for SfenSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "positions {} rejected {} check {} mate {} moves {}",
+            self.positions, self.rejected, self.in_check, self.mates, self.legal_moves
+        )
+    }
+}
+
+/// `sfenstats` サブコマンド。
+///
+/// `sfenstats <file> [--threads N] [--clean PATH]`
+///
+/// 行ごとの結果を出し、最後に集計を出す。`--clean` を付けると、問題のない局面のSFENだけを
+/// 元の順に書き出す。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut threads = thread::available_parallelism().map_or(1, usize::from);
+    let mut clean_path = None;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--threads" => threads = parse_value(flag, iter.next())?,
+            "--clean" => clean_path = Some(parse_value::<String>(flag, iter.next())?),
+            other if !other.starts_with("--") && path.is_none() => {
+                path = Some(other.to_string());
+            }
+            other => return Err(format!("unknown sfenstats option: {other}").into()),
+        }
+    }
+    let path = path.ok_or("sfenstats requires a file of SFENs")?;
+    let lines = analyze_text(&fs::read_to_string(&path)?, threads);
+    let mut clean = String::new();
+    for line in &lines {
+        match &line.result {
+            Ok(stats) => {
+                println!("{stats}");
+                clean.push_str(&stats.sfen);
+                clean.push('\n');
+            }
+            Err(err) => println!("{path}:{}: {err}", line.line),
+        }
+    }
+    println!("{}", SfenSummary::from_lines(&lines));
+    if let Some(clean_path) = clean_path {
+        fs::write(clean_path, clean)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::INITIAL_SFEN;
+
+    #[test]
+    fn stats_and_faults_are_reported_in_line_order() {
+        let text = format!(
+            "# corpus\n{INITIAL_SFEN}\nk4/5/5/5/4K b - 1\n\nk4/5/5/4r/4K w - 1\nk4/5/5/5/K3K b - 1\nk3P/5/5/5/4K b - 1\n4k/P4/P4/5/K4 b - 1\nk4/5/2p2/2R2/4K b RR 1\nbad\n{}\n",
+            "4k/3G1/4K/5/5 w - 1"
+        );
+        let lines = analyze_text(&text, 3);
+        let numbers: Vec<usize> = lines.iter().map(|line| line.line).collect();
+        assert_eq!(numbers, [2, 3, 5, 6, 7, 8, 9, 10, 11]);
+
+        let initial = lines[0].result.as_ref().expect("initial");
+        assert_eq!(initial.legal_moves, 14);
+        assert_eq!(initial.status, GameStatus::Ongoing);
+        assert_eq!(initial.material.to_string(), "KGSBRP v kgsbrp");
+
+        let faults: Vec<Option<SfenFault>> = lines[2..7]
+            .iter()
+            .map(|line| match &line.result {
+                Err(SfenRejection::Fault(fault)) => Some(*fault),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            faults,
+            [
+                Some(SfenFault::OpponentInCheck),
+                Some(SfenFault::KingCount(Color::Black)),
+                Some(SfenFault::DeadPiece(Square::from_coord("1a").unwrap())),
+                Some(SfenFault::DoublePawn(Color::Black)),
+                Some(SfenFault::TooManyPieces(HandPieceKind::Rook)),
+            ]
+        );
+        assert!(matches!(lines[7].result, Err(SfenRejection::Parse(_))));
+
+        let mated = lines[8].result.as_ref().expect("mate");
+        assert!(mated.in_check);
+        assert_eq!(
+            mated.status,
+            GameStatus::Mate {
+                winner: Color::Black
+            }
+        );
+
+        let summary = SfenSummary::from_lines(&lines);
+        assert_eq!(summary.positions, 9);
+        assert_eq!(summary.rejected, 6);
+        assert_eq!(summary.mates, 1);
+    }
+}
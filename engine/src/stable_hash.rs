@@ -0,0 +1,111 @@
+//! 定跡・終盤データベース・経験ファイルのキーに使う、ビルドをまたいで変わらないハッシュ。
+//!
+//! 探索用の zobrist キーは乱数の種や表の並びに依存し、作り直すと値が変わる。
+//! ここでは局面を次のバイト列に直してから FNV-1a (64ビット) をかける。
+//! 配置を変えるときは `STABLE_HASH_VERSION` を上げる。
+//!
+//! | 位置 | 内容 |
+//! |------|------|
+//! | 0 | `STABLE_HASH_VERSION` |
+//! | 1..26 | 1a, 2a, …, 5a, 1b, …, 5e の順に各マスの駒コード（空きは0） |
+//! | 26..31 | 先手の持ち駒の枚数（金・銀・角・飛・歩の順） |
+//! | 31..36 | 後手の持ち駒の枚数（同じ順） |
+//! | 36 | 手番（先手0、後手1） |
+//!
+//! 駒コードは先手の玉1・金2・銀3・成銀4・角5・馬6・飛7・竜8・歩9・と10で、
+//! 後手の駒はこれに16を足す。手数は含めない。
+
+use crate::board::{BOARD_SQUARES, Square};
+use crate::hand::HandPieceKind;
+use crate::piece::{Color, Piece, PieceKind};
+use crate::position::Position;
+
+/// バイト列の配置の版。
+pub const STABLE_HASH_VERSION: u8 = 1;
+
+/// `layout` が返すバイト列の長さ。
+pub const LAYOUT_LEN: usize = 1 + BOARD_SQUARES + 2 * HAND_KINDS.len() + 1;
+
+/// 持ち駒を並べる順。
+const HAND_KINDS: [HandPieceKind; 5] = [
+    HandPieceKind::Gold,
+    HandPieceKind::Silver,
+    HandPieceKind::Bishop,
+    HandPieceKind::Rook,
+    HandPieceKind::Pawn,
+];
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn piece_code(piece: Piece) -> u8 {
+    let code = match piece.kind {
+        PieceKind::King => 1,
+        PieceKind::Gold => 2,
+        PieceKind::Silver => 3,
+        PieceKind::PromotedSilver => 4,
+        PieceKind::Bishop => 5,
+        PieceKind::PromotedBishop => 6,
+        PieceKind::Rook => 7,
+        PieceKind::PromotedRook => 8,
+        PieceKind::Pawn => 9,
+        PieceKind::Tokin => 10,
+    };
+    match piece.color {
+        Color::Black => code,
+        Color::White => code + 16,
+    }
+}
+
+/// 局面をモジュールの説明にある配置のバイト列に直す。
+pub fn layout(position: &Position) -> [u8; LAYOUT_LEN] {
+    let mut bytes = [0u8; LAYOUT_LEN];
+    bytes[0] = STABLE_HASH_VERSION;
+    for (index, byte) in bytes[1..=BOARD_SQUARES].iter_mut().enumerate() {
+        let square = Square::from_index(index as u8);
+        *byte = position.piece_at(square).map_or(0, piece_code);
+    }
+    let mut offset = 1 + BOARD_SQUARES;
+    for color in [Color::Black, Color::White] {
+        for kind in HAND_KINDS {
+            bytes[offset] = position.hand(color).count(kind);
+            offset += 1;
+        }
+    }
+    bytes[offset] = match position.side_to_move() {
+        Color::Black => 0,
+        Color::White => 1,
+    };
+    bytes
+}
+
+/// ビルドや版をまたいで変わらない局面のキー。
+pub fn stable_key(position: &Position) -> u64 {
+    layout(position).iter().fold(FNV_OFFSET, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_pinned_and_ignore_the_ply() {
+        let initial = Position::initial().unwrap();
+        let bytes = layout(&initial);
+        assert_eq!(bytes[0], STABLE_HASH_VERSION);
+        // 1a は後手玉、5e は先手玉。
+        assert_eq!(bytes[1], 17);
+        assert_eq!(bytes[BOARD_SQUARES], 1);
+        assert_eq!(stable_key(&initial), 0xa5d3_f866_1219_50bc);
+
+        let mut later = initial.clone();
+        later.set_ply(30);
+        assert_eq!(stable_key(&later), stable_key(&initial));
+        let with_hand = Position::from_sfen("rbsgk/4p/5/P4/KGSBR b P 1").unwrap();
+        assert_ne!(stable_key(&with_hand), stable_key(&initial));
+        let white = Position::from_sfen("rbsgk/4p/5/P4/KGSBR w - 1").unwrap();
+        assert_ne!(stable_key(&white), stable_key(&initial));
+    }
+}
@@ -1,7 +1,14 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use crate::board::Square;
 use crate::moves::Move;
+use crate::piece::PieceKind;
 use crate::position::Position;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -11,32 +18,62 @@ pub enum Bound {
     Upper,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct TableEntry {
     pub depth: usize,
     pub score: i32,
     pub bound: Bound,
     pub best_move: Option<Move>,
+    /// 書き込んだ局面のハッシュから取った検証用のビット。`verification_bits` を参照。
+    pub check: u16,
+}
+
+impl TableEntry {
+    /// `hash` の局面に書かれたエントリとして検証ビットが一致するか。
+    pub fn matches(&self, hash: u64) -> bool {
+        self.check == verification_bits(hash)
+    }
 }
 
+/// エントリに残す検証用のビット。表の添字に使う下位ビットとは独立した上位16ビットを使い、
+/// キーを切り詰めた表で別の局面のエントリを引いたときに見分けられるようにする。
+pub fn verification_bits(hash: u64) -> u16 {
+    (hash >> 48) as u16
+}
+
+/// 置換表。`&self` で読み書きできるので、並列版は複数スレッドから共有できる。
+///
+/// 同じハッシュのエントリは、深さが同じか深いときだけ上書きする。
+pub trait TranspositionTable: Send {
+    fn probe(&self, hash: u64) -> Option<TableEntry>;
+    fn store(&self, hash: u64, entry: TableEntry);
+    fn clear(&self);
+
+    /// 表が使っているおおよそのバイト数。
+    fn bytes(&self) -> usize {
+        0
+    }
+}
+
+/// 単一スレッド用の置換表。衝突のないハッシュマップで持つ。
 #[derive(Default)]
-pub struct TranspositionTable {
-    map: HashMap<u64, TableEntry>,
+pub struct LocalTable {
+    map: RefCell<HashMap<u64, TableEntry>>,
 }
 
-impl TranspositionTable {
+impl LocalTable {
     pub fn new() -> Self {
-        Self {
-            map: HashMap::new(),
-        }
+        Self::default()
     }
+}
 
-    pub fn clear(&mut self) {
-        self.map.clear();
+impl TranspositionTable for LocalTable {
+    fn probe(&self, hash: u64) -> Option<TableEntry> {
+        self.map.borrow().get(&hash).copied()
     }
 
-    pub fn store(&mut self, hash: u64, entry: TableEntry) {
-        match self.map.entry(hash) {
+    fn store(&self, hash: u64, entry: TableEntry) {
+        match self.map.borrow_mut().entry(hash) {
             Entry::Occupied(mut occ) => {
                 if entry.depth >= occ.get().depth {
                     occ.insert(entry);
@@ -48,11 +85,385 @@ impl TranspositionTable {
         }
     }
 
-    pub fn probe(&self, hash: u64) -> Option<&TableEntry> {
-        self.map.get(&hash)
+    fn clear(&self) {
+        self.map.borrow_mut().clear();
+    }
+
+    /// 確保済みの要素の分だけ数え、ハッシュマップ自体の管理領域は含めない。
+    fn bytes(&self) -> usize {
+        self.map.borrow().capacity() * size_of::<(u64, TableEntry)>()
     }
 }
 
+/// 複数スレッドで共有する固定サイズの置換表。
+///
+/// 各スロットはキーとデータの2語で、キーには `hash ^ data` を書く。読み出し時に
+/// 復元したハッシュが一致しなければ、書き込みが途中で混ざったものとして捨てる。
+/// 1つのバケットは深さ優先のスロットと常に上書きするスロットの2つからなる。
+pub struct SharedTable {
+    buckets: Box<[[Slot; 2]]>,
+    mask: usize,
+}
+
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self, hash: u64) -> Option<TableEntry> {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed);
+        if data == 0 || key ^ data != hash {
+            return None;
+        }
+        unpack_entry(data)
+    }
+
+    fn depth(&self) -> Option<usize> {
+        let data = self.data.load(Ordering::Relaxed);
+        (data != 0).then_some((data & 0xff) as usize)
+    }
+
+    fn write(&self, hash: u64, data: u64) {
+        self.key.store(hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.key.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
+/// 既定のバケット数（32バイト×32768 = 1MiB）。
+const DEFAULT_SHARED_BUCKETS: usize = 1 << 15;
+
+/// 1バケットの大きさ（バイト）。
+pub const SHARED_BUCKET_BYTES: usize = size_of::<[Slot; 2]>();
+
+/// `USI_Hash` の既定値（MiB）。
+pub const DEFAULT_HASH_MB: usize = (DEFAULT_SHARED_BUCKETS * SHARED_BUCKET_BYTES) >> 20;
+
+/// 置換表をどの大きさでも確保できなかった。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocationError {
+    /// 求めた大きさ（MiB）。
+    pub megabytes: usize,
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot allocate a transposition table of {} MB",
+            self.megabytes
+        )
+    }
+}
+
+impl Error for AllocationError {}
+
+impl Default for SharedTable {
+    fn default() -> Self {
+        Self::with_buckets(DEFAULT_SHARED_BUCKETS)
+    }
+}
+
+impl SharedTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `buckets` は2の冪に切り上げる。
+    pub fn with_buckets(buckets: usize) -> Self {
+        let buckets = buckets.max(1).next_power_of_two();
+        Self {
+            buckets: (0..buckets).map(|_| Default::default()).collect(),
+            mask: buckets - 1,
+        }
+    }
+
+    /// `buckets` を2の冪に切り上げて確保する。メモリが足りなければ `None`。
+    pub fn try_with_buckets(buckets: usize) -> Option<Self> {
+        let buckets = buckets.max(1).checked_next_power_of_two()?;
+        let mut slots = Vec::new();
+        slots.try_reserve_exact(buckets).ok()?;
+        slots.extend((0..buckets).map(|_| Default::default()));
+        Some(Self {
+            buckets: slots.into_boxed_slice(),
+            mask: buckets - 1,
+        })
+    }
+
+    /// `megabytes` MiBに収まる最大の表を確保する。確保できなければ大きさを半分にして
+    /// 試し直すので、実際の大きさは `megabytes()` で確かめる。
+    ///
+    /// OSはメモリを約束だけして確保を成功させることがあり、使い始めてから足りなくなると
+    /// プロセスごと落とされる。そうならないよう、空いている物理メモリに収まる大きさから試す。
+    pub fn with_megabytes(megabytes: usize) -> Result<Self, AllocationError> {
+        Self::allocate(megabytes, available_memory(), Self::try_with_buckets)
+    }
+
+    fn allocate(
+        megabytes: usize,
+        available: Option<usize>,
+        mut try_alloc: impl FnMut(usize) -> Option<Self>,
+    ) -> Result<Self, AllocationError> {
+        let bytes = megabytes
+            .max(1)
+            .saturating_mul(1 << 20)
+            .min(available.unwrap_or(usize::MAX));
+        let mut buckets = prev_power_of_two((bytes / SHARED_BUCKET_BYTES).max(1));
+        loop {
+            if let Some(table) = try_alloc(buckets) {
+                return Ok(table);
+            }
+            if buckets == 1 {
+                return Err(AllocationError { megabytes });
+            }
+            buckets /= 2;
+        }
+    }
+
+    /// 確保したバイト数。
+    pub fn bytes(&self) -> usize {
+        self.buckets.len() * SHARED_BUCKET_BYTES
+    }
+
+    /// 確保した大きさ（MiB、切り捨て）。
+    pub fn megabytes(&self) -> usize {
+        self.bytes() >> 20
+    }
+
+    fn bucket(&self, hash: u64) -> &[Slot; 2] {
+        &self.buckets[hash as usize & self.mask]
+    }
+}
+
+impl TranspositionTable for SharedTable {
+    fn probe(&self, hash: u64) -> Option<TableEntry> {
+        let [deep, recent] = self.bucket(hash);
+        deep.load(hash).or_else(|| recent.load(hash))
+    }
+
+    fn store(&self, hash: u64, entry: TableEntry) {
+        let [deep, recent] = self.bucket(hash);
+        let data = pack_entry(&entry);
+        if let Some(existing) = deep.load(hash) {
+            if entry.depth >= existing.depth {
+                deep.write(hash, data);
+            }
+            return;
+        }
+        if deep.depth().is_none_or(|depth| entry.depth >= depth) {
+            deep.write(hash, data);
+        } else if recent
+            .load(hash)
+            .is_none_or(|existing| entry.depth >= existing.depth)
+        {
+            recent.write(hash, data);
+        }
+    }
+
+    fn clear(&self) {
+        for slot in self.buckets.iter().flatten() {
+            slot.reset();
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        SharedTable::bytes(self)
+    }
+}
+
+impl<T: TranspositionTable + Sync + ?Sized> TranspositionTable for Arc<T> {
+    fn probe(&self, hash: u64) -> Option<TableEntry> {
+        (**self).probe(hash)
+    }
+
+    fn store(&self, hash: u64, entry: TableEntry) {
+        (**self).store(hash, entry)
+    }
+
+    fn clear(&self) {
+        (**self).clear()
+    }
+
+    fn bytes(&self) -> usize {
+        (**self).bytes()
+    }
+}
+
+/// 空いている物理メモリ（バイト）。Linuxでは `/proc/meminfo` の `MemAvailable`。
+/// 分からなければ `None`。
+fn available_memory() -> Option<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kilobytes: usize = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kilobytes.saturating_mul(1 << 10))
+}
+
+/// `n` 以下で最大の2の冪。`n` は1以上。
+fn prev_power_of_two(n: usize) -> usize {
+    1 << (usize::BITS - 1 - n.leading_zeros())
+}
+
+// データ語のビット配置:
+//   0..8 深さ / 8..24 評価値(i16) / 24..26 境界 / 26 指し手あり / 27..32 移動元+1 (0は打つ手)
+//   32..37 移動先 / 37..41 駒種 / 41 成り / 42..58 検証ビット / 63 使用中
+const VALID_BIT: u64 = 1 << 63;
+
+fn pack_entry(entry: &TableEntry) -> u64 {
+    let score = entry.score.clamp(i16::MIN as i32, i16::MAX as i32) as i16 as u16 as u64;
+    let bound = match entry.bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    };
+    let mut data = VALID_BIT
+        | entry.depth.min(u8::MAX as usize) as u64
+        | score << 8
+        | bound << 24
+        | (entry.check as u64) << 42;
+    if let Some(mv) = entry.best_move {
+        let from = mv.from.map_or(0, |sq| sq.index() as u64 + 1);
+        data |= 1 << 26
+            | from << 27
+            | (mv.to.index() as u64) << 32
+            | (mv.piece.index() as u64) << 37
+            | (mv.promote as u64) << 41;
+    }
+    data
+}
+
+fn unpack_entry(data: u64) -> Option<TableEntry> {
+    let bound = match (data >> 24) & 0b11 {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        2 => Bound::Upper,
+        _ => return None,
+    };
+    let best_move = if data & (1 << 26) != 0 {
+        let from = ((data >> 27) & 0x1f) as u8;
+        let piece = *PieceKind::all().get(((data >> 37) & 0xf) as usize)?;
+        Some(Move {
+            from: from.checked_sub(1).map(Square::from_index),
+            to: Square::from_index(((data >> 32) & 0x1f) as u8),
+            piece,
+            promote: data & (1 << 41) != 0,
+        })
+    } else {
+        None
+    };
+    Some(TableEntry {
+        depth: (data & 0xff) as usize,
+        score: ((data >> 8) & 0xffff) as u16 as i16 as i32,
+        bound,
+        best_move,
+        check: ((data >> 42) & 0xffff) as u16,
+    })
+}
+
 pub fn compute_hash(position: &Position) -> u64 {
     position.zobrist_key()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn sample_entry(depth: usize) -> TableEntry {
+        TableEntry {
+            depth,
+            score: -29_950,
+            bound: Bound::Lower,
+            best_move: Some(Move::normal(
+                Square::from_coord("2b").unwrap(),
+                Square::from_coord("2a").unwrap(),
+                PieceKind::Silver,
+                true,
+            )),
+            check: 0xbeef,
+        }
+    }
+
+    #[test]
+    fn allocation_halves_until_it_fits() {
+        let table = SharedTable::with_megabytes(2).unwrap();
+        assert_eq!(table.megabytes(), 2);
+        assert_eq!(
+            SharedTable::with_megabytes(DEFAULT_HASH_MB)
+                .unwrap()
+                .bytes(),
+            1 << 20
+        );
+
+        let mut attempts = Vec::new();
+        let table = SharedTable::allocate(4, None, |buckets| {
+            attempts.push(buckets);
+            (buckets <= DEFAULT_SHARED_BUCKETS).then(|| SharedTable::with_buckets(buckets))
+        })
+        .unwrap();
+        assert_eq!(attempts, [1 << 17, 1 << 16, 1 << 15]);
+        assert_eq!(table.megabytes(), 1);
+
+        // 空いているメモリより大きい表は、約束だけで確保が成功しても試さない。
+        let table = SharedTable::allocate(4, Some(3 << 19), |buckets| {
+            Some(SharedTable::with_buckets(buckets))
+        })
+        .unwrap();
+        assert_eq!(table.megabytes(), 1);
+        assert_eq!(
+            SharedTable::allocate(4, None, |_| None).err(),
+            Some(AllocationError { megabytes: 4 })
+        );
+    }
+
+    #[test]
+    fn shared_table_roundtrips_and_keeps_deeper_entries() {
+        let table = SharedTable::with_buckets(8);
+        let hash = 0x1234_5678_9abc_def0;
+        table.store(hash, sample_entry(5));
+        assert_eq!(table.probe(hash), Some(sample_entry(5)));
+        table.store(hash, sample_entry(2));
+        assert_eq!(table.probe(hash).unwrap().depth, 5);
+        assert_eq!(table.probe(hash ^ 0x10_0000), None);
+
+        let drop = TableEntry {
+            best_move: Some(Move::drop(
+                Square::from_coord("3c").unwrap(),
+                PieceKind::Pawn,
+            )),
+            ..sample_entry(1)
+        };
+        table.store(7, drop);
+        assert_eq!(table.probe(7), Some(drop));
+    }
+
+    #[test]
+    fn shared_table_is_usable_from_several_threads() {
+        let table = Arc::new(SharedTable::with_buckets(512));
+        let handles: Vec<_> = (0..4u64)
+            .map(|worker| {
+                let table = Arc::clone(&table);
+                thread::spawn(move || {
+                    for i in 0..1000u64 {
+                        let hash = (i * 4 + worker).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                        table.store(hash, sample_entry((i % 10) as usize));
+                        if let Some(entry) = table.probe(hash) {
+                            assert_eq!(entry.score, -29_950);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::kif;
+use crate::match_runner::{
+    self, EngineConfig, EngineKind, GameRecord, MatchConfig, Player, parse_value,
+};
+use crate::match_stats::MatchStats;
+use crate::pgn;
+use crate::search::SearchLimits;
+
+/// 対戦表の組み方。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TournamentKind {
+    /// 全員総当たり。
+    RoundRobin,
+    /// 最初のエンジンと残り全員の対戦。
+    Gauntlet,
+}
+
+/// 1組の対戦結果。統計は `first` 側から見たもの。
+#[derive(Clone, Debug)]
+pub struct PairingResult {
+    pub first: usize,
+    pub second: usize,
+    pub stats: MatchStats,
+}
+
+/// トーナメント全体の結果。
+#[derive(Clone, Debug)]
+pub struct TournamentResult {
+    pub names: Vec<String>,
+    pub pairings: Vec<PairingResult>,
+}
+
+impl TournamentResult {
+    /// `row` が `column` から得た得点。対戦がなければ `None`。
+    pub fn points(&self, row: usize, column: usize) -> Option<f64> {
+        self.pairings.iter().find_map(|pairing| {
+            let stats = &pairing.stats;
+            let won = stats.wins() as f64 + 0.5 * stats.draws() as f64;
+            let lost = stats.losses() as f64 + 0.5 * stats.draws() as f64;
+            if pairing.first == row && pairing.second == column {
+                Some(won)
+            } else if pairing.first == column && pairing.second == row {
+                Some(lost)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn total_points(&self, row: usize) -> f64 {
+        (0..self.names.len())
+            .filter_map(|column| self.points(row, column))
+            .sum()
+    }
+
+    /// 得点の対戦表（クロステーブル）を文字列にする。
+    pub fn crosstable(&self) -> String {
+        let width = self
+            .names
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(6);
+        let mut out = format!("{:width$}", "");
+        for name in &self.names {
+            out.push_str(&format!(" {name:>width$}"));
+        }
+        out.push_str(&format!(" {:>width$}\n", "total"));
+        for (row, name) in self.names.iter().enumerate() {
+            out.push_str(&format!("{name:width$}"));
+            for column in 0..self.names.len() {
+                let cell = if row == column {
+                    "-".to_string()
+                } else {
+                    self.points(row, column)
+                        .map_or(String::new(), |points| format!("{points:.1}"))
+                };
+                out.push_str(&format!(" {cell:>width$}"));
+            }
+            out.push_str(&format!(" {:>width$.1}\n", self.total_points(row)));
+        }
+        out
+    }
+}
+
+fn schedule(kind: TournamentKind, count: usize) -> Vec<(usize, usize)> {
+    match kind {
+        TournamentKind::RoundRobin => (0..count)
+            .flat_map(|i| (i + 1..count).map(move |j| (i, j)))
+            .collect(),
+        TournamentKind::Gauntlet => (1..count).map(|j| (0, j)).collect(),
+    }
+}
+
+/// 複数エンジンでトーナメントを行う。`on_game` には組番号、対局番号、棋譜、
+/// 先手と後手の名前が渡される。
+pub fn run_tournament(
+    engines: &[EngineConfig],
+    kind: TournamentKind,
+    config: &MatchConfig,
+    mut on_game: impl FnMut(usize, usize, &GameRecord, &str, &str),
+) -> Result<TournamentResult, Box<dyn Error>> {
+    if engines.len() < 2 {
+        return Err("a tournament needs at least two engines".into());
+    }
+    let mut players: Vec<Box<dyn Player>> = engines
+        .iter()
+        .map(EngineConfig::create_player)
+        .collect::<Result<_, _>>()?;
+
+    let mut pairings = Vec::new();
+    for (pairing_idx, (i, j)) in schedule(kind, engines.len()).into_iter().enumerate() {
+        let (left, right) = players.split_at_mut(j);
+        let first = left[i].as_mut();
+        let second = right[0].as_mut();
+        let stats =
+            match_runner::run_pairing(first, second, config, |number, record, first_is_black| {
+                let (black, white) = if first_is_black { (i, j) } else { (j, i) };
+                on_game(
+                    pairing_idx + 1,
+                    number,
+                    record,
+                    &engines[black].name,
+                    &engines[white].name,
+                );
+            })?;
+        pairings.push(PairingResult {
+            first: i,
+            second: j,
+            stats,
+        });
+    }
+
+    Ok(TournamentResult {
+        names: engines.iter().map(|engine| engine.name.clone()).collect(),
+        pairings,
+    })
+}
+
+/// `name=A,depth=3,random=20` や `name=ext,cmd=/path/to/engine,go=go byoyomi 1000` を解釈する。
+fn parse_engine_spec(spec: &str) -> Result<EngineConfig, String> {
+    let mut name = None;
+    let mut limits = SearchLimits::default();
+    let mut command = None;
+    let mut go = "go depth 3".to_string();
+    for field in spec.split(',') {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("invalid engine field: {field}"))?;
+        match key {
+            "name" => name = Some(value.to_string()),
+            "depth" => {
+                limits.depth = value
+                    .parse()
+                    .map_err(|_| format!("invalid depth: {value}"))?
+            }
+            "random" => {
+                limits.randomness = value
+                    .parse()
+                    .map_err(|_| format!("invalid random: {value}"))?
+            }
+            "random-plies" => {
+                limits.random_plies = value
+                    .parse()
+                    .map_err(|_| format!("invalid random-plies: {value}"))?
+            }
+            "cmd" => command = Some(value.to_string()),
+            "go" => go = value.to_string(),
+            _ => return Err(format!("unknown engine field: {key}")),
+        }
+    }
+    let name = name.ok_or("engine spec requires name=")?;
+    let kind = match command {
+        Some(command) => {
+            let mut parts = command.split_whitespace().map(str::to_string);
+            let program = parts.next().ok_or("empty engine command")?;
+            EngineKind::External {
+                program,
+                args: parts.collect(),
+                go,
+            }
+        }
+        None => EngineKind::Internal(limits),
+    };
+    Ok(EngineConfig { name, kind })
+}
+
+/// `tournament` サブコマンド。
+///
+/// `tournament --engine SPEC --engine SPEC ... [--gauntlet] [--pairs N] [--max-plies N]
+///             [--openings PATH] [--seed N] [--adjudicate-entering-king] [--kif-dir DIR]
+///             [--pgn PATH] [--black-depth D] [--white-depth D] [--black-random R]
+///             [--white-random R] [--black-go CMD] [--white-go CMD]`
+///
+/// `--black-*` と `--white-*` は、どのエンジンが持つかによらずその色の手に使う。外部エンジンには
+/// `depth` を `go depth D` として送り、`go` はそのまま送る。内部の探索器に `go` は使えない。
+/// `--kif-dir` には1局ずつKIFを、`--pgn` には全局を1つのPGNファイルに書く。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut engines = Vec::new();
+    let mut kind = TournamentKind::RoundRobin;
+    let mut config = MatchConfig::default();
+    let mut kif_dir: Option<PathBuf> = None;
+    let mut pgn_path: Option<PathBuf> = None;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--engine" => {
+                let spec: String = parse_value(flag, iter.next())?;
+                engines.push(parse_engine_spec(&spec)?);
+            }
+            "--gauntlet" => kind = TournamentKind::Gauntlet,
+            "--pairs" => config.pairs = parse_value(flag, iter.next())?,
+            "--max-plies" => config.max_plies = parse_value(flag, iter.next())?,
+            "--openings" => {
+                config.openings =
+                    match_runner::load_openings(&parse_value::<String>(flag, iter.next())?)?
+            }
+            "--seed" => config.opening_seed = Some(parse_value(flag, iter.next())?),
+            "--adjudicate-entering-king" => config.adjudicate_entering_king = true,
+            "--kif-dir" => kif_dir = Some(PathBuf::from(parse_value::<String>(flag, iter.next())?)),
+            "--pgn" => pgn_path = Some(PathBuf::from(parse_value::<String>(flag, iter.next())?)),
+            other
+                if match_runner::parse_color_limit(other, &mut iter, &mut config.color_limits)? => {
+            }
+            other => return Err(format!("unknown tournament option: {other}").into()),
+        }
+    }
+    if let Some(dir) = &kif_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut dump_error = None;
+    let mut pgn_text = String::new();
+    let result = run_tournament(
+        &engines,
+        kind,
+        &config,
+        |pairing, number, record, black, white| {
+            println!(
+                "pairing {pairing} game {number}: {black} vs {white} {} ({:?}, {} plies, {})",
+                match_runner::result_label(record),
+                record.termination,
+                record.moves.len(),
+                match_runner::time_label(record)
+            );
+            if let Some(dir) = &kif_dir {
+                let path = dir.join(format!("{pairing:02}-{number:03}.kifu"));
+                let written = kif::game_to_kif(record, black, white)
+                    .map_err(|err| err.to_string())
+                    .and_then(|text| fs::write(&path, text).map_err(|err| err.to_string()));
+                if let Err(err) = written {
+                    dump_error.get_or_insert(err);
+                }
+            }
+            if pgn_path.is_some() {
+                let round = format!("{pairing}.{number}");
+                match pgn::game_to_pgn(record, black, white, "tournament", &round) {
+                    Ok(text) => pgn_text.push_str(&text),
+                    Err(err) => {
+                        dump_error.get_or_insert(err.to_string());
+                    }
+                }
+            }
+        },
+    )?;
+    if let Some(path) = &pgn_path
+        && let Err(err) = fs::write(path, &pgn_text)
+    {
+        dump_error.get_or_insert(err.to_string());
+    }
+    if let Some(err) = dump_error {
+        return Err(format!("failed to write game records: {err}").into());
+    }
+
+    for pairing in &result.pairings {
+        println!(
+            "\n{} vs {}\n{}",
+            result.names[pairing.first], result.names[pairing.second], pairing.stats
+        );
+    }
+    println!("\n{}", result.crosstable());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_plays_every_pairing() {
+        let engine = |name: &str| {
+            EngineConfig::internal(
+                name,
+                SearchLimits {
+                    depth: 1,
+                    randomness: 50,
+                    ..SearchLimits::default()
+                },
+            )
+        };
+        let engines = [engine("a"), engine("b"), engine("c")];
+        let config = MatchConfig {
+            pairs: 1,
+            max_plies: 8,
+            ..MatchConfig::default()
+        };
+        let result = run_tournament(
+            &engines,
+            TournamentKind::RoundRobin,
+            &config,
+            |_, _, _, _, _| {},
+        )
+        .expect("tournament");
+        assert_eq!(result.pairings.len(), 3);
+        let total: f64 = (0..3).map(|row| result.total_points(row)).sum();
+        assert!((total - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_external_engine_spec() {
+        let config = parse_engine_spec("name=ext,cmd=./engine --flag,go=go byoyomi 1000").unwrap();
+        match config.kind {
+            EngineKind::External { program, args, go } => {
+                assert_eq!(program, "./engine");
+                assert_eq!(args, vec!["--flag".to_string()]);
+                assert_eq!(go, "go byoyomi 1000");
+            }
+            EngineKind::Internal(_) => panic!("expected external engine"),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! 最適化の効果を切り分けるための計測。
+//!
+//! `tracing` フィーチャーを有効にしたときだけ、探索の枝刈りごとの回数と、指し手生成・評価・
+//! 探索にかかった時間を全スレッド共通のカウンタに積む。無効なら `count` と `span` は何もしない
+//! 関数になり、探索の速さに影響しない。
+
+use core::fmt;
+
+/// 数える出来事。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    /// 通常探索で訪れた節点。
+    MainNodes,
+    /// 静止探索で訪れた節点。
+    QuiescenceNodes,
+    /// 置換表の値だけで返した節点。
+    TtCutoffs,
+    /// 指し手のループをbetaカットで抜けた節点。
+    BetaCutoffs,
+    /// 静止探索のstand-patで返した節点。
+    StandPatCutoffs,
+    /// 王手で深さを延ばした手。
+    CheckExtensions,
+    /// 千日手として値を決めた節点。
+    RepetitionHits,
+    /// 最大手数に達して評価値で打ち切った節点。
+    HorizonStops,
+    /// 浅い節点で、ヒストリーの悪い静かな手を読まずに飛ばした回数。
+    LateMovePrunes,
+}
+
+impl Counter {
+    pub const ALL: [Self; 9] = [
+        Self::MainNodes,
+        Self::QuiescenceNodes,
+        Self::TtCutoffs,
+        Self::BetaCutoffs,
+        Self::StandPatCutoffs,
+        Self::CheckExtensions,
+        Self::RepetitionHits,
+        Self::HorizonStops,
+        Self::LateMovePrunes,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MainNodes => "main_nodes",
+            Self::QuiescenceNodes => "qsearch_nodes",
+            Self::TtCutoffs => "tt_cutoffs",
+            Self::BetaCutoffs => "beta_cutoffs",
+            Self::StandPatCutoffs => "stand_pat_cutoffs",
+            Self::CheckExtensions => "check_extensions",
+            Self::RepetitionHits => "repetition_hits",
+            Self::HorizonStops => "horizon_stops",
+            Self::LateMovePrunes => "lmp_prunes",
+        }
+    }
+}
+
+/// 時間を測る処理。区間は入れ子になりうるので、`Search` は他の区間を含む。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Movegen,
+    Eval,
+    Search,
+}
+
+impl Section {
+    pub const ALL: [Self; 3] = [Self::Movegen, Self::Eval, Self::Search];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Movegen => "movegen",
+            Self::Eval => "eval",
+            Self::Search => "search",
+        }
+    }
+}
+
+/// このビルドで計測が有効か。
+pub const ENABLED: bool = cfg!(feature = "tracing");
+
+/// ある時点までに積まれたカウンタの値。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceReport {
+    pub counters: [u64; Counter::ALL.len()],
+    /// 区間ごとの合計時間（ナノ秒）。
+    pub nanos: [u64; Section::ALL.len()],
+}
+
+impl TraceReport {
+    pub fn count(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    pub fn nanos(&self, section: Section) -> u64 {
+        self.nanos[section as usize]
+    }
+}
+
+impl fmt::Display for TraceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nodes = self.count(Counter::MainNodes) + self.count(Counter::QuiescenceNodes);
+        for counter in Counter::ALL {
+            let value = self.count(counter);
+            let percent = if nodes == 0 {
+                0.0
+            } else {
+                value as f64 * 100.0 / nodes as f64
+            };
+            writeln!(f, "{:<18} {value:>12} {percent:>6.1}%", counter.name())?;
+        }
+        let search = self.nanos(Section::Search);
+        for section in Section::ALL {
+            let nanos = self.nanos(section);
+            let percent = if search == 0 {
+                0.0
+            } else {
+                nanos as f64 * 100.0 / search as f64
+            };
+            writeln!(
+                f,
+                "{:<18} {:>10.1}ms {percent:>6.1}%",
+                section.name(),
+                nanos as f64 / 1e6
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub use imp::{Span, count, reset, snapshot, span};
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    use super::{Counter, Section, TraceReport};
+
+    static COUNTERS: [AtomicU64; Counter::ALL.len()] =
+        [const { AtomicU64::new(0) }; Counter::ALL.len()];
+    static NANOS: [AtomicU64; Section::ALL.len()] =
+        [const { AtomicU64::new(0) }; Section::ALL.len()];
+
+    #[inline]
+    pub fn count(counter: Counter) {
+        COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 落とされたときに、作られてからの時間を区間に足す。
+    pub struct Span {
+        section: Section,
+        started: Instant,
+    }
+
+    impl Drop for Span {
+        fn drop(&mut self) {
+            let nanos = self.started.elapsed().as_nanos() as u64;
+            NANOS[self.section as usize].fetch_add(nanos, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn span(section: Section) -> Span {
+        Span {
+            section,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn reset() {
+        for value in COUNTERS.iter().chain(&NANOS) {
+            value.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot() -> TraceReport {
+        TraceReport {
+            counters: core::array::from_fn(|i| COUNTERS[i].load(Ordering::Relaxed)),
+            nanos: core::array::from_fn(|i| NANOS[i].load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use super::{Counter, Section, TraceReport};
+
+    #[inline(always)]
+    pub fn count(_counter: Counter) {}
+
+    pub struct Span;
+
+    #[inline(always)]
+    pub fn span(_section: Section) -> Span {
+        Span
+    }
+
+    pub fn reset() {}
+
+    pub fn snapshot() -> TraceReport {
+        TraceReport::default()
+    }
+}
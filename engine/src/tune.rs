@@ -0,0 +1,382 @@
+//! 自己対局から中盤と終盤の駒の位置の表を調整する。
+//!
+//! 1. 評価値を揺らした自己対局で棋譜を作り、王手のかかっていない局面と対局の結果を集める。
+//! 2. 評価値を勝率に直した値と結果の二乗誤差が減る向きに、表の値を勾配に比例して少しずつ動かす。
+//! 3. 調整した表と元の表で先後を入れ替えて対局し、勝ち越したときだけパラメータファイルを書く。
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::board::{BOARD_SQUARES, all_squares};
+use crate::eval_file::{EvalFileParams, MAX_PST_VALUE};
+use crate::evaluation::{EvalParams, GAME_PHASE_PLIES, evaluate_absolute, game_phase, pst_square};
+use crate::match_runner::{MatchConfig, SearcherPlayer, parse_value, play_game, run_pairing};
+use crate::match_stats::MatchStats;
+use crate::piece::{Color, PIECE_KIND_COUNT};
+use crate::position::Position;
+use crate::search::SearchLimits;
+
+/// 既定で書き出すパラメータファイル。
+pub const DEFAULT_TUNE_OUTPUT: &str = "ginko-eval.toml";
+
+/// 棋譜の最初のこの手数の局面は、評価値の揺らぎで決まった手が多いので使わない。
+const SKIPPED_OPENING_PLIES: usize = 4;
+
+/// 評価値を勝率に直すときの尺度。評価値がこの値のとき勝率は約91%になる。
+const WIN_PROBABILITY_SCALE: f64 = 400.0;
+
+/// 表を1回動かすときの最大の幅。
+const MAX_STEP: f64 = 2.0;
+
+/// 調整の設定。
+#[derive(Clone, Copy, Debug)]
+pub struct TuneConfig {
+    /// 局面を集める自己対局の数。
+    pub games: usize,
+    pub depth: usize,
+    /// 自己対局と確認の対局で評価値に加える揺らぎの幅。同じ棋譜ばかりにならないようにする。
+    pub eval_noise: i32,
+    pub max_plies: u32,
+    /// 表を動かす回数。
+    pub iterations: usize,
+    /// 確認の対局の、先後を入れ替えた2局1組の数。
+    pub gate_pairs: usize,
+    pub seed: u64,
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self {
+        Self {
+            games: 200,
+            depth: 3,
+            eval_noise: 60,
+            max_plies: 160,
+            iterations: 100,
+            gate_pairs: 50,
+            seed: 1,
+        }
+    }
+}
+
+/// 調整に使う1局面。
+#[derive(Clone, Debug)]
+pub struct Sample {
+    /// 盤上の駒の表の位置（駒種×25+升目）と、先手の駒なら1、後手の駒なら-1。
+    entries: Vec<(usize, i32)>,
+    /// `game_phase` の値。
+    phase: i32,
+    /// 集めたときのパラメータでの先手から見た評価値。
+    base: i32,
+    /// 先手から見た対局の結果。勝ちは1、引き分けは0.5、負けは0。
+    result: f64,
+}
+
+impl Sample {
+    /// 王手がかかっている局面と裸玉の局面は、表で評価値が決まらないので `None`。
+    pub fn new(position: &Position, params: &EvalParams, result: f64) -> Option<Self> {
+        if position.in_check() || position.material().is_bare_kings() {
+            return None;
+        }
+        let entries = all_squares()
+            .into_iter()
+            .filter_map(|square| {
+                let piece = position.piece_at(square)?;
+                let sign = match piece.color {
+                    Color::Black => 1,
+                    Color::White => -1,
+                };
+                let index = piece.kind.index() * BOARD_SQUARES + pst_square(piece.color, square);
+                Some((index, sign))
+            })
+            .collect();
+        Some(Self {
+            entries,
+            phase: game_phase(position),
+            base: evaluate_absolute(position, params).value(),
+            result,
+        })
+    }
+
+    /// 表の各値に `deltas`（中盤、終盤の順）を足したときの先手から見た評価値。
+    fn evaluate(&self, deltas: &[[f64; 2]]) -> f64 {
+        let middlegame = f64::from(self.phase) / f64::from(GAME_PHASE_PLIES);
+        let delta: f64 = self
+            .entries
+            .iter()
+            .map(|&(index, sign)| {
+                let [mg, eg] = deltas[index];
+                f64::from(sign) * (mg * middlegame + eg * (1.0 - middlegame))
+            })
+            .sum();
+        f64::from(self.base) + delta
+    }
+}
+
+/// 先手から見た評価値を先手の勝率に直す。
+fn win_probability(eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-eval / WIN_PROBABILITY_SCALE))
+}
+
+/// `base` と `tuned` の表の差を、表の位置ごとの中盤と終盤の値にする。
+fn table_deltas(tuned: &EvalParams, base: &EvalParams) -> Vec<[f64; 2]> {
+    (0..PIECE_KIND_COUNT * BOARD_SQUARES)
+        .map(|index| {
+            let (kind, square) = (index / BOARD_SQUARES, index % BOARD_SQUARES);
+            [
+                tuned.pst_middlegame[kind][square] - base.pst_middlegame[kind][square],
+                tuned.pst_endgame[kind][square] - base.pst_endgame[kind][square],
+            ]
+            .map(f64::from)
+        })
+        .collect()
+}
+
+fn squared_error(samples: &[Sample], deltas: &[[f64; 2]]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = samples
+        .iter()
+        .map(|sample| (win_probability(sample.evaluate(deltas)) - sample.result).powi(2))
+        .sum();
+    total / samples.len() as f64
+}
+
+/// 表を `base` から `tuned` に変えたときの、勝率と結果の二乗誤差の平均。
+pub fn mean_squared_error(samples: &[Sample], tuned: &EvalParams, base: &EvalParams) -> f64 {
+    squared_error(samples, &table_deltas(tuned, base))
+}
+
+/// `params` で自己対局し、各局面を対局の結果とともに集める。
+pub fn collect_samples(
+    config: &TuneConfig,
+    params: &EvalParams,
+) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let limits = SearchLimits {
+        depth: config.depth,
+        eval_noise: config.eval_noise,
+        eval_noise_seed: config.seed,
+        ..SearchLimits::default()
+    };
+    let mut black = SearcherPlayer::new("black".to_string(), limits);
+    let mut white = SearcherPlayer::new(
+        "white".to_string(),
+        SearchLimits {
+            eval_noise_seed: config.seed.rotate_left(32),
+            ..limits
+        },
+    );
+    black.set_eval_params(*params);
+    white.set_eval_params(*params);
+    let match_config = MatchConfig {
+        max_plies: config.max_plies,
+        ..MatchConfig::default()
+    };
+    let start = Position::initial()?;
+    let mut samples = Vec::new();
+    for _ in 0..config.games {
+        let record = play_game(&mut black, &mut white, &start, &match_config)?;
+        let result = match record.winner {
+            Some(Color::Black) => 1.0,
+            Some(Color::White) => 0.0,
+            None => 0.5,
+        };
+        let mut position = start.clone();
+        for (ply, mv) in record.moves.iter().enumerate() {
+            position.play_move_mut(mv)?;
+            if ply + 1 >= SKIPPED_OPENING_PLIES
+                && let Some(sample) = Sample::new(&position, params, result)
+            {
+                samples.push(sample);
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// `base` の中盤と終盤の表を、`samples` の二乗誤差が減る向きに `iterations` 回動かす。
+/// 1回ごとに、勾配が最も大きい値が `MAX_STEP` だけ動くよう勾配に比例して動かす。
+pub fn tune_tables(samples: &[Sample], base: &EvalParams, iterations: usize) -> EvalParams {
+    let mut deltas = vec![[0.0f64; 2]; PIECE_KIND_COUNT * BOARD_SQUARES];
+    for _ in 0..iterations {
+        let mut gradient = vec![[0.0f64; 2]; deltas.len()];
+        for sample in samples {
+            let probability = win_probability(sample.evaluate(&deltas));
+            let slope = (probability - sample.result) * probability * (1.0 - probability);
+            let middlegame = f64::from(sample.phase) / f64::from(GAME_PHASE_PLIES);
+            for &(index, sign) in &sample.entries {
+                let weight = slope * f64::from(sign);
+                gradient[index][0] += weight * middlegame;
+                gradient[index][1] += weight * (1.0 - middlegame);
+            }
+        }
+        let largest = gradient
+            .iter()
+            .flatten()
+            .fold(0.0f64, |largest, value| largest.max(value.abs()));
+        if largest == 0.0 {
+            break;
+        }
+        for (delta, gradient) in deltas.iter_mut().zip(&gradient) {
+            for (value, slope) in delta.iter_mut().zip(gradient) {
+                *value -= MAX_STEP * slope / largest;
+            }
+        }
+    }
+    let mut tuned = *base;
+    for (index, [middlegame, endgame]) in deltas.into_iter().enumerate() {
+        let (kind, square) = (index / BOARD_SQUARES, index % BOARD_SQUARES);
+        for (table, delta) in [
+            (&mut tuned.pst_middlegame, middlegame),
+            (&mut tuned.pst_endgame, endgame),
+        ] {
+            let value = &mut table[kind][square];
+            *value = (*value + delta.round() as i32).clamp(-MAX_PST_VALUE, MAX_PST_VALUE);
+        }
+    }
+    tuned
+}
+
+/// 調整した表と元の表で対局し、調整した表から見た成績を返す。
+pub fn gate(
+    config: &TuneConfig,
+    tuned: &EvalParams,
+    base: &EvalParams,
+) -> Result<MatchStats, Box<dyn Error>> {
+    let limits = SearchLimits {
+        depth: config.depth,
+        eval_noise: config.eval_noise,
+        eval_noise_seed: config.seed,
+        ..SearchLimits::default()
+    };
+    let mut candidate = SearcherPlayer::new("tuned".to_string(), limits);
+    let mut incumbent = SearcherPlayer::new("base".to_string(), limits);
+    candidate.set_eval_params(*tuned);
+    incumbent.set_eval_params(*base);
+    let match_config = MatchConfig {
+        pairs: config.gate_pairs,
+        max_plies: config.max_plies,
+        ..MatchConfig::default()
+    };
+    run_pairing(&mut candidate, &mut incumbent, &match_config, |_, _, _| {})
+}
+
+/// 調整の一連の結果。
+#[derive(Clone, Debug)]
+pub struct TuneOutcome {
+    pub params: EvalParams,
+    pub samples: usize,
+    /// 調整前と調整後の二乗誤差の平均。
+    pub error_before: f64,
+    pub error_after: f64,
+    /// 確認の対局の、調整した表から見た成績。
+    pub gate: MatchStats,
+}
+
+impl TuneOutcome {
+    /// 確認の対局で勝ち越したか。
+    pub fn adopted(&self) -> bool {
+        self.gate.score().is_some_and(|score| score > 0.5)
+    }
+}
+
+/// 局面を集め、表を調整し、確認の対局までを行う。ファイルは書かない。
+pub fn run_pipeline(config: &TuneConfig, base: &EvalParams) -> Result<TuneOutcome, Box<dyn Error>> {
+    let samples = collect_samples(config, base)?;
+    let params = tune_tables(&samples, base, config.iterations);
+    Ok(TuneOutcome {
+        params,
+        samples: samples.len(),
+        error_before: mean_squared_error(&samples, base, base),
+        error_after: mean_squared_error(&samples, &params, base),
+        gate: gate(config, &params, base)?,
+    })
+}
+
+/// `tune-pst` サブコマンド。
+///
+/// `tune-pst [--games N] [--depth D] [--eval-noise CP] [--max-plies N] [--iterations N]
+///           [--gate-pairs N] [--seed N] [--base PATH] [--out PATH]`
+///
+/// `--base` のパラメータファイルから始め、確認の対局で勝ち越したら `--out` に書く。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config = TuneConfig::default();
+    let mut base = EvalParams::default();
+    let mut out = DEFAULT_TUNE_OUTPUT.to_string();
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--games" => config.games = parse_value(flag, iter.next())?,
+            "--depth" => config.depth = parse_value(flag, iter.next())?,
+            "--eval-noise" => config.eval_noise = parse_value(flag, iter.next())?,
+            "--max-plies" => config.max_plies = parse_value(flag, iter.next())?,
+            "--iterations" => config.iterations = parse_value(flag, iter.next())?,
+            "--gate-pairs" => config.gate_pairs = parse_value(flag, iter.next())?,
+            "--seed" => config.seed = parse_value(flag, iter.next())?,
+            "--base" => {
+                let path = parse_value::<String>(flag, iter.next())?;
+                EvalFileParams::load(Path::new(&path))?.apply(&mut base);
+            }
+            "--out" => out = parse_value(flag, iter.next())?,
+            other => return Err(format!("unknown tune-pst option: {other}").into()),
+        }
+    }
+
+    let outcome = run_pipeline(&config, &base)?;
+    println!("samples {}", outcome.samples);
+    println!(
+        "error before {:.6} after {:.6}",
+        outcome.error_before, outcome.error_after
+    );
+    println!("gate {}", outcome.gate);
+    if outcome.adopted() {
+        fs::write(&out, EvalFileParams::from_params(&outcome.params).to_text())?;
+        println!("adopted: wrote {out}");
+    } else {
+        println!("rejected: the tuned tables did not beat the base");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Square;
+    use crate::piece::PieceKind;
+
+    #[test]
+    fn tables_move_towards_the_results() {
+        let base = EvalParams::default();
+        // 先手の歩が5cにいる局面はいつも先手が勝つ。中盤の局面だけなので中盤の表が大きく動く。
+        let winning = Position::from_sfen("rbsgk/4p/P4/5/KGSBR w - 2").unwrap();
+        let drawn = Position::from_sfen("rbsgk/4p/5/P4/KGSBR b - 3").unwrap();
+        let samples: Vec<Sample> = [(&winning, 1.0), (&drawn, 0.5)]
+            .iter()
+            .cycle()
+            .take(20)
+            .map(|&(position, result)| Sample::new(position, &base, result).unwrap())
+            .collect();
+        let tuned = tune_tables(&samples, &base, 30);
+        let pawn = PieceKind::Pawn.index();
+        let square = pst_square(Color::Black, Square::from_coord("5c").unwrap());
+        assert!(tuned.pst_middlegame[pawn][square] > 20);
+        assert!(tuned.pst_middlegame[pawn][square] > tuned.pst_endgame[pawn][square]);
+        assert!(
+            mean_squared_error(&samples, &tuned, &base)
+                < mean_squared_error(&samples, &base, &base)
+        );
+
+        let config = TuneConfig {
+            games: 2,
+            depth: 1,
+            max_plies: 24,
+            iterations: 2,
+            gate_pairs: 1,
+            ..TuneConfig::default()
+        };
+        let outcome = run_pipeline(&config, &base).expect("pipeline");
+        assert!(outcome.samples > 0);
+        assert_eq!(outcome.gate.games(), 2);
+    }
+}
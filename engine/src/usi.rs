@@ -1,23 +1,665 @@
+use std::collections::VecDeque;
 use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
 use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::board::Square;
+use crate::build_info::engine_info;
+use crate::config::{self, ConfigError, EngineConfig};
+use crate::eval_file::{EvalFileParams, MAX_PIECE_VALUE, PIECE_VALUE_OPTIONS};
+use crate::evaluation::{DEFAULT_PIECE_VALUES, PieceSquareTable};
+use crate::experience::{Experience, ExperienceEntry, LineOutcome, RESULT_PLIES};
+use crate::game::{Game, GameStatus};
+use crate::handicap::Handicap;
+use crate::locale::{Locale, Message};
 use crate::moves::Move;
+use crate::options::{ENGINE_IDENTITY, EngineIdentity, OptionDescriptor, OptionError, OptionValue};
+use crate::piece::{Color, PIECE_KIND_COUNT, PieceKind};
 use crate::position::{Position, PositionError};
-use crate::search::{SearchLimits, Searcher};
+use crate::query_cache::{MoveFilter, PositionQuery, QueryCache};
+use crate::root_split::RootSplitSearcher;
+use crate::rules::ForcedPromotion;
+use crate::search::{
+    DEFAULT_MAX_PLY, InfoSink, LeveledInfoSink, MAX_SEARCH_PLY, MemoryUsage, NullInfoSink,
+    SearchBackend, SearchDetail, SearchInfo, SearchLimits, Searcher, StopToken, Verbosity,
+};
+use crate::see;
+use crate::selftest;
+use crate::table::{Bound, DEFAULT_HASH_MB, SharedTable, TranspositionTable};
 
-pub struct UsiEngine {
+/// 自動先読みの最大深さ。実際には次のコマンドで止められる。
+const AUTO_PONDER_DEPTH: usize = 32;
+
+/// パニックの記録を追記する既定のファイル。
+pub const CRASH_LOG_FILE: &str = "ginko-crash.log";
+
+/// 確定していない `info` 行を出す既定の間隔（ミリ秒）。
+const DEFAULT_INFO_INTERVAL_MS: u64 = 100;
+
+/// コマンドの処理中に `info` 行を書き出す間隔。
+const INFO_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `Threads` に指定できる上限。
+const MAX_THREADS: usize = 64;
+
+/// `USI_Hash` で指定できる最大の大きさ（MiB）。
+const MAX_HASH_MB: usize = 1 << 16;
+
+/// 次の探索に引き継ぐ読み筋の最大の長さ。
+const PV_FOLLOW_LEN: usize = 16;
+
+/// クラッシュログに残す直近のコマンド数。
+const CRASH_HISTORY_LEN: usize = 32;
+
+/// GUIの座標の数え方。5五将棋のGUIには、筋を左から数えるものや段を下から数えるものがある。
+/// エンジン内部の表記（筋は右から1〜5、段は上からa〜e）との変換は鏡映なので、
+/// 同じ `translate` で行きも帰りも変換できる。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoordinateMapping {
+    pub mirror_files: bool,
+    pub mirror_ranks: bool,
+}
+
+impl CoordinateMapping {
+    pub const STANDARD: Self = Self {
+        mirror_files: false,
+        mirror_ranks: false,
+    };
+
+    /// 自動判定で試す順。
+    pub const ALL: [Self; 4] = [
+        Self::STANDARD,
+        Self {
+            mirror_files: true,
+            mirror_ranks: false,
+        },
+        Self {
+            mirror_files: false,
+            mirror_ranks: true,
+        },
+        Self {
+            mirror_files: true,
+            mirror_ranks: true,
+        },
+    ];
+
+    pub fn name(self) -> &'static str {
+        match (self.mirror_files, self.mirror_ranks) {
+            (false, false) => "standard",
+            (true, false) => "mirror-files",
+            (false, true) => "mirror-ranks",
+            (true, true) => "rotated",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mapping| mapping.name() == name)
+    }
+
+    /// 指し手の文字列に含まれるマス（`1a` など）の座標を変換する。
+    pub fn translate(self, token: &str) -> String {
+        let chars: Vec<char> = token.chars().collect();
+        let mut out = String::with_capacity(token.len());
+        let mut i = 0;
+        while i < chars.len() {
+            let (c, next) = (chars[i], chars.get(i + 1).copied());
+            if let Some(rank) = next
+                && ('1'..='5').contains(&c)
+                && ('a'..='e').contains(&rank)
+            {
+                out.push(if self.mirror_files {
+                    (b'6' - (c as u8 - b'0')) as char
+                } else {
+                    c
+                });
+                out.push(if self.mirror_ranks {
+                    (b'e' - (rank as u8 - b'a')) as char
+                } else {
+                    rank
+                });
+                i += 2;
+            } else {
+                out.push(c);
+                i += 1;
+            }
+        }
+        out
+    }
+}
+
+/// 探索器の報告を溜めておく所。`execute` が応答に含め、`run_engine` は探索中にも書き出す。
+type InfoBuffer = Arc<Mutex<Vec<UsiResponse>>>;
+
+/// 探索器の報告を `UsiResponse` にして `InfoBuffer` に溜める。
+struct BufferedInfoSink {
+    coordinates: CoordinateMapping,
+    buffer: InfoBuffer,
+}
+
+impl BufferedInfoSink {
+    fn push(&self, response: UsiResponse) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.push(response);
+        }
+    }
+}
+
+impl InfoSink for BufferedInfoSink {
+    fn info(&mut self, info: &SearchInfo) {
+        self.push(UsiResponse::SearchInfo {
+            info: info.clone(),
+            coordinates: self.coordinates,
+        });
+    }
+
+    fn detail(&mut self, detail: &SearchDetail) {
+        self.push(UsiResponse::SearchDetail {
+            detail: *detail,
+            coordinates: self.coordinates,
+        });
+    }
+}
+
+/// `line` のうち `marker` より後ろの指し手の座標をGUIの数え方に直す。
+fn translate_after(line: String, marker: &str, coordinates: CoordinateMapping) -> String {
+    match line.split_once(marker) {
+        Some((head, moves)) => format!("{head}{marker}{}", coordinates.translate(moves)),
+        None => line,
+    }
+}
+
+/// 探索器に渡す出力先。中身は `UsiEngine` が差し替えるので、オプションが変わっても
+/// 探索器に渡し直さなくてよい。
+#[derive(Clone)]
+struct SharedInfoSink(Arc<Mutex<Box<dyn InfoSink>>>);
+
+impl SharedInfoSink {
+    fn replace(&self, sink: Box<dyn InfoSink>) {
+        if let Ok(mut current) = self.0.lock() {
+            *current = sink;
+        }
+    }
+}
+
+impl InfoSink for SharedInfoSink {
+    fn info(&mut self, info: &SearchInfo) {
+        if let Ok(mut sink) = self.0.lock() {
+            sink.info(info);
+        }
+    }
+
+    fn detail(&mut self, detail: &SearchDetail) {
+        if let Ok(mut sink) = self.0.lock() {
+            sink.detail(detail);
+        }
+    }
+
+    fn wants_detail(&self) -> bool {
+        self.0.lock().is_ok_and(|sink| sink.wants_detail())
+    }
+
+    fn flush(&mut self) {
+        if let Ok(mut sink) = self.0.lock() {
+            sink.flush();
+        }
+    }
+}
+
+/// `info` 行を間引いてから `inner` へ渡す。深さの読みが確定した報告はすぐに渡し、
+/// それ以外は前回から `interval` 経つまで最新の1件だけを溜めておく。
+/// 溜めた報告は次の確定報告で置き換わり、探索の終わりに残っていれば書き出す。
+/// 読んでいる手の報告は、間隔が空いていなければ捨てる。
+pub struct RateLimitedInfoSink<S> {
+    inner: S,
+    interval: Duration,
+    last_sent: Option<Instant>,
+    pending: Option<SearchInfo>,
+}
+
+impl<S: InfoSink> RateLimitedInfoSink<S> {
+    pub fn new(inner: S, interval: Duration) -> Self {
+        Self {
+            inner,
+            interval,
+            last_sent: None,
+            pending: None,
+        }
+    }
+
+    fn send(&mut self, info: &SearchInfo) {
+        self.inner.info(info);
+        self.last_sent = Some(Instant::now());
+    }
+}
+
+impl<S: InfoSink> InfoSink for RateLimitedInfoSink<S> {
+    fn info(&mut self, info: &SearchInfo) {
+        let due = self
+            .last_sent
+            .is_none_or(|sent| sent.elapsed() >= self.interval);
+        if info.complete || due {
+            self.pending = None;
+            self.send(info);
+        } else {
+            self.pending = Some(info.clone());
+        }
+    }
+
+    fn detail(&mut self, detail: &SearchDetail) {
+        if let SearchDetail::CurrentMove { .. } = detail {
+            if self
+                .last_sent
+                .is_some_and(|sent| sent.elapsed() < self.interval)
+            {
+                return;
+            }
+            self.last_sent = Some(Instant::now());
+        }
+        self.inner.detail(detail);
+    }
+
+    fn wants_detail(&self) -> bool {
+        self.inner.wants_detail()
+    }
+
+    fn flush(&mut self) {
+        if let Some(info) = self.pending.take() {
+            self.send(&info);
+        }
+        self.inner.flush();
+    }
+}
+
+/// `UsiEngine::execute` の応答。`Display` でUSIの行に直す。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UsiResponse {
+    /// `id name` と `id author`。
+    Id {
+        name: String,
+        author: String,
+    },
+    Option(OptionDescriptor),
+    UsiOk,
+    ReadyOk,
+    /// `info string` で返す知らせ。
+    Info(String),
+    /// 探索の途中経過。読み筋は `coordinates` の数え方で書く。
+    SearchInfo {
+        info: SearchInfo,
+        coordinates: CoordinateMapping,
+    },
+    /// `Verbosity::Verbose` のときの細かい報告。読んでいる手は `coordinates` の数え方で書く。
+    SearchDetail {
+        detail: SearchDetail,
+        coordinates: CoordinateMapping,
+    },
+    /// 探索にかかった時間。`info time` で返す。
+    Time(Duration),
+    BestMove(String),
+    /// `legalmoves` と `checkstate` の2行。
+    LegalMoves {
+        moves: Vec<String>,
+        in_check: bool,
+    },
+    /// コマンドの失敗。`info string` で返す。
+    Error(String),
+}
+
+impl fmt::Display for UsiResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id { name, author } => write!(f, "id name {name}\nid author {author}"),
+            Self::Option(option) => write!(f, "{}", option.to_usi()),
+            Self::UsiOk => write!(f, "usiok"),
+            Self::ReadyOk => write!(f, "readyok"),
+            Self::Info(text) | Self::Error(text) => write!(f, "info string {text}"),
+            Self::SearchInfo { info, coordinates } => {
+                write!(
+                    f,
+                    "{}",
+                    translate_after(info.to_usi(), " pv ", *coordinates)
+                )
+            }
+            Self::SearchDetail {
+                detail,
+                coordinates,
+            } => write!(
+                f,
+                "{}",
+                translate_after(detail.to_usi(), " currmove ", *coordinates)
+            ),
+            Self::Time(elapsed) => write!(f, "info time {}", elapsed.as_millis()),
+            Self::BestMove(best) => write!(f, "bestmove {best}"),
+            Self::LegalMoves { moves, in_check } => {
+                if moves.is_empty() {
+                    writeln!(f, "legalmoves")?;
+                } else {
+                    writeln!(f, "legalmoves {}", moves.join(" "))?;
+                }
+                write!(f, "checkstate {in_check}")
+            }
+        }
+    }
+}
+
+/// 裏で動いている自動先読み。
+struct PonderJob {
+    stop: StopToken,
+    handle: JoinHandle<()>,
+}
+
+/// USIのコマンドを処理するエンジン本体。探索は `B` で差し替えられる。
+pub struct UsiEngine<B = Searcher> {
     position: Position,
-    searcher: Searcher,
+    searcher: B,
     default_limits: SearchLimits,
+    auto_ponder: bool,
+    /// 探索に使う置換表。`USI_Hash` を受けたときに確保し直すので、GUIが `isready` の前に
+    /// 送る `setoption` で確保が済み、最初の `go` で確保することはない。
+    shared_tt: Arc<SharedTable>,
+    ponder: Option<PonderJob>,
+    crash_log: Option<PathBuf>,
+    coordinates: CoordinateMapping,
+    /// `CoordinateMapping` が `auto` で、まだ座標の数え方が決まっていない。
+    detect_coordinates: bool,
+    /// 確定していない `info` 行を出す最短の間隔。0なら間引かない。
+    info_interval: Duration,
+    /// 探索器と並列探索に渡している出力先。
+    info_sink: SharedInfoSink,
+    /// `set_info_sink` で呼び出し側が出力先を与えた。オプションが変わっても作り直さない。
+    custom_info_sink: bool,
+    /// 書き出していない探索器の報告。
+    info_buffer: InfoBuffer,
+    verbosity: Verbosity,
+    /// `info string` で返す文言の言語。
+    locale: Locale,
+    /// `ExperienceFile` が空でなければ、その読み込み先と対局中に覚えた結果。
+    experience_file: Option<PathBuf>,
+    experience: Experience,
+    /// この対局で自分の手で進んだ序盤の局面。`gameover` で結果とともに経験に足す。
+    game_line: Vec<Position>,
+    /// `Threads` と `RootSplit` の設定。
+    threads: usize,
+    root_split_enabled: bool,
+    /// 2スレッド以上でルート分割が有効なら、`searcher` の代わりに使う並列探索。
+    root_split: Option<RootSplitSearcher>,
+    /// 直前の `position` の開始局面が駒落ちならその手合い。評価関数の駒割りの基準にする。
+    handicap: Option<Handicap>,
+    /// 設定ファイルで与えた値。`usi` に返すオプションの既定値にする。
+    config: EngineConfig,
+    /// `position` の指し手で、成りが強制されるのに `+` のない手を成る手に読み替えるか。
+    forced_promotion: ForcedPromotion,
+    /// GUIが同じ局面に繰り返す `legalmoves` などの問い合わせの答え。
+    queries: QueryCache,
+    /// 次のコマンドの応答の後に `info string` で知らせること。
+    notices: Vec<String>,
+    /// 直前に返した `bestmove` の手。`stop` に同じ手を返す。
+    last_bestmove: Option<String>,
+    /// 直前の `go` の読み筋。各局面のハッシュとそこでの手。
+    previous_pv: Vec<(u64, Move)>,
+    /// `PawnValue` などで設定した駒の価値。
+    piece_values: [i32; PIECE_KIND_COUNT],
+    /// `EvalFile` で読んだ中盤と終盤の駒の位置の表。
+    piece_square_tables: [PieceSquareTable; 2],
+    /// `EvalFile` で与えた評価関数のパラメータファイルと、最後に読んだときの更新時刻と大きさ。
+    eval_file: Option<(PathBuf, Option<(SystemTime, u64)>)>,
+    /// `EvalFileWatch` が有効なら、`isready` と探索の前にファイルの変更を調べて読み直す。
+    watch_eval_file: bool,
 }
 
 impl UsiEngine {
     pub fn new() -> Result<Self, PositionError> {
-        Ok(Self {
+        Self::with_backend(Searcher::new())
+    }
+}
+
+impl<B> UsiEngine<B> {
+    fn stop_ponder(&mut self) {
+        if let Some(job) = self.ponder.take() {
+            job.stop.stop();
+            let _ = job.handle.join();
+        }
+    }
+}
+
+impl<B: SearchBackend + Default + 'static> UsiEngine<B> {
+    pub fn with_backend(searcher: B) -> Result<Self, PositionError> {
+        let mut engine = Self {
             position: Position::initial()?,
-            searcher: Searcher::new(),
+            searcher,
             default_limits: SearchLimits::default(),
-        })
+            auto_ponder: false,
+            shared_tt: Arc::new(SharedTable::new()),
+            ponder: None,
+            crash_log: None,
+            coordinates: CoordinateMapping::STANDARD,
+            detect_coordinates: true,
+            info_interval: Duration::from_millis(DEFAULT_INFO_INTERVAL_MS),
+            info_sink: SharedInfoSink(Arc::new(Mutex::new(Box::new(NullInfoSink)))),
+            custom_info_sink: false,
+            info_buffer: Arc::default(),
+            verbosity: Verbosity::Normal,
+            locale: Locale::English,
+            experience_file: None,
+            experience: Experience::new(),
+            game_line: Vec::new(),
+            threads: 1,
+            root_split_enabled: false,
+            root_split: None,
+            handicap: None,
+            config: EngineConfig::default(),
+            forced_promotion: ForcedPromotion::AutoCorrect,
+            queries: QueryCache::default(),
+            notices: Vec::new(),
+            last_bestmove: None,
+            previous_pv: Vec::new(),
+            piece_values: DEFAULT_PIECE_VALUES,
+            piece_square_tables: {
+                let defaults = EvalFileParams::default();
+                [defaults.pst_middlegame, defaults.pst_endgame]
+            },
+            eval_file: None,
+            watch_eval_file: false,
+        };
+        let sink = engine.info_sink.clone();
+        engine.searcher.set_info_sink(Box::new(sink));
+        engine
+            .searcher
+            .set_transposition_table(Box::new(Arc::clone(&engine.shared_tt)));
+        engine.install_info_sink();
+        Ok(engine)
+    }
+
+    pub fn coordinates(&self) -> CoordinateMapping {
+        self.coordinates
+    }
+
+    fn set_coordinates(&mut self, mapping: CoordinateMapping) {
+        self.coordinates = mapping;
+        self.install_info_sink();
+    }
+
+    /// 探索の報告を `sink` に渡す。以後は `CoordinateMapping` などを変えても差し替えない。
+    pub fn set_info_sink(&mut self, sink: Box<dyn InfoSink>) {
+        self.custom_info_sink = true;
+        self.info_sink.replace(sink);
+    }
+
+    /// 溜まっている探索器の報告を取り出す。
+    pub fn take_search_info(&mut self) -> Vec<UsiResponse> {
+        drain_info_buffer(&self.info_buffer)
+    }
+
+    /// 座標の数え方、報告量と出力間隔に合わせて、探索器の出力先を作り直す。
+    /// 呼び出し側が与えた出力先はそのまま使う。
+    fn install_info_sink(&mut self) {
+        if !self.custom_info_sink {
+            self.info_sink.replace(self.usi_info_sink());
+        }
+    }
+
+    fn usi_info_sink(&self) -> Box<dyn InfoSink> {
+        if self.verbosity == Verbosity::Silent {
+            return Box::new(NullInfoSink);
+        }
+        let sink = LeveledInfoSink::new(
+            BufferedInfoSink {
+                coordinates: self.coordinates,
+                buffer: Arc::clone(&self.info_buffer),
+            },
+            self.verbosity,
+        );
+        if self.info_interval.is_zero() {
+            Box::new(sink)
+        } else {
+            Box::new(RateLimitedInfoSink::new(sink, self.info_interval))
+        }
+    }
+
+    /// `Threads` と `RootSplit` に合わせて並列探索を作り直す。
+    fn update_root_split(&mut self) {
+        self.root_split = (self.root_split_enabled && self.threads > 1).then(|| {
+            let mut root_split = RootSplitSearcher::new(self.threads);
+            root_split.set_shared_table(Arc::clone(&self.shared_tt));
+            root_split.set_info_sink(Box::new(self.info_sink.clone()));
+            root_split.set_handicap(self.handicap);
+            root_split.set_piece_values(self.piece_values);
+            let [middlegame, endgame] = &self.piece_square_tables;
+            root_split.set_piece_square_tables(middlegame, endgame);
+            root_split
+        });
+    }
+
+    /// 開始局面から駒落ちの手合いを判定し、変わっていれば評価関数に伝える。
+    fn detect_handicap(&mut self) {
+        let handicap = Handicap::detect(&self.position);
+        if handicap != self.handicap {
+            self.handicap = handicap;
+            self.searcher.set_handicap(handicap);
+            if let Some(root_split) = &mut self.root_split {
+                root_split.set_handicap(handicap);
+            }
+        }
+    }
+
+    /// エンジン内部の指し手の表記をGUIの座標に直す。
+    fn to_gui(&self, token: &str) -> String {
+        self.coordinates.translate(token)
+    }
+
+    /// パニックを記録するファイルを変える。`None` なら記録しない。
+    pub fn set_crash_log(&mut self, path: Option<PathBuf>) {
+        self.crash_log = path;
+    }
+
+    /// 実際に確保した置換表の大きさ（MiB）。`USI_Hash` の値より小さいことがある。
+    pub fn hash_megabytes(&self) -> usize {
+        self.shared_tt.megabytes()
+    }
+
+    /// 最初の探索の前に済ませておく準備。`isready` で `readyok` を返す前に呼び、
+    /// `isready` を送らないGUIのために探索の前にも呼ぶ。今は変わった `EvalFile` を読み直す。
+    pub fn warm_up(&mut self) {
+        if self.watch_eval_file
+            && let Some((path, stamp)) = &self.eval_file
+            && file_stamp(path) != *stamp
+        {
+            let notice = match self.reload_eval() {
+                Ok(path) => format!("reloaded eval file {}", path.display()),
+                Err(err) => format!("failed to reload eval file: {err}"),
+            };
+            self.notices.push(notice);
+        }
+    }
+
+    /// 置換表・探索器・経験ファイルのおおよそのメモリ。
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let backend = match &self.root_split {
+            Some(root_split) => root_split.memory_usage(),
+            None => self.searcher.memory_usage(),
+        };
+        MemoryUsage {
+            book: backend.book + self.experience.bytes(),
+            ..backend
+        }
+    }
+
+    fn set_piece_values(&mut self, values: [i32; PIECE_KIND_COUNT]) {
+        self.piece_values = values;
+        self.searcher.set_piece_values(values);
+        if let Some(root_split) = &mut self.root_split {
+            root_split.set_piece_values(values);
+        }
+    }
+
+    fn set_piece_square_tables(&mut self, tables: [PieceSquareTable; 2]) {
+        self.piece_square_tables = tables;
+        let [middlegame, endgame] = &self.piece_square_tables;
+        self.searcher.set_piece_square_tables(middlegame, endgame);
+        if let Some(root_split) = &mut self.root_split {
+            root_split.set_piece_square_tables(middlegame, endgame);
+        }
+    }
+
+    /// 評価関数のパラメータファイルを読む。空なら読まず、今の値を残す。
+    fn set_eval_file(&mut self, path: &str) -> Result<(), ConfigError> {
+        if path.is_empty() {
+            self.eval_file = None;
+            return Ok(());
+        }
+        self.eval_file = Some((PathBuf::from(path), None));
+        self.reload_eval().map(|_| ())
+    }
+
+    /// `EvalFile` を読み直し、書かれていない駒の価値と駒の位置の表は既定値に戻す。
+    /// 置換表と対局の状態は残す。
+    ///
+    /// ファイルの書式は `eval_file` を参照。読めなければ今の値を残す。読んだファイルを返す。
+    /// 読めなくても更新時刻は覚え、`EvalFileWatch` が同じ壊れたファイルを読み直し続けないようにする。
+    pub fn reload_eval(&mut self) -> Result<PathBuf, ConfigError> {
+        let Some((path, _)) = &self.eval_file else {
+            return Err(ConfigError {
+                line: 0,
+                message: "EvalFile is not set".to_string(),
+            });
+        };
+        let path = path.clone();
+        self.eval_file = Some((path.clone(), file_stamp(&path)));
+        let params = EvalFileParams::load(&path)?;
+        self.set_piece_values(params.piece_values);
+        self.set_piece_square_tables([params.pst_middlegame, params.pst_endgame]);
+        Ok(path)
+    }
+
+    /// `megabytes` MiBの置換表を確保し直す。足りなければ小さくして、そのことを知らせる。
+    /// まったく確保できなければ今の表を残す。
+    fn set_hash(&mut self, megabytes: usize) {
+        self.stop_ponder();
+        match SharedTable::with_megabytes(megabytes) {
+            Ok(table) => self.shared_tt = Arc::new(table),
+            Err(err) => {
+                self.notices.push(err.to_string());
+                return;
+            }
+        }
+        let allocated = self.hash_megabytes();
+        if allocated < megabytes {
+            self.notices.push(format!(
+                "USI_Hash {megabytes} MB could not be allocated, using {allocated} MB"
+            ));
+        }
+        self.searcher
+            .set_transposition_table(Box::new(Arc::clone(&self.shared_tt)));
+        if let Some(root_split) = &mut self.root_split {
+            root_split.set_shared_table(Arc::clone(&self.shared_tt));
+        }
     }
 
     fn reset(&mut self) -> Result<(), PositionError> {
@@ -25,7 +667,79 @@ impl UsiEngine {
         Ok(())
     }
 
-    fn parse_position(&mut self, tokens: &[&str]) -> Result<(), PositionError> {
+    fn new_game(&mut self) -> Result<(), PositionError> {
+        self.stop_ponder();
+        self.shared_tt.clear();
+        self.game_line.clear();
+        self.previous_pv.clear();
+        self.reset()
+    }
+
+    /// 経験ファイルを読み込んで使い始める。空文字列なら使わない。
+    fn set_experience_file(&mut self, path: &str) -> io::Result<()> {
+        if path.is_empty() {
+            self.experience_file = None;
+            self.experience = Experience::new();
+            return Ok(());
+        }
+        let path = PathBuf::from(path);
+        self.experience = Experience::load(&path)?;
+        self.experience_file = Some(path);
+        Ok(())
+    }
+
+    /// `gameover` を受けて、対局中に覚えた結果と序盤の勝敗を経験ファイルへ書き出す。
+    fn game_over(&mut self, outcome: Option<LineOutcome>) -> io::Result<()> {
+        self.stop_ponder();
+        let line = std::mem::take(&mut self.game_line);
+        if let Some(outcome) = outcome {
+            self.experience.record_result(&line, outcome);
+        }
+        match &self.experience_file {
+            Some(path) => self.experience.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// `AutoPonder` が有効なら、予想される相手の応手の後の局面（読めていなければ現局面）を
+    /// 共有置換表を使って裏で探索し始める。
+    fn start_auto_ponder(&mut self) {
+        if !self.auto_ponder {
+            return;
+        }
+        self.stop_ponder();
+        let mut target = self.position.clone();
+        if let Some(reply) = self.searcher.principal_variation(&self.position, 1).first()
+            && target.play_move_mut(reply).is_err()
+        {
+            target = self.position.clone();
+        }
+        let stop = StopToken::new();
+        let job_stop = stop.clone();
+        let table = Arc::clone(&self.shared_tt);
+        let handicap = self.handicap;
+        let piece_values = self.piece_values;
+        let [middlegame, endgame] = self.piece_square_tables;
+        let handle = thread::spawn(move || {
+            let mut searcher = B::default();
+            searcher.set_handicap(handicap);
+            searcher.set_piece_values(piece_values);
+            searcher.set_piece_square_tables(&middlegame, &endgame);
+            searcher.set_info_sink(Box::new(NullInfoSink));
+            searcher.set_transposition_table(Box::new(table));
+            searcher.set_keep_tt(true);
+            let limits = SearchLimits {
+                depth: AUTO_PONDER_DEPTH,
+                ..SearchLimits::default()
+            };
+            let _ = searcher.search_with_stop(&target, limits, &job_stop);
+        });
+        self.ponder = Some(PonderJob { stop, handle });
+    }
+
+    /// 局面を設定し、その局面での対局の状態を返す。
+    fn parse_position(&mut self, tokens: &[&str]) -> Result<GameStatus, PositionError> {
+        self.stop_ponder();
         if tokens.is_empty() {
             return Err(PositionError::Format("position requires arguments"));
         }
@@ -36,33 +750,62 @@ impl UsiEngine {
                 idx += 1;
             }
             "sfen" => {
-                if tokens.len() < idx + 5 {
+                // GUIによっては手数を省くので、`moves` までの3〜4欄をSFENとして緩く読む。
+                let fields = tokens[idx + 1..]
+                    .iter()
+                    .take(4)
+                    .take_while(|&&token| token != "moves")
+                    .count();
+                if fields < 3 {
                     return Err(PositionError::Format("invalid sfen command"));
                 }
-                let sfen = tokens[idx + 1..idx + 5].join(" ");
+                let sfen = tokens[idx + 1..idx + 1 + fields].join(" ");
                 self.position = Position::from_sfen(&sfen)?;
-                idx += 5;
+                idx += 1 + fields;
             }
             _ => return Err(PositionError::Format("unknown position command")),
         }
+        self.detect_handicap();
 
-        if idx < tokens.len() && tokens[idx] == "moves" {
-            idx += 1;
-            while idx < tokens.len() {
-                let mv = self.find_legal_move(tokens[idx])?;
-                self.position.play_move_mut(&mv)?;
-                idx += 1;
+        let moves = if idx < tokens.len() && tokens[idx] == "moves" {
+            &tokens[idx + 1..]
+        } else {
+            &[][..]
+        };
+        if self.detect_coordinates && !moves.is_empty() {
+            // 指し手がすべて合法になる数え方が1つだけなら、それに固定する。
+            // 複数あるときは決めずに、今の数え方（合わなければ合う最初の数え方）で読み、
+            // 次の `position` で調べ直す。
+            let matching: Vec<CoordinateMapping> = CoordinateMapping::ALL
+                .into_iter()
+                .filter(|&mapping| {
+                    let mut game = Game::new(self.position.clone());
+                    moves.iter().all(|token| {
+                        game.play_usi_with(&mapping.translate(token), self.forced_promotion)
+                            .is_ok()
+                    })
+                })
+                .collect();
+            match matching[..] {
+                [mapping] => {
+                    self.set_coordinates(mapping);
+                    self.detect_coordinates = false;
+                }
+                [first, ..] if !matching.contains(&self.coordinates) => {
+                    self.set_coordinates(first);
+                }
+                _ => {}
             }
         }
-        Ok(())
-    }
 
-    fn find_legal_move(&self, token: &str) -> Result<Move, PositionError> {
-        let legal_moves = self.position.generate_legal_moves()?;
-        legal_moves
-            .into_iter()
-            .find(|mv| mv.to_usi() == token)
-            .ok_or_else(|| PositionError::message(format!("illegal move: {token}")))
+        let mut game = Game::new(self.position.clone());
+        for token in moves {
+            game.play_usi_with(&self.coordinates.translate(token), self.forced_promotion)?;
+        }
+        self.position = game.position().clone();
+        // 全合法手と王手を求める `query` は重いので、合法手を1つ見つけた時点で打ち切る
+        // `Game::status` で調べる。
+        game.status()
     }
 
     fn parse_go_limits(&self, args: &[&str]) -> SearchLimits {
@@ -71,118 +814,1332 @@ impl UsiEngine {
         let mut iter = args.iter();
         while let Some(&token) = iter.next() {
             if token.eq_ignore_ascii_case("depth") {
-                if let Some(&value) = iter.next() {
-                    if let Ok(parsed) = value.parse::<usize>() {
-                        depth = Some(parsed.max(1));
-                    }
-                }
-            } else if token.eq_ignore_ascii_case("random") {
-                if let Some(&value) = iter.next() {
-                    if let Ok(parsed) = value.parse::<i32>() {
-                        randomness = Some(parsed.max(0));
-                    }
+                if let Some(parsed) = iter.next().and_then(|v| v.parse::<usize>().ok()) {
+                    depth = Some(parsed.max(1));
                 }
+            } else if token.eq_ignore_ascii_case("random")
+                && let Some(parsed) = iter.next().and_then(|v| v.parse::<i32>().ok())
+            {
+                randomness = Some(parsed.max(0));
             }
         }
         SearchLimits {
             depth: depth.unwrap_or(self.default_limits.depth),
             randomness: randomness.unwrap_or(self.default_limits.randomness),
+            ..self.default_limits
         }
     }
 
-    fn legal_moves(&self) -> Result<(Vec<String>, bool), PositionError> {
-        let moves = self.position.generate_legal_moves()?;
-        let move_strings = moves.into_iter().map(|mv| mv.to_usi()).collect();
-        let in_check = self.position.is_in_check(self.position.side_to_move());
-        Ok((move_strings, in_check))
+    pub fn identity(&self) -> EngineIdentity {
+        ENGINE_IDENTITY
     }
 
-    fn go(&mut self, args: &[&str]) -> Result<String, PositionError> {
-        let limits = self.parse_go_limits(args);
-        let result = self.searcher.search(&self.position, limits)?;
-        if let Some(best) = result.best_move {
-            let move_txt = best.to_usi();
-            self.position.play_move_mut(&best)?;
-            Ok(move_txt)
-        } else {
-            Ok("resign".to_string())
+    /// 設定できるオプションの一覧。既定値は現在の値ではなく、設定ファイルを反映した起動時の値。
+    pub fn options(&self) -> Vec<OptionDescriptor> {
+        let mut options = vec![
+            OptionDescriptor::spin("USI_Hash", DEFAULT_HASH_MB as i64, 1, MAX_HASH_MB as i64),
+            OptionDescriptor::check("USI_AnalyseMode", false),
+            OptionDescriptor::check("TTInstantMove", false),
+            OptionDescriptor::check("AutoPonder", false),
+            OptionDescriptor::spin("RandomPlies", 0, 0, 1_000),
+            OptionDescriptor::spin("MaxPly", DEFAULT_MAX_PLY as i64, 1, MAX_SEARCH_PLY as i64),
+            OptionDescriptor::check("VerifyPv", false),
+            OptionDescriptor::spin("EvalNoise", 0, 0, 50),
+            OptionDescriptor::spin("EvalNoiseSeed", 0, 0, u32::MAX as i64),
+            OptionDescriptor::spin("TrapMargin", 0, 0, 1_000),
+            OptionDescriptor::combo(
+                "CoordinateMapping",
+                "auto",
+                &[
+                    "auto",
+                    "standard",
+                    "mirror-files",
+                    "mirror-ranks",
+                    "rotated",
+                ],
+            ),
+            OptionDescriptor::string("ExperienceFile", ""),
+            OptionDescriptor::string("EvalFile", ""),
+            OptionDescriptor::check("EvalFileWatch", false),
+            OptionDescriptor::combo(
+                "Verbosity",
+                Verbosity::Normal.name(),
+                &Verbosity::ALL.map(Verbosity::name),
+            ),
+            OptionDescriptor::combo(
+                "Locale",
+                Locale::English.name(),
+                &Locale::ALL.map(Locale::name),
+            ),
+            OptionDescriptor::combo(
+                "ForcedPromotion",
+                ForcedPromotion::AutoCorrect.name(),
+                &ForcedPromotion::ALL.map(ForcedPromotion::name),
+            ),
+            OptionDescriptor::spin("InfoInterval", DEFAULT_INFO_INTERVAL_MS as i64, 0, 10_000),
+            OptionDescriptor::spin("Threads", 1, 1, MAX_THREADS as i64),
+            OptionDescriptor::check("RootSplit", false),
+        ];
+        options.extend(PIECE_VALUE_OPTIONS.map(|(name, kind)| {
+            OptionDescriptor::spin(
+                name,
+                DEFAULT_PIECE_VALUES[kind.index()] as i64,
+                0,
+                MAX_PIECE_VALUE as i64,
+            )
+        }));
+        options
+            .into_iter()
+            .map(|option| {
+                match self
+                    .config
+                    .get(&option.name)
+                    .and_then(|value| option.parse_value(value).ok())
+                {
+                    Some(value) => option.with_default(&value),
+                    None => option,
+                }
+            })
+            .collect()
+    }
+
+    /// 設定ファイルの値を書かれた順に設定し、以後の `usi` でそれを既定値として返す。
+    pub fn apply_config(&mut self, config: &EngineConfig) -> Result<(), OptionError> {
+        for (name, value) in &config.options {
+            self.set_option(name, value)?;
         }
+        self.config = config.clone();
+        Ok(())
     }
-}
 
-pub fn run() -> Result<(), Box<dyn Error>> {
-    let stdin = io::stdin();
-    let mut engine = UsiEngine::new()?;
-    let mut last_bestmove: Option<String> = None;
+    /// オプションを検証してから設定する。
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        let descriptor = self
+            .options()
+            .into_iter()
+            .find(|option| option.name == name)
+            .ok_or_else(|| OptionError::Unknown(name.to_string()))?;
+        let parsed = descriptor.parse_value(value)?;
+        if let (Some(&(_, kind)), OptionValue::Spin(value)) = (
+            PIECE_VALUE_OPTIONS
+                .iter()
+                .find(|(option, _)| *option == name),
+            &parsed,
+        ) {
+            let mut values = self.piece_values;
+            values[kind.index()] = *value as i32;
+            self.set_piece_values(values);
+            return Ok(());
+        }
+        match (name, parsed) {
+            ("USI_Hash", OptionValue::Spin(megabytes)) => self.set_hash(megabytes as usize),
+            ("USI_AnalyseMode", OptionValue::Check(enabled)) => {
+                self.default_limits.analysis = enabled;
+            }
+            ("TTInstantMove", OptionValue::Check(enabled)) => {
+                self.default_limits.tt_instant_move = enabled;
+            }
+            ("RandomPlies", OptionValue::Spin(plies)) => {
+                self.default_limits.random_plies = plies as u32;
+            }
+            ("MaxPly", OptionValue::Spin(plies)) => {
+                self.default_limits.max_ply = plies as usize;
+            }
+            ("VerifyPv", OptionValue::Check(enabled)) => {
+                self.default_limits.verify_pv = enabled;
+            }
+            ("EvalNoise", OptionValue::Spin(noise)) => {
+                self.default_limits.eval_noise = noise as i32;
+            }
+            ("EvalNoiseSeed", OptionValue::Spin(seed)) => {
+                self.default_limits.eval_noise_seed = seed as u64;
+            }
+            ("TrapMargin", OptionValue::Spin(margin)) => {
+                self.default_limits.trap_margin = margin as i32;
+            }
+            ("AutoPonder", OptionValue::Check(enabled)) => {
+                self.stop_ponder();
+                self.auto_ponder = enabled;
+                self.searcher.set_keep_tt(enabled);
+            }
+            ("CoordinateMapping", OptionValue::String(value)) => {
+                self.detect_coordinates = value == "auto";
+                let mapping =
+                    CoordinateMapping::from_name(&value).unwrap_or(CoordinateMapping::STANDARD);
+                self.set_coordinates(mapping);
+            }
+            ("Verbosity", OptionValue::String(value)) => {
+                self.verbosity = Verbosity::from_name(&value).unwrap_or_default();
+                self.install_info_sink();
+            }
+            ("Locale", OptionValue::String(value)) => {
+                self.locale = Locale::from_name(&value).unwrap_or_default();
+            }
+            ("ForcedPromotion", OptionValue::String(value)) => {
+                self.forced_promotion =
+                    ForcedPromotion::from_name(&value).unwrap_or(ForcedPromotion::AutoCorrect);
+            }
+            ("InfoInterval", OptionValue::Spin(ms)) => {
+                self.info_interval = Duration::from_millis(ms as u64);
+                self.install_info_sink();
+            }
+            ("Threads", OptionValue::Spin(threads)) => {
+                self.threads = threads as usize;
+                self.update_root_split();
+            }
+            ("RootSplit", OptionValue::Check(enabled)) => {
+                self.root_split_enabled = enabled;
+                self.update_root_split();
+            }
+            ("ExperienceFile", OptionValue::String(value)) => {
+                let value = if value == "<empty>" { "" } else { &value };
+                self.set_experience_file(value)
+                    .map_err(|_| OptionError::InvalidValue {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })?;
+            }
+            ("EvalFile", OptionValue::String(value)) => {
+                let value = if value == "<empty>" { "" } else { &value };
+                if let Err(err) = self.set_eval_file(value) {
+                    self.notices
+                        .push(format!("failed to load eval file {value}: {err}"));
+                    return Err(OptionError::InvalidValue {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    });
+                }
+            }
+            ("EvalFileWatch", OptionValue::Check(enabled)) => {
+                self.watch_eval_file = enabled;
+            }
+            _ => return Err(OptionError::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
 
-    for line in stdin.lock().lines() {
-        let line = line?;
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
+    fn setoption_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let (name, value) = parse_setoption(args)?;
+        self.set_option(&name, &value)
+            .map_err(|err| err.to_string())
+    }
+
+    /// 今の局面の合法手・王手になる手・詰みの判定。同じ局面への問い合わせはキャッシュから返す。
+    pub fn query(&mut self) -> Result<&PositionQuery, PositionError> {
+        self.queries.query(&self.position)
+    }
+
+    /// `filters` に当てはまる合法手をGUIの座標で返す。王手されているかも返す。
+    fn legal_moves(
+        &mut self,
+        filters: &[MoveFilter],
+    ) -> Result<(Vec<String>, bool), PositionError> {
+        let coordinates = self.coordinates;
+        let query = self.queries.query(&self.position)?;
+        let move_strings = query
+            .matching(filters)
+            .iter()
+            .map(|mv| coordinates.translate(&mv.to_usi()))
+            .collect();
+        Ok((move_strings, query.in_check))
+    }
+
+    /// `legalmoves` の引数を絞り込みの条件にする。マスはGUIの座標で書く。
+    ///
+    /// `from <マス>`、`drops [駒]`、`captures` を並べると、すべてに当てはまる手だけを返す。
+    fn parse_move_filters(&self, args: &[&str]) -> Result<Vec<MoveFilter>, String> {
+        let mut filters = Vec::new();
+        let mut iter = args.iter().peekable();
+        while let Some(&token) = iter.next() {
+            let filter = match token {
+                "from" => {
+                    let square = iter
+                        .next()
+                        .and_then(|coord| Square::from_coord(&self.coordinates.translate(coord)))
+                        .ok_or("from requires a square")?;
+                    MoveFilter::From(square)
+                }
+                "drops" => {
+                    let kind = iter.peek().and_then(|piece| {
+                        let mut chars = piece.chars();
+                        let kind = PieceKind::from_drop_char(chars.next()?);
+                        if chars.next().is_some() { None } else { kind }
+                    });
+                    if kind.is_some() {
+                        iter.next();
+                    }
+                    MoveFilter::Drops(kind)
+                }
+                "captures" => MoveFilter::Captures,
+                other => return Err(format!("unknown filter: {other}")),
+            };
+            filters.push(filter);
         }
-        let mut parts = trimmed.split_whitespace();
-        let command = parts.next().unwrap();
+        Ok(filters)
+    }
+
+    /// `eval` 拡張コマンド。評価値の内訳を先手から見た値で返す。
+    fn eval_command(&self) -> String {
+        match self.searcher.eval_breakdown(&self.position) {
+            Some(breakdown) => format!("eval {breakdown}"),
+            None => "eval unavailable".to_string(),
+        }
+    }
+
+    /// `see <move>` 拡張コマンド。
+    fn see_command(&self, args: &[&str]) -> Result<String, PositionError> {
+        let token = args
+            .first()
+            .ok_or_else(|| PositionError::message("see requires a move"))?;
+        let mv = self
+            .position
+            .parse_usi_move(&self.coordinates.translate(token))?;
+        Ok(format!(
+            "see {token} {}",
+            see::static_exchange(&self.position, &mv)
+        ))
+    }
+
+    /// `probe` 拡張コマンド。現局面の置換表エントリを返す。
+    fn probe_command(&self) -> String {
+        let Some(entry) = self.searcher.probe_tt(&self.position) else {
+            return "probe none".to_string();
+        };
+        let bound = match entry.bound {
+            Bound::Exact => "exact",
+            Bound::Lower => "lower",
+            Bound::Upper => "upper",
+        };
+        let best = entry
+            .best_move
+            .map_or("none".to_string(), |mv| self.to_gui(&mv.to_usi()));
+        format!(
+            "probe depth {} score {} bound {bound} move {best}",
+            entry.depth, entry.score
+        )
+    }
+
+    /// `attacks [black|white]` 拡張コマンド。省略時は手番側の利きを描く。
+    fn attacks_command(&self, args: &[&str]) -> Result<String, PositionError> {
+        let color = match args.first().copied() {
+            None => self.position.side_to_move(),
+            Some("black") => Color::Black,
+            Some("white") => Color::White,
+            Some(other) => {
+                return Err(PositionError::message(format!("unknown color: {other}")));
+            }
+        };
+        Ok(self.position.render_attack_map(color))
+    }
+
+    /// 直前の読み筋が今の局面を通っていれば、そこから先の予想手順。
+    fn expected_line(&self) -> Vec<Move> {
+        let key = self.position.zobrist_key();
+        self.previous_pv
+            .iter()
+            .position(|&(expected, _)| expected == key)
+            .map_or_else(Vec::new, |start| {
+                self.previous_pv[start..]
+                    .iter()
+                    .map(|&(_, mv)| mv)
+                    .collect()
+            })
+    }
+
+    /// 読み筋 `pv` を、次の探索で辿れるように各局面のハッシュと組にして残す。
+    /// 経験ファイルの `entry` の手を探索せずに指してよいか。記録が要求深さ以上で、検討中でなく、
+    /// 負け越した局面へ進む手でも千日手に絡む手でもないこと。どちらの記録も対局の履歴を
+    /// 見ないので、繰り返しは今の対局で確かめる。
+    fn replays_experience(
+        &self,
+        entry: &ExperienceEntry,
+        limits: &SearchLimits,
+        penalties: &[(Move, i32)],
+    ) -> Result<bool, PositionError> {
+        if entry.depth < limits.depth || limits.analysis {
+            return Ok(false);
+        }
+        if penalties.iter().any(|&(mv, _)| mv == entry.best_move) {
+            return Ok(false);
+        }
+        Ok(!self.repeats_with(entry.best_move)?)
+    }
+
+    /// 今の局面がすでに繰り返されているか、`mv` を指すと前に現れた局面に戻るか。
+    /// 経験ファイルは対局の履歴を見ないので、このときは記録された手をそのまま指さない。
+    fn repeats_with(&self, mv: Move) -> Result<bool, PositionError> {
+        if self.position.current_repetition_count() > 1 {
+            return Ok(true);
+        }
+        Ok(self.position.play_move(&mv)?.current_repetition_count() > 1)
+    }
+
+    fn remember_pv(&mut self, pv: &[Move]) {
+        self.previous_pv.clear();
+        let mut current = self.position.clone_without_history();
+        for &mv in pv {
+            let key = current.zobrist_key();
+            if current.play_move_mut(&mv).is_err() {
+                break;
+            }
+            self.previous_pv.push((key, mv));
+        }
+    }
+
+    /// 探索して最善手のUSI表記と探索にかかった時間を返す。
+    /// 経験ファイルに要求深さ以上の結果があれば探索せずにその手を指し、
+    /// 浅い結果か、千日手が絡む局面ならその手から読む。過去に負け越した序盤の局面へ進む手は評価値を下げる。
+    /// 直前の読み筋どおりに進んでいれば、その続きを先に読む。
+    fn go(&mut self, args: &[&str]) -> Result<(String, Duration), PositionError> {
+        self.stop_ponder();
+        self.warm_up();
+        let mut limits = self.parse_go_limits(args);
+        let penalties = self.experience.lost_line_penalties(&self.position);
+        if let Some(entry) = self.experience.probe(&self.position) {
+            if self.replays_experience(&entry, &limits, &penalties)? {
+                let move_txt = self.to_gui(&entry.best_move.to_usi());
+                self.play_own_move(entry.best_move)?;
+                return Ok((move_txt, Duration::ZERO));
+            }
+            limits.root_hint = Some(entry.best_move);
+        }
+        let expected = self.expected_line();
+        if limits.root_hint.is_none() {
+            limits.root_hint = expected.first().copied();
+        }
+        let penalties = if limits.analysis {
+            Vec::new()
+        } else {
+            penalties
+        };
+        self.searcher
+            .set_root_penalties(&self.position, penalties.clone());
+        self.searcher.set_expected_line(&self.position, &expected);
+        if let Some(root_split) = &mut self.root_split {
+            root_split.set_root_penalties(&self.position, penalties);
+            root_split.set_expected_line(&self.position, &expected);
+        }
+        let (result, pv) = match &mut self.root_split {
+            Some(root_split) => {
+                let result = root_split.search(&self.position, limits)?;
+                (
+                    result,
+                    root_split.principal_variation(&self.position, PV_FOLLOW_LEN),
+                )
+            }
+            None => {
+                let result = self.searcher.search(&self.position, limits)?;
+                let pv = self
+                    .searcher
+                    .principal_variation(&self.position, PV_FOLLOW_LEN);
+                (result, pv)
+            }
+        };
+        self.remember_pv(&pv);
+        if self.experience_file.is_some()
+            && let Some(best) = result.best_move
+        {
+            self.experience
+                .record(&self.position, best, result.score.value(), result.depth);
+        }
+        if let Some(best) = result.best_move {
+            let move_txt = self.to_gui(&best.to_usi());
+            self.play_own_move(best)?;
+            Ok((move_txt, result.elapsed))
+        } else {
+            Ok(("resign".to_string(), result.elapsed))
+        }
+    }
+
+    /// 1行のコマンドを処理して応答を返す。標準入出力のループはこれを行に直して書くだけ。
+    /// まだ取り出されていない探索器の報告を先頭に、処理の後にたまった知らせを最後の `Info` に
+    /// 入れて返す。空行と `quit` には何も返さない。
+    pub fn execute(&mut self, command: &str) -> Vec<UsiResponse> {
+        let mut parts = command.split_whitespace();
+        let Some(command) = parts.next() else {
+            return Vec::new();
+        };
         let args: Vec<&str> = parts.collect();
+        let handled = self.execute_command(command, &args);
+        let mut responses = self.take_search_info();
+        responses.extend(handled);
+        responses.extend(self.notices.drain(..).map(UsiResponse::Info));
+        responses
+    }
 
+    fn execute_command(&mut self, command: &str, args: &[&str]) -> Vec<UsiResponse> {
+        let locale = self.locale;
+        let error = |message: Message, err: &dyn fmt::Display| {
+            UsiResponse::Error(format!("{}: {err}", locale.text(message)))
+        };
         match command {
             "usi" => {
-                println!("id name Ginko5x5");
-                println!("id author AkaakuHub");
-                println!("usiok");
+                let info = engine_info();
+                let mut responses = vec![
+                    UsiResponse::Id {
+                        name: format!("{} {}", info.identity.name, info.version),
+                        author: info.identity.author.to_string(),
+                    },
+                    UsiResponse::Info(format!("build {info}")),
+                ];
+                responses.extend(self.options().into_iter().map(UsiResponse::Option));
+                responses.push(UsiResponse::UsiOk);
+                responses
             }
             "isready" => {
-                println!("readyok");
+                self.warm_up();
+                let mut responses: Vec<UsiResponse> =
+                    self.notices.drain(..).map(UsiResponse::Info).collect();
+                responses.push(UsiResponse::Info(format!("memory {}", self.memory_usage())));
+                responses.push(UsiResponse::ReadyOk);
+                responses
             }
-            "usinewgame" => {
-                engine.reset()?;
-            }
-            "position" => {
-                if let Err(err) = engine.parse_position(&args) {
-                    println!("info string position error: {err}");
+            "usinewgame" => match self.new_game() {
+                Ok(()) => Vec::new(),
+                Err(err) => vec![error(Message::PositionError, &err)],
+            },
+            "gameover" => {
+                let outcome = args.first().and_then(|token| LineOutcome::from_usi(token));
+                match self.game_over(outcome) {
+                    Ok(()) => Vec::new(),
+                    Err(err) => vec![UsiResponse::Error(format!(
+                        "failed to write experience file: {err}"
+                    ))],
                 }
             }
-            "legalmoves" => match engine.legal_moves() {
-                Ok((moves, in_check)) => {
-                    if moves.is_empty() {
-                        println!("legalmoves");
-                    } else {
-                        println!("legalmoves {}", moves.join(" "));
-                    }
-                    println!("checkstate {}", if in_check { "true" } else { "false" });
-                }
-                Err(err) => {
-                    println!("info string legalmoves error: {err}");
-                }
+            "position" => match self.parse_position(args) {
+                Ok(status) if status.is_over() => vec![UsiResponse::Info(format!(
+                    "{}: {}",
+                    locale.text(Message::GameOver),
+                    locale.game_status(status)
+                ))],
+                Ok(_) => Vec::new(),
+                Err(err) => vec![error(Message::PositionError, &err)],
+            },
+            "legalmoves" => match self
+                .parse_move_filters(args)
+                .and_then(|filters| self.legal_moves(&filters).map_err(|err| err.to_string()))
+            {
+                Ok((moves, in_check)) => vec![UsiResponse::LegalMoves { moves, in_check }],
+                Err(err) => vec![error(Message::LegalMovesError, &err)],
             },
-            "go" => match engine.go(&args) {
-                Ok(best) => {
-                    println!("bestmove {best}");
-                    last_bestmove = Some(best);
+            "go" => match self.go(args) {
+                Ok((best, elapsed)) => {
+                    self.last_bestmove = Some(best.clone());
+                    self.start_auto_ponder();
+                    vec![UsiResponse::Time(elapsed), UsiResponse::BestMove(best)]
                 }
                 Err(err) => {
-                    println!("info string go error: {err}");
-                    println!("bestmove resign");
-                    last_bestmove = Some("resign".to_string());
+                    self.last_bestmove = Some("resign".to_string());
+                    vec![
+                        error(Message::GoError, &err),
+                        UsiResponse::BestMove("resign".to_string()),
+                    ]
                 }
             },
-            "stop" => {
-                if let Some(best) = last_bestmove.as_deref() {
-                    println!("bestmove {best}");
-                } else {
-                    println!("bestmove resign");
+            "stop" => vec![UsiResponse::BestMove(
+                self.last_bestmove
+                    .clone()
+                    .unwrap_or_else(|| "resign".to_string()),
+            )],
+            "eval" => vec![UsiResponse::Info(self.eval_command())],
+            "see" => vec![match self.see_command(args) {
+                Ok(line) => UsiResponse::Info(line),
+                Err(err) => UsiResponse::Error(format!("see error: {err}")),
+            }],
+            "probe" => vec![UsiResponse::Info(self.probe_command())],
+            "reloadeval" => vec![match self.reload_eval() {
+                Ok(path) => UsiResponse::Info(format!("reloaded eval file {}", path.display())),
+                Err(err) => UsiResponse::Error(format!("reloadeval error: {err}")),
+            }],
+            "attacks" => match self.attacks_command(args) {
+                Ok(board) => board
+                    .lines()
+                    .map(|row| UsiResponse::Info(row.to_string()))
+                    .collect(),
+                Err(err) => vec![UsiResponse::Error(format!("attacks error: {err}"))],
+            },
+            "selftest" => {
+                let results = selftest::run_selftest();
+                let failed = results.iter().filter(|result| !result.passed()).count();
+                let mut responses: Vec<UsiResponse> = results
+                    .iter()
+                    .map(|result| UsiResponse::Info(result.to_string()))
+                    .collect();
+                responses.push(UsiResponse::Info(format!(
+                    "selftest {} passed {failed} failed",
+                    results.len() - failed
+                )));
+                responses
+            }
+            "setoption" => match self.setoption_command(args) {
+                Ok(()) => Vec::new(),
+                Err(err) => vec![error(Message::SetOptionError, &err)],
+            },
+            "quit" => {
+                self.stop_ponder();
+                Vec::new()
+            }
+            _ => vec![error(Message::UnknownCommand, &command)],
+        }
+    }
+}
+
+impl<B> UsiEngine<B> {
+    /// 自分の手を指し、序盤なら勝敗を覚える局面として残す。
+    fn play_own_move(&mut self, mv: Move) -> Result<(), PositionError> {
+        self.position.play_move_mut(&mv)?;
+        if self.experience_file.is_some() && self.position.ply() <= RESULT_PLIES {
+            self.game_line.push(self.position.clone_without_history());
+        }
+        Ok(())
+    }
+}
+
+impl<B> Drop for UsiEngine<B> {
+    fn drop(&mut self) {
+        self.stop_ponder();
+    }
+}
+
+/// `setoption name <id> [value <x>]` の引数を名前と値に分解する。
+fn parse_setoption(args: &[&str]) -> Result<(String, String), String> {
+    if args.first() != Some(&"name") {
+        return Err("setoption requires a name".to_string());
+    }
+    let rest = &args[1..];
+    let value_pos = rest.iter().position(|&token| token == "value");
+    let (name, value) = match value_pos {
+        Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+        None => (rest, &[][..]),
+    };
+    if name.is_empty() {
+        return Err("setoption requires a name".to_string());
+    }
+    Ok((name.join(" "), value.join(" ")))
+}
+
+pub fn run() -> Result<(), Box<dyn Error>> {
+    run_cli(&[])
+}
+
+/// 標準入出力でUSIエンジンとして動く。`--config PATH` の設定ファイルを、指定がなければ
+/// 実行ファイルの隣の `ginko.toml` があればそれを、`usi` を受け取る前に反映する。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        Some(index) => Some(PathBuf::from(
+            args.get(index + 1).ok_or("--config requires a path")?,
+        )),
+        None => config::default_path().filter(|path| path.is_file()),
+    };
+    let mut engine = UsiEngine::new()?;
+    engine.set_crash_log(Some(PathBuf::from(CRASH_LOG_FILE)));
+    if let Some(path) = path {
+        engine.apply_config(&EngineConfig::load(&path)?)?;
+    }
+    run_engine(engine, io::stdin().lock(), io::stdout())
+}
+
+/// 任意の入出力でUSIのコマンドループを回す。
+pub fn run_with<R: BufRead, W: Write>(input: R, output: W) -> Result<(), Box<dyn Error>> {
+    run_engine(UsiEngine::new()?, input, output)
+}
+
+/// パニックの内容を、その時点の局面と直近のコマンドとともに `path` へ追記する。
+fn write_crash_log(
+    path: &Path,
+    message: &str,
+    sfen: &str,
+    history: &VecDeque<String>,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "panic: {message}")?;
+    writeln!(file, "build: {}", engine_info())?;
+    writeln!(file, "position: {sfen}")?;
+    writeln!(file, "commands:")?;
+    for command in history {
+        writeln!(file, "  {command}")?;
+    }
+    writeln!(file)?;
+    Ok(())
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// 探索を差し替えたエンジンでUSIのコマンドループを回す。
+/// コマンドの処理中にパニックしても終了せず、記録を残して知らせる。パニックしたのが
+/// `go` なら、GUIが指し手を待ち続けないように `bestmove resign` も返す。
+pub fn run_engine<B, R, W>(
+    mut engine: UsiEngine<B>,
+    input: R,
+    mut output: W,
+) -> Result<(), Box<dyn Error>>
+where
+    B: SearchBackend + Default + 'static,
+    R: BufRead,
+    W: Write,
+{
+    let mut history = VecDeque::with_capacity(CRASH_HISTORY_LEN);
+
+    for line in input.lines() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if history.len() == CRASH_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(trimmed.to_string());
+
+        // 探索中の `info` 行を書き出せるように、コマンドは別のスレッドで処理する。
+        let buffer = Arc::clone(&engine.info_buffer);
+        let handled = thread::scope(|scope| -> io::Result<_> {
+            let (done, finished) = mpsc::channel();
+            let engine = &mut engine;
+            scope.spawn(move || {
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| engine.execute(trimmed)));
+                let _ = done.send(handled);
+            });
+            loop {
+                match finished.recv_timeout(INFO_POLL_INTERVAL) {
+                    Ok(handled) => return Ok(handled),
+                    Err(RecvTimeoutError::Timeout) => write_search_info(&mut output, &buffer)?,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        unreachable!("command thread exits after sending its result")
+                    }
                 }
             }
-            "setoption" => {
-                // Options are not implemented yet.
+        })?;
+        match handled {
+            Ok(responses) => {
+                for response in &responses {
+                    writeln!(output, "{response}")?;
+                }
+                if trimmed.split_whitespace().next() == Some("quit") {
+                    break;
+                }
             }
-            "quit" => break,
-            _ => {
-                println!("info string unknown command: {command}");
+            Err(payload) => {
+                write_search_info(&mut output, &buffer)?;
+                let message = panic_message(payload.as_ref());
+                if let Some(path) = engine.crash_log.as_deref()
+                    && let Err(err) =
+                        write_crash_log(path, &message, &engine.position.to_sfen(), &history)
+                {
+                    writeln!(output, "info string failed to write crash log: {err}")?;
+                }
+                let label = engine.locale.text(Message::InternalError);
+                writeln!(output, "info string {label}: {message}")?;
+                if trimmed.split_whitespace().next() == Some("go") {
+                    writeln!(output, "bestmove resign")?;
+                    engine.last_bestmove = Some("resign".to_string());
+                }
             }
         }
-        io::stdout().flush().ok();
+        output.flush()?;
     }
 
     Ok(())
 }
+
+fn drain_info_buffer(buffer: &InfoBuffer) -> Vec<UsiResponse> {
+    buffer
+        .lock()
+        .map(|mut buffer| std::mem::take(&mut *buffer))
+        .unwrap_or_default()
+}
+
+/// 探索中に溜まった報告を `output` に書く。
+fn write_search_info(output: &mut impl Write, buffer: &InfoBuffer) -> io::Result<()> {
+    let responses = drain_info_buffer(buffer);
+    for response in &responses {
+        writeln!(output, "{response}")?;
+    }
+    if !responses.is_empty() {
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// ファイルの更新時刻と大きさ。読めなければ `None`。
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::RelScore;
+    use std::sync::Mutex;
+
+    #[test]
+    fn setoption_toggles_analysis_mode() {
+        let mut engine = UsiEngine::new().expect("engine");
+        engine
+            .setoption_command(&["name", "USI_AnalyseMode", "value", "true"])
+            .expect("setoption");
+        assert!(engine.parse_go_limits(&["depth", "2"]).analysis);
+        assert!(engine.setoption_command(&["name", "NoSuchOption"]).is_err());
+        assert_eq!(
+            engine.set_option("TTInstantMove", "maybe"),
+            Err(OptionError::InvalidValue {
+                name: "TTInstantMove".to_string(),
+                value: "maybe".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn execute_returns_typed_responses() {
+        let mut engine = UsiEngine::new().expect("engine");
+        let responses = engine.execute("usi");
+        assert!(matches!(responses.first(), Some(UsiResponse::Id { .. })));
+        assert_eq!(responses.last(), Some(&UsiResponse::UsiOk));
+        let responses = engine.execute("isready");
+        assert_eq!(responses.last(), Some(&UsiResponse::ReadyOk));
+        let memory = format!("info string memory tt {} ", DEFAULT_HASH_MB * 1024);
+        assert!(responses[0].to_string().starts_with(&memory));
+        assert!(engine.execute("position startpos").is_empty());
+        let responses = engine.execute("legalmoves from 1e");
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            responses[0].to_string(),
+            "legalmoves 1e1b 1e1c 1e1d\ncheckstate false"
+        );
+
+        engine
+            .set_option("CoordinateMapping", "mirror-files")
+            .expect("setoption");
+        let responses = engine.execute("go depth 1");
+        let [
+            UsiResponse::SearchInfo { info, .. },
+            UsiResponse::Time(_),
+            UsiResponse::BestMove(best),
+        ] = responses.as_slice()
+        else {
+            panic!("unexpected responses: {responses:?}");
+        };
+        assert_eq!(info.depth, 1);
+        assert!(responses[0].to_string().ends_with(&format!(" pv {best}")));
+        assert_eq!(
+            engine.execute("stop"),
+            [UsiResponse::BestMove(best.clone())]
+        );
+        assert!(matches!(
+            engine.execute("frobnicate").as_slice(),
+            [UsiResponse::Error(_)]
+        ));
+        assert!(engine.execute("   ").is_empty());
+    }
+
+    #[test]
+    fn root_split_needs_the_option_and_two_threads() {
+        let mut engine = UsiEngine::new().expect("engine");
+        engine.set_option("RootSplit", "true").expect("setoption");
+        assert!(engine.root_split.is_none());
+        engine.set_option("Threads", "2").expect("setoption");
+        assert_eq!(engine.root_split.as_ref().map(|s| s.threads()), Some(2));
+        let (best, _) = engine.go(&["depth", "2"]).expect("go");
+        assert_ne!(best, "resign");
+        engine.set_option("RootSplit", "false").expect("setoption");
+        assert!(engine.root_split.is_none());
+    }
+
+    #[test]
+    fn auto_ponder_fills_shared_table_until_interrupted() {
+        let mut engine = UsiEngine::new().expect("engine");
+        engine.set_option("AutoPonder", "true").expect("setoption");
+        engine.go(&["depth", "2"]).expect("go");
+        let mut target = engine.position.clone();
+        if let Some(reply) = engine
+            .searcher
+            .principal_variation(&engine.position, 1)
+            .first()
+        {
+            target.play_move_mut(reply).expect("reply");
+        }
+        engine.start_auto_ponder();
+        assert!(engine.ponder.is_some());
+        engine.parse_position(&["startpos"]).expect("position");
+        assert!(engine.ponder.is_none());
+        assert!(engine.shared_tt.probe(target.zobrist_key()).is_some());
+    }
+
+    #[test]
+    fn run_with_answers_handshake_on_given_streams() {
+        let input = b"usi\nisready\nposition startpos\nlegalmoves\nquit\n";
+        let mut output = Vec::new();
+        run_with(&input[..], &mut output).expect("usi loop");
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("usiok\ninfo string memory "));
+        assert!(text.contains(" KiB\nreadyok\n"));
+        assert!(text.contains("checkstate false"));
+    }
+
+    #[test]
+    fn search_info_goes_to_the_given_writer() {
+        let input = b"setoption name CoordinateMapping value mirror-files\nposition startpos\ngo depth 2\nquit\n";
+        let mut output = Vec::new();
+        run_with(&input[..], &mut output).expect("usi loop");
+        let text = String::from_utf8(output).unwrap();
+        let info = text.find("info depth 2 ").expect("info line");
+        let best = text.find("bestmove ").expect("bestmove");
+        assert!(info < best);
+        let best = text[best + "bestmove ".len()..].lines().next().unwrap();
+        assert!(text[info..].contains(&format!(" pv {best}")), "{text}");
+
+        let mut engine = UsiEngine::new().expect("engine");
+        let recorder = Arc::new(Mutex::new(Vec::new()));
+        engine.set_info_sink(Box::new(LineRecorder(Arc::clone(&recorder))));
+        engine
+            .set_option("CoordinateMapping", "mirror-files")
+            .expect("setoption");
+        engine.go(&["depth", "2"]).expect("go");
+        assert!(!recorder.lock().unwrap().is_empty());
+        assert!(engine.take_search_info().is_empty());
+    }
+
+    struct LineRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl InfoSink for LineRecorder {
+        fn info(&mut self, info: &SearchInfo) {
+            self.0.lock().unwrap().push(info.to_usi());
+        }
+    }
+
+    #[test]
+    fn handicap_start_positions_rebase_the_material_term() {
+        let input = b"position sfen 1bsgk/4p/5/P4/KGSBR w - 1 moves 4a5b\neval\nposition startpos\neval\nquit\n";
+        let mut output = Vec::new();
+        run_with(&input[..], &mut output).expect("usi loop");
+        let text = String::from_utf8(output).unwrap();
+        assert_eq!(text.matches("info string eval material 0 ").count(), 2);
+    }
+
+    #[test]
+    fn piece_value_options_change_the_material_term() {
+        let input = b"usi\nsetoption name RookValue value 1500\nsetoption name PawnValue value 5001\nposition sfen 4k/5/5/5/KR3 b - 1\neval\nquit\n";
+        let mut output = Vec::new();
+        run_with(&input[..], &mut output).expect("usi loop");
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("option name PawnValue type spin default 100 min 0 max 5000\n"));
+        assert!(text.contains("info string eval material 1500 "));
+    }
+
+    #[test]
+    fn eval_file_is_reloaded_on_command_and_when_watched() {
+        let path = std::env::temp_dir().join(format!("ginko-eval-{}.toml", std::process::id()));
+        let path_text = path.to_str().expect("utf-8 path");
+        let material = |engine: &UsiEngine| {
+            engine
+                .searcher
+                .eval_breakdown(&engine.position)
+                .expect("breakdown")
+                .material
+        };
+        fs::write(&path, "RookValue = 1500\n").expect("write");
+        let mut engine = UsiEngine::new().expect("engine");
+        engine.set_option("EvalFile", path_text).expect("setoption");
+        engine
+            .parse_position(&["sfen", "4k/5/5/5/KR3", "b", "-", "1"])
+            .expect("position");
+        assert_eq!(material(&engine), 1500);
+
+        fs::write(&path, "RookValue = 1200\n").expect("write");
+        engine.reload_eval().expect("reload");
+        assert_eq!(material(&engine), 1200);
+
+        engine
+            .set_option("EvalFileWatch", "true")
+            .expect("setoption");
+        fs::write(&path, "# tuned\nRookValue = 900\n").expect("write");
+        engine.warm_up();
+        assert_eq!(material(&engine), 900);
+        assert_eq!(engine.notices.len(), 1);
+
+        fs::write(&path, "KnightValue = 300\n").expect("write");
+        assert!(engine.reload_eval().is_err());
+        assert_eq!(material(&engine), 900);
+        engine.notices.clear();
+        engine.warm_up();
+        assert!(engine.notices.is_empty());
+
+        assert!(engine.set_option("EvalFile", path_text).is_err());
+        assert_eq!(engine.notices.len(), 1);
+        assert!(
+            engine.notices[0].contains("KnightValue"),
+            "{:?}",
+            engine.notices
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn warm_up_notices_come_before_readyok() {
+        let path = std::env::temp_dir().join(format!("ginko-ready-{}.toml", std::process::id()));
+        fs::write(&path, "RookValue = 1500\n").expect("write");
+        let mut engine = UsiEngine::new().expect("engine");
+        engine
+            .set_option("EvalFile", path.to_str().expect("utf-8 path"))
+            .expect("setoption");
+        engine
+            .set_option("EvalFileWatch", "true")
+            .expect("setoption");
+        fs::write(&path, "# tuned\nRookValue = 1200\n").expect("write");
+
+        let mut output = Vec::new();
+        run_engine(engine, &b"isready\nquit\n"[..], &mut output).expect("run");
+        let text = String::from_utf8(output).expect("utf-8");
+        let lines: Vec<&str> = text.lines().collect();
+        let reloaded = lines
+            .iter()
+            .position(|line| line.starts_with("info string reloaded eval file"));
+        let ready = lines.iter().position(|line| *line == "readyok");
+        assert!(reloaded.is_some() && reloaded < ready, "{text}");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn config_values_are_applied_and_advertised_as_defaults() {
+        let mut engine = UsiEngine::new().expect("engine");
+        let config = EngineConfig::parse("Threads = 2\nLocale = \"ja\"\n").expect("config");
+        engine.apply_config(&config).expect("apply");
+        assert_eq!(engine.threads, 2);
+        assert_eq!(engine.locale, Locale::Japanese);
+        let lines: Vec<String> = engine.options().iter().map(|o| o.to_usi()).collect();
+        assert!(
+            lines.contains(&"option name Threads type spin default 2 min 1 max 64".to_string())
+        );
+
+        let unknown = EngineConfig::parse("Hash = 64\n").expect("config");
+        assert!(engine.apply_config(&unknown).is_err());
+    }
+
+    #[test]
+    fn hash_option_resizes_the_shared_table() {
+        let mut engine = UsiEngine::new().expect("engine");
+        assert_eq!(engine.hash_megabytes(), DEFAULT_HASH_MB);
+        engine.set_option("USI_Hash", "4").expect("setoption");
+        assert_eq!(engine.hash_megabytes(), 4);
+        assert!(engine.notices.is_empty());
+        engine.set_option("RootSplit", "true").expect("setoption");
+        engine.set_option("Threads", "2").expect("setoption");
+        engine.go(&["depth", "2"]).expect("go");
+        assert!(
+            engine
+                .shared_tt
+                .probe(engine.position.zobrist_key())
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn single_thread_search_uses_the_sized_table() {
+        let mut engine = UsiEngine::new().expect("engine");
+        engine.set_info_sink(Box::new(NullInfoSink));
+        engine.set_option("USI_Hash", "2").expect("setoption");
+        engine.go(&["depth", "2"]).expect("go");
+        assert_eq!(engine.hash_megabytes(), 2);
+        assert_eq!(engine.memory_usage().tt, 2 << 20);
+        assert!(
+            engine
+                .shared_tt
+                .probe(engine.position.zobrist_key())
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn next_search_follows_the_previous_principal_variation() {
+        let mut engine = UsiEngine::new().expect("engine");
+        engine.set_info_sink(Box::new(NullInfoSink));
+        engine.go(&["depth", "4"]).expect("go");
+        let pv: Vec<Move> = engine.previous_pv.iter().map(|&(_, mv)| mv).collect();
+        assert!(pv.len() >= 3, "{pv:?}");
+        assert_eq!(engine.expected_line(), pv[1..]);
+
+        let reply = pv[1].to_usi();
+        let own = pv[0].to_usi();
+        engine
+            .parse_position(&["startpos", "moves", &own, &reply])
+            .expect("position");
+        assert_eq!(engine.expected_line(), pv[2..]);
+
+        engine.new_game().expect("new game");
+        assert!(engine.expected_line().is_empty());
+    }
+
+    #[test]
+    fn locale_option_translates_info_strings() {
+        let input = "setoption name Locale value ja\nfrobnicate\nposition sfen 4k/5/5/5/K4 b - 1 moves 5e5x\nquit\n";
+        let mut output = Vec::new();
+        run_with(input.as_bytes(), &mut output).expect("usi loop");
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("info string 不明なコマンド: frobnicate\n"));
+        assert!(text.contains("info string 局面を設定できません: "));
+    }
+
+    #[test]
+    fn forced_promotion_is_corrected_unless_disabled() {
+        let mut engine = UsiEngine::new().expect("engine");
+        let tokens = ["sfen", "k4/4P/5/5/4K", "b", "-", "1", "moves", "1b1a"];
+        engine.parse_position(&tokens).expect("corrected");
+        assert_eq!(engine.position.to_sfen(), "k3+P/5/5/5/4K w - 2");
+
+        engine
+            .set_option("ForcedPromotion", "reject")
+            .expect("setoption");
+        let err = engine.parse_position(&tokens).unwrap_err();
+        assert_eq!(err.to_string(), "illegal move: 1b1a (P must promote on 1a)");
+    }
+
+    #[test]
+    fn debug_commands_report_internals() {
+        let input = b"position startpos\neval\nsee 1e1d\nprobe\nattacks white\ngo depth 2\nposition startpos\nprobe\nquit\n";
+        let mut output = Vec::new();
+        run_with(&input[..], &mut output).expect("usi loop");
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains(
+            "info string eval material 0 positional 0 hands 0 tropism 0 king_escapes 0 entering_king 0 race 0 opening 0 mating 0 tempo 20 total 20\n"
+        ));
+        assert!(text.contains("info string see 1e1d "));
+        assert!(text.contains("info string probe none\n"));
+        assert!(text.contains("info string  r  b* s* g* k* a\n"));
+        assert!(text.contains("info string probe depth 2 "));
+    }
+
+    #[derive(Default)]
+    struct PanickingBackend;
+
+    impl SearchBackend for PanickingBackend {
+        fn search_with_stop(
+            &mut self,
+            _position: &Position,
+            _limits: SearchLimits,
+            _stop: &StopToken,
+        ) -> Result<crate::search::SearchResult, PositionError> {
+            panic!("search exploded");
+        }
+
+        fn principal_variation(&self, _position: &Position, _max_len: usize) -> crate::MoveList {
+            crate::MoveList::new()
+        }
+
+        fn set_info_sink(&mut self, _sink: Box<dyn crate::InfoSink>) {}
+
+        fn eval_breakdown(&self, _position: &Position) -> Option<crate::evaluation::EvalBreakdown> {
+            panic!("eval exploded");
+        }
+    }
+
+    #[test]
+    fn panics_are_logged_and_answered_with_resign() {
+        let log = std::env::temp_dir().join(format!("ginko-crash-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&log);
+        let mut engine = UsiEngine::with_backend(PanickingBackend).expect("engine");
+        engine.set_crash_log(Some(log.clone()));
+        let input = b"position startpos moves 1e1d\neval\ngo depth 1\nisready\nquit\n";
+        let mut output = Vec::new();
+        run_engine(engine, &input[..], &mut output).expect("usi loop");
+
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("info string internal error: eval exploded\ninfo string "));
+        assert!(text.contains("info string internal error: search exploded\nbestmove resign\n"));
+        assert_eq!(text.matches("bestmove").count(), 1);
+        assert!(text.ends_with("readyok\n"));
+        let crash = std::fs::read_to_string(&log).expect("crash log");
+        let _ = std::fs::remove_file(&log);
+        assert!(crash.contains("panic: search exploded"));
+        assert!(crash.contains("position: rbsgk/4p/5/P3R/KGSB1 w - 2"));
+        assert!(crash.contains("  go depth 1\n"));
+    }
+
+    #[test]
+    fn coordinates_are_detected_from_position_moves() {
+        assert_eq!(
+            CoordinateMapping::ALL[3].translate("1e1d+ G*2b"),
+            "5a5b+ G*4d"
+        );
+        let mut engine = UsiEngine::new().expect("engine");
+        // 中央の筋だけを使う手は、筋をどちらから数えても同じなので決めない。
+        engine
+            .parse_position(&["sfen", "2k2/5/5/5/2K2", "b", "-", "1", "moves", "3e3d"])
+            .expect("position");
+        assert!(engine.detect_coordinates);
+        assert_eq!(engine.coordinates().name(), "standard");
+        // 筋を左から数えるGUIでは、1eの飛車を1dへ動かす手が5e5dになる。
+        engine
+            .parse_position(&["startpos", "moves", "5e5d"])
+            .expect("position");
+        assert_eq!(engine.coordinates().name(), "mirror-files");
+        let (moves, _) = engine.legal_moves(&[]).expect("legal moves");
+        // 内部表記の1a2b（後手玉）はGUIでは5a4bになる。
+        assert!(moves.contains(&"5a4b".to_string()));
+        assert!(!moves.contains(&"1a2b".to_string()));
+        let (best, _) = engine.go(&["depth", "1"]).expect("go");
+        let mut gui_moves = vec!["startpos", "moves", "5e5d"];
+        gui_moves.push(&best);
+        engine.parse_position(&gui_moves).expect("replayed");
+
+        engine
+            .set_option("CoordinateMapping", "standard")
+            .expect("setoption");
+        assert!(
+            engine
+                .parse_position(&["startpos", "moves", "5e5d"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn experience_is_saved_on_gameover_and_replayed() {
+        let path = std::env::temp_dir().join(format!("ginko-usi-exp-{}.txt", std::process::id()));
+        let path_text = path.to_str().expect("utf-8 path");
+        let mut engine = UsiEngine::new().expect("engine");
+        engine
+            .set_option("ExperienceFile", path_text)
+            .expect("setoption");
+        let (best, _) = engine.go(&["depth", "2"]).expect("go");
+        engine.game_over(None).expect("save");
+
+        let mut replay = UsiEngine::new().expect("engine");
+        replay
+            .set_option("ExperienceFile", path_text)
+            .expect("setoption");
+        let (instant, elapsed) = replay.go(&["depth", "2"]).expect("go");
+        std::fs::remove_file(&path).expect("cleanup");
+        assert_eq!(instant, best);
+        assert_eq!(elapsed, Duration::ZERO);
+    }
+
+    #[test]
+    fn experience_reply_is_searched_when_it_repeats_a_position() {
+        let searched = |engine: &mut UsiEngine, position: &[&str]| {
+            let recorded = RecordingSink::default();
+            engine.set_info_sink(Box::new(recorded.clone()));
+            engine.parse_position(position).expect("position");
+            let stored = engine.position.parse_usi_move("5b5a").expect("move");
+            engine.experience.record(&engine.position, stored, 0, 10);
+            engine.go(&["depth", "1"]).expect("go");
+            !recorded.0.lock().unwrap().is_empty()
+        };
+        let mut engine = UsiEngine::new().expect("engine");
+        // 記録された 5b5a は初期局面に戻る手。
+        assert!(searched(
+            &mut engine,
+            &["startpos", "moves", "1e1d", "5a5b", "1d1e"]
+        ));
+        // 同じ局面でも履歴がなければ記録どおりにすぐ指す。
+        let mut fresh = UsiEngine::new().expect("engine");
+        assert!(!searched(
+            &mut fresh,
+            &["sfen", "1bsgk/r3p/5/P4/KGSBR", "w", "-", "4"]
+        ));
+    }
+
+    #[test]
+    fn penalized_experience_reply_with_a_repetition_is_searched() {
+        let mut engine = UsiEngine::new().expect("engine");
+        let recorded = RecordingSink::default();
+        engine.set_info_sink(Box::new(recorded.clone()));
+        engine
+            .parse_position(&["startpos", "moves", "1e1d", "5a5b", "1d1e", "5b5a"])
+            .expect("position");
+        let stored = engine.position.parse_usi_move("1e1d").expect("move");
+        engine.experience.record(&engine.position, stored, 0, 10);
+        let lost = engine.position.play_move(&stored).expect("move");
+        for _ in 0..3 {
+            engine
+                .experience
+                .record_result(std::slice::from_ref(&lost), LineOutcome::Loss);
+        }
+        let penalties = engine.experience.lost_line_penalties(&engine.position);
+        assert!(penalties.iter().any(|&(mv, _)| mv == stored));
+        assert!(engine.position.current_repetition_count() > 1);
+
+        engine.go(&["depth", "1"]).expect("go");
+        assert!(!recorded.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn engine_deviates_from_a_repeatedly_lost_opening() {
+        let path = std::env::temp_dir().join(format!("ginko-usi-lost-{}.txt", std::process::id()));
+        let mut engine = UsiEngine::new().expect("engine");
+        engine
+            .set_option("ExperienceFile", path.to_str().expect("utf-8 path"))
+            .expect("setoption");
+        let mut played = Vec::new();
+        for _ in 0..3 {
+            engine.new_game().expect("usinewgame");
+            let (best, _) = engine.go(&["depth", "2"]).expect("go");
+            engine.game_over(Some(LineOutcome::Loss)).expect("save");
+            played.push(best);
+        }
+        std::fs::remove_file(&path).expect("cleanup");
+        assert_eq!(played[0], played[1]);
+        assert_ne!(played[1], played[2]);
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingSink(Arc<Mutex<Vec<usize>>>);
+
+    impl InfoSink for RecordingSink {
+        fn info(&mut self, info: &SearchInfo) {
+            self.0.lock().unwrap().push(info.depth);
+        }
+    }
+
+    #[test]
+    fn info_lines_are_throttled_except_completed_depths() {
+        let recorded = RecordingSink::default();
+        let mut sink = RateLimitedInfoSink::new(recorded.clone(), Duration::from_secs(3600));
+        let info = |depth, complete| SearchInfo {
+            depth,
+            score: RelScore(0),
+            nodes: 0,
+            best_move: None,
+            pv: Vec::new(),
+            elapsed: Duration::ZERO,
+            complete,
+        };
+        sink.info(&info(1, false));
+        sink.info(&info(2, false));
+        sink.info(&info(3, false));
+        sink.info(&info(3, true));
+        sink.info(&info(4, false));
+        sink.flush();
+        assert_eq!(*recorded.0.lock().unwrap(), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn position_sfen_accepts_a_missing_ply() {
+        let mut engine = UsiEngine::new().expect("engine");
+        engine
+            .parse_position(&["sfen", "4k/5/5/5/K4", "b", "G", "moves", "G*2b"])
+            .expect("position");
+        assert_eq!(engine.position.to_sfen(), "4k/3G1/5/5/K4 w - 2");
+    }
+
+    #[test]
+    fn position_reports_repetition_result() {
+        let mut engine = UsiEngine::new().expect("engine");
+        let mut tokens = vec!["startpos", "moves"];
+        for _ in 0..3 {
+            tokens.extend(["1e1d", "5a5b", "1d1e", "5b5a"]);
+        }
+        assert_eq!(
+            engine.parse_position(&tokens).expect("position"),
+            GameStatus::RepetitionDraw
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! 探索の再現性を確かめる検査。リリース前や並列探索を変えた後に使う。
+//!
+//! 1スレッドの探索は、同じ乱数の種と同じ制限なら節点数・最善手・評価値・読み筋まで
+//! 一致しなければならない。複数スレッドの探索は実行ごとに変わってよいが、合法な手と
+//! 読み筋、詰みの値を超えない評価値を返さなければならない。
+
+use std::error::Error;
+
+use crate::bench::BENCH_SFENS;
+use crate::match_runner::parse_value;
+use crate::moves::{Move, MoveList};
+use crate::position::Position;
+use crate::root_split::RootSplitSearcher;
+use crate::search::{MATE_VALUE, NullInfoSink, SearchBackend, SearchLimits, Searcher};
+use crate::selftest::CheckResult;
+
+/// 既定の探索の深さ。
+pub const DEFAULT_VERIFY_DEPTH: usize = 4;
+
+/// 複数スレッドの検査に使う既定のスレッド数。
+pub const DEFAULT_VERIFY_THREADS: usize = 4;
+
+/// 乱択を働かせるため、1スレッドの検査で最善手からこれだけ悪い手まで選ばせる。
+const VERIFY_RANDOMNESS: i32 = 30;
+
+/// 検査の条件。
+#[derive(Clone, Copy, Debug)]
+pub struct VerifyConfig {
+    pub depth: usize,
+    pub threads: usize,
+    pub seed: u64,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            depth: DEFAULT_VERIFY_DEPTH,
+            threads: DEFAULT_VERIFY_THREADS,
+            seed: 1,
+        }
+    }
+}
+
+/// 1回の探索で比べる値。
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Outcome {
+    nodes: u64,
+    best_move: Option<Move>,
+    score: i32,
+    pv: MoveList,
+}
+
+fn run(
+    backend: &mut dyn SearchBackend,
+    position: &Position,
+    limits: SearchLimits,
+) -> Result<Outcome, String> {
+    let result = backend
+        .search(position, limits)
+        .map_err(|err| format!("{}: {err}", position.to_sfen()))?;
+    Ok(Outcome {
+        nodes: result.nodes,
+        best_move: result.best_move,
+        score: result.score.value(),
+        pv: backend.principal_variation(position, limits.depth),
+    })
+}
+
+fn seeded_searcher(seed: u64) -> Searcher {
+    let mut searcher = Searcher::new();
+    searcher.set_info_sink(Box::new(NullInfoSink));
+    searcher.set_seed(seed);
+    searcher
+}
+
+/// すべての検査を順に行う。
+pub fn run_verify(config: &VerifyConfig) -> Vec<CheckResult> {
+    vec![
+        CheckResult {
+            name: "single-thread-determinism",
+            failure: check_determinism(config).err(),
+        },
+        CheckResult {
+            name: "multi-thread-sanity",
+            failure: check_multi_thread(config).err(),
+        },
+    ]
+}
+
+/// 別々に作った探索器と、使い回して種を設定し直した探索器で、同じ結果になるか。
+fn check_determinism(config: &VerifyConfig) -> Result<(), String> {
+    let limits = SearchLimits {
+        depth: config.depth.max(1),
+        randomness: VERIFY_RANDOMNESS,
+        ..SearchLimits::default()
+    };
+    let mut reused = seeded_searcher(config.seed);
+    for sfen in BENCH_SFENS {
+        let position = Position::from_sfen(sfen).map_err(|err| format!("{sfen}: {err}"))?;
+        let expected = run(&mut seeded_searcher(config.seed), &position, limits)?;
+        let fresh = run(&mut seeded_searcher(config.seed), &position, limits)?;
+        reused.set_seed(config.seed);
+        let again = run(&mut reused, &position, limits)?;
+        for (label, outcome) in [("fresh searcher", fresh), ("reused searcher", again)] {
+            if outcome != expected {
+                return Err(format!(
+                    "{sfen}: {label} gave {}, expected {}",
+                    describe(&outcome),
+                    describe(&expected)
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 複数スレッドの探索の最善手と読み筋が合法で、評価値が範囲に収まるか。
+fn check_multi_thread(config: &VerifyConfig) -> Result<(), String> {
+    let limits = SearchLimits {
+        depth: config.depth.max(1),
+        ..SearchLimits::default()
+    };
+    let mut searcher = RootSplitSearcher::new(config.threads.max(2));
+    searcher.set_info_sink(Box::new(NullInfoSink));
+    for sfen in BENCH_SFENS {
+        let position = Position::from_sfen(sfen).map_err(|err| format!("{sfen}: {err}"))?;
+        let outcome = run(&mut searcher, &position, limits)?;
+        if outcome.score.abs() > MATE_VALUE {
+            return Err(format!("{sfen}: score {} is out of range", outcome.score));
+        }
+        let legal = position
+            .generate_legal_moves()
+            .map_err(|err| format!("{sfen}: {err}"))?;
+        match outcome.best_move {
+            Some(mv) if !legal.contains(&mv) => {
+                return Err(format!("{sfen}: best move {} is illegal", mv.to_usi()));
+            }
+            None if !legal.is_empty() => return Err(format!("{sfen}: no best move")),
+            _ => {}
+        }
+        let mut current = position.clone();
+        for (ply, mv) in outcome.pv.iter().enumerate() {
+            let legal = current
+                .generate_legal_moves()
+                .map_err(|err| format!("{sfen}: {err}"))?;
+            if !legal.contains(mv) {
+                return Err(format!(
+                    "{sfen}: pv move {} at ply {ply} is illegal",
+                    mv.to_usi()
+                ));
+            }
+            current
+                .play_move_mut(mv)
+                .map_err(|err| format!("{sfen}: {err}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn describe(outcome: &Outcome) -> String {
+    let pv: Vec<String> = outcome.pv.iter().map(Move::to_usi).collect();
+    format!(
+        "nodes {} bestmove {} score {} pv {}",
+        outcome.nodes,
+        outcome
+            .best_move
+            .map_or("none".to_string(), |mv| mv.to_usi()),
+        outcome.score,
+        pv.join(" ")
+    )
+}
+
+/// `verify` サブコマンド。1つでも失敗したらエラーを返す。
+///
+/// `verify [--depth D] [--threads N] [--seed S]`
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config = VerifyConfig::default();
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--depth" => config.depth = parse_value(flag, iter.next())?,
+            "--threads" => config.threads = parse_value(flag, iter.next())?,
+            "--seed" => config.seed = parse_value(flag, iter.next())?,
+            other => return Err(format!("unknown verify option: {other}").into()),
+        }
+    }
+    let results = run_verify(&config);
+    for result in &results {
+        println!("{result}");
+    }
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    if failed > 0 {
+        return Err(format!("{failed} of {} checks failed", results.len()).into());
+    }
+    println!("all {} checks passed", results.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shallow_verification_passes() {
+        let config = VerifyConfig {
+            depth: 2,
+            threads: 2,
+            seed: 7,
+        };
+        for result in run_verify(&config) {
+            assert!(result.passed(), "{result}");
+        }
+    }
+}
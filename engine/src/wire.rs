@@ -0,0 +1,193 @@
+//! USI以外のフロントエンドに渡す、探索の途中経過と結果のJSON形式。
+//!
+//! 1メッセージを1行のJSONオブジェクトにする。どのメッセージも `"v"`（形式の版）と
+//! `"type"` を持つ。フィールドを足すときは版を変えず、意味や型を変えるときだけ
+//! `WIRE_VERSION` を上げる。受け取る側は知らないフィールドを読み飛ばすこと。
+//!
+//! ```text
+//! {"v":1,"type":"info","depth":5,"score":{"cp":32},"nodes":1234,"time_ms":3,"nps":411333,"best_move":"2e2d","pv":["2e2d","1a2b"],"complete":true}
+//! {"v":1,"type":"result","best_move":"2e2d","score":{"mate":3},"depth":5,"nodes":5678,"elapsed_ms":12,"pv":["2e2d","1a2b"]}
+//! ```
+//!
+//! - `score` は手番側から見た値で、`{"cp":N}` か、詰みなら `{"mate":N}`（詰まされる側なら負）。
+//! - 指し手はエンジン内部の座標のUSI表記。手がなければ `null`。
+//! - `time_ms` は探索開始からの経過ミリ秒、`nps` は1秒あたりのノード数。
+//!
+//! `engine analyze [--depth N] [--sfen SFEN]` はこの形式で標準出力に書く。
+
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::moves::Move;
+use crate::position::Position;
+use crate::score::RelScore;
+use crate::search::{InfoSink, SearchInfo, SearchLimits, SearchResult, Searcher, mate_distance};
+
+/// 形式の版。
+pub const WIRE_VERSION: u32 = 1;
+
+/// 1行のメッセージ。
+#[derive(Clone, Debug)]
+pub enum WireMessage<'a> {
+    Info(&'a SearchInfo),
+    /// 探索の結果と、その局面からの読み筋。
+    Result {
+        result: &'a SearchResult,
+        pv: &'a [Move],
+    },
+}
+
+impl WireMessage<'_> {
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Info(info) => format!(
+                "{{\"v\":{WIRE_VERSION},\"type\":\"info\",\"depth\":{},\"score\":{},\"nodes\":{},\"time_ms\":{},\"nps\":{},\"best_move\":{},\"pv\":{},\"complete\":{}}}",
+                info.depth,
+                score_json(info.score),
+                info.nodes,
+                info.elapsed.as_millis(),
+                info.nps(),
+                move_json(info.best_move),
+                moves_json(&info.pv),
+                info.complete
+            ),
+            Self::Result { result, pv } => {
+                format!(
+                    "{{\"v\":{WIRE_VERSION},\"type\":\"result\",\"best_move\":{},\"score\":{},\"depth\":{},\"nodes\":{},\"elapsed_ms\":{},\"pv\":{}}}",
+                    move_json(result.best_move),
+                    score_json(result.score),
+                    result.depth,
+                    result.nodes,
+                    result.elapsed.as_millis(),
+                    moves_json(pv)
+                )
+            }
+        }
+    }
+}
+
+fn score_json(score: RelScore) -> String {
+    match mate_distance(score) {
+        Some(mate) => format!("{{\"mate\":{mate}}}"),
+        None => format!("{{\"cp\":{}}}", score.value()),
+    }
+}
+
+fn move_json(mv: Option<Move>) -> String {
+    mv.map_or("null".to_string(), |mv| format!("\"{}\"", mv.to_usi()))
+}
+
+fn moves_json(moves: &[Move]) -> String {
+    let moves: Vec<String> = moves
+        .iter()
+        .map(|mv| format!("\"{}\"", mv.to_usi()))
+        .collect();
+    format!("[{}]", moves.join(","))
+}
+
+/// 途中経過を1行ずつJSONで `W` に書く。書けなかった行は捨てる。
+pub struct JsonLinesSink<W> {
+    out: W,
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// 探索の結果を書く。
+    pub fn result(&mut self, result: &SearchResult, pv: &[Move]) {
+        let line = WireMessage::Result { result, pv }.to_json();
+        let _ = writeln!(self.out, "{line}");
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Send> InfoSink for JsonLinesSink<W> {
+    fn info(&mut self, info: &SearchInfo) {
+        let _ = writeln!(self.out, "{}", WireMessage::Info(info).to_json());
+    }
+
+    fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// `engine analyze`：局面を読み、途中経過と結果をJSON Linesで標準出力に書く。
+pub fn run_cli(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut limits = SearchLimits {
+        depth: 6,
+        ..SearchLimits::default()
+    };
+    let mut position = Position::initial()?;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("missing value for {flag}"))?;
+        match flag.as_str() {
+            "--depth" => limits.depth = value.parse()?,
+            "--sfen" => position = Position::from_sfen(value)?,
+            other => return Err(format!("unknown analyze option: {other}").into()),
+        }
+    }
+    let mut searcher = Searcher::new();
+    searcher.set_info_sink(Box::new(JsonLinesSink::new(io::stdout())));
+    let result = searcher.search(&position, limits)?;
+    let pv = searcher.principal_variation(&position, result.depth);
+    JsonLinesSink::new(io::stdout()).result(&result, &pv);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+    use crate::search::{SearchLimits, Searcher};
+    use std::sync::{Arc, Mutex};
+
+    /// 探索器に渡した後も中身を読めるようにする。
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn messages_are_versioned_json_lines() {
+        let position = Position::from_sfen("4k/5/3S1/5/K4 b G 1").unwrap();
+        let buffer = Shared::default();
+        let mut searcher = Searcher::new();
+        searcher.set_info_sink(Box::new(JsonLinesSink::new(buffer.clone())));
+        let limits = SearchLimits {
+            depth: 2,
+            ..SearchLimits::default()
+        };
+        let result = searcher.search(&position, limits).unwrap();
+        let pv = searcher.principal_variation(&position, 2);
+        let mut sink = JsonLinesSink::new(buffer.clone());
+        sink.result(&result, &pv);
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.len() >= 3);
+        assert!(lines[0].starts_with("{\"v\":1,\"type\":\"info\",\"depth\":1,"));
+        assert!(lines[0].contains("\"time_ms\":"));
+        assert!(lines[0].contains("\"nps\":"));
+        assert!(lines[0].contains("\"best_move\":\"G*3a\",\"pv\":[\"G*3a\"]"));
+        let last = lines.last().unwrap();
+        assert!(last.starts_with("{\"v\":1,\"type\":\"result\",\"best_move\":\"G*3a\","));
+        assert!(last.contains("\"score\":{\"mate\":1}"));
+        assert!(last.ends_with("\"pv\":[\"G*3a\"]}"));
+    }
+}
@@ -1,5 +1,3 @@
-use std::sync::OnceLock;
-
 use crate::board::{BOARD_SQUARES, Square};
 use crate::hand::{HAND_MAX_COUNT, HAND_PIECE_KIND_COUNT, HandPieceKind};
 use crate::piece::{Color, PIECE_KIND_COUNT, PieceKind};
@@ -12,39 +10,50 @@ struct ZobristTables {
     side_to_move: u64,
 }
 
-static TABLES: OnceLock<ZobristTables> = OnceLock::new();
+/// コンパイル時に生成するので、実行時の初期化や同期は要らない。
+static TABLES: ZobristTables = ZobristTables::generate();
 
 fn tables() -> &'static ZobristTables {
-    TABLES.get_or_init(ZobristTables::generate)
+    &TABLES
 }
 
 impl ZobristTables {
-    fn generate() -> Self {
+    const fn generate() -> Self {
         let mut state = 0x9E3779B97F4A7C15u64;
-        let mut next = || -> u64 {
-            state = splitmix64(state);
-            state
-        };
 
         let mut piece_square = [[[0u64; BOARD_SQUARES]; PIECE_KIND_COUNT]; COLORS];
-        for color in 0..COLORS {
-            for kind in 0..PIECE_KIND_COUNT {
-                for square in 0..BOARD_SQUARES {
-                    piece_square[color][kind][square] = next();
+        let mut color = 0;
+        while color < COLORS {
+            let mut kind = 0;
+            while kind < PIECE_KIND_COUNT {
+                let mut square = 0;
+                while square < BOARD_SQUARES {
+                    state = splitmix64(state);
+                    piece_square[color][kind][square] = state;
+                    square += 1;
                 }
+                kind += 1;
             }
+            color += 1;
         }
 
         let mut hand = [[[0u64; HAND_MAX_COUNT]; HAND_PIECE_KIND_COUNT]; COLORS];
-        for color in 0..COLORS {
-            for kind in 0..HAND_PIECE_KIND_COUNT {
-                for count in 0..HAND_MAX_COUNT {
-                    hand[color][kind][count] = next();
+        let mut color = 0;
+        while color < COLORS {
+            let mut kind = 0;
+            while kind < HAND_PIECE_KIND_COUNT {
+                let mut count = 0;
+                while count < HAND_MAX_COUNT {
+                    state = splitmix64(state);
+                    hand[color][kind][count] = state;
+                    count += 1;
                 }
+                kind += 1;
             }
+            color += 1;
         }
 
-        let side_to_move = next();
+        let side_to_move = splitmix64(state);
 
         Self {
             piece_square,
@@ -54,7 +63,7 @@ impl ZobristTables {
     }
 }
 
-fn splitmix64(mut x: u64) -> u64 {
+pub(crate) const fn splitmix64(mut x: u64) -> u64 {
     x = x.wrapping_add(0x9E3779B97F4A7C15);
     let mut z = x;
     z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
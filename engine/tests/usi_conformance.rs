@@ -0,0 +1,151 @@
+//! USIのコマンドループを台本どおりに動かし、応答を1行ずつ照合する。
+#![cfg(feature = "usi")]
+
+use engine::engine_info;
+use engine::usi::{UsiEngine, run_with};
+
+/// `script` を1行ずつ送り、応答を行に分けて返す。探索の進み具合や時間を表す
+/// `info`（`info string` 以外）と、`isready` に付くメモリの報告は実行ごとに変わるので取り除く。
+fn transcript(script: &[&str]) -> Vec<String> {
+    let input = script.join("\n") + "\n";
+    let mut output = Vec::new();
+    run_with(input.as_bytes(), &mut output).expect("usi loop");
+    String::from_utf8(output)
+        .expect("utf-8 output")
+        .lines()
+        .filter(|line| !line.starts_with("info ") || line.starts_with("info string "))
+        .filter(|line| !line.starts_with("info string memory "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[track_caller]
+fn assert_session(script: &[&str], expected: &[&str]) {
+    assert_eq!(transcript(script), expected, "script: {script:?}");
+}
+
+#[test]
+fn handshake_lists_identity_and_options() {
+    let info = engine_info();
+    let mut expected = vec![
+        format!("id name {} {}", info.identity.name, info.version),
+        format!("id author {}", info.identity.author),
+        format!("info string build {info}"),
+    ];
+    let engine = UsiEngine::new().expect("engine");
+    expected.extend(engine.options().iter().map(|option| option.to_usi()));
+    expected.push("usiok".to_string());
+    expected.push("readyok".to_string());
+    let expected: Vec<&str> = expected.iter().map(String::as_str).collect();
+    assert_session(&["usi", "isready", "quit", "isready"], &expected);
+}
+
+#[test]
+fn go_answers_with_a_single_bestmove() {
+    assert_session(
+        &[
+            "isready",
+            "usinewgame",
+            "position sfen 4k/5/3S1/5/K4 b G 1",
+            "go depth 1",
+            "quit",
+        ],
+        &["readyok", "bestmove G*3a"],
+    );
+}
+
+#[test]
+fn stop_repeats_the_last_bestmove() {
+    assert_session(&["stop", "quit"], &["bestmove resign"]);
+    assert_session(
+        &[
+            "position sfen 4k/5/3S1/5/K4 b G 1",
+            "go depth 1",
+            "stop",
+            "quit",
+        ],
+        &["bestmove G*3a", "bestmove G*3a"],
+    );
+}
+
+#[test]
+fn commands_work_before_the_handshake() {
+    assert_session(
+        &["position startpos moves 1e1d", "legalmoves", "quit"],
+        &[
+            "legalmoves 1a2b 2a2b 2a3b 3a2b 3a3b 3a4b 4a3b 4a5b 4a2c 4a1d 5a5b 5a5c 5a5d 1b1c",
+            "checkstate false",
+        ],
+    );
+}
+
+#[test]
+fn legalmoves_filters_narrow_the_list() {
+    assert_session(
+        &[
+            "position sfen k4/5/2p2/2R2/4K b GP 1",
+            "legalmoves from 3d captures",
+            "legalmoves drops G from 1e",
+            "legalmoves drops P captures",
+            "legalmoves from 9z",
+            "legalmoves sideways",
+            "quit",
+        ],
+        &[
+            "legalmoves 3d3c",
+            "checkstate false",
+            "legalmoves",
+            "checkstate false",
+            "legalmoves",
+            "checkstate false",
+            "info string legalmoves error: from requires a square",
+            "info string legalmoves error: unknown filter: sideways",
+        ],
+    );
+}
+
+#[test]
+fn malformed_input_is_reported_without_ending_the_session() {
+    assert_session(
+        &[
+            "",
+            "   ",
+            "position",
+            "position sfen 4k/5",
+            "position fen 4k/5/5/5/K4 b - 1",
+            "position startpos moves 9z9z",
+            "setoption value 1",
+            "setoption name NoSuchOption value 1",
+            "frobnicate now",
+            "isready",
+            "quit",
+        ],
+        &[
+            "info string position error: position requires arguments",
+            "info string position error: invalid sfen command",
+            "info string position error: unknown position command",
+            "info string position error: illegal move: 9z9z",
+            "info string setoption error: setoption requires a name",
+            "info string setoption error: unknown option: NoSuchOption",
+            "info string unknown command: frobnicate",
+            "readyok",
+        ],
+    );
+}
+
+#[test]
+fn finished_games_are_announced_and_go_resigns() {
+    assert_session(
+        &[
+            "position startpos moves 1e1d 5a5b 1d1e 5b5a 1e1d 5a5b 1d1e 5b5a 1e1d 5a5b 1d1e 5b5a",
+            "position sfen 4k/5/3S1/5/K4 b G 1 moves G*3a",
+            "go depth 1",
+            "quit",
+        ],
+        &[
+            "info string game over: repetition, white wins",
+            "info string game over: mate, black wins",
+            "bestmove resign",
+        ],
+    );
+}